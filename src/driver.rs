@@ -0,0 +1,865 @@
+use std::fs;
+
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::repl::Repl;
+use noah::lexer::{
+    ast::AstFormat,
+    checker::TypeChecker,
+    chunk::FunctionProto,
+    compiler::Compiler,
+    diagnostic::{Diagnostic, Origin},
+    emitter::CEmitter,
+    formatter::format_source,
+    interpreter::Interpreter,
+    interpreter::MAX_CALL_DEPTH,
+    module::ModuleLoader,
+    optimizer::optimize,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    stmt::Stmt,
+    token::Token,
+    vm::Vm,
+};
+
+// Exit codes follow the BSD sysexits convention, like clox/jlox do.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_UNFORMATTED: i32 = 1; // `noah fmt --check` found a script to reformat.
+pub const EXIT_USAGE: i32 = 64; // Wrong command line.
+pub const EXIT_DATA_ERR: i32 = 65; // Scan or parse error in the script.
+pub const EXIT_NO_INPUT: i32 = 66; // Script file can not be read.
+pub const EXIT_RUNTIME: i32 = 70; // Runtime error while interpreting.
+
+pub const USAGE: &str = "Usage:
+    noah                        Start an interactive repl.
+    noah run <file> [args...]   Run a script, args are visible through argc() and argv(i).
+    noah run --vm <file> [...]  Run a script on the bytecode vm instead of the tree-walker.
+    noah run --no-optimize <file> [...]
+                                Run the script as written, without folding constants and
+                                removing dead code first.
+    noah run --max-depth <n> <file> [...]
+                                Allow calls to nest <n> deep instead of 1000, at most 16384
+                                on the tree-walker.
+    noah run --path <dir> <file> [...]
+                                Also look for imported modules in <dir>, after the directory
+                                of the importing file; can be repeated, $NOAH_PATH is searched
+                                last.
+    noah run --ast json|sexpr <file> [...]
+                                Run a syntax tree written by `noah ast --format`.
+    noah tokens <file>          Print the tokens of a script.
+    noah ast <file>             Print the syntax tree of a script.
+    noah ast --format json|sexpr <file>
+                                Print it in a form other tools can read and write back.
+    noah ast --optimized [--format json|sexpr] <file>
+                                Print the tree after constant folding and dead code removal.
+    noah dis <file>             Print the bytecode of a script, optimized.
+    noah check <file>           Scan, parse, resolve and type check a script without running it.
+    noah build --emit c [-o <out>] <file>
+                                Write the script as C to <out>, next to it by default or to
+                                stdout for -, then `cc -o prog <out> -lm` builds a binary.
+    noah fmt [--check] <file>...
+                                Rewrite scripts in the canonical style, --check only lists
+                                the ones that would change.
+    noah <file> [args...]       Same as `noah run`.
+    noah repl                   Same as `noah` without arguments.
+    noah help                   Print this message.";
+
+// Which backend runs the script.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Backend {
+    Tree, // tree-walking Interpreter.
+    Vm,   // Compiler + Vm.
+}
+
+// The `--` options of `noah run`, given before the script path.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RunOptions {
+    pub backend: Backend,
+    pub max_depth: usize, // deeper calls are a "Stack overflow." runtime error.
+    pub ast: Option<AstFormat>, // the file holds a syntax tree in this format, not source.
+    pub module_path: Vec<PathBuf>, // searched for imports, in order.
+    pub optimize: bool,   // run the tree the Optimizer gives.
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Tree,
+            max_depth: MAX_CALL_DEPTH,
+            ast: None,
+            module_path: vec![],
+            optimize: true,
+        }
+    }
+}
+
+// Rust stack reserved per Noah call: a tree-walker call nests a few dozen Rust frames, which
+// take about 50KiB in a debug build.
+const STACK_PER_CALL: usize = 128 * 1024;
+const MIN_STACK: usize = 256 * 1024 * 1024;
+// The biggest thread stack the driver asks for, larger ones may not be mapped at all. The Vm
+// keeps its frames on the heap and is not limited by it.
+const MAX_STACK: usize = 2 * 1024 * 1024 * 1024;
+pub const MAX_TREE_DEPTH: usize = MAX_STACK / STACK_PER_CALL;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run {
+        file: String,
+        args: Vec<String>,
+        options: RunOptions,
+    },
+    Tokens {
+        file: String,
+    },
+    Ast {
+        file: String,
+        format: Option<AstFormat>, // None for the Debug form of the tree.
+        optimized: bool,           // print the tree the Optimizer gives.
+    },
+    Dis {
+        file: String,
+    },
+    Check {
+        file: String,
+    },
+    Build {
+        file: String,
+        output: Option<String>, // None for the script path with a .c extension.
+    },
+    Fmt {
+        files: Vec<String>,
+        check: bool,
+    },
+    Repl,
+    Help,
+}
+
+impl Command {
+    // brief: Build a Command from the command line arguments (program name excluded).
+    // input:
+    // output: Err with a message if the arguments do not match any subcommand.
+    pub fn from_args(args: &[String]) -> Result<Command, String> {
+        let Some(first) = args.first() else {
+            return Ok(Command::Repl);
+        };
+        let file = || -> Result<String, String> {
+            match args.get(1) {
+                Some(v) => Ok(v.clone()),
+                None => Err(format!("Missing script path for `{}`.", first)),
+            }
+        };
+        let no_extra = |count: usize| -> Result<(), String> {
+            if args.len() > count {
+                return Err(format!("Unexpected argument `{}`.", args[count]));
+            }
+            Ok(())
+        };
+
+        match first.as_str() {
+            "run" => {
+                let (options, rest) = run_options(&args[1..])?;
+                match rest.split_first() {
+                    Some((file, args)) => Ok(Command::Run {
+                        file: file.clone(),
+                        args: args.to_vec(),
+                        options,
+                    }),
+                    None => Err("Missing script path for `run`.".to_string()),
+                }
+            }
+            "tokens" => {
+                no_extra(2)?;
+                Ok(Command::Tokens { file: file()? })
+            }
+            "ast" => {
+                let mut format = None;
+                let mut optimized = false;
+                let mut rest = &args[1..];
+                while let Some((option, tail)) = rest.split_first() {
+                    match option.as_str() {
+                        "--format" => {
+                            let (value, tail) = tail.split_first().unzip();
+                            format = Some(ast_format(value)?);
+                            rest = tail.unwrap_or_default();
+                        }
+                        "--optimized" => {
+                            optimized = true;
+                            rest = tail;
+                        }
+                        _ => break,
+                    }
+                }
+                match rest {
+                    [file] => Ok(Command::Ast {
+                        file: file.clone(),
+                        format,
+                        optimized,
+                    }),
+                    [] => Err("Missing script path for `ast`.".to_string()),
+                    [_, extra, ..] => Err(format!("Unexpected argument `{}`.", extra)),
+                }
+            }
+            "dis" => {
+                no_extra(2)?;
+                Ok(Command::Dis { file: file()? })
+            }
+            "check" => {
+                no_extra(2)?;
+                Ok(Command::Check { file: file()? })
+            }
+            "build" => {
+                let mut emit = None;
+                let mut output = None;
+                let mut rest = &args[1..];
+                while let Some((option, tail)) = rest.split_first() {
+                    let (value, tail) = tail.split_first().unzip();
+                    match option.as_str() {
+                        "--emit" => emit = value,
+                        "-o" => output = Some(value.ok_or("Missing path for `-o`.")?.clone()),
+                        _ => break,
+                    }
+                    rest = tail.unwrap_or_default();
+                }
+                match emit.map(String::as_str) {
+                    Some("c") => {}
+                    Some(target) => return Err(format!("Unknown target `{}`, use c.", target)),
+                    None => return Err("Missing `--emit c` for `build`.".to_string()),
+                }
+                match rest {
+                    [file] => Ok(Command::Build {
+                        file: file.clone(),
+                        output,
+                    }),
+                    [] => Err("Missing script path for `build`.".to_string()),
+                    [_, extra, ..] => Err(format!("Unexpected argument `{}`.", extra)),
+                }
+            }
+            "fmt" => {
+                let check = args.get(1).map(String::as_str) == Some("--check");
+                let files = args[if check { 2 } else { 1 }..].to_vec();
+                match files.iter().find(|file| file.starts_with("--")) {
+                    _ if files.is_empty() => Err("Missing script path for `fmt`.".to_string()),
+                    Some(option) => Err(format!("Unknown option `{}` for `fmt`.", option)),
+                    None => Ok(Command::Fmt { files, check }),
+                }
+            }
+            "repl" => {
+                no_extra(1)?;
+                Ok(Command::Repl)
+            }
+            "help" | "-h" | "--help" => Ok(Command::Help),
+            other if other.starts_with('-') => Err(format!("Unknown option `{}`.", other)),
+            _ => Ok(Command::Run {
+                file: first.clone(),
+                args: args[1..].to_vec(),
+                options: RunOptions::default(),
+            }),
+        }
+    }
+
+    // brief: How much Rust stack `execute` needs, main runs it on a thread of this size.
+    // Only the tree-walker nests Rust calls for Noah calls, run_options caps its depth.
+    // input:
+    // output:
+    pub fn stack_size(&self) -> usize {
+        let max_depth = match self {
+            Command::Run { options, .. } if options.backend == Backend::Vm => 0,
+            Command::Run { options, .. } => options.max_depth,
+            _ => MAX_CALL_DEPTH,
+        };
+        max_depth
+            .saturating_mul(STACK_PER_CALL)
+            .clamp(MIN_STACK, MAX_STACK)
+    }
+
+    // brief: Execute the command.
+    // input:
+    // output: process exit code.
+    pub fn execute(self) -> i32 {
+        let result = match self {
+            Command::Run {
+                file,
+                args,
+                options,
+            } => run_file(&file, args, options),
+            Command::Tokens { file } => dump_tokens(&file),
+            Command::Ast {
+                file,
+                format,
+                optimized,
+            } => dump_ast(&file, format, optimized),
+            Command::Dis { file } => disassemble(&file),
+            Command::Check { file } => check_file(&file),
+            Command::Build { file, output } => build_file(&file, output),
+            Command::Fmt { files, check } => format_files(&files, check),
+            Command::Repl => {
+                Repl::new().run();
+                Ok(())
+            }
+            Command::Help => {
+                println!("{}", USAGE);
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => EXIT_OK,
+            Err(code) => code,
+        }
+    }
+}
+
+// brief: Read the options of `noah run`, they stop at the first argument not starting with "--".
+// input: the arguments after `run`.
+// output: the options and the remaining arguments, script path first.
+fn run_options(args: &[String]) -> Result<(RunOptions, &[String]), String> {
+    let mut options = RunOptions::default();
+    let mut rest = args;
+    while let Some((option, tail)) = rest.split_first() {
+        if !option.starts_with("--") {
+            break;
+        }
+        rest = tail;
+        match option.as_str() {
+            "--vm" => options.backend = Backend::Vm,
+            "--no-optimize" => options.optimize = false,
+            "--max-depth" => {
+                let Some((value, tail)) = rest.split_first() else {
+                    return Err("Missing value for `--max-depth`.".to_string());
+                };
+                rest = tail;
+                options.max_depth = match value.parse::<usize>() {
+                    Ok(depth) if depth > 0 => depth,
+                    _ => {
+                        return Err(format!(
+                            "`--max-depth` expects a positive number, got `{}`.",
+                            value
+                        ))
+                    }
+                };
+            }
+            "--path" => {
+                let Some((dir, tail)) = rest.split_first() else {
+                    return Err("Missing directory for `--path`.".to_string());
+                };
+                rest = tail;
+                options.module_path.push(PathBuf::from(dir));
+            }
+            "--ast" => {
+                let (value, tail) = rest.split_first().unzip();
+                options.ast = Some(ast_format(value)?);
+                rest = tail.unwrap_or_default();
+            }
+            other => return Err(format!("Unknown option `{}` for `run`.", other)),
+        }
+    }
+    if options.backend == Backend::Tree && options.max_depth > MAX_TREE_DEPTH {
+        return Err(format!(
+            "`--max-depth` can be at most {} on the tree-walker, got {}; use `--vm` for deeper calls.",
+            MAX_TREE_DEPTH, options.max_depth
+        ));
+    }
+    Ok((options, rest))
+}
+
+// brief: The value of `--format` or `--ast`.
+// input:
+// output:
+fn ast_format(value: Option<&String>) -> Result<AstFormat, String> {
+    match value {
+        Some(name) => AstFormat::from_name(name)
+            .ok_or_else(|| format!("Unknown syntax tree format `{}`, use json or sexpr.", name)),
+        None => Err("Missing syntax tree format, use json or sexpr.".to_string()),
+    }
+}
+
+// brief: Read the whole script into a String.
+// input:
+// output: Err(EXIT_NO_INPUT) if the file can not be read.
+fn read_source(file_path: &str) -> Result<String, i32> {
+    fs::read_to_string(file_path).map_err(|err| {
+        report(&format!("Can not read {}: {}.", file_path, err));
+        EXIT_NO_INPUT
+    })
+}
+
+// brief: String => Vec[Token]
+// input:
+// output: Err(EXIT_DATA_ERR) if the scanner complains.
+fn scan(file_path: &str, source: &str) -> Result<Vec<Token>, i32> {
+    Scanner::new(source.to_string())
+        .scan_tokens()
+        .map_err(|errors| {
+            report_diagnostics(file_path, source, &errors);
+            EXIT_DATA_ERR
+        })
+}
+
+// brief: Vec[Token] => Vec[Stmt]
+// input:
+// output: Err(EXIT_DATA_ERR) if the parser complains, after reporting every error.
+fn parse(file_path: &str, source: &str) -> Result<Vec<Stmt>, i32> {
+    Parser::new(scan(file_path, source)?)
+        .parse()
+        .map_err(|errors| {
+            report_diagnostics(file_path, source, &errors);
+            EXIT_DATA_ERR
+        })
+}
+
+// brief: Vec[Stmt] => scope depth of every local variable.
+// input:
+// output: Err(EXIT_DATA_ERR) if the resolver complains.
+fn resolve(
+    file_path: &str,
+    source: &str,
+    statements: &Vec<Stmt>,
+) -> Result<HashMap<usize, usize>, i32> {
+    Resolver::new().resolve(statements).map_err(|err| {
+        report_diagnostics(file_path, source, &[err]);
+        EXIT_DATA_ERR
+    })
+}
+
+// brief: Vec[Stmt] => bytecode of the script.
+// input:
+// output: Err(EXIT_DATA_ERR) if the compiler hits one of its limits.
+fn compile(file_path: &str, source: &str, statements: &Vec<Stmt>) -> Result<FunctionProto, i32> {
+    Compiler::new().compile(statements).map_err(|err| {
+        report_diagnostics(file_path, source, &[err]);
+        EXIT_DATA_ERR
+    })
+}
+
+// brief: Scan, parse and run a script with `options`, exposing `args` to the program.
+// input:
+// output:
+pub fn run_file(file_path: &str, args: Vec<String>, options: RunOptions) -> Result<(), i32> {
+    let text = read_source(file_path)?;
+    let statements = match options.ast {
+        None => parse(file_path, &text)?,
+        Some(format) => read_ast(file_path, &text, format)?,
+    };
+    // The positions in a syntax tree point into a source that is not at hand.
+    let source = if options.ast.is_some() { "" } else { &text };
+    // The Vm does not need the scope depths, but the Resolver still reports the static errors.
+    let mut locals = resolve(file_path, source, &statements)?;
+    let statements = if options.optimize {
+        let optimized = optimize(statements);
+        locals = resolve(file_path, source, &optimized)?;
+        optimized
+    } else {
+        statements
+    };
+
+    let mut module_path = options.module_path;
+    if let Some(paths) = env::var_os("NOAH_PATH") {
+        module_path.extend(env::split_paths(&paths));
+    }
+    let modules = Rc::new(ModuleLoader::new(module_path));
+    modules.start(Path::new(file_path));
+    let dir = Path::new(file_path).parent().unwrap_or(Path::new("."));
+
+    // Runtime errors go through the error sink of the backend, like the printed output. One
+    // raised inside a module already points into the module.
+    let origin = Origin::new(file_path, source);
+    let failed = match options.backend {
+        Backend::Tree => {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_origin(origin.clone());
+            interpreter.set_max_depth(options.max_depth);
+            interpreter.set_modules(modules, dir);
+            interpreter.define_script_args(file_path, args);
+            interpreter.resolve(locals);
+            interpreter
+                .interpreter(&statements)
+                .map_err(|err| interpreter.report(&err.or_in(&origin), source))
+                .is_err()
+        }
+        Backend::Vm => {
+            let script = compile(file_path, source, &statements)?;
+            let mut vm = Vm::new();
+            vm.set_max_depth(options.max_depth);
+            vm.set_modules(modules, dir);
+            vm.define_script_args(file_path, args);
+            vm.run(script)
+                .map_err(|err| vm.report(&err.or_in(&origin), source))
+                .is_err()
+        }
+    };
+    if failed {
+        return Err(EXIT_RUNTIME);
+    }
+    Ok(())
+}
+
+// brief: Syntax tree written by `noah ast --format` => Vec[Stmt]
+// input:
+// output: Err(EXIT_DATA_ERR) if it is malformed.
+fn read_ast(file_path: &str, text: &str, format: AstFormat) -> Result<Vec<Stmt>, i32> {
+    format.read(text).map_err(|err| {
+        report_diagnostics(file_path, text, &[err]);
+        EXIT_DATA_ERR
+    })
+}
+
+// brief: Print one token per line.
+// input:
+// output:
+fn dump_tokens(file_path: &str) -> Result<(), i32> {
+    for token in scan(file_path, &read_source(file_path)?)? {
+        println!(
+            "{:>4}:{:<3} {}",
+            token.line_number,
+            token.column,
+            token.two_string()
+        );
+    }
+    Ok(())
+}
+
+// brief: Print the syntax tree of a script, even the part that parsed fine if there are errors.
+// input: format: the form `noah run --ast` reads back, only printed for a script that parses.
+// output:
+fn dump_ast(file_path: &str, format: Option<AstFormat>, optimized: bool) -> Result<(), i32> {
+    let source = read_source(file_path)?;
+    if optimized {
+        let statements = parse(file_path, &source)?;
+        resolve(file_path, &source, &statements)?;
+        let statements = optimize(statements);
+        match format {
+            Some(format) => print!("{}", format.write(&statements)),
+            None => println!("{:#?}", statements),
+        }
+        return Ok(());
+    }
+    if let Some(format) = format {
+        print!("{}", format.write(&parse(file_path, &source)?));
+        return Ok(());
+    }
+    let (statements, errors) = Parser::new(scan(file_path, &source)?).parse_partial();
+    println!("{:#?}", statements);
+
+    if !errors.is_empty() {
+        report_diagnostics(file_path, &source, &errors);
+        return Err(EXIT_DATA_ERR);
+    }
+    Ok(())
+}
+
+// brief: Print the bytecode of a script and of every function in it.
+// input:
+// output:
+fn disassemble(file_path: &str) -> Result<(), i32> {
+    let source = read_source(file_path)?;
+    let statements = parse(file_path, &source)?;
+    resolve(file_path, &source, &statements)?;
+    let statements = optimize(statements);
+    let script = compile(file_path, &source, &statements)?;
+    print!("{}", script.chunk.disassemble(&script.name));
+    Ok(())
+}
+
+// brief: Scan, parse, resolve and type check a script without running it.
+// input:
+// output:
+fn check_file(file_path: &str) -> Result<(), i32> {
+    let source = read_source(file_path)?;
+    let statements = parse(file_path, &source)?;
+    resolve(file_path, &source, &statements)?;
+    TypeChecker::new().check(&statements).map_err(|errors| {
+        report_diagnostics(file_path, &source, &errors);
+        EXIT_DATA_ERR
+    })?;
+    println!("[     PASS!    ] ---> {} checked successfully.", file_path);
+    Ok(())
+}
+
+// brief: Write a script as C source, optimized.
+// input: output: the path to write, "-" for stdout.
+// output:
+fn build_file(file_path: &str, output: Option<String>) -> Result<(), i32> {
+    let source = read_source(file_path)?;
+    let statements = parse(file_path, &source)?;
+    resolve(file_path, &source, &statements)?;
+    let statements = optimize(statements);
+    let c = CEmitter::new(file_path).emit(&statements).map_err(|err| {
+        report_diagnostics(file_path, &source, &[err]);
+        EXIT_DATA_ERR
+    })?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(file_path)
+            .with_extension("c")
+            .display()
+            .to_string()
+    });
+    if output == "-" {
+        print!("{}", c);
+        return Ok(());
+    }
+    if Path::new(&output) == Path::new(file_path) {
+        report(&format!("Writing {} would overwrite the script.", output));
+        return Err(EXIT_USAGE);
+    }
+    fs::write(&output, c).map_err(|err| {
+        report(&format!("Can not write {}: {}.", output, err));
+        EXIT_NO_INPUT
+    })?;
+    println!("[     PASS!    ] ---> {} written.", output);
+    Ok(())
+}
+
+// brief: Rewrite scripts in the canonical style, leaving alone the ones that do not parse.
+// input: check: only report the scripts that would change.
+// output: Err with the worst exit code, after every script was looked at.
+fn format_files(files: &[String], check: bool) -> Result<(), i32> {
+    let mut result = Ok(());
+    for file_path in files {
+        let outcome = read_source(file_path).and_then(|source| {
+            let formatted = format_source(&source).map_err(|errors| {
+                report_diagnostics(file_path, &source, &errors);
+                EXIT_DATA_ERR
+            })?;
+            if formatted == source {
+                return Ok(());
+            }
+            if check {
+                report(&format!("{} is not formatted.", file_path));
+                return Err(EXIT_UNFORMATTED);
+            }
+            fs::write(file_path, formatted).map_err(|err| {
+                report(&format!("Can not write {}: {}.", file_path, err));
+                EXIT_NO_INPUT
+            })?;
+            println!("[     PASS!    ] ---> {} formatted.", file_path);
+            Ok(())
+        });
+        if let Err(code) = outcome {
+            result = Err(result.err().map_or(code, |worst: i32| worst.max(code)));
+        }
+    }
+    result
+}
+
+fn report(msg: &str) {
+    eprintln!("[    Error!    ] ---> {}", msg);
+}
+
+// brief: Print diagnostics with the source lines they point at.
+// input:
+// output:
+fn report_diagnostics(file_path: &str, source: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.clone().with_file(file_path).render(source));
+    }
+    if diagnostics.len() > 1 {
+        report(&format!(
+            "{} errors found in {}.",
+            diagnostics.len(),
+            file_path
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_subcommands() {
+        assert_eq!(
+            Command::from_args(&args(&["run", "a.noah", "x", "y"])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: args(&["x", "y"]),
+                options: RunOptions::default(),
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["run", "--vm", "a.noah", "x"])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: args(&["x"]),
+                options: RunOptions {
+                    backend: Backend::Vm,
+                    ..RunOptions::default()
+                },
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&[
+                "run",
+                "--max-depth",
+                "50",
+                "--vm",
+                "a.noah",
+                "--x"
+            ])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: args(&["--x"]),
+                options: RunOptions {
+                    backend: Backend::Vm,
+                    max_depth: 50,
+                    ..RunOptions::default()
+                },
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["run", "--no-optimize", "a.noah"])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: vec![],
+                options: RunOptions {
+                    optimize: false,
+                    ..RunOptions::default()
+                },
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["run", "--ast", "sexpr", "a.sexpr"])),
+            Ok(Command::Run {
+                file: "a.sexpr".to_string(),
+                args: vec![],
+                options: RunOptions {
+                    ast: Some(AstFormat::Sexpr),
+                    ..RunOptions::default()
+                },
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["run", "--path", "lib", "--path", "/x", "a.noah"])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: vec![],
+                options: RunOptions {
+                    module_path: vec![PathBuf::from("lib"), PathBuf::from("/x")],
+                    ..RunOptions::default()
+                },
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["ast", "--format", "json", "a.noah"])),
+            Ok(Command::Ast {
+                file: "a.noah".to_string(),
+                format: Some(AstFormat::Json),
+                optimized: false,
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&[
+                "ast",
+                "--optimized",
+                "--format",
+                "sexpr",
+                "a.noah"
+            ])),
+            Ok(Command::Ast {
+                file: "a.noah".to_string(),
+                format: Some(AstFormat::Sexpr),
+                optimized: true,
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["build", "--emit", "c", "a.noah"])),
+            Ok(Command::Build {
+                file: "a.noah".to_string(),
+                output: None,
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["build", "-o", "-", "--emit", "c", "a.noah"])),
+            Ok(Command::Build {
+                file: "a.noah".to_string(),
+                output: Some("-".to_string()),
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["dis", "a.noah"])),
+            Ok(Command::Dis {
+                file: "a.noah".to_string()
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["fmt", "--check", "a.noah", "b.noah"])),
+            Ok(Command::Fmt {
+                files: args(&["a.noah", "b.noah"]),
+                check: true,
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["tokens", "a.noah"])),
+            Ok(Command::Tokens {
+                file: "a.noah".to_string()
+            })
+        );
+        assert_eq!(
+            Command::from_args(&args(&["a.noah"])),
+            Ok(Command::Run {
+                file: "a.noah".to_string(),
+                args: vec![],
+                options: RunOptions::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn no_arguments_start_the_repl() {
+        assert_eq!(Command::from_args(&args(&[])), Ok(Command::Repl));
+    }
+
+    #[test]
+    fn reject_bad_command_lines() {
+        assert!(Command::from_args(&args(&["repl", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["check"])).is_err());
+        assert!(Command::from_args(&args(&["ast", "a.noah", "b.noah"])).is_err());
+        assert!(Command::from_args(&args(&["ast", "--format", "xml", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["ast", "--format", "json"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--ast"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--path"])).is_err());
+        assert!(Command::from_args(&args(&["--verbose"])).is_err());
+        assert!(Command::from_args(&args(&["fmt"])).is_err());
+        assert!(Command::from_args(&args(&["fmt", "--check"])).is_err());
+        assert!(Command::from_args(&args(&["fmt", "a.noah", "--diff"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--vm"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--max-depth", "0", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--max-depth"])).is_err());
+        assert!(Command::from_args(&args(&["run", "--max-depth", "100000", "a.noah"])).is_err());
+        let deep = Command::from_args(&args(&["run", "--max-depth", "100000", "--vm", "a.noah"]));
+        assert_eq!(deep.map(|command| command.stack_size()), Ok(MIN_STACK));
+        assert!(Command::from_args(&args(&["run", "--fast", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["build", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["build", "--emit", "wasm", "a.noah"])).is_err());
+        assert!(Command::from_args(&args(&["build", "--emit", "c", "-o"])).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(
+            run_file("no/such/file.noah", vec![], RunOptions::default()),
+            Err(EXIT_NO_INPUT)
+        );
+        assert_eq!(
+            run_file(
+                "no/such/file.noah",
+                vec![],
+                RunOptions {
+                    backend: Backend::Vm,
+                    ..RunOptions::default()
+                }
+            ),
+            Err(EXIT_NO_INPUT)
+        );
+        assert_eq!(build_file("no/such/file.noah", None), Err(EXIT_NO_INPUT));
+    }
+}
@@ -1,9 +1,28 @@
+pub mod ast;
 pub mod callable;
+pub mod checker;
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod convert;
+pub mod diagnostic;
+pub mod emitter;
+pub mod engine;
 pub mod environment;
 pub mod expr;
+pub mod formatter;
 pub mod function;
 pub mod interpreter;
+pub mod map;
+pub mod module;
+pub mod optimizer;
+pub mod output;
 pub mod parser;
+pub mod printer;
+pub mod resolver;
 pub mod scanner;
+pub mod stack;
+pub mod stdlib;
 pub mod stmt;
 pub mod token;
+pub mod vm;
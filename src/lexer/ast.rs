@@ -0,0 +1,1204 @@
+use std::{iter::Peekable, str::Chars};
+
+use super::{
+    diagnostic::{Diagnostic, Span, E_BAD_AST},
+    expr::{Expr, ExprLiteral},
+    stdlib::Registry,
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+// The syntax tree as text, for tools that generate or transform Noah programs.
+//
+// Every node is an object whose "type" is its variant, the other fields are named as in Expr
+// and Stmt. Ids are not written, reading gives every node a fresh one.
+//
+//     json:  {"type": "Print", "expression": {"type": "Literal", "value": 1}}
+//     sexpr: (Print :expression (Literal :value 1))
+//
+// A program is a list of statements: [...] in both formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AstFormat {
+    Json,
+    Sexpr,
+}
+
+impl AstFormat {
+    // brief: The format named on the command line.
+    // input:
+    // output: None for an unknown name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(AstFormat::Json),
+            "sexpr" => Some(AstFormat::Sexpr),
+            _ => None,
+        }
+    }
+
+    // brief: Vec[Stmt] => text.
+    // input:
+    // output: ends with a newline.
+    pub fn write(&self, statements: &[Stmt]) -> String {
+        let program = Value::List(statements.iter().map(stmt_value).collect());
+        let mut out = String::new();
+        write_value(&program, *self, 0, &mut out);
+        out.push('\n');
+        out
+    }
+
+    // brief: text => Vec[Stmt], ready for the Resolver and a backend.
+    // input:
+    // output: Err pointing into the text if it is malformed, or naming the node that is not
+    // a valid statement or expression.
+    pub fn read(&self, text: &str) -> Result<Vec<Stmt>, Diagnostic> {
+        let mut reader = Reader::new(text);
+        let program = match self {
+            AstFormat::Json => reader.json()?,
+            AstFormat::Sexpr => reader.sexpr()?,
+        };
+        reader.end()?;
+        match &program {
+            Value::List(items) => items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| value_stmt(item, &format!("[{}]", index)))
+                .collect(),
+            other => Err(shape("the program", "a list of statements", other)),
+        }
+    }
+}
+
+// What both formats can express.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    Node(String, Vec<(String, Value)>), // "type" and the other fields, in order.
+}
+
+fn node(kind: &str, fields: Vec<(&str, Value)>) -> Value {
+    Value::Node(
+        kind.to_string(),
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn optional<T>(value: &Option<T>, convert: impl Fn(&T) -> Value) -> Value {
+    value.as_ref().map_or(Value::Null, convert)
+}
+
+fn list<T>(items: &[T], convert: impl Fn(&T) -> Value) -> Value {
+    Value::List(items.iter().map(convert).collect())
+}
+
+fn token_value(token: &Token) -> Value {
+    node(
+        "Token",
+        vec![
+            ("kind", Value::Str(token.token_type.to_string())),
+            ("lexeme", Value::Str(token.lexeme.clone())),
+            ("line", Value::Int(token.line_number as i64)),
+            ("column", Value::Int(token.column as i64)),
+            (
+                "span",
+                Value::List(vec![
+                    Value::Int(token.span.start as i64),
+                    Value::Int(token.span.end as i64),
+                ]),
+            ),
+        ],
+    )
+}
+
+fn literal_value(value: &ExprLiteral) -> Value {
+    match value {
+        ExprLiteral::Nil => Value::Null,
+        ExprLiteral::True => Value::Bool(true),
+        ExprLiteral::False => Value::Bool(false),
+        ExprLiteral::IntLiteral(v) => Value::Int(*v),
+        ExprLiteral::NumberLiteral(v) => Value::Float(*v),
+        ExprLiteral::StringLiteral(s) => Value::Str(s.clone()),
+        // The Parser calls items, len and to_string directly in what it desugars.
+        ExprLiteral::FunctionLiteral(f) => {
+            let signature = f.signature();
+            let name = signature.split('(').next().unwrap_or_default();
+            node("Native", vec![("name", Value::Str(name.to_string()))])
+        }
+        // Only values made at runtime, never in a parsed tree.
+        other => node("Opaque", vec![("value", Value::Str(other.two_string()))]),
+    }
+}
+
+fn expr_value(expr: &Expr) -> Value {
+    match expr {
+        Expr::Unary { operator, right } => node(
+            "Unary",
+            vec![
+                ("operator", token_value(operator)),
+                ("right", expr_value(right)),
+            ],
+        ),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => node(
+            "Binary",
+            vec![
+                ("left", expr_value(left)),
+                ("operator", token_value(operator)),
+                ("right", expr_value(right)),
+            ],
+        ),
+        Expr::Grouping { expression } => {
+            node("Grouping", vec![("expression", expr_value(expression))])
+        }
+        Expr::Literal { value } => node("Literal", vec![("value", literal_value(value))]),
+        Expr::Variable { name, .. } => node("Variable", vec![("name", token_value(name))]),
+        Expr::Assign { name, value, .. } => node(
+            "Assign",
+            vec![("name", token_value(name)), ("value", expr_value(value))],
+        ),
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => node(
+            "Logical",
+            vec![
+                ("left", expr_value(left)),
+                ("operator", token_value(operator)),
+                ("right", expr_value(right)),
+            ],
+        ),
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => node(
+            "Call",
+            vec![
+                ("callee", expr_value(callee)),
+                ("paren", token_value(paren)),
+                ("arguments", list(arguments, expr_value)),
+            ],
+        ),
+        Expr::Get { object, name } => node(
+            "Get",
+            vec![("object", expr_value(object)), ("name", token_value(name))],
+        ),
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => node(
+            "Set",
+            vec![
+                ("object", expr_value(object)),
+                ("name", token_value(name)),
+                ("value", expr_value(value)),
+            ],
+        ),
+        Expr::This { keyword, .. } => node("This", vec![("keyword", token_value(keyword))]),
+        Expr::Super {
+            keyword, method, ..
+        } => node(
+            "Super",
+            vec![
+                ("keyword", token_value(keyword)),
+                ("method", token_value(method)),
+            ],
+        ),
+        Expr::List { bracket, elements } => node(
+            "List",
+            vec![
+                ("bracket", token_value(bracket)),
+                ("elements", list(elements, expr_value)),
+            ],
+        ),
+        Expr::Index {
+            object,
+            bracket,
+            index,
+        } => node(
+            "Index",
+            vec![
+                ("object", expr_value(object)),
+                ("bracket", token_value(bracket)),
+                ("index", expr_value(index)),
+            ],
+        ),
+        Expr::SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+        } => node(
+            "SetIndex",
+            vec![
+                ("object", expr_value(object)),
+                ("bracket", token_value(bracket)),
+                ("index", expr_value(index)),
+                ("value", expr_value(value)),
+            ],
+        ),
+        Expr::Map { brace, entries } => node(
+            "Map",
+            vec![
+                ("brace", token_value(brace)),
+                (
+                    "entries",
+                    list(entries, |(key, value)| {
+                        Value::List(vec![expr_value(key), expr_value(value)])
+                    }),
+                ),
+            ],
+        ),
+    }
+}
+
+fn stmt_value(stmt: &Stmt) -> Value {
+    match stmt {
+        Stmt::Expression(expr) => node("Expression", vec![("expression", expr_value(expr))]),
+        Stmt::Print(expr) => node("Print", vec![("expression", expr_value(expr))]),
+        Stmt::Let {
+            name,
+            annotation,
+            initializer,
+        } => node(
+            "Let",
+            vec![
+                ("name", token_value(name)),
+                ("annotation", optional(annotation, token_value)),
+                ("initializer", expr_value(initializer)),
+            ],
+        ),
+        Stmt::Block { statements } => {
+            node("Block", vec![("statements", list(statements, stmt_value))])
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => node(
+            "If",
+            vec![
+                ("condition", expr_value(condition)),
+                ("then_branch", stmt_value(then_branch)),
+                (
+                    "else_branch",
+                    optional(else_branch, |stmt| stmt_value(stmt)),
+                ),
+            ],
+        ),
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => node(
+            "While",
+            vec![
+                ("condition", expr_value(condition)),
+                ("body", stmt_value(body)),
+                ("increment", optional(increment, expr_value)),
+            ],
+        ),
+        Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            annotations,
+            returns,
+        } => node(
+            "Function",
+            vec![
+                ("name", token_value(name)),
+                ("params", list(params, token_value)),
+                ("defaults", list(defaults, expr_value)),
+                ("rest", optional(rest, token_value)),
+                ("body", stmt_value(body)),
+                (
+                    "annotations",
+                    list(annotations, |annotation| optional(annotation, token_value)),
+                ),
+                ("returns", optional(returns, token_value)),
+            ],
+        ),
+        Stmt::Return { keyword, value } => node(
+            "Return",
+            vec![
+                ("keyword", token_value(keyword)),
+                ("value", optional(value, expr_value)),
+            ],
+        ),
+        Stmt::Break { keyword } => node("Break", vec![("keyword", token_value(keyword))]),
+        Stmt::Continue { keyword } => node("Continue", vec![("keyword", token_value(keyword))]),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => node(
+            "Class",
+            vec![
+                ("name", token_value(name)),
+                ("superclass", optional(superclass, expr_value)),
+                ("methods", list(methods, stmt_value)),
+            ],
+        ),
+        Stmt::Import {
+            keyword,
+            path,
+            name,
+        } => node(
+            "Import",
+            vec![
+                ("keyword", token_value(keyword)),
+                ("path", Value::Str(path.clone())),
+                ("name", token_value(name)),
+            ],
+        ),
+    }
+}
+
+// brief: An error about a value that has the wrong shape.
+// input: where it is, e.g. "[2].initializer", and what was expected there.
+// output:
+fn shape(path: &str, expected: &str, found: &Value) -> Diagnostic {
+    let found = match found {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "a bool".to_string(),
+        Value::Int(_) | Value::Float(_) => "a number".to_string(),
+        Value::Str(s) => format!("the string {:?}", s),
+        Value::List(_) => "a list".to_string(),
+        Value::Node(kind, _) => format!("a {} node", kind),
+    };
+    Diagnostic::error(
+        E_BAD_AST,
+        format!("Expected {} at {}, found {}.", expected, path, found),
+    )
+}
+
+// The fields of a node being read, with the path to it for errors.
+struct Fields<'a> {
+    kind: &'a str,
+    fields: &'a [(String, Value)],
+    path: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, key: &str) -> Result<(&'a Value, String), Diagnostic> {
+        let path = format!("{}.{}", self.path, key);
+        match self.fields.iter().find(|(name, _)| name == key) {
+            Some((_, value)) => Ok((value, path)),
+            None => Err(Diagnostic::error(
+                E_BAD_AST,
+                format!("{} at {} has no field {}.", self.kind, self.path, key),
+            )),
+        }
+    }
+
+    fn expr(&self, key: &str) -> Result<Expr, Diagnostic> {
+        let (value, path) = self.get(key)?;
+        value_expr(value, &path)
+    }
+
+    fn boxed(&self, key: &str) -> Result<Box<Expr>, Diagnostic> {
+        self.expr(key).map(Box::new)
+    }
+
+    fn stmt(&self, key: &str) -> Result<Box<Stmt>, Diagnostic> {
+        let (value, path) = self.get(key)?;
+        value_stmt(value, &path).map(Box::new)
+    }
+
+    fn token(&self, key: &str) -> Result<Token, Diagnostic> {
+        let (value, path) = self.get(key)?;
+        value_token(value, &path)
+    }
+
+    // brief: A field that may be null.
+    // input:
+    // output:
+    fn optional<T>(
+        &self,
+        key: &str,
+        read: impl Fn(&Value, &str) -> Result<T, Diagnostic>,
+    ) -> Result<Option<T>, Diagnostic> {
+        match self.get(key)? {
+            (Value::Null, _) => Ok(None),
+            (value, path) => read(value, &path).map(Some),
+        }
+    }
+
+    fn list<T>(
+        &self,
+        key: &str,
+        read: impl Fn(&Value, &str) -> Result<T, Diagnostic>,
+    ) -> Result<Vec<T>, Diagnostic> {
+        match self.get(key)? {
+            (Value::List(items), path) => items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| read(item, &format!("{}[{}]", path, index)))
+                .collect(),
+            (other, path) => Err(shape(&path, "a list", other)),
+        }
+    }
+}
+
+// brief: The node at `path`, with the fields of its variant.
+// input:
+// output:
+fn fields<'a>(value: &'a Value, path: &'a str, what: &str) -> Result<Fields<'a>, Diagnostic> {
+    match value {
+        Value::Node(kind, fields) => Ok(Fields { kind, fields, path }),
+        other => Err(shape(path, what, other)),
+    }
+}
+
+fn value_token(value: &Value, path: &str) -> Result<Token, Diagnostic> {
+    let node = fields(value, path, "a Token")?;
+    if node.kind != "Token" {
+        return Err(shape(path, "a Token", value));
+    }
+    let int = |key: &str| -> Result<usize, Diagnostic> {
+        match node.get(key)? {
+            (Value::Int(v), _) if *v >= 0 => Ok(*v as usize),
+            (other, path) => Err(shape(&path, "a position", other)),
+        }
+    };
+    let kind = match node.get("kind")? {
+        (Value::Str(name), path) => TokenType::from_name(name)
+            .ok_or_else(|| shape(&path, "a token kind", &Value::Str(name.clone())))?,
+        (other, path) => Err(shape(&path, "a token kind", other))?,
+    };
+    let lexeme = match node.get("lexeme")? {
+        (Value::Str(lexeme), _) => lexeme.clone(),
+        (other, path) => Err(shape(&path, "a string", other))?,
+    };
+    // The span is optional, a tool making up tokens may not know it.
+    let span = match node.fields.iter().find(|(key, _)| key == "span") {
+        Some((_, Value::List(bounds))) => match bounds.as_slice() {
+            [Value::Int(start), Value::Int(end)] if 0 <= *start && start <= end => {
+                Span::new(*start as usize, *end as usize)
+            }
+            _ => Err(shape(&format!("{}.span", path), "[start, end]", &bounds[0]))?,
+        },
+        Some((_, other)) => Err(shape(&format!("{}.span", path), "[start, end]", other))?,
+        None => Span::default(),
+    };
+    Ok(Token {
+        token_type: kind,
+        lexeme,
+        literial: None,
+        line_number: int("line")?,
+        column: int("column")?,
+        span,
+    })
+}
+
+fn value_literal(value: &Value, path: &str) -> Result<ExprLiteral, Diagnostic> {
+    match value {
+        Value::Null => Ok(ExprLiteral::Nil),
+        Value::Bool(v) => Ok(ExprLiteral::from_bool(*v)),
+        Value::Int(v) => Ok(ExprLiteral::IntLiteral(*v)),
+        Value::Float(v) => Ok(ExprLiteral::NumberLiteral(*v)),
+        Value::Str(s) => Ok(ExprLiteral::StringLiteral(s.clone())),
+        Value::Node(kind, _) if kind == "Native" => {
+            let node = fields(value, path, "a native")?;
+            let (name, name_path) = node.get("name")?;
+            Registry::standard()
+                .entries()
+                .into_iter()
+                .chain(Registry::desugared().entries())
+                .find(|(native, _)| Value::Str(native.to_string()) == *name)
+                .map(|(_, native)| native)
+                .ok_or_else(|| shape(&name_path, "the name of a native", name))
+        }
+        other => Err(shape(path, "a literal value", other)),
+    }
+}
+
+fn value_expr(value: &Value, path: &str) -> Result<Expr, Diagnostic> {
+    let node = fields(value, path, "an expression")?;
+    let expr = match node.kind {
+        "Unary" => Expr::Unary {
+            operator: node.token("operator")?,
+            right: node.boxed("right")?,
+        },
+        "Binary" => Expr::Binary {
+            left: node.boxed("left")?,
+            operator: node.token("operator")?,
+            right: node.boxed("right")?,
+        },
+        "Grouping" => Expr::Grouping {
+            expression: node.boxed("expression")?,
+        },
+        "Literal" => {
+            let (value, path) = node.get("value")?;
+            Expr::Literal {
+                value: value_literal(value, &path)?,
+            }
+        }
+        "Variable" => Expr::Variable {
+            id: Expr::next_id(),
+            name: node.token("name")?,
+        },
+        "Assign" => Expr::Assign {
+            id: Expr::next_id(),
+            name: node.token("name")?,
+            value: node.boxed("value")?,
+        },
+        "Logical" => Expr::Logical {
+            left: node.boxed("left")?,
+            operator: node.token("operator")?,
+            right: node.boxed("right")?,
+        },
+        "Call" => Expr::Call {
+            callee: node.boxed("callee")?,
+            paren: node.token("paren")?,
+            arguments: node.list("arguments", value_expr)?,
+        },
+        "Get" => Expr::Get {
+            object: node.boxed("object")?,
+            name: node.token("name")?,
+        },
+        "Set" => Expr::Set {
+            object: node.boxed("object")?,
+            name: node.token("name")?,
+            value: node.boxed("value")?,
+        },
+        "This" => Expr::This {
+            id: Expr::next_id(),
+            keyword: node.token("keyword")?,
+        },
+        "Super" => Expr::Super {
+            id: Expr::next_id(),
+            keyword: node.token("keyword")?,
+            method: node.token("method")?,
+        },
+        "List" => Expr::List {
+            bracket: node.token("bracket")?,
+            elements: node.list("elements", value_expr)?,
+        },
+        "Index" => Expr::Index {
+            object: node.boxed("object")?,
+            bracket: node.token("bracket")?,
+            index: node.boxed("index")?,
+        },
+        "SetIndex" => Expr::SetIndex {
+            object: node.boxed("object")?,
+            bracket: node.token("bracket")?,
+            index: node.boxed("index")?,
+            value: node.boxed("value")?,
+        },
+        "Map" => Expr::Map {
+            brace: node.token("brace")?,
+            entries: node.list("entries", |entry, path| match entry {
+                Value::List(pair) if pair.len() == 2 => Ok((
+                    value_expr(&pair[0], &format!("{}[0]", path))?,
+                    value_expr(&pair[1], &format!("{}[1]", path))?,
+                )),
+                other => Err(shape(path, "a [key, value] pair", other)),
+            })?,
+        },
+        _ => return Err(shape(path, "an expression", value)),
+    };
+    Ok(expr)
+}
+
+fn value_stmt(value: &Value, path: &str) -> Result<Stmt, Diagnostic> {
+    let node = fields(value, path, "a statement")?;
+    let stmt = match node.kind {
+        "Expression" => Stmt::Expression(node.expr("expression")?),
+        "Print" => Stmt::Print(node.expr("expression")?),
+        "Let" => Stmt::Let {
+            name: node.token("name")?,
+            annotation: node.optional("annotation", value_token)?,
+            initializer: node.expr("initializer")?,
+        },
+        "Block" => Stmt::Block {
+            statements: node.list("statements", value_stmt)?,
+        },
+        "If" => Stmt::If {
+            condition: node.expr("condition")?,
+            then_branch: node.stmt("then_branch")?,
+            else_branch: node.optional("else_branch", |v, p| value_stmt(v, p).map(Box::new))?,
+        },
+        "While" => Stmt::While {
+            condition: node.expr("condition")?,
+            body: node.stmt("body")?,
+            increment: node.optional("increment", value_expr)?,
+        },
+        "Function" => {
+            let params = node.list("params", value_token)?;
+            let defaults = node.list("defaults", value_expr)?;
+            if defaults.len() > params.len() {
+                return Err(Diagnostic::error(
+                    E_BAD_AST,
+                    format!("Function at {} has more defaults than params.", path),
+                ));
+            }
+            let rest = node.optional("rest", value_token)?;
+            let annotations = node.list("annotations", |v, p| match v {
+                Value::Null => Ok(None),
+                _ => value_token(v, p).map(Some),
+            })?;
+            if annotations.len() != params.len() + rest.is_some() as usize {
+                return Err(Diagnostic::error(
+                    E_BAD_AST,
+                    format!("Function at {} needs one annotation per param.", path),
+                ));
+            }
+            // The backends run the statements of the body in the scope of the params.
+            let body = node.stmt("body")?;
+            if !matches!(body.as_ref(), Stmt::Block { .. }) {
+                return Err(Diagnostic::error(
+                    E_BAD_AST,
+                    format!("Function at {} needs a Block body.", path),
+                ));
+            }
+            Stmt::Function {
+                name: node.token("name")?,
+                params,
+                defaults,
+                rest,
+                body,
+                annotations,
+                returns: node.optional("returns", value_token)?,
+            }
+        }
+        "Return" => Stmt::Return {
+            keyword: node.token("keyword")?,
+            value: node.optional("value", value_expr)?,
+        },
+        "Break" => Stmt::Break {
+            keyword: node.token("keyword")?,
+        },
+        "Continue" => Stmt::Continue {
+            keyword: node.token("keyword")?,
+        },
+        "Class" => Stmt::Class {
+            name: node.token("name")?,
+            superclass: node.optional("superclass", value_expr)?,
+            methods: node.list("methods", |v, p| match value_stmt(v, p)? {
+                method @ Stmt::Function { .. } => Ok(method),
+                _ => Err(shape(p, "a Function", v)),
+            })?,
+        },
+        "Import" => Stmt::Import {
+            keyword: node.token("keyword")?,
+            path: match node.get("path")? {
+                (Value::Str(module), _) => module.clone(),
+                (other, path) => Err(shape(&path, "a string", other))?,
+            },
+            name: node.token("name")?,
+        },
+        _ => return Err(shape(path, "a statement", value)),
+    };
+    Ok(stmt)
+}
+
+// brief: Write a value, on one line if it fits in 100 columns, else one field per line.
+// input:
+// output:
+fn write_value(value: &Value, format: AstFormat, indent: usize, out: &mut String) {
+    let compact = compact(value, format);
+    if indent * 2 + compact.len() <= 100 {
+        out.push_str(&compact);
+        return;
+    }
+    let pad = "  ".repeat(indent + 1);
+    match (value, format) {
+        (Value::List(items), _) => {
+            let separator = if format == AstFormat::Json { "," } else { "" };
+            out.push_str("[\n");
+            for (index, item) in items.iter().enumerate() {
+                out.push_str(&pad);
+                write_value(item, format, indent + 1, out);
+                if index + 1 < items.len() {
+                    out.push_str(separator);
+                }
+                out.push('\n');
+            }
+            out.push_str(&format!("{}]", "  ".repeat(indent)));
+        }
+        (Value::Node(kind, fields), AstFormat::Json) => {
+            out.push_str(&format!("{{\n{}\"type\": {}", pad, quote(kind)));
+            for (key, field) in fields {
+                out.push_str(&format!(",\n{}{}: ", pad, quote(key)));
+                write_value(field, format, indent + 1, out);
+            }
+            out.push_str(&format!("\n{}}}", "  ".repeat(indent)));
+        }
+        (Value::Node(kind, fields), AstFormat::Sexpr) => {
+            out.push_str(&format!("({}", kind));
+            for (key, field) in fields {
+                out.push_str(&format!("\n{}:{} ", pad, key));
+                write_value(field, format, indent + 1, out);
+            }
+            out.push(')');
+        }
+        _ => out.push_str(&compact),
+    }
+}
+
+fn compact(value: &Value, format: AstFormat) -> String {
+    match value {
+        Value::Null if format == AstFormat::Json => "null".to_string(),
+        Value::Null => "nil".to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Int(v) => v.to_string(),
+        // Always with a "." or an exponent, so that it reads back as a float.
+        Value::Float(v) if v.is_finite() => format!("{:?}", v),
+        // Too large a literal, the way both formats read it back as infinity.
+        Value::Float(v) if v.is_infinite() => format!("{}1e999", if *v < 0.0 { "-" } else { "" }),
+        Value::Float(_) => "NaN".to_string(), // the Parser never makes one.
+        Value::Str(s) => quote(s),
+        Value::List(items) => {
+            let separator = if format == AstFormat::Json { ", " } else { " " };
+            let items: Vec<String> = items.iter().map(|item| compact(item, format)).collect();
+            format!("[{}]", items.join(separator))
+        }
+        Value::Node(kind, fields) if format == AstFormat::Json => {
+            let mut text = format!("{{\"type\": {}", quote(kind));
+            for (key, field) in fields {
+                text.push_str(&format!(", {}: {}", quote(key), compact(field, format)));
+            }
+            text.push('}');
+            text
+        }
+        Value::Node(kind, fields) => {
+            let mut text = format!("({}", kind);
+            for (key, field) in fields {
+                text.push_str(&format!(" :{} {}", key, compact(field, format)));
+            }
+            text.push(')');
+            text
+        }
+    }
+}
+
+// brief: A string literal both formats read, JSON escapes only.
+// input:
+// output:
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Reads JSON or s-expressions into a Value, tracking the position for errors.
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize, // bytes.
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::error(E_BAD_AST, message).at(
+            Span::new(self.offset, self.offset + 1),
+            self.line,
+            self.column,
+        )
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.advance();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Diagnostic> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.advance();
+                Ok(())
+            }
+            other => Err(self.found(&format!("{:?}", expected), other)),
+        }
+    }
+
+    fn found(&self, expected: &str, found: Option<char>) -> Diagnostic {
+        match found {
+            Some(c) => self.error(format!("Expected {}, found {:?}.", expected, c)),
+            None => self.error(format!(
+                "Expected {}, found the end of the input.",
+                expected
+            )),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), Diagnostic> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(()),
+            other => Err(self.found("the end of the input", other)),
+        }
+    }
+
+    fn json(&mut self) -> Result<Value, Diagnostic> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => {
+                self.advance();
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.advance();
+                    return Ok(Value::List(items));
+                }
+                loop {
+                    items.push(self.json()?);
+                    self.skip_whitespace();
+                    match self.advance() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Value::List(items)),
+                        other => return Err(self.found("\",\" or \"]\"", other)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.advance();
+                let mut kind = None;
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.peek() != Some('}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        let value = self.json()?;
+                        match (key.as_str(), value) {
+                            ("type", Value::Str(name)) => kind = Some(name),
+                            ("type", _) => {
+                                return Err(self.error("\"type\" must be a string.".to_string()))
+                            }
+                            (_, value) => fields.push((key, value)),
+                        }
+                        self.skip_whitespace();
+                        match self.advance() {
+                            Some(',') => continue,
+                            Some('}') => break,
+                            other => return Err(self.found("\",\" or \"}\"", other)),
+                        }
+                    }
+                } else {
+                    self.advance();
+                }
+                match kind {
+                    Some(kind) => Ok(Value::Node(kind, fields)),
+                    None => Err(self.error("Every object needs a \"type\".".to_string())),
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => match self.word().as_str() {
+                "null" => Ok(Value::Null),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                word => Err(self.error(format!("Unknown value {}.", word))),
+            },
+            other => Err(self.found("a value", other)),
+        }
+    }
+
+    fn sexpr(&mut self) -> Result<Value, Diagnostic> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => {
+                self.advance();
+                let mut items = vec![];
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(']') {
+                        self.advance();
+                        return Ok(Value::List(items));
+                    }
+                    items.push(self.sexpr()?);
+                }
+            }
+            Some('(') => {
+                self.advance();
+                self.skip_whitespace();
+                let kind = self.word();
+                if kind.is_empty() {
+                    let found = self.peek();
+                    return Err(self.found("the type of the node", found));
+                }
+                let mut fields = vec![];
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(')') => {
+                            self.advance();
+                            return Ok(Value::Node(kind, fields));
+                        }
+                        Some(':') => {
+                            self.advance();
+                            let key = self.word();
+                            fields.push((key, self.sexpr()?));
+                        }
+                        other => return Err(self.found("\":field\" or \")\"", other)),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => match self.word().as_str() {
+                "nil" => Ok(Value::Null),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                word => Err(self.error(format!("Unknown value {}.", word))),
+            },
+            other => Err(self.found("a value", other)),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            word.push(c);
+            self.advance();
+        }
+        word
+    }
+
+    fn number(&mut self) -> Result<Value, Diagnostic> {
+        let mut text = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            text.push(c);
+            self.advance();
+        }
+        if !text.contains(['.', 'e', 'E']) {
+            if let Ok(v) = text.parse::<i64>() {
+                return Ok(Value::Int(v));
+            }
+        }
+        text.parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| self.error(format!("Invalid number {}.", text)))
+    }
+
+    fn string(&mut self) -> Result<String, Diagnostic> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.advance() {
+                None => return Err(self.error("Unterminated string.".to_string())),
+                Some('"') => return Ok(value),
+                Some('\\') => match self.advance() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('/') => value.push('/'),
+                    Some('b') => value.push('\u{8}'),
+                    Some('f') => value.push('\u{c}'),
+                    Some('u') => {
+                        let c = self.unicode_escape()?;
+                        value.push(c);
+                    }
+                    other => return Err(self.found("an escape", other)),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    // brief: The character of a \u escape, the "\u" already read. Characters outside the
+    // Basic Multilingual Plane are a surrogate pair of two escapes, as json.dump writes them.
+    // input:
+    // output:
+    fn unicode_escape(&mut self) -> Result<char, Diagnostic> {
+        let high = self.code_unit()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high)
+                .ok_or_else(|| self.error(format!("Invalid escape \\u{:04x}.", high)));
+        }
+        if self.advance() != Some('\\') || self.advance() != Some('u') {
+            return Err(self.error(format!("Expected a low surrogate after \\u{:04x}.", high)));
+        }
+        let low = self.code_unit()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error(format!(
+                "Expected a low surrogate after \\u{:04x}, found \\u{:04x}.",
+                high, low
+            )));
+        }
+        let c = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        Ok(char::from_u32(c).expect("a surrogate pair is a valid char"))
+    }
+
+    // brief: The four hex digits of a \u escape.
+    // input:
+    // output:
+    fn code_unit(&mut self) -> Result<u32, Diagnostic> {
+        let digits: String = (0..4).filter_map(|_| self.advance()).collect();
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 4)
+            .ok_or_else(|| self.error(format!("Invalid escape \\u{}.", digits)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, printer::Printer, scanner::Scanner};
+
+    const PROGRAM: &str = "class A < B {
+    init(n, m = 2, ...rest) {
+        this.n = -n;
+        super.init(n)[0] = {\"k\": [1.5, nil, true]};
+    }
+}
+for (let x in [1, 2]) print \"x = ${x}\\n\";
+for (let i = 0; i < 3; i = i + 1) if (i == 1 or !false) continue;
+else break;
+fn f() {
+    return;
+}
+";
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn both_formats_round_trip() {
+        let statements = parse(PROGRAM);
+        let printed = Printer::program(&statements);
+        for format in [AstFormat::Json, AstFormat::Sexpr] {
+            let text = format.write(&statements);
+            let read = format.read(&text).unwrap();
+            assert_eq!(Printer::program(&read), printed, "{:?}:\n{}", format, text);
+            assert_eq!(format.write(&read), text);
+        }
+        // Floats stay floats, tokens keep their positions.
+        let text = AstFormat::Json.write(&parse("print 2.0 + x;"));
+        assert!(text.contains("\"value\": 2.0"), "{}", text);
+        for field in ["\"lexeme\": \"x\"", "\"column\": 13", "\"span\": [12, 13]"] {
+            assert!(text.contains(field), "{}", text);
+        }
+        let text = AstFormat::Sexpr.write(&parse("print nil;"));
+        assert_eq!(text, "[(Print :expression (Literal :value nil))]\n");
+    }
+
+    #[test]
+    fn hand_written_trees_are_read() {
+        let json = r#"[{"type": "Print", "expression": {"type": "Binary",
+            "left": {"type": "Literal", "value": 1},
+            "operator": {"type": "Token", "kind": "Plus", "lexeme": "+", "line": 1, "column": 0},
+            "right": {"type": "Literal", "value": "aA"}}}]"#;
+        let statements = AstFormat::Json.read(json).unwrap();
+        assert_eq!(Printer::program(&statements), "print 1 + \"aA\";\n");
+
+        // What Python's json.dump writes for a character outside the BMP, and \b \f.
+        let json = r#"[{"type": "Print",
+            "expression": {"type": "Literal", "value": "\ud83d\ude00 \b\f"}}]"#;
+        let statements = AstFormat::Json.read(json).unwrap();
+        let Stmt::Print(Expr::Literal { value, .. }) = &statements[0] else {
+            panic!("expected a print of a literal");
+        };
+        assert_eq!(
+            *value,
+            ExprLiteral::StringLiteral("\u{1f600} \u{8}\u{c}".to_string())
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let err = AstFormat::Json.read("[{\"type\": \"Print\"}]").unwrap_err();
+        assert_eq!(err.code, E_BAD_AST);
+        assert_eq!(err.message, "Print at [0] has no field expression.");
+
+        let err = AstFormat::Sexpr
+            .read("[(Let :name (Literal :value 1) :initializer (Literal :value 1))]")
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Expected a Token at [0].name, found a Literal node."
+        );
+
+        let err = AstFormat::Json.read("[\n  {\"type\" 1}]").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 11));
+        assert!(AstFormat::Sexpr.read("[(Print :expression").is_err());
+        assert!(AstFormat::Json.read("[] []").is_err());
+
+        let err = AstFormat::Json
+            .read(r#"[{"type": "Print", "expression": {"type": "Literal", "value": "\ud83d"}}]"#)
+            .unwrap_err();
+        assert_eq!(err.message, "Expected a low surrogate after \\ud83d.");
+        let err = AstFormat::Json
+            .read(r#"[{"type": "Print", "expression": {"type": "Literal", "value": "\ude00"}}]"#)
+            .unwrap_err();
+        assert_eq!(err.message, "Invalid escape \\ude00.");
+
+        let function = r#"[{"type": "Function",
+            "name": {"type": "Token", "kind": "Identifier", "lexeme": "f", "line": 1, "column": 4},
+            "params": [], "defaults": [], "rest": null, "annotations": [], "returns": null,
+            "body": {"type": "Print", "expression": {"type": "Literal", "value": 1}}}]"#;
+        let err = AstFormat::Json.read(function).unwrap_err();
+        assert_eq!(err.code, E_BAD_AST);
+        assert_eq!(err.message, "Function at [0] needs a Block body.");
+        let block = function.replace(
+            r#"{"type": "Print", "expression": {"type": "Literal", "value": 1}}"#,
+            r#"{"type": "Block", "statements": []}"#,
+        );
+        assert!(AstFormat::Json.read(&block).is_ok());
+    }
+}
@@ -1,74 +1,142 @@
-use std::time::UNIX_EPOCH;
-use std::{fmt::Debug, time::SystemTime};
+use std::any::Any;
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
 
-use super::{expr::ExprLiteral, interpreter::Interpreter};
+use super::{
+    diagnostic::{Diagnostic, E_ARITY, E_INTERNAL},
+    expr::ExprLiteral,
+    interpreter::Interpreter,
+    output::Output,
+};
 
-pub trait Callable: Debug {
-    fn call(
-        &mut self,
-        interpreter: &mut Interpreter,
-        arguments: Vec<ExprLiteral>,
-    ) -> Result<ExprLiteral, String>;
-
-    fn arity(&self) -> usize;
-
-    fn clone_box(&self) -> Box<dyn Callable>;
+// How many arguments a callable takes: `min` required ones, up to `max`, no limit for variadics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
 }
 
-impl Clone for Box<dyn Callable> {
-    fn clone(&self) -> Self {
-        self.clone_box()
+impl Arity {
+    pub fn exact(count: usize) -> Self {
+        Self {
+            min: count,
+            max: Some(count),
+        }
     }
-}
 
-impl PartialEq for Box<dyn Callable> {
-    fn eq(&self, other: &Self) -> bool {
-        false // Todo
+    pub fn range(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+        }
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-pub struct MyCallable;
+    pub fn at_least(min: usize) -> Self {
+        Self { min, max: None }
+    }
 
-impl Callable for MyCallable {
-    fn call(
-        &mut self,
-        interpreter: &mut Interpreter,
-        arguments: Vec<ExprLiteral>,
-    ) -> Result<ExprLiteral, String> {
-        todo!()
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
     }
 
-    fn arity(&self) -> usize {
-        todo!()
+    // brief: The noun that goes after the Display of this arity.
+    // input:
+    // output: "argument" for "1" and "at least 1", "arguments" otherwise.
+    pub fn noun(&self) -> &'static str {
+        if self.min == 1 && self.max.is_none_or(|max| max == 1) {
+            "argument"
+        } else {
+            "arguments"
+        }
     }
 
-    fn clone_box(&self) -> Box<dyn Callable> {
-        Box::new(*self)
+    // brief: The error of a call with `count` arguments.
+    // input: the callable, used to name it and show its signature.
+    // output:
+    pub fn mismatch(&self, callee: &dyn Callable, count: usize) -> Diagnostic {
+        Diagnostic::error(
+            E_ARITY,
+            format!(
+                "{} expects {} {} but got {}.",
+                callee.two_string(),
+                self,
+                self.noun(),
+                count
+            ),
+        )
+        .with_note(format!("signature: {}", callee.signature()))
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct MyClock;
+impl From<usize> for Arity {
+    fn from(count: usize) -> Self {
+        Arity::exact(count)
+    }
+}
+
+// "2", "1 to 3" or "at least 1".
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
 
-impl Callable for MyClock {
+// Anything a script can call: natives (stdlib::NativeFn), functions, classes and bound methods.
+// Any lets the Vm downcast to its own closures.
+pub trait Callable: Any + Debug {
+    // brief: Call from the tree-walking Interpreter, natives only need call_native.
+    // input:
+    // output:
     fn call(
-        &mut self,
+        &self,
         interpreter: &mut Interpreter,
         arguments: Vec<ExprLiteral>,
-    ) -> Result<ExprLiteral, String> {
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
+    ) -> Result<ExprLiteral, Diagnostic> {
+        self.call_with_output(interpreter.output(), arguments)
+    }
 
-        Ok(ExprLiteral::NumberLiteral(since_the_epoch.as_secs_f64()))
+    // brief: Call without a backend, natives implement this so that the Interpreter and the
+    // Vm share them.
+    // input:
+    // output:
+    fn call_native(&self, _arguments: Vec<ExprLiteral>) -> Result<ExprLiteral, Diagnostic> {
+        Err(Diagnostic::error(
+            E_INTERNAL,
+            format!("{} can not be called by this backend.", self.two_string()),
+        ))
     }
 
-    fn arity(&self) -> usize {
-        0
+    // brief: Call without a backend, for natives that write where print writes, e.g. the
+    // prompt of input. Other natives ignore the output.
+    // input: output: the sink of the backend making the call.
+    // output:
+    fn call_with_output(
+        &self,
+        _output: &mut Output,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        self.call_native(arguments)
     }
-    fn clone_box(&self) -> Box<dyn Callable> {
-        Box::new(*self)
+
+    // brief: A method with "this" bound to `instance`.
+    // input:
+    // output: None for callables that can not be methods.
+    fn bind_this(&self, _instance: ExprLiteral) -> Option<Rc<dyn Callable>> {
+        None
     }
+
+    fn arity(&self) -> Arity;
+
+    // brief: The parameter list for arity errors, e.g. "f(a, b = 2, ...rest)".
+    // input:
+    // output:
+    fn signature(&self) -> String {
+        self.two_string()
+    }
+
+    fn two_string(&self) -> String;
 }
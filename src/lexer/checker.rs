@@ -0,0 +1,1074 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc};
+
+use super::{
+    callable::{Arity, Callable},
+    diagnostic::{
+        Diagnostic, E_MISSING_RETURN, E_TYPE_ARITY, E_TYPE_CALL, E_TYPE_INDEX, E_TYPE_MISMATCH,
+        E_TYPE_OPERAND, E_TYPE_PROPERTY, E_UNKNOWN_TYPE,
+    },
+    expr::{Expr, ExprLiteral},
+    stdlib::Registry,
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+// The type of a value as far as the TypeChecker can tell before the program runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any, // not known: unannotated parameters, fields, list elements...
+    Nil,
+    Bool,
+    Int,
+    Float,
+    Num, // an int or a float.
+    Str,
+    List,
+    Map,
+    Module,
+    Fn(Option<Rc<FnType>>), // None if the parameters are not known.
+    Class(String),          // the class itself, calling it makes an Instance.
+    Instance(String),       // of the class with this name.
+}
+
+// What calling a function needs and gives.
+#[derive(Debug, PartialEq)]
+pub struct FnType {
+    pub callee: String,    // "<fn f>", for messages.
+    pub signature: String, // "f(a, b = 2)".
+    pub arity: Arity,
+    pub params: Vec<Type>, // of the declared parameters, empty for natives.
+    pub returns: Type,
+}
+
+impl Type {
+    // brief: The type an annotation names, see Parser::type_name.
+    // input:
+    // output: None for a name that is not a built in type, it may be a class.
+    fn from_name(name: &str) -> Option<Type> {
+        Some(match name {
+            "any" => Type::Any,
+            "nil" => Type::Nil,
+            "bool" => Type::Bool,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "num" => Type::Num,
+            "str" => Type::Str,
+            "list" => Type::List,
+            "map" => Type::Map,
+            "module" => Type::Module,
+            "fn" => Type::Fn(None),
+            _ => return None,
+        })
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Num)
+    }
+
+    // Whether a value of this type may be a number, the operands of "-" and "<" must.
+    fn may_be_number(&self) -> bool {
+        self.is_number() || *self == Type::Any
+    }
+
+    fn may_be_int(&self) -> bool {
+        matches!(self, Type::Int | Type::Num | Type::Any)
+    }
+
+    fn may_be_str(&self) -> bool {
+        matches!(self, Type::Str | Type::Any)
+    }
+
+    // brief: The type of a value that is either of two types, e.g. the result of "or".
+    // input:
+    // output: Any if they have nothing in common.
+    fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (a, b) if a.is_number() && b.is_number() => Type::Num,
+            (Type::Fn(_), Type::Fn(_)) => Type::Fn(None),
+            _ => Type::Any,
+        }
+    }
+
+    // brief: The result of "+" "-" "*" "/" "%" on two numbers, promoted as at runtime.
+    // input:
+    // output:
+    fn arithmetic(&self, other: &Type) -> Type {
+        match (self, other) {
+            (Type::Int, Type::Int) => Type::Int,
+            (Type::Float, _) | (_, Type::Float) => Type::Float,
+            _ => Type::Num,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Num => write!(f, "num"),
+            Type::Str => write!(f, "str"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Module => write!(f, "module"),
+            Type::Fn(_) => write!(f, "fn"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// A name in scope, only annotated ones keep their type when assigned.
+#[derive(Debug, Clone)]
+struct Binding {
+    ty: Type,
+    annotated: bool,
+}
+
+#[derive(Debug, Default)]
+struct ClassInfo {
+    superclass: Option<String>,
+    methods: HashMap<String, Type>, // filled in when the class declaration is checked.
+}
+
+// The types a function declares, Any where a parameter has no annotation.
+struct Declared {
+    params: Vec<Type>,
+    rest: Type,
+    returns: Option<Type>,
+}
+
+// The function whose body is being checked.
+struct FunctionContext {
+    callee: String,
+    returns: Option<Type>, // the declared one.
+    found: Vec<Type>,      // of every return seen.
+}
+
+// Checks a program against its type annotations before it runs, inferring the types of
+// unannotated code. The typing is gradual: what is not known is Any, which goes with
+// everything, so only a mismatch the program can not avoid is reported.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, Binding>>, // the globals first.
+    classes: HashMap<String, ClassInfo>,   // every class of the program, usable as a type.
+    functions: Vec<FunctionContext>,
+    current_classes: Vec<String>, // the classes whose methods are being checked, for "this".
+    errors: Vec<Diagnostic>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        let globals = Registry::standard()
+            .entries()
+            .into_iter()
+            .map(|(name, value)| {
+                let binding = Binding {
+                    ty: literal(&value),
+                    annotated: false,
+                };
+                (name.to_string(), binding)
+            })
+            .collect();
+        Self {
+            scopes: vec![globals],
+            classes: HashMap::new(),
+            functions: vec![],
+            current_classes: vec![],
+            errors: vec![],
+        }
+    }
+
+    // brief: Check a whole program.
+    // input:
+    // output: Err with every mismatch found, in source order.
+    pub fn check(mut self, statements: &[Stmt]) -> Result<(), Vec<Diagnostic>> {
+        self.collect_classes(statements);
+        for statement in statements {
+            self.stmt(statement);
+        }
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    // brief: Find every class before checking, an annotation may name a class declared later.
+    // input:
+    // output:
+    fn collect_classes(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            match statement {
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                } => {
+                    let superclass = match superclass {
+                        Some(Expr::Variable { name, .. }) => Some(name.lexeme.clone()),
+                        _ => None,
+                    };
+                    self.classes.insert(
+                        name.lexeme.clone(),
+                        ClassInfo {
+                            superclass,
+                            ..ClassInfo::default()
+                        },
+                    );
+                    self.collect_classes(methods);
+                }
+                Stmt::Function { body, .. } | Stmt::While { body, .. } => {
+                    self.collect_classes(std::slice::from_ref(body))
+                }
+                Stmt::Block { statements } => self.collect_classes(statements),
+                Stmt::If {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.collect_classes(std::slice::from_ref(then_branch));
+                    if let Some(else_branch) = else_branch {
+                        self.collect_classes(std::slice::from_ref(else_branch));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn stmt(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(expr) | Stmt::Print(expr) => {
+                self.expr(expr);
+            }
+            Stmt::Let {
+                name,
+                annotation,
+                initializer,
+            } => {
+                let actual = self.expr(initializer);
+                let binding = match annotation {
+                    Some(annotation) => {
+                        let declared = self.resolve_type(annotation);
+                        self.expect(&declared, &actual, &name.lexeme, name);
+                        Binding {
+                            ty: declared,
+                            annotated: true,
+                        }
+                    }
+                    None => Binding {
+                        // A variable starting as nil is usually given its value later.
+                        ty: if actual == Type::Nil {
+                            Type::Any
+                        } else {
+                            actual
+                        },
+                        annotated: false,
+                    },
+                };
+                self.declare(&name.lexeme, binding);
+            }
+            Stmt::Block { statements } => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.stmt(statement);
+                }
+                self.scopes.pop();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.expr(condition);
+                self.stmt(body);
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+            }
+            Stmt::Function { name, .. } => {
+                let declared = self.declared(statement);
+                // Declared before the body is checked, for recursive calls.
+                let returns = declared.returns.clone().unwrap_or(Type::Any);
+                self.declare(
+                    &name.lexeme,
+                    function_binding(statement, &declared, returns),
+                );
+                let returns = self.function(statement, &declared, false);
+                if declared.returns.is_none() {
+                    self.declare(
+                        &name.lexeme,
+                        function_binding(statement, &declared, returns),
+                    );
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                let actual = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Nil,
+                };
+                let Some(context) = self.functions.last_mut() else {
+                    return; // the Resolver reports it.
+                };
+                context.found.push(actual.clone());
+                if let Some(expected) = context.returns.clone() {
+                    let what = format!("the result of {}", context.callee);
+                    self.expect(&expected, &actual, &what, keyword);
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                if let Some(superclass) = superclass {
+                    self.expr(superclass);
+                }
+                let class = Type::Class(name.lexeme.clone());
+                self.declare(
+                    &name.lexeme,
+                    Binding {
+                        ty: class,
+                        annotated: false,
+                    },
+                );
+                // Every method is known before any body is checked, they call each other.
+                let declared: Vec<Declared> = methods.iter().map(|m| self.declared(m)).collect();
+                let table = methods
+                    .iter()
+                    .zip(&declared)
+                    .filter_map(|(method, declared)| match method {
+                        Stmt::Function { name, .. } => {
+                            let returns = declared.returns.clone().unwrap_or(Type::Any);
+                            let binding = function_binding(method, declared, returns);
+                            Some((name.lexeme.clone(), binding.ty))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if let Some(info) = self.classes.get_mut(&name.lexeme) {
+                    info.methods = table;
+                }
+                self.current_classes.push(name.lexeme.clone());
+                for (method, declared) in methods.iter().zip(&declared) {
+                    let is_initializer =
+                        matches!(method, Stmt::Function { name, .. } if name.lexeme == "init");
+                    self.function(method, declared, is_initializer);
+                }
+                self.current_classes.pop();
+            }
+            Stmt::Import { name, .. } => self.declare(
+                &name.lexeme,
+                Binding {
+                    ty: Type::Module,
+                    annotated: false,
+                },
+            ),
+        }
+    }
+
+    // brief: The types a function or method declares, reporting unknown type names once.
+    // input: a Stmt::Function.
+    // output:
+    fn declared(&mut self, function: &Stmt) -> Declared {
+        let Stmt::Function {
+            params,
+            rest,
+            annotations,
+            returns,
+            ..
+        } = function
+        else {
+            return Declared {
+                params: vec![],
+                rest: Type::List,
+                returns: None,
+            };
+        };
+        let annotation = |checker: &mut Self, index: usize| match annotations.get(index) {
+            Some(Some(annotation)) => checker.resolve_type(annotation),
+            _ => Type::Any,
+        };
+        let params: Vec<Type> = (0..params.len())
+            .map(|index| annotation(self, index))
+            .collect();
+        let rest = match (rest, annotations.get(params.len())) {
+            (Some(rest), Some(Some(annotation))) => {
+                let ty = self.resolve_type(annotation);
+                let what = format!("the rest parameter {}", rest.lexeme);
+                self.expect(&ty, &Type::List, &what, annotation);
+                ty
+            }
+            _ => Type::List,
+        };
+        let returns = returns.as_ref().map(|returns| self.resolve_type(returns));
+        Declared {
+            params,
+            rest,
+            returns,
+        }
+    }
+
+    // brief: Check a function body, in one scope with its parameters as MyFunction::call runs it.
+    // input: a Stmt::Function and the types it declares.
+    // output: what it returns: the declared type, or the join of the returned ones.
+    fn function(&mut self, function: &Stmt, declared: &Declared, is_initializer: bool) -> Type {
+        let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            ..
+        } = function
+        else {
+            return Type::Any;
+        };
+        let Stmt::Block { statements } = body.as_ref() else {
+            return Type::Any;
+        };
+
+        self.scopes.push(HashMap::new());
+        let first_default = params.len() - defaults.len();
+        for (index, param) in params.iter().enumerate() {
+            let ty = declared.params[index].clone();
+            if let Some(default) = index.checked_sub(first_default).map(|i| &defaults[i]) {
+                let actual = self.expr(default);
+                self.expect(&ty, &actual, &format!("parameter {}", param.lexeme), param);
+            }
+            self.declare(
+                &param.lexeme,
+                Binding {
+                    ty,
+                    annotated: true,
+                },
+            );
+        }
+        if let Some(rest) = rest {
+            let binding = Binding {
+                ty: declared.rest.clone(),
+                annotated: true,
+            };
+            self.declare(&rest.lexeme, binding);
+        }
+
+        self.functions.push(FunctionContext {
+            callee: format!("<fn {}>", name.lexeme),
+            returns: declared.returns.clone(),
+            found: vec![],
+        });
+        for statement in statements {
+            self.stmt(statement);
+        }
+        let context = self.functions.pop().unwrap();
+        self.scopes.pop();
+
+        let falls_through = !statements.iter().any(always_returns);
+        if is_initializer {
+            return Type::Any;
+        }
+        match &declared.returns {
+            Some(expected) => {
+                if falls_through && !self.accepts(expected, &Type::Nil) {
+                    self.error(
+                        E_MISSING_RETURN,
+                        format!("{} may end without returning {}.", context.callee, expected),
+                        name,
+                    );
+                }
+                expected.clone()
+            }
+            None => {
+                let mut found = context.found;
+                if falls_through {
+                    found.push(Type::Nil);
+                }
+                found.into_iter().reduce(Type::join).unwrap_or(Type::Nil)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal { value } => literal(value),
+            Expr::Grouping { expression } => self.expr(expression),
+            Expr::Unary { operator, right } => {
+                let right = self.expr(right);
+                if operator.token_type != TokenType::Minus {
+                    return Type::Bool; // "!" takes anything.
+                }
+                match right {
+                    Type::Int | Type::Float => right,
+                    _ if right.may_be_number() => Type::Num,
+                    _ => {
+                        let msg = format!("Bad operand for -: {}.", right);
+                        self.error(E_TYPE_OPERAND, msg, operator);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.binary(operator, &left, &right)
+            }
+            Expr::Logical { left, right, .. } => {
+                let left = self.expr(left);
+                left.join(self.expr(right))
+            }
+            Expr::Variable { name, .. } => self
+                .lookup(&name.lexeme)
+                .map_or(Type::Any, |binding| binding.ty.clone()),
+            Expr::Assign { name, value, .. } => {
+                let actual = self.expr(value);
+                let Some(binding) = self.lookup(&name.lexeme).cloned() else {
+                    return actual;
+                };
+                if binding.annotated {
+                    self.expect(&binding.ty, &actual, &name.lexeme, name);
+                } else if let Some(binding) = self.lookup_mut(&name.lexeme) {
+                    // An unannotated variable holds whatever it is given.
+                    binding.ty = binding.ty.clone().join(actual.clone());
+                }
+                actual
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                // The items a for-in loop indexes index like the sequence itself.
+                if let (
+                    Expr::Literal {
+                        value: ExprLiteral::FunctionLiteral(function),
+                    },
+                    [sequence],
+                ) = (callee.as_ref(), arguments.as_slice())
+                {
+                    if function.two_string() == "<native fn items>" {
+                        return self.expr(sequence);
+                    }
+                }
+                let callee = self.expr(callee);
+                let arguments: Vec<Type> = arguments.iter().map(|arg| self.expr(arg)).collect();
+                self.call(&callee, &arguments, paren)
+            }
+            Expr::Get { object, name } => match self.expr(object) {
+                Type::Instance(class) => self.method(&class, &name.lexeme).unwrap_or(Type::Any),
+                Type::Any | Type::Module => Type::Any,
+                other => {
+                    let msg = format!(
+                        "Only instances have properties, can't read {} of {}.",
+                        name.lexeme, other
+                    );
+                    self.error(E_TYPE_PROPERTY, msg, name);
+                    Type::Any
+                }
+            },
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object = self.expr(object);
+                let value = self.expr(value);
+                if !matches!(object, Type::Instance(_) | Type::Any) {
+                    let msg = format!(
+                        "Only instances have fields, can't set {} of {}.",
+                        name.lexeme, object
+                    );
+                    self.error(E_TYPE_PROPERTY, msg, name);
+                }
+                value
+            }
+            Expr::This { .. } => self
+                .current_classes
+                .last()
+                .map_or(Type::Any, |class| Type::Instance(class.clone())),
+            Expr::Super { method, .. } => {
+                let superclass = self
+                    .current_classes
+                    .last()
+                    .and_then(|class| self.classes.get(class))
+                    .and_then(|info| info.superclass.clone());
+                superclass
+                    .and_then(|superclass| self.method(&superclass, &method.lexeme))
+                    .unwrap_or(Type::Any)
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.expr(element);
+                }
+                Type::List
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                Type::Map
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.expr(object);
+                let index = self.expr(index);
+                match object {
+                    Type::List | Type::Str => {
+                        self.expect_int_index(&object, &index, bracket);
+                        if object == Type::Str {
+                            Type::Str
+                        } else {
+                            Type::Any
+                        }
+                    }
+                    Type::Map | Type::Any => Type::Any,
+                    other => {
+                        let msg =
+                            format!("Can only index lists, maps and strings, found {}.", other);
+                        self.error(E_TYPE_INDEX, msg, bracket);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.expr(object);
+                let index = self.expr(index);
+                let value = self.expr(value);
+                match object {
+                    Type::List => self.expect_int_index(&object, &index, bracket),
+                    Type::Map | Type::Any => {}
+                    other => {
+                        let msg = format!("Can only assign into lists and maps, found {}.", other);
+                        self.error(E_TYPE_INDEX, msg, bracket);
+                    }
+                }
+                value
+            }
+        }
+    }
+
+    // brief: The type of a binary operation, following ExprLiteral::binary.
+    // input:
+    // output: Any after reporting operands that can never work.
+    fn binary(&mut self, operator: &Token, left: &Type, right: &Type) -> Type {
+        let both_numbers = left.may_be_number() && right.may_be_number();
+        let result = match operator.token_type {
+            TokenType::EqualEqual | TokenType::BangEqual => Some(Type::Bool),
+            TokenType::Plus if *left == Type::Str || *right == Type::Str => {
+                (left.may_be_str() && right.may_be_str()).then_some(Type::Str)
+            }
+            TokenType::Plus if *left == Type::Any && *right == Type::Any => Some(Type::Any),
+            TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Percent => both_numbers.then(|| left.arithmetic(right)),
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => both_numbers.then_some(Type::Bool),
+            _ => Some(Type::Any),
+        };
+        result.unwrap_or_else(|| {
+            let msg = format!(
+                "Bad operands for {}: {} and {}.",
+                operator.lexeme, left, right
+            );
+            self.error(E_TYPE_OPERAND, msg, operator);
+            Type::Any
+        })
+    }
+
+    // brief: The type of a call, checking its arguments when the callee is known.
+    // input:
+    // output:
+    fn call(&mut self, callee: &Type, arguments: &[Type], paren: &Token) -> Type {
+        match callee {
+            Type::Fn(Some(function)) => {
+                self.arguments(&function.callee, function, arguments, paren);
+                function.returns.clone()
+            }
+            Type::Class(class) => {
+                let callee = format!("<class {}>", class);
+                match self.method(class, "init") {
+                    Some(Type::Fn(Some(init))) => self.arguments(&callee, &init, arguments, paren),
+                    Some(_) => {}
+                    None if arguments.is_empty() => {}
+                    None => {
+                        let msg = format!(
+                            "{} expects 0 arguments but got {}.",
+                            callee,
+                            arguments.len()
+                        );
+                        self.error(E_TYPE_ARITY, msg, paren);
+                    }
+                }
+                Type::Instance(class.clone())
+            }
+            Type::Fn(None) | Type::Any => Type::Any,
+            other => {
+                let msg = format!("Can only call functions and classes, found {}.", other);
+                self.error(E_TYPE_CALL, msg, paren);
+                Type::Any
+            }
+        }
+    }
+
+    fn arguments(&mut self, callee: &str, function: &FnType, arguments: &[Type], paren: &Token) {
+        if !function.arity.accepts(arguments.len()) {
+            self.errors.push(
+                Diagnostic::error(
+                    E_TYPE_ARITY,
+                    format!(
+                        "{} expects {} {} but got {}.",
+                        callee,
+                        function.arity,
+                        function.arity.noun(),
+                        arguments.len()
+                    ),
+                )
+                .at_token(paren)
+                .with_note(format!("signature: {}", function.signature)),
+            );
+            return;
+        }
+        for (index, (expected, actual)) in function.params.iter().zip(arguments).enumerate() {
+            let what = format!("argument {} of {}", index + 1, callee);
+            self.expect(expected, actual, &what, paren);
+        }
+    }
+
+    fn expect_int_index(&mut self, object: &Type, index: &Type, bracket: &Token) {
+        if !index.may_be_int() {
+            let msg = format!(
+                "Bad index: a {} is indexed by an int, found {}.",
+                object, index
+            );
+            self.error(E_TYPE_INDEX, msg, bracket);
+        }
+    }
+
+    // brief: A method of a class or of its superclasses.
+    // input:
+    // output: None if no class on the way declares it, or a class is not known.
+    fn method(&self, class: &str, name: &str) -> Option<Type> {
+        let mut class = self.classes.get(class)?;
+        // Bounded, a class redeclared under the name of its superclass would loop.
+        for _ in 0..=self.classes.len() {
+            if let Some(method) = class.methods.get(name) {
+                return Some(method.clone());
+            }
+            class = self.classes.get(class.superclass.as_ref()?)?;
+        }
+        None
+    }
+
+    // brief: Whether a value of type `actual` may be used where `expected` is declared.
+    // input:
+    // output: true unless they can never agree.
+    fn accepts(&self, expected: &Type, actual: &Type) -> bool {
+        match (expected, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Num, Type::Int | Type::Float) | (Type::Int | Type::Float, Type::Num) => true,
+            (Type::Fn(_), Type::Fn(_)) => true,
+            (Type::Instance(expected), Type::Instance(actual)) => {
+                let mut class = Some(actual);
+                for _ in 0..=self.classes.len() {
+                    match class {
+                        Some(name) if name == expected => return true,
+                        Some(name) => {
+                            class = self
+                                .classes
+                                .get(name)
+                                .and_then(|info| info.superclass.as_ref())
+                        }
+                        None => return false,
+                    }
+                }
+                false
+            }
+            _ => expected == actual,
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, actual: &Type, what: &str, token: &Token) {
+        if !self.accepts(expected, actual) {
+            let msg = format!("Expected {} for {}, found {}.", expected, what, actual);
+            self.error(E_TYPE_MISMATCH, msg, token);
+        }
+    }
+
+    // brief: The type an annotation names.
+    // input:
+    // output: Any after reporting an unknown name.
+    fn resolve_type(&mut self, annotation: &Token) -> Type {
+        if let Some(ty) = Type::from_name(&annotation.lexeme) {
+            return ty;
+        }
+        if self.classes.contains_key(&annotation.lexeme) {
+            return Type::Instance(annotation.lexeme.clone());
+        }
+        self.errors.push(
+            Diagnostic::error(
+                E_UNKNOWN_TYPE,
+                format!("Unknown type {}.", annotation.lexeme),
+            )
+            .at_token(annotation)
+            .with_note(
+                "types are any, nil, bool, int, float, num, str, list, map, module, fn and classes",
+            ),
+        );
+        Type::Any
+    }
+
+    fn declare(&mut self, name: &str, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), binding);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn error(&mut self, code: u16, message: String, token: &Token) {
+        self.errors
+            .push(Diagnostic::error(code, message).at_token(token));
+    }
+}
+
+// brief: The type of a value written in the program, or given to it by the host.
+// input:
+// output:
+fn literal(value: &ExprLiteral) -> Type {
+    match value {
+        ExprLiteral::IntLiteral(_) => Type::Int,
+        ExprLiteral::NumberLiteral(_) => Type::Float,
+        ExprLiteral::StringLiteral(_) => Type::Str,
+        ExprLiteral::True | ExprLiteral::False => Type::Bool,
+        ExprLiteral::Nil => Type::Nil,
+        ExprLiteral::FunctionLiteral(function) => native(function.as_ref()),
+        ExprLiteral::ClassLiteral(class) => Type::Class(class.name.clone()),
+        ExprLiteral::InstanceLiteral(_) => Type::Any,
+        ExprLiteral::ListLiteral(_) => Type::List,
+        ExprLiteral::MapLiteral(_) => Type::Map,
+        ExprLiteral::ModuleLiteral(_) => Type::Module,
+    }
+}
+
+// brief: The type of a native: its arity is checked, its arguments are not.
+// input:
+// output:
+fn native(function: &dyn Callable) -> Type {
+    let signature = function.signature();
+    let name = signature.split('(').next().unwrap_or_default();
+    let returns = match name {
+        "clock" | "sqrt" => Type::Float,
+        "len" => Type::Int,
+        "type_of" | "to_string" | "upper" | "lower" | "substr" | "format" => Type::Str,
+        "split" | "slice" | "keys" | "values" => Type::List,
+        "has" => Type::Bool,
+        _ => Type::Any,
+    };
+    Type::Fn(Some(Rc::new(FnType {
+        callee: function.two_string(),
+        signature: function.signature(),
+        arity: function.arity(),
+        params: vec![],
+        returns,
+    })))
+}
+
+// brief: The binding of a declared function or method.
+// input: a Stmt::Function, its declared types and what it returns.
+// output:
+fn function_binding(function: &Stmt, declared: &Declared, returns: Type) -> Binding {
+    let name = match function {
+        Stmt::Function { name, .. } => name.lexeme.as_str(),
+        _ => "",
+    };
+    let ty = Type::Fn(Some(Rc::new(FnType {
+        callee: format!("<fn {}>", name),
+        signature: function.signature(),
+        arity: function.arity(),
+        params: declared.params.clone(),
+        returns,
+    })));
+    Binding {
+        ty,
+        annotated: false,
+    }
+}
+
+// brief: Whether a statement returns on every path, so that nothing after it runs.
+// input:
+// output: false if unsure.
+fn always_returns(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Return { .. } => true,
+        Stmt::Block { statements } => statements.iter().any(always_returns),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        // "while (true)" only ends through a break.
+        Stmt::While {
+            condition: Expr::Literal {
+                value: ExprLiteral::True,
+            },
+            body,
+            ..
+        } => !breaks(body),
+        _ => false,
+    }
+}
+
+// brief: Whether a loop body has a break of its own loop.
+// input:
+// output:
+fn breaks(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Break { .. } => true,
+        Stmt::Block { statements } => statements.iter().any(breaks),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => breaks(then_branch) || else_branch.as_deref().is_some_and(breaks),
+        _ => false, // a break in a nested loop or function is not ours.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, scanner::Scanner};
+
+    // brief: The codes of the errors found in `source`, in order.
+    fn check(source: &str) -> Vec<u16> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        match TypeChecker::new().check(&statements) {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|err| err.code).collect(),
+        }
+    }
+
+    #[test]
+    fn annotations_are_checked() {
+        assert_eq!(check("let x: num = 1; let y: float = x;"), vec![]);
+        assert_eq!(check("let x: num = \"a\";"), vec![E_TYPE_MISMATCH]);
+        assert_eq!(check("let x: int = 1; x = 1.5;"), vec![E_TYPE_MISMATCH]);
+        assert_eq!(check("let x: integer = 1;"), vec![E_UNKNOWN_TYPE]);
+        assert_eq!(
+            check("fn f(a: str) -> num { return a; } f(1);"),
+            vec![E_TYPE_MISMATCH, E_TYPE_MISMATCH]
+        );
+        assert_eq!(check("fn f(...rest: map) {}"), vec![E_TYPE_MISMATCH]);
+        assert_eq!(check("fn f(a: int = \"1\") {}"), vec![E_TYPE_MISMATCH]);
+    }
+
+    #[test]
+    fn unannotated_code_is_inferred() {
+        assert_eq!(check("let a = 1; print a + \"b\";"), vec![E_TYPE_OPERAND]);
+        assert_eq!(
+            check("fn f() { return 1; } let s: str = f();"),
+            vec![E_TYPE_MISMATCH]
+        );
+        assert_eq!(check("fn f(a, b) {} f(1);"), vec![E_TYPE_ARITY]);
+        assert_eq!(check("print len([1]) + 1.5 < 3;"), vec![]);
+        assert_eq!(
+            check("let n = 1; n(); n.x; n[0];"),
+            vec![E_TYPE_CALL, E_TYPE_PROPERTY, E_TYPE_INDEX]
+        );
+        // A variable given values of several types, or nil at first, is any.
+        assert_eq!(check("let a = 1; a = \"s\"; print a + \"b\";"), vec![]);
+        assert_eq!(check("let a; a = 2; print -a;"), vec![]);
+        assert_eq!(check("fn f(x) { return x.y * x[0]; }"), vec![]);
+    }
+
+    #[test]
+    fn classes_are_types() {
+        let source = "
+            class A { init(n: int) {} get() -> int { return 1; } }
+            class B < A {}
+            fn f(a: A) -> int { return a.get(); }
+            f(B(1));
+            let s: str = A(1).get();
+            A();
+        ";
+        assert_eq!(check(source), vec![E_TYPE_MISMATCH, E_TYPE_ARITY]);
+        assert_eq!(
+            check("class A {} class B {} fn f(a: A) {} f(B());"),
+            vec![E_TYPE_MISMATCH]
+        );
+        assert_eq!(
+            check("fn f() -> Later { return Later(); } class Later {}"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn missing_returns_are_found() {
+        assert_eq!(
+            check("fn f(a) -> int { if (a) return 1; }"),
+            vec![E_MISSING_RETURN]
+        );
+        assert_eq!(
+            check("fn f(a) -> int { if (a) return 1; else return 2; }"),
+            vec![]
+        );
+        assert_eq!(
+            check("fn f() -> int { while (true) { return 1; } }"),
+            vec![]
+        );
+        assert_eq!(
+            check("fn f() -> int { while (true) { break; } }"),
+            vec![E_MISSING_RETURN]
+        );
+        assert_eq!(check("fn f() -> nil {} fn g() -> any {}"), vec![]);
+    }
+}
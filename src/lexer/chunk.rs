@@ -0,0 +1,375 @@
+use std::{fmt::Debug, rc::Rc};
+
+use super::{
+    callable::Arity,
+    diagnostic::{Origin, Span},
+    expr::ExprLiteral,
+    token::Token,
+};
+
+// One byte per opcode, operands follow it in the code. The comment is the operand layout.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant,     // u16 constant.
+    Nil,          //
+    True,         //
+    False,        //
+    Pop,          //
+    GetLocal,     // u8 slot.
+    SetLocal,     // u8 slot.
+    GetGlobal,    // u16 name constant.
+    DefineGlobal, // u16 name constant.
+    SetGlobal,    // u16 name constant.
+    GetUpvalue,   // u8 upvalue.
+    SetUpvalue,   // u8 upvalue.
+    GetProperty,  // u16 name constant.
+    SetProperty,  // u16 name constant.
+    GetSuper,     // u16 name constant.
+    BuildList,    // u16 item count.
+    BuildMap,     // u16 entry count, keys and values alternate on the stack.
+    GetIndex,     //
+    SetIndex,     //
+    Equal,        //
+    NotEqual,     //
+    Greater,      //
+    GreaterEqual, //
+    Less,         //
+    LessEqual,    //
+    Add,          //
+    Subtract,     //
+    Multiply,     //
+    Divide,       //
+    Modulo,       //
+    Not,          //
+    Negate,       //
+    Print,        //
+    Jump,         // u16 forward offset.
+    JumpIfFalse,  // u16 forward offset, the condition stays on the stack.
+    JumpIfPassed, // u8 parameter, u16 forward offset over the code of its default.
+    Loop,         // u16 backward offset.
+    Call,         // u8 argument count.
+    Closure,      // u16 function, then (u8 is_local, u8 index) for every upvalue.
+    CloseUpvalue, //
+    Return,       //
+    Class, // u16 name constant, u8 has superclass, u8 method count, u16 name constant per method.
+    Import, // u16 path constant, pushes the module.
+}
+
+impl OpCode {
+    const ALL: [OpCode; 43] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::BuildList,
+        OpCode::BuildMap,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Modulo,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::JumpIfPassed,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Import,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        Self::ALL.get(byte as usize).copied()
+    }
+}
+
+// Where an instruction comes from, used for runtime diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+}
+
+impl Position {
+    pub fn of(token: &Token) -> Self {
+        Self {
+            line: token.line_number,
+            column: token.column,
+            span: token.span,
+        }
+    }
+}
+
+// Line table entry: code from `offset` up to the next entry comes from `position`.
+#[derive(Debug, Clone, Copy)]
+struct LineStart {
+    offset: usize,
+    position: Position,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<ExprLiteral>,
+    pub functions: Vec<Rc<FunctionProto>>, // referenced by OpCode::Closure.
+    lines: Vec<LineStart>,                 // run-length encoded.
+    pub origin: Option<Rc<Origin>>,        // the file the lines are in, None for the script run.
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, position: Position) {
+        if self.lines.last().map(|v| v.position) != Some(position) {
+            self.lines.push(LineStart {
+                offset: self.code.len(),
+                position,
+            });
+        }
+        self.code.push(byte);
+    }
+
+    pub fn write_u16(&mut self, value: u16, position: Position) {
+        for byte in value.to_be_bytes() {
+            self.write(byte, position);
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // brief: Add a constant, names and other strings are stored once.
+    // input:
+    // output: index in the constants pool.
+    pub fn add_constant(&mut self, value: ExprLiteral) -> usize {
+        if let ExprLiteral::StringLiteral(_) = value {
+            if let Some(index) = self.constants.iter().position(|v| *v == value) {
+                return index;
+            }
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_function(&mut self, function: FunctionProto) -> usize {
+        self.functions.push(Rc::new(function));
+        self.functions.len() - 1
+    }
+
+    // brief: Look up the line table.
+    // input:
+    // output:
+    pub fn position(&self, offset: usize) -> Position {
+        let index = self.lines.partition_point(|v| v.offset <= offset);
+        match index {
+            0 => Position::default(),
+            _ => self.lines[index - 1].position,
+        }
+    }
+
+    // brief: Human readable listing of the chunk, and of every function declared in it.
+    // input:
+    // output:
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        let mut offset = 0;
+        let mut last_line = 0;
+        while offset < self.code.len() {
+            let line = self.position(offset).line;
+            let line_str = if line == last_line {
+                "   |".to_string()
+            } else {
+                format!("{:>4}", line)
+            };
+            last_line = line;
+
+            let (text, next) = self.disassemble_instruction(offset);
+            out.push_str(&format!("{:04} {} {}\n", offset, line_str, text));
+            offset = next;
+        }
+        for function in &self.functions {
+            out.push('\n');
+            out.push_str(&function.chunk.disassemble(&function.name));
+        }
+        out
+    }
+
+    // brief: One instruction.
+    // input:
+    // output: the text and the offset of the next instruction.
+    fn disassemble_instruction(&self, offset: usize) -> (String, usize) {
+        let Some(op) = OpCode::from_byte(self.code[offset]) else {
+            return (format!("Unknown opcode {}", self.code[offset]), offset + 1);
+        };
+        let name = format!("{:?}", op);
+        let constant = |offset: usize| {
+            let index = self.read_u16(offset) as usize;
+            format!(
+                "{:<14} {:>4} '{}'",
+                name,
+                index,
+                self.constants[index].two_string()
+            )
+        };
+
+        match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Import => (constant(offset + 1), offset + 3),
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => (
+                format!("{:<14} {:>4}", name, self.code[offset + 1]),
+                offset + 2,
+            ),
+            OpCode::BuildList | OpCode::BuildMap => (
+                format!("{:<14} {:>4}", name, self.read_u16(offset + 1)),
+                offset + 3,
+            ),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = self.read_u16(offset + 1) as usize;
+                let target = if op == OpCode::Loop {
+                    offset + 3 - jump
+                } else {
+                    offset + 3 + jump
+                };
+                (
+                    format!("{:<14} {:>4} -> {}", name, offset, target),
+                    offset + 3,
+                )
+            }
+            OpCode::JumpIfPassed => {
+                let jump = self.read_u16(offset + 2) as usize;
+                (
+                    format!(
+                        "{:<14} {:>4} -> {}",
+                        name,
+                        self.code[offset + 1],
+                        offset + 4 + jump
+                    ),
+                    offset + 4,
+                )
+            }
+            OpCode::Closure => {
+                let index = self.read_u16(offset + 1) as usize;
+                let function = &self.functions[index];
+                let mut text = format!("{:<14} {:>4} <fn {}>", name, index, function.name);
+                let mut next = offset + 3;
+                for _ in 0..function.upvalue_count {
+                    let kind = if self.code[next] == 1 {
+                        "local"
+                    } else {
+                        "upvalue"
+                    };
+                    text.push_str(&format!(" {} {}", kind, self.code[next + 1]));
+                    next += 2;
+                }
+                (text, next)
+            }
+            OpCode::Class => {
+                let mut text = constant(offset + 1);
+                let has_superclass = self.code[offset + 3] == 1;
+                let count = self.code[offset + 4] as usize;
+                if has_superclass {
+                    text.push_str(" <");
+                }
+                let mut next = offset + 5;
+                for _ in 0..count {
+                    let index = self.read_u16(next) as usize;
+                    text.push_str(&format!(" {}", self.constants[index].two_string()));
+                    next += 2;
+                }
+                (text, next)
+            }
+            _ => (name, offset + 1),
+        }
+    }
+}
+
+// A compiled function, shared by all the closures created from it.
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: Arity,
+    pub params: usize,     // named parameters, without the rest parameter.
+    pub signature: String, // for arity errors.
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl Debug for FunctionProto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_table_is_run_length_encoded() {
+        let at = |line| Position {
+            line,
+            column: 1,
+            span: Span::default(),
+        };
+        let mut chunk = Chunk::new();
+        let index = chunk.add_constant(ExprLiteral::IntLiteral(7));
+        chunk.write(OpCode::Constant as u8, at(1));
+        chunk.write_u16(index as u16, at(1));
+        chunk.write(OpCode::Print as u8, at(2));
+        chunk.write(OpCode::Return as u8, at(2));
+
+        assert_eq!(chunk.lines.len(), 2);
+        assert_eq!(chunk.position(2).line, 1);
+        assert_eq!(chunk.position(3).line, 2);
+        assert_eq!(
+            chunk.disassemble("test"),
+            "== test ==\n0000    1 Constant          0 '7'\n0003    2 Print\n0004    | Return\n"
+        );
+    }
+
+    #[test]
+    fn opcodes_round_trip() {
+        for op in OpCode::ALL {
+            assert_eq!(OpCode::from_byte(op as u8), Some(op));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::ALL.len() as u8), None);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use super::{
+    callable::{Arity, Callable},
+    diagnostic::Diagnostic,
+    expr::ExprLiteral,
+    interpreter::Interpreter,
+};
+
+// Runtime class, created when the Interpreter executes a Stmt::Class or the Vm runs OpCode::Class.
+// Calling it creates a MyInstance and runs "init" if there is one.
+// Methods are MyFunction for the Interpreter and Closure for the Vm.
+#[derive(Clone)]
+pub struct MyClass {
+    pub name: String,
+    pub superclass: Option<Rc<MyClass>>,
+    methods: Rc<HashMap<String, Rc<dyn Callable>>>,
+}
+
+impl MyClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<MyClass>>,
+        methods: HashMap<String, Rc<dyn Callable>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods: Rc::new(methods),
+        }
+    }
+
+    // brief: Find a method in this class, then up the superclass chain.
+    // input:
+    // output:
+    pub fn find_method(&self, name: &str) -> Option<Rc<dyn Callable>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+        match &self.superclass {
+            Some(superclass) => superclass.find_method(name),
+            None => None,
+        }
+    }
+}
+
+// Methods hold closures which may hold the class itself, so Debug must not walk into them.
+impl Debug for MyClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
+
+impl Callable for MyClass {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        let instance = ExprLiteral::InstanceLiteral(Rc::new(RefCell::new(MyInstance::new(
+            Rc::new(self.clone()),
+        ))));
+
+        if let Some(initializer) = self
+            .find_method("init")
+            .and_then(|init| init.bind_this(instance.clone()))
+        {
+            initializer.call(interpreter, arguments)?;
+        }
+        Ok(instance)
+    }
+
+    fn arity(&self) -> Arity {
+        match self.find_method("init") {
+            Some(initializer) => initializer.arity(),
+            None => Arity::exact(0),
+        }
+    }
+
+    fn signature(&self) -> String {
+        match self.find_method("init") {
+            Some(initializer) => format!(
+                "{}{}",
+                self.name,
+                initializer.signature().trim_start_matches("init")
+            ),
+            None => format!("{}()", self.name),
+        }
+    }
+
+    fn two_string(&self) -> String {
+        format!("<class {}>", self.name)
+    }
+}
+
+// Runtime instance, fields are created the first time they are assigned.
+pub struct MyInstance {
+    pub class: Rc<MyClass>,
+    fields: HashMap<String, ExprLiteral>,
+}
+
+impl MyInstance {
+    pub fn new(class: Rc<MyClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    // brief: Read a property: fields shadow methods, methods come back bound to the instance.
+    // input: the instance itself is needed to bind "this".
+    // output: None if there is no such field or method.
+    pub fn get(instance: &Rc<RefCell<MyInstance>>, name: &str) -> Option<ExprLiteral> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Some(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(name);
+        method
+            .and_then(|method| method.bind_this(ExprLiteral::InstanceLiteral(instance.clone())))
+            .map(ExprLiteral::FunctionLiteral)
+    }
+
+    pub fn set(&mut self, name: &str, value: ExprLiteral) {
+        self.fields.insert(name.to_string(), value);
+    }
+
+    pub fn two_string(&self) -> String {
+        format!("<{} instance>", self.class.name)
+    }
+}
+
+// Fields may hold the instance itself, so Debug must not walk into them.
+impl Debug for MyInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
@@ -0,0 +1,871 @@
+use std::rc::Rc;
+
+use super::{
+    callable::Arity,
+    chunk::{Chunk, FunctionProto, OpCode, Position},
+    diagnostic::{Diagnostic, Origin, E_COMPILE_LIMIT, E_INTERNAL},
+    expr::{Expr, ExprLiteral},
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool, // closed over by an inner function, so it must become an upvalue when popped.
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool, // a local of the enclosing function, or one of its upvalues.
+}
+
+// A loop being compiled, break and continue jumps are patched once its end is known.
+struct LoopState {
+    scope_depth: usize, // locals deeper than this are popped before jumping out.
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// The function being compiled, one per nesting level.
+struct FunctionState {
+    proto: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<LoopState>, // innermost last.
+}
+
+impl FunctionState {
+    fn new(name: String, kind: FunctionKind) -> Self {
+        // Slot 0 holds the callee, methods see it as "this".
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        Self {
+            proto: FunctionProto {
+                name,
+                arity: Arity::exact(0),
+                params: 0,
+                signature: String::new(),
+                upvalue_count: 0,
+                chunk: Chunk::new(),
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+// Single pass from Vec<Stmt> to bytecode for the Vm. Locals live in stack slots found at compile
+// time, captured locals become upvalues, everything at top level is a global looked up by name.
+// The program must have passed the Resolver, its static errors are not checked again here.
+pub struct Compiler {
+    states: Vec<FunctionState>, // innermost last.
+    position: Position,         // of the node being compiled, recorded in the line table.
+    origin: Option<Rc<Origin>>, // the file being compiled, kept by every chunk.
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            states: vec![FunctionState::new(
+                "script".to_string(),
+                FunctionKind::Script,
+            )],
+            position: Position::default(),
+            origin: None,
+        }
+    }
+
+    // brief: Name the file being compiled, so that the Vm points errors inside its functions
+    // into it, e.g. for a module.
+    // input:
+    // output:
+    pub fn with_origin(mut self, origin: Rc<Origin>) -> Self {
+        self.state_mut().proto.chunk.origin = Some(origin.clone());
+        self.origin = Some(origin);
+        self
+    }
+
+    // brief: Pub function to compile a whole program.
+    // input:
+    // output: the top level function, to be handed to Vm::run.
+    pub fn compile(mut self, statements: &Vec<Stmt>) -> Result<FunctionProto, Diagnostic> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.emit_return();
+        Ok(self.states.pop().unwrap().proto)
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), Diagnostic> {
+        match statement {
+            Stmt::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop);
+            }
+            Stmt::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print);
+            }
+            Stmt::Let {
+                name, initializer, ..
+            } => {
+                self.expression(initializer)?;
+                self.position = Position::of(name);
+                self.define_variable(name)?;
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+            } => {
+                self.position = Position::of(keyword);
+                let index = self.make_constant(ExprLiteral::StringLiteral(path.clone()))?;
+                self.emit(OpCode::Import);
+                self.emit_u16(index);
+                self.position = Position::of(name);
+                self.define_variable(name)?;
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.statement(then_branch)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(condition)?;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+
+                let scope_depth = self.state().scope_depth;
+                self.state_mut().loops.push(LoopState {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body)?;
+                let state = self.state_mut().loops.pop().unwrap();
+
+                for jump in state.continues {
+                    self.patch_jump(jump)?;
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_loop(loop_start)?;
+
+                self.patch_jump(exit_jump)?;
+                self.emit(OpCode::Pop);
+                for jump in state.breaks {
+                    self.patch_jump(jump)?;
+                }
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                self.position = Position::of(keyword);
+                let Some(scope_depth) = self.state().loops.last().map(|v| v.scope_depth) else {
+                    return Err(self.error("Can't jump outside of a loop."));
+                };
+                self.pop_locals(scope_depth);
+                let jump = self.emit_jump(OpCode::Jump);
+                let state = self.state_mut().loops.last_mut().unwrap();
+                match statement {
+                    Stmt::Break { .. } => state.breaks.push(jump),
+                    _ => state.continues.push(jump),
+                }
+            }
+            Stmt::Function { name, .. } => {
+                self.position = Position::of(name);
+                if self.state().scope_depth > 0 {
+                    // Declared before the body, so that the function can call itself.
+                    self.add_local(name)?;
+                    self.function(statement, FunctionKind::Function)?;
+                } else {
+                    self.function(statement, FunctionKind::Function)?;
+                    self.define_variable(name)?;
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                self.position = Position::of(keyword);
+                match value {
+                    Some(value) => {
+                        self.expression(value)?;
+                        self.emit(OpCode::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass, methods)?,
+        }
+        Ok(())
+    }
+
+    // brief: The methods are compiled to closures on the stack, then OpCode::Class collects them.
+    // A subclass gets an extra scope holding "super", captured by the methods as an upvalue.
+    // input:
+    // output:
+    fn class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &Vec<Stmt>,
+    ) -> Result<(), Diagnostic> {
+        self.position = Position::of(name);
+        let name_constant = self.identifier_constant(&name.lexeme)?;
+
+        // Nil until the class is built, the same as the Interpreter does.
+        self.emit(OpCode::Nil);
+        self.define_variable(name)?;
+
+        if let Some(superclass) = superclass {
+            self.expression(superclass)?;
+            self.begin_scope();
+            self.add_local(&Token::new(
+                TokenType::Super,
+                "super".to_string(),
+                None,
+                name.line_number,
+            ))?;
+        }
+
+        let mut method_names = vec![];
+        for method in methods {
+            if let Stmt::Function {
+                name: method_name, ..
+            } = method
+            {
+                let kind = if method_name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.position = Position::of(method_name);
+                self.function(method, kind)?;
+                method_names.push(self.identifier_constant(&method_name.lexeme)?);
+            }
+        }
+        if method_names.len() > u8::MAX as usize {
+            return Err(self.error("Too many methods in one class."));
+        }
+
+        self.position = Position::of(name);
+        self.emit(OpCode::Class);
+        self.emit_u16(name_constant);
+        self.emit_byte(superclass.is_some() as u8);
+        self.emit_byte(method_names.len() as u8);
+        for method_name in method_names {
+            self.emit_u16(method_name);
+        }
+
+        self.set_variable(name)?;
+        self.emit(OpCode::Pop);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    // brief: Compile a function body in its own FunctionState, then emit the closure.
+    // Params and body share one scope, the same as MyFunction::call.
+    // input:
+    // output:
+    fn function(&mut self, declaration: &Stmt, kind: FunctionKind) -> Result<(), Diagnostic> {
+        let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            ..
+        } = declaration
+        else {
+            return Err(Diagnostic::error(
+                E_INTERNAL,
+                "Expected a function declaration.",
+            ));
+        };
+        // Parser and AstFormat only build functions with a Block body.
+        let Stmt::Block { statements } = body.as_ref() else {
+            return Err(Diagnostic::error(
+                E_INTERNAL,
+                format!("The body of {} is not a block.", name.lexeme),
+            )
+            .at_token(name));
+        };
+
+        self.states
+            .push(FunctionState::new(name.lexeme.clone(), kind));
+        self.state_mut().proto.chunk.origin = self.origin.clone();
+        self.state_mut().proto.arity = declaration.arity();
+        self.state_mut().proto.params = params.len();
+        self.state_mut().proto.signature = declaration.signature();
+        self.begin_scope();
+        // The Vm fills params that were not passed with nil, the prologue replaces them with
+        // their defaults. A default only sees the params before it, as in the Resolver.
+        let first_default = params.len() - defaults.len();
+        for (index, param) in params.iter().enumerate() {
+            if let Some(default) = index.checked_sub(first_default).map(|i| &defaults[i]) {
+                self.position = Position::of(param);
+                self.emit(OpCode::JumpIfPassed);
+                self.emit_byte(index as u8);
+                let jump = self.chunk().code.len();
+                self.emit_u16(u16::MAX);
+                self.expression(default)?;
+                self.emit(OpCode::SetLocal);
+                self.emit_byte(index as u8 + 1);
+                self.emit(OpCode::Pop);
+                self.patch_jump(jump)?;
+            }
+            self.add_local(param)?;
+        }
+        if let Some(rest) = rest {
+            self.add_local(rest)?;
+        }
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.emit_return();
+
+        let state = self.states.pop().unwrap();
+        let mut proto = state.proto;
+        proto.upvalue_count = state.upvalues.len();
+
+        self.position = Position::of(name);
+        let index = self.chunk().add_function(proto);
+        if index > u16::MAX as usize {
+            return Err(self.error("Too many functions in one chunk."));
+        }
+        self.emit(OpCode::Closure);
+        self.emit_u16(index as u16);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Literal { value } => match value {
+                ExprLiteral::Nil => self.emit(OpCode::Nil),
+                ExprLiteral::True => self.emit(OpCode::True),
+                ExprLiteral::False => self.emit(OpCode::False),
+                _ => {
+                    let index = self.make_constant(value.clone())?;
+                    self.emit(OpCode::Constant);
+                    self.emit_u16(index);
+                }
+            },
+            Expr::Grouping { expression } => self.expression(expression)?,
+            Expr::Unary { operator, right } => {
+                self.expression(right)?;
+                self.position = Position::of(operator);
+                match operator.token_type {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.position = Position::of(operator);
+                let op = match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Percent => OpCode::Modulo,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    _ => OpCode::NotEqual,
+                };
+                self.emit(op);
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.position = Position::of(operator);
+                if operator.token_type == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump)?;
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                }
+            }
+            Expr::Variable { name, .. } => self.get_variable(name)?,
+            Expr::Assign { name, value, .. } => {
+                self.expression(value)?;
+                self.set_variable(name)?;
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee)?;
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                self.position = Position::of(paren);
+                self.emit(OpCode::Call);
+                self.emit_byte(arguments.len() as u8); // the Parser stops at 255.
+            }
+            Expr::Get { object, name } => {
+                self.expression(object)?;
+                self.position = Position::of(name);
+                let index = self.identifier_constant(&name.lexeme)?;
+                self.emit(OpCode::GetProperty);
+                self.emit_u16(index);
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                self.position = Position::of(name);
+                let index = self.identifier_constant(&name.lexeme)?;
+                self.emit(OpCode::SetProperty);
+                self.emit_u16(index);
+            }
+            Expr::List { bracket, elements } => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.position = Position::of(bracket);
+                if elements.len() > u16::MAX as usize {
+                    return Err(self.error("Too many items in a list literal."));
+                }
+                self.emit(OpCode::BuildList);
+                self.emit_u16(elements.len() as u16);
+            }
+            Expr::Map { brace, entries } => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.position = Position::of(brace);
+                if entries.len() > u16::MAX as usize {
+                    return Err(self.error("Too many entries in a map literal."));
+                }
+                self.emit(OpCode::BuildMap);
+                self.emit_u16(entries.len() as u16);
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.position = Position::of(bracket);
+                self.emit(OpCode::GetIndex);
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.position = Position::of(bracket);
+                self.emit(OpCode::SetIndex);
+            }
+            Expr::This { keyword, .. } => self.get_variable(keyword)?,
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                let this = Token::new(
+                    TokenType::This,
+                    "this".to_string(),
+                    None,
+                    keyword.line_number,
+                );
+                self.get_variable(&this)?;
+                self.get_variable(keyword)?;
+                self.position = Position::of(method);
+                let index = self.identifier_constant(&method.lexeme)?;
+                self.emit(OpCode::GetSuper);
+                self.emit_u16(index);
+            }
+        }
+        Ok(())
+    }
+
+    // brief: Bind the value on top of the stack to a new variable.
+    // input:
+    // output:
+    fn define_variable(&mut self, name: &Token) -> Result<(), Diagnostic> {
+        if self.state().scope_depth > 0 {
+            return self.add_local(name); // The value already sits in its slot.
+        }
+        let index = self.identifier_constant(&name.lexeme)?;
+        self.emit(OpCode::DefineGlobal);
+        self.emit_u16(index);
+        Ok(())
+    }
+
+    fn get_variable(&mut self, name: &Token) -> Result<(), Diagnostic> {
+        self.position = Position::of(name);
+        let top = self.states.len() - 1;
+        if let Some(slot) = self.resolve_local(top, &name.lexeme) {
+            self.emit(OpCode::GetLocal);
+            self.emit_byte(slot);
+        } else if let Some(index) = self.resolve_upvalue(top, &name.lexeme)? {
+            self.emit(OpCode::GetUpvalue);
+            self.emit_byte(index);
+        } else {
+            let index = self.identifier_constant(&name.lexeme)?;
+            self.emit(OpCode::GetGlobal);
+            self.emit_u16(index);
+        }
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &Token) -> Result<(), Diagnostic> {
+        self.position = Position::of(name);
+        let top = self.states.len() - 1;
+        if let Some(slot) = self.resolve_local(top, &name.lexeme) {
+            self.emit(OpCode::SetLocal);
+            self.emit_byte(slot);
+        } else if let Some(index) = self.resolve_upvalue(top, &name.lexeme)? {
+            self.emit(OpCode::SetUpvalue);
+            self.emit_byte(index);
+        } else {
+            let index = self.identifier_constant(&name.lexeme)?;
+            self.emit(OpCode::SetGlobal);
+            self.emit_u16(index);
+        }
+        Ok(())
+    }
+
+    // brief: Slot of the innermost local called `name` in the function at `state`.
+    // input:
+    // output:
+    fn resolve_local(&self, state: usize, name: &str) -> Option<u8> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    // brief: Find `name` in the enclosing functions, adding an upvalue to every function on the way.
+    // input:
+    // output: None if it's a global.
+    fn resolve_upvalue(&mut self, state: usize, name: &str) -> Result<Option<u8>, Diagnostic> {
+        if state == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(state, slot, true).map(Some);
+        }
+        match self.resolve_upvalue(state - 1, name)? {
+            Some(index) => self.add_upvalue(state, index, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool) -> Result<u8, Diagnostic> {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.states[state].upvalues;
+        if let Some(existing) = upvalues.iter().position(|v| *v == upvalue) {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() > u8::MAX as usize {
+            return Err(self.error("Too many closure variables in function."));
+        }
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn add_local(&mut self, name: &Token) -> Result<(), Diagnostic> {
+        if self.state().locals.len() > u8::MAX as usize {
+            return Err(self.error("Too many local variables in function."));
+        }
+        let depth = self.state().scope_depth;
+        self.state_mut().locals.push(Local {
+            name: name.lexeme.clone(),
+            depth,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    // brief: Pop the locals of the scope, captured ones are moved into their upvalue.
+    // input:
+    // output:
+    fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+        let depth = self.state().scope_depth;
+        while let Some(local) = self.state().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            if local.is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+            self.state_mut().locals.pop();
+        }
+    }
+
+    // brief: Pop the locals deeper than `depth` without forgetting them, for a jump out of
+    // their scopes.
+    // input:
+    // output:
+    fn pop_locals(&mut self, depth: usize) {
+        let captured: Vec<bool> = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| local.is_captured)
+            .collect();
+        for is_captured in captured {
+            if is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> Result<u16, Diagnostic> {
+        self.make_constant(ExprLiteral::StringLiteral(name.to_string()))
+    }
+
+    fn make_constant(&mut self, value: ExprLiteral) -> Result<u16, Diagnostic> {
+        let index = self.chunk().add_constant(value);
+        if index > u16::MAX as usize {
+            return Err(self.error("Too many constants in one chunk."));
+        }
+        Ok(index as u16)
+    }
+
+    // brief: Implicit return: an initializer returns "this", everything else nil.
+    // input:
+    // output:
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    // brief: Emit a jump with a placeholder offset.
+    // input:
+    // output: where the offset is, for patch_jump.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit(op);
+        self.emit_u16(u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<(), Diagnostic> {
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(self.error("Too much code to jump over."));
+        }
+        let bytes = (jump as u16).to_be_bytes();
+        self.chunk().code[offset] = bytes[0];
+        self.chunk().code[offset + 1] = bytes[1];
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), Diagnostic> {
+        self.emit(OpCode::Loop);
+        let jump = self.chunk().code.len() - loop_start + 2;
+        if jump > u16::MAX as usize {
+            return Err(self.error("Loop body too large."));
+        }
+        self.emit_u16(jump as u16);
+        Ok(())
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let position = self.position;
+        self.chunk().write(byte, position);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let position = self.position;
+        self.chunk().write_u16(value, position);
+    }
+
+    fn error(&self, message: &str) -> Diagnostic {
+        Diagnostic::error(E_COMPILE_LIMIT, message).at(
+            self.position.span,
+            self.position.line,
+            self.position.column,
+        )
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().proto.chunk
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, scanner::Scanner};
+
+    fn compile(source: &str) -> FunctionProto {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Compiler::new().compile(&statements).unwrap()
+    }
+
+    fn ops(chunk: &Chunk) -> Vec<String> {
+        chunk
+            .disassemble("test")
+            .lines()
+            .skip(1)
+            .map(|line| line[10..].split_whitespace().next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn globals_and_locals() {
+        let script = compile("let a = 1; { let b = a; print b; }");
+        assert_eq!(
+            ops(&script.chunk),
+            vec![
+                "Constant",
+                "DefineGlobal",
+                "GetGlobal",
+                "GetLocal",
+                "Print",
+                "Pop",
+                "Nil",
+                "Return"
+            ]
+        );
+    }
+
+    #[test]
+    fn closures_capture_upvalues() {
+        let script = compile("fn outer() { let x = 1; fn inner() { return x; } return inner; }");
+        let outer = &script.chunk.functions[0];
+        let inner = &outer.chunk.functions[0];
+        assert_eq!(outer.upvalue_count, 0);
+        assert_eq!(inner.upvalue_count, 1);
+        assert!(ops(&inner.chunk).contains(&"GetUpvalue".to_string()));
+    }
+
+    #[test]
+    fn a_function_body_that_is_not_a_block_is_an_internal_error() {
+        let tokens = Scanner::new("fn f() { print 1; }".to_string())
+            .scan_tokens()
+            .unwrap();
+        let mut statements = Parser::new(tokens).parse().unwrap();
+        let Stmt::Function { body, .. } = &mut statements[0] else {
+            panic!("expected a function");
+        };
+        let Stmt::Block { statements: inner } = body.as_mut() else {
+            panic!("expected a block");
+        };
+        **body = inner.remove(0);
+        let err = Compiler::new().compile(&statements).unwrap_err();
+        assert_eq!(err.code, E_INTERNAL);
+        assert_eq!(err.message, "The body of f is not a block.");
+    }
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::{callable::Callable, expr::ExprLiteral, stdlib::expected};
+
+// Rust value => script value, for globals and arguments passed in by a host.
+pub trait IntoLiteral {
+    fn into_literal(self) -> ExprLiteral;
+}
+
+// Script value => Rust value, for results and native arguments read by a host.
+// The error reads like the ones of the natives, e.g. "expects an int, got \"a\"".
+pub trait FromLiteral: Sized {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String>;
+}
+
+impl ExprLiteral {
+    // brief: Convert to a Rust value, e.g. `value.to::<i64>()`.
+    // input:
+    // output:
+    pub fn to<T: FromLiteral>(&self) -> Result<T, String> {
+        T::from_literal(self)
+    }
+}
+
+impl IntoLiteral for ExprLiteral {
+    fn into_literal(self) -> ExprLiteral {
+        self
+    }
+}
+
+impl IntoLiteral for () {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::Nil
+    }
+}
+
+impl IntoLiteral for bool {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::from_bool(self)
+    }
+}
+
+impl IntoLiteral for i64 {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::IntLiteral(self)
+    }
+}
+
+impl IntoLiteral for i32 {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::IntLiteral(self as i64)
+    }
+}
+
+impl IntoLiteral for f64 {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::NumberLiteral(self)
+    }
+}
+
+impl IntoLiteral for String {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::StringLiteral(self)
+    }
+}
+
+impl IntoLiteral for &str {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::StringLiteral(self.to_string())
+    }
+}
+
+impl IntoLiteral for Rc<dyn Callable> {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::FunctionLiteral(self)
+    }
+}
+
+impl<T: IntoLiteral> IntoLiteral for Option<T> {
+    fn into_literal(self) -> ExprLiteral {
+        self.map_or(ExprLiteral::Nil, T::into_literal)
+    }
+}
+
+impl<T: IntoLiteral> IntoLiteral for Vec<T> {
+    fn into_literal(self) -> ExprLiteral {
+        ExprLiteral::list(self.into_iter().map(T::into_literal).collect())
+    }
+}
+
+// Entries are sorted by key, so that the map prints the same on every run.
+impl<T: IntoLiteral> IntoLiteral for HashMap<String, T> {
+    fn into_literal(self) -> ExprLiteral {
+        let mut entries: Vec<(String, T)> = self.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let entries = entries
+            .into_iter()
+            .map(|(k, v)| (ExprLiteral::StringLiteral(k), v.into_literal()))
+            .collect();
+        ExprLiteral::map(entries).expect("string keys can always be hashed")
+    }
+}
+
+impl FromLiteral for ExprLiteral {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromLiteral for () {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::Nil => Ok(()),
+            other => Err(expected("nil", other)),
+        }
+    }
+}
+
+impl FromLiteral for bool {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::True => Ok(true),
+            ExprLiteral::False => Ok(false),
+            other => Err(expected("a bool", other)),
+        }
+    }
+}
+
+// A float converts only if it has no fractional part, the same rule as map keys.
+impl FromLiteral for i64 {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::IntLiteral(v) => Ok(*v),
+            ExprLiteral::NumberLiteral(v)
+                if v.fract() == 0.0 && *v >= i64::MIN as f64 && *v < i64::MAX as f64 =>
+            {
+                Ok(*v as i64)
+            }
+            other => Err(expected("an int", other)),
+        }
+    }
+}
+
+impl FromLiteral for f64 {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::IntLiteral(v) => Ok(*v as f64),
+            ExprLiteral::NumberLiteral(v) => Ok(*v),
+            other => Err(expected("a number", other)),
+        }
+    }
+}
+
+impl FromLiteral for String {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::StringLiteral(s) => Ok(s.clone()),
+            other => Err(expected("a string", other)),
+        }
+    }
+}
+
+impl FromLiteral for Rc<dyn Callable> {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::FunctionLiteral(f) => Ok(f.clone()),
+            ExprLiteral::ClassLiteral(c) => Ok(c.clone()),
+            other => Err(expected("a function", other)),
+        }
+    }
+}
+
+impl<T: FromLiteral> FromLiteral for Option<T> {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::Nil => Ok(None),
+            other => T::from_literal(other).map(Some),
+        }
+    }
+}
+
+impl<T: FromLiteral> FromLiteral for Vec<T> {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::ListLiteral(list) => list.borrow().iter().map(T::from_literal).collect(),
+            other => Err(expected("a list", other)),
+        }
+    }
+}
+
+impl<T: FromLiteral> FromLiteral for HashMap<String, T> {
+    fn from_literal(value: &ExprLiteral) -> Result<Self, String> {
+        match value {
+            ExprLiteral::MapLiteral(map) => map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(k, v)| Ok((String::from_literal(k)?, T::from_literal(v)?)))
+                .collect(),
+            other => Err(expected("a map", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        assert_eq!(i64::from_literal(&7.into_literal()), Ok(7));
+        assert_eq!(f64::from_literal(&7.into_literal()), Ok(7.0));
+        assert_eq!(i64::from_literal(&2.0.into_literal()), Ok(2));
+        assert!(i64::from_literal(&2.5.into_literal()).is_err());
+        assert_eq!("a".into_literal().to::<String>(), Ok("a".to_string()));
+        assert_eq!(None::<i64>.into_literal(), ExprLiteral::Nil);
+
+        let list = vec![Some(1), None].into_literal();
+        assert_eq!(list.two_string(), "[1, Nil]");
+        assert_eq!(list.to::<Vec<Option<i64>>>(), Ok(vec![Some(1), None]));
+
+        let map = HashMap::from([("b".to_string(), true), ("a".to_string(), false)]);
+        let value = map.clone().into_literal();
+        assert_eq!(value.two_string(), "{\"a\": False, \"b\": True}");
+        assert_eq!(value.to::<HashMap<String, bool>>(), Ok(map));
+
+        assert_eq!(
+            ExprLiteral::True.to::<String>(),
+            Err("expects a string, got True".to_string())
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use std::{fmt::Display, rc::Rc};
+
+use super::token::Token;
+
+// Byte range [start, end) in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// Error codes, grouped by the stage reporting them.
+// E00xx Scanner.
+pub const E_UNEXPECTED_CHAR: u16 = 1;
+pub const E_UNTERMINATED_STRING: u16 = 2;
+pub const E_BAD_NUMBER: u16 = 3;
+pub const E_BAD_ESCAPE: u16 = 4;
+// E01xx Parser.
+pub const E_EXPECT_TOKEN: u16 = 101;
+pub const E_EXPECT_EXPRESSION: u16 = 102;
+pub const E_INVALID_ASSIGN: u16 = 103;
+pub const E_TOO_MANY_ARGS: u16 = 104;
+pub const E_BAD_PARAMS: u16 = 105;
+// E02xx Resolver.
+pub const E_OWN_INITIALIZER: u16 = 201;
+pub const E_ALREADY_DECLARED: u16 = 202;
+pub const E_BAD_RETURN: u16 = 203;
+pub const E_BAD_THIS: u16 = 204;
+pub const E_BAD_SUPER: u16 = 205;
+pub const E_SELF_INHERIT: u16 = 206;
+pub const E_BAD_JUMP: u16 = 207;
+// E03xx Interpreter.
+pub const E_INTERNAL: u16 = 300;
+pub const E_UNDEFINED_VARIABLE: u16 = 301;
+pub const E_BAD_OPERAND: u16 = 302;
+pub const E_NOT_CALLABLE: u16 = 303;
+pub const E_ARITY: u16 = 304;
+pub const E_PROPERTY: u16 = 305;
+pub const E_BAD_SUPERCLASS: u16 = 306;
+pub const E_NATIVE: u16 = 307;
+pub const E_STACK_OVERFLOW: u16 = 308;
+pub const E_INDEX: u16 = 309;
+pub const E_OUTPUT: u16 = 310;
+pub const E_IMPORT: u16 = 311;
+pub const E_IMPORT_CYCLE: u16 = 312;
+// E04xx Compiler and C backend.
+pub const E_COMPILE_LIMIT: u16 = 401;
+pub const E_UNSUPPORTED: u16 = 402;
+// E05xx Syntax trees read from JSON or s-expressions.
+pub const E_BAD_AST: u16 = 501;
+// E06xx Type checker.
+pub const E_UNKNOWN_TYPE: u16 = 601;
+pub const E_TYPE_MISMATCH: u16 = 602;
+pub const E_TYPE_OPERAND: u16 = 603;
+pub const E_TYPE_ARITY: u16 = 604;
+pub const E_TYPE_CALL: u16 = 605;
+pub const E_TYPE_PROPERTY: u16 = 606;
+pub const E_TYPE_INDEX: u16 = 607;
+pub const E_MISSING_RETURN: u16 = 608;
+
+// One call on the way to a runtime error: what was called and the line of the call,
+// 0 for a call made by the host through Engine::call_function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            0 => write!(f, "{} called by the host", self.function),
+            line => write!(f, "{} called at line {}", self.function, line),
+        }
+    }
+}
+
+// Frames of a long trace after which render stops, runaway recursion would print thousands.
+const TRACE_LIMIT: usize = 10;
+
+// The file of a diagnostic. Functions and chunks keep the one they were compiled from, with
+// its source, so that a runtime error raised inside an imported function points into the
+// module rather than the main script.
+#[derive(Debug, PartialEq)]
+pub struct Origin {
+    pub file: Rc<str>,
+    pub source: Option<Rc<str>>, // None when it is what render is given.
+}
+
+impl Origin {
+    pub fn new(file: &str, source: &str) -> Rc<Self> {
+        Rc::new(Self {
+            file: file.into(),
+            source: Some(source.into()),
+        })
+    }
+}
+
+// One message of the Scanner, Parser, Resolver or Interpreter, with enough position
+// information to point at the offending source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: u16, // rendered as E0001.
+    pub message: String,
+    pub origin: Option<Rc<Origin>>,
+    pub span: Option<Span>,
+    pub line: usize,   // 1 based, 0 if unknown.
+    pub column: usize, // 1 based, 0 if unknown.
+    pub notes: Vec<String>,
+    // Calls active when a runtime error happened, innermost first. The Rcs keep the Err of
+    // every Result small.
+    pub trace: Rc<Vec<TraceFrame>>,
+}
+
+impl Diagnostic {
+    pub fn error(code: u16, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            origin: None,
+            span: None,
+            line: 0,
+            column: 0,
+            notes: vec![],
+            trace: Rc::default(),
+        }
+    }
+
+    // brief: Point the diagnostic at a token.
+    // input:
+    // output:
+    pub fn at_token(self, token: &Token) -> Self {
+        self.at(token.span, token.line_number, token.column)
+    }
+
+    pub fn at(mut self, span: Span, line: usize, column: usize) -> Self {
+        self.span = Some(span);
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    // brief: Point at the token only if nothing more precise is known, e.g. for native errors.
+    // input:
+    // output:
+    pub fn or_at_token(self, token: &Token) -> Self {
+        if self.span.is_some() {
+            return self;
+        }
+        self.at_token(token)
+    }
+
+    // brief: Place a positioned error in the file it happened in, unless a deeper call
+    // already did.
+    // input:
+    // output:
+    pub fn or_in(mut self, origin: &Rc<Origin>) -> Self {
+        if self.span.is_some() && !self.placed() {
+            self.origin = Some(origin.clone());
+        }
+        self
+    }
+
+    fn placed(&self) -> bool {
+        self.origin
+            .as_ref()
+            .is_some_and(|origin| origin.source.is_some())
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // brief: Record the call stack, unless a deeper call already did.
+    // input: innermost call first.
+    // output:
+    pub fn with_trace(mut self, trace: Vec<TraceFrame>) -> Self {
+        if self.trace.is_empty() {
+            self.trace = Rc::new(trace);
+        }
+        self
+    }
+
+    // brief: Name the file of the source render will be given, unless the error happened in
+    // another file, e.g. inside a function of a module.
+    // input:
+    // output:
+    pub fn with_file(mut self, file: &str) -> Self {
+        if self.placed() {
+            return self;
+        }
+        self.origin = Some(Rc::new(Origin {
+            file: file.into(),
+            source: None,
+        }));
+        self
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        self.origin.as_ref().map(|origin| &*origin.file)
+    }
+
+    // brief: Render as a header, the offending source line and carets under the span.
+    // input: the source the span points into, unless the diagnostic has its own.
+    // output:
+    //     error[E0301]: Undefined variable x.
+    //      --> test.noah:3:7
+    //        |
+    //      3 | print x;
+    //        |       ^
+    //        = note: ...
+    pub fn render(&self, source: &str) -> String {
+        let source = self
+            .origin
+            .as_ref()
+            .and_then(|origin| origin.source.as_deref())
+            .unwrap_or(source);
+        let mut out = format!("{}[E{:04}]: {}\n", self.severity, self.code, self.message);
+
+        let gutter = " ".repeat(self.line.to_string().len());
+        if self.line > 0 {
+            out.push_str(&format!(
+                "{}--> {}:{}:{}\n",
+                gutter,
+                self.file().unwrap_or("<input>"),
+                self.line,
+                self.column
+            ));
+        }
+
+        if let (Some(span), Some(text)) = (self.span, source.lines().nth(self.line.wrapping_sub(1)))
+        {
+            // Underline from the column to the end of the span, clipped to this line.
+            let start = self.column.max(1) - 1;
+            let width = source
+                .get(span.start..span.end)
+                .map_or(1, |lexeme| lexeme.chars().count())
+                .max(1);
+            let width = width.min(text.chars().count().saturating_sub(start).max(1));
+            // Tabs are kept so that the caret lines up with the echoed line.
+            let padding: String = text
+                .chars()
+                .take(start)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", self.line, text));
+            out.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(width)));
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        if !self.trace.is_empty() {
+            out.push_str("stack trace, innermost call first:\n");
+            for frame in self.trace.iter().take(TRACE_LIMIT) {
+                out.push_str(&format!("    {}\n", frame));
+            }
+            if self.trace.len() > TRACE_LIMIT {
+                out.push_str(&format!(
+                    "    ... {} more calls\n",
+                    self.trace.len() - TRACE_LIMIT
+                ));
+            }
+        }
+        out
+    }
+}
+
+// One line form for places without the source at hand.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(
+                f,
+                "{}:{}:{}: ",
+                self.file().unwrap_or("<input>"),
+                self.line,
+                self.column
+            )?;
+        }
+        write!(f, "{}[E{:04}]: {}", self.severity, self.code, self.message)?;
+        for note in &self.notes {
+            write!(f, " (note: {})", note)?;
+        }
+        if let Some(frame) = self.trace.first() {
+            write!(f, " (in {})", frame)?;
+        }
+        Ok(())
+    }
+}
+
+// So that a host embedding the Engine can use "?" on any Diagnostic.
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_points_at_the_span() {
+        let source = "let a = 1;\nprint abc;\n";
+        let diagnostic = Diagnostic::error(E_UNDEFINED_VARIABLE, "Undefined variable abc.")
+            .at(Span::new(17, 20), 2, 7)
+            .with_file("test.noah")
+            .with_note("declare it with let first");
+
+        assert_eq!(
+            diagnostic.render(source),
+            "error[E0301]: Undefined variable abc.
+ --> test.noah:2:7
+  |
+2 | print abc;
+  |       ^^^
+  = note: declare it with let first
+"
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "test.noah:2:7: error[E0301]: Undefined variable abc. (note: declare it with let first)"
+        );
+    }
+
+    #[test]
+    fn errors_in_another_file_render_against_it() {
+        let module = Origin::new("lib.noah", "fn boom(x) {\n  return x + 1;\n}\n");
+        let diagnostic = Diagnostic::error(E_BAD_OPERAND, "Bad operands for +.")
+            .at(Span::new(24, 25), 2, 12)
+            .or_in(&module)
+            .or_in(&Origin::new("main.noah", "lib.boom(\"s\");\n"))
+            .with_file("main.noah");
+        assert_eq!(diagnostic.file(), Some("lib.noah"));
+        assert!(diagnostic
+            .render("lib.boom(\"s\");\n")
+            .contains(" --> lib.noah:2:12\n  |\n2 |   return x + 1;\n  |            ^\n"));
+    }
+
+    #[test]
+    fn render_counts_chars_not_bytes() {
+        let source = "print \"名字\" + 名字;";
+        let diagnostic = Diagnostic::error(E_UNDEFINED_VARIABLE, "Undefined variable 名字.").at(
+            Span::new(17, 23),
+            1,
+            14,
+        );
+        assert!(diagnostic
+            .render(source)
+            .ends_with("1 | print \"名字\" + 名字;\n  |              ^^\n"));
+    }
+
+    #[test]
+    fn render_keeps_the_tabs_of_the_line() {
+        let source = "fn f() {\n\t\tprint abc;\n}\n";
+        let diagnostic = Diagnostic::error(E_UNDEFINED_VARIABLE, "Undefined variable abc.").at(
+            Span::new(17, 20),
+            2,
+            9,
+        );
+        assert!(diagnostic
+            .render(source)
+            .ends_with("2 | \t\tprint abc;\n  | \t\t      ^^^\n"));
+    }
+
+    #[test]
+    fn render_without_position() {
+        let diagnostic = Diagnostic::error(E_NATIVE, "clock broke.");
+        assert_eq!(diagnostic.render(""), "error[E0307]: clock broke.\n");
+    }
+}
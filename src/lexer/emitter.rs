@@ -0,0 +1,797 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    diagnostic::{Diagnostic, E_UNSUPPORTED},
+    expr::{Expr, ExprLiteral},
+    stdlib::Registry,
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+// Tagged values, strings, closures, operators and natives, see the comment at its top.
+const RUNTIME: &str = include_str!("runtime.c");
+
+// The natives the runtime implements as nh_<name>_code, with their arity. The emitted program
+// defines the NhNative nh_native_<name> of those it uses.
+pub const NATIVES: [(&str, usize); 4] =
+    [("clock", 0), ("len", 1), ("to_string", 1), ("type_of", 1)];
+
+// Where a variable lives in the C function being emitted.
+#[derive(Clone, Copy)]
+enum Place {
+    Local(usize), // the C variable l<id>, a pointer to its box when an inner function captures it.
+    Cell(usize),  // self->cells[i], captured from an enclosing function.
+    Global(usize), // nh_globals[i].
+}
+
+// The function being lifted to C, one per nesting level, the top level becomes nh_script.
+#[derive(Default)]
+struct FunctionState {
+    scopes: Vec<Vec<(String, usize)>>, // names declared in each block with their local id.
+    cells: Vec<usize>,                 // local ids of the variables it captured, by cell.
+    loops: Vec<Option<usize>>, // innermost last, the label continue jumps to, for an increment.
+    jumped: HashSet<usize>,    // labels some continue jumps to.
+    code: String,
+    indent: usize,
+}
+
+// Lowers a program to one C file holding the runtime, the top level as nh_script and every
+// function lifted to a C function of its own. Expressions are evaluated into temporaries one
+// at a time, so that C runs them in the order the tree-walker does. Locals are C variables,
+// boxed when an inner function captures them: a first pass finds those.
+// Classes, lists, maps, imports and most natives are not supported, the program must have
+// passed the Resolver.
+pub struct CEmitter {
+    file: String, // the script, named by runtime errors.
+    states: Vec<FunctionState>,
+    protos: Vec<String>,    // declaration and NhProto of every lifted function.
+    functions: Vec<String>, // their definitions.
+    strings: HashMap<String, usize>,
+    globals: Vec<String>,           // by slot in nh_globals.
+    top_level: HashSet<String>,     // names the top level declares, they hide natives.
+    natives: HashSet<&'static str>, // of the tree-walker.
+    used: HashSet<&'static str>,    // of NATIVES, as values the Parser desugared to.
+    captured: HashSet<usize>,       // locals some inner function captures.
+    found: HashSet<usize>,          // captured, as found by this pass.
+    next_id: usize,                 // of locals, temporaries, labels and functions.
+}
+
+impl CEmitter {
+    pub fn new(file: &str) -> Self {
+        let natives = Registry::standard()
+            .entries()
+            .into_iter()
+            .chain(Registry::script_args(file, vec![]).entries())
+            .map(|(name, _)| name)
+            .collect();
+        Self {
+            file: file.to_string(),
+            states: vec![],
+            protos: vec![],
+            functions: vec![],
+            strings: HashMap::new(),
+            globals: vec![],
+            top_level: HashSet::new(),
+            natives,
+            used: HashSet::new(),
+            captured: HashSet::new(),
+            found: HashSet::new(),
+            next_id: 0,
+        }
+    }
+
+    // brief: Emit a whole program.
+    // input:
+    // output: C source for `cc -std=c11 ... -lm`, Err at the first construct not supported.
+    pub fn emit(self, statements: &[Stmt]) -> Result<String, Diagnostic> {
+        // Whether a local needs a box is known once the functions after it were emitted.
+        let mut first = Self::new(&self.file);
+        first.program(statements)?;
+        let mut second = self;
+        second.captured = first.found;
+        second.program(statements)
+    }
+
+    fn program(&mut self, statements: &[Stmt]) -> Result<String, Diagnostic> {
+        self.top_level = statements.iter().filter_map(declared_name).collect();
+        self.states.push(FunctionState {
+            indent: 1,
+            ..FunctionState::default()
+        });
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        let script = self.states.pop().unwrap();
+
+        let mut out = format!(
+            "/* Emitted by `noah build --emit c`. */\n#define NH_FILE {}\n\n{}\n",
+            c_string(&self.file),
+            RUNTIME
+        );
+        if !self.globals.is_empty() {
+            out.push_str("static NhGlobal nh_globals[] = {\n");
+            for name in &self.globals {
+                out.push_str(&format!(
+                    "    {{{}, false, {{NH_NIL, {{.boolean = false}}}}}},\n",
+                    c_string(name)
+                ));
+            }
+            out.push_str("};\n\n");
+        }
+        for (name, arity) in NATIVES {
+            if self.used.contains(name) || self.globals.iter().any(|global| global == name) {
+                out.push_str(&format!(
+                    "static const NhNative nh_native_{} = {{{}, {}, nh_{}_code}};\n",
+                    name,
+                    c_string(name),
+                    arity,
+                    name
+                ));
+            }
+        }
+        let mut strings: Vec<(&String, &usize)> = self.strings.iter().collect();
+        strings.sort_by_key(|(_, id)| **id);
+        for (text, id) in strings {
+            out.push_str(&format!(
+                "static const NhString nh_s{} = {{{}, {}}};\n",
+                id,
+                text.len(),
+                c_string(text)
+            ));
+        }
+        for proto in &self.protos {
+            out.push_str(proto);
+        }
+        for function in &self.functions {
+            out.push('\n');
+            out.push_str(function);
+        }
+        out.push_str("\nstatic void nh_script(void) {\n");
+        for (slot, name) in self.globals.iter().enumerate() {
+            if NATIVES.iter().any(|(native, _)| native == name) {
+                out.push_str(&format!(
+                    "    nh_define(&nh_globals[{}], nh_native(&nh_native_{}));\n",
+                    slot, name
+                ));
+            }
+        }
+        out.push_str(&script.code);
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), Diagnostic> {
+        match statement {
+            Stmt::Expression(expr) => {
+                let value = self.expression(expr)?;
+                if value.starts_with('t') {
+                    self.line(format!("(void){};", value));
+                }
+            }
+            Stmt::Print(expr) => {
+                let value = self.expression(expr)?;
+                self.line(format!("nh_print({});", value));
+            }
+            Stmt::Let {
+                name, initializer, ..
+            } => {
+                let value = self.expression(initializer)?;
+                match self.is_top_level() {
+                    true => {
+                        let slot = self.global(&name.lexeme);
+                        self.line(format!("nh_define(&nh_globals[{}], {});", slot, value));
+                    }
+                    false => {
+                        self.local(name, &value);
+                    }
+                }
+            }
+            Stmt::Block { .. } => {
+                self.open("{".to_string());
+                self.nested(statement)?;
+                self.close("}");
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.expression(condition)?;
+                self.open(format!("if (nh_truthy({})) {{", condition));
+                self.nested(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.reopen("} else {");
+                    self.nested(else_branch)?;
+                }
+                self.close("}");
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let label = increment.as_ref().map(|_| self.id());
+                self.open("for (;;) {".to_string());
+                let condition = self.expression(condition)?;
+                self.line(format!("if (!nh_truthy({})) break;", condition));
+                self.state().loops.push(label);
+                // Continue jumps over what the body declares, to the increment.
+                match (label, increment) {
+                    (Some(label), Some(increment)) => {
+                        self.open("{".to_string());
+                        self.nested(body)?;
+                        self.close("}");
+                        if self.state().jumped.contains(&label) {
+                            self.line(format!("nh_next_{}:;", label));
+                        }
+                        self.expression(increment)?;
+                    }
+                    _ => self.nested(body)?,
+                }
+                self.state().loops.pop();
+                self.close("}");
+            }
+            Stmt::Function { name, .. } => {
+                if self.is_top_level() {
+                    let closure = self.function(statement)?;
+                    let slot = self.global(&name.lexeme);
+                    self.line(format!("nh_define(&nh_globals[{}], {});", slot, closure));
+                } else {
+                    // Declared first, the function may call itself.
+                    let id = self.local(name, "nh_nil()");
+                    let closure = self.function(statement)?;
+                    let target = self.access(Place::Local(id));
+                    self.line(format!("{} = {};", target, closure));
+                }
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.expression(value)?,
+                    None => "nh_nil()".to_string(),
+                };
+                self.line(format!("return {};", value));
+            }
+            Stmt::Break { .. } => self.line("break;".to_string()),
+            Stmt::Continue { .. } => match self.state().loops.last() {
+                Some(Some(label)) => {
+                    let label = *label;
+                    self.state().jumped.insert(label);
+                    self.line(format!("goto nh_next_{};", label));
+                }
+                _ => self.line("continue;".to_string()),
+            },
+            Stmt::Class { name, .. } => return Err(unsupported("classes", name)),
+            Stmt::Import { keyword, .. } => return Err(unsupported("imports", keyword)),
+        }
+        Ok(())
+    }
+
+    // brief: Emit a statement inside braces already open, a block in a scope of its own.
+    // input:
+    // output:
+    fn nested(&mut self, statement: &Stmt) -> Result<(), Diagnostic> {
+        let Stmt::Block { statements } = statement else {
+            return self.statement(statement);
+        };
+        self.state().scopes.push(vec![]);
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.state().scopes.pop();
+        Ok(())
+    }
+
+    // brief: Lift a Stmt::Function to a C function.
+    // input:
+    // output: the C expression creating its closure, in the enclosing function.
+    fn function(&mut self, function: &Stmt) -> Result<String, Diagnostic> {
+        let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            ..
+        } = function
+        else {
+            return Ok("nh_nil()".to_string());
+        };
+        if let Some(rest) = rest {
+            return Err(unsupported("rest parameters", rest));
+        }
+        let index = self.id();
+        self.states.push(FunctionState {
+            scopes: vec![vec![]],
+            indent: 1,
+            ..FunctionState::default()
+        });
+        // Missing arguments take their defaults, which see the params before them.
+        let first_default = params.len() - defaults.len();
+        for (position, param) in params.iter().enumerate() {
+            let value = match position.checked_sub(first_default) {
+                None => format!("argv[{}]", position),
+                Some(default) => {
+                    let temp = format!("t{}", self.id());
+                    self.line(format!("NhValue {};", temp));
+                    self.open(format!("if (argc > {}) {{", position));
+                    self.line(format!("{} = argv[{}];", temp, position));
+                    self.reopen("} else {");
+                    let value = self.expression(&defaults[default])?;
+                    self.line(format!("{} = {};", temp, value));
+                    self.close("}");
+                    temp
+                }
+            };
+            self.local(param, &value);
+        }
+        match body.as_ref() {
+            Stmt::Block { statements } => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+            }
+            other => self.statement(other)?,
+        }
+        self.line("return nh_nil();".to_string());
+        let state = self.states.pop().unwrap();
+
+        let signature = format!(
+            "static NhValue nh_fn_{}(NhClosure *self, int argc, NhValue *argv)",
+            index
+        );
+        self.protos.push(format!(
+            "{};\nstatic const NhProto nh_proto_{} = {{{}, {}, {}, nh_fn_{}}};\n",
+            signature,
+            index,
+            c_string(&name.lexeme),
+            first_default,
+            params.len(),
+            index
+        ));
+        // Not every function uses its closure or its arguments.
+        self.functions.push(format!(
+            "{} {{\n    (void)self;\n    (void)argc;\n    (void)argv;\n{}}}\n",
+            signature, state.code
+        ));
+
+        let cells: Vec<String> = state.cells.iter().map(|id| self.pointer(*id)).collect();
+        Ok(match cells.len() {
+            0 => format!("nh_closure(&nh_proto_{}, 0, NULL)", index),
+            count => format!(
+                "nh_closure(&nh_proto_{}, {}, (NhValue *[]){{{}}})",
+                index,
+                count,
+                cells.join(", ")
+            ),
+        })
+    }
+
+    // brief: Emit an expression, what it needs first goes before it.
+    // input:
+    // output: a C expression of its value: a temporary, or a constant.
+    fn expression(&mut self, expr: &Expr) -> Result<String, Diagnostic> {
+        match expr {
+            Expr::Literal { value } => self.literal(value),
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Variable { name, .. } => {
+                let value = match self.resolve(name)? {
+                    Place::Global(slot) => {
+                        self.at(name);
+                        format!("nh_get(&nh_globals[{}])", slot)
+                    }
+                    place => self.access(place),
+                };
+                Ok(self.temp(value))
+            }
+            Expr::Assign { name, value, .. } => {
+                let value = self.expression(value)?;
+                match self.resolve(name)? {
+                    Place::Global(slot) => {
+                        self.at(name);
+                        self.line(format!("nh_set(&nh_globals[{}], {});", slot, value));
+                    }
+                    place => {
+                        let target = self.access(place);
+                        self.line(format!("{} = {};", target, value));
+                    }
+                }
+                Ok(value)
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                let function = match operator.token_type {
+                    TokenType::Plus => "nh_add",
+                    TokenType::Minus => "nh_subtract",
+                    TokenType::Star => "nh_multiply",
+                    TokenType::Slash => "nh_divide",
+                    TokenType::Percent => "nh_modulo",
+                    TokenType::Greater => "nh_greater",
+                    TokenType::GreaterEqual => "nh_greater_equal",
+                    TokenType::Less => "nh_less",
+                    TokenType::LessEqual => "nh_less_equal",
+                    TokenType::EqualEqual => "nh_equal",
+                    TokenType::BangEqual => "nh_not_equal",
+                    _ => {
+                        return Err(unsupported(
+                            &format!("the operator {}", operator.lexeme),
+                            operator,
+                        ))
+                    }
+                };
+                self.at(operator);
+                Ok(self.temp(format!("{}({}, {})", function, left, right)))
+            }
+            Expr::Unary { operator, right } => {
+                let right = self.expression(right)?;
+                let function = match operator.token_type {
+                    TokenType::Minus => "nh_negate",
+                    TokenType::Bang => "nh_not",
+                    _ => {
+                        return Err(unsupported(
+                            &format!("the operator {}", operator.lexeme),
+                            operator,
+                        ))
+                    }
+                };
+                self.at(operator);
+                Ok(self.temp(format!("{}({})", function, right)))
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                // "or" gives its left operand when it is truthy, "and" when it is not.
+                let left = self.expression(left)?;
+                let result = self.temp(left);
+                let test = match operator.token_type {
+                    TokenType::Or => "!",
+                    _ => "",
+                };
+                self.open(format!("if ({}nh_truthy({})) {{", test, result));
+                let right = self.expression(right)?;
+                self.line(format!("{} = {};", result, right));
+                self.close("}");
+                Ok(result)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let callee = self.expression(callee)?;
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(self.expression(argument)?);
+                }
+                self.at(paren);
+                let arguments = match values.len() {
+                    0 => "NULL".to_string(),
+                    _ => format!("(NhValue[]){{{}}}", values.join(", ")),
+                };
+                Ok(self.temp(format!(
+                    "nh_call({}, {}, {})",
+                    callee,
+                    values.len(),
+                    arguments
+                )))
+            }
+            Expr::Get { name, .. } | Expr::Set { name, .. } => Err(unsupported("properties", name)),
+            Expr::This { keyword, .. } | Expr::Super { keyword, .. } => {
+                Err(unsupported("classes", keyword))
+            }
+            Expr::List { bracket, .. } => Err(unsupported("lists", bracket)),
+            Expr::Index { bracket, .. } | Expr::SetIndex { bracket, .. } => {
+                Err(unsupported("indexing", bracket))
+            }
+            Expr::Map { brace, .. } => Err(unsupported("maps", brace)),
+        }
+    }
+
+    // brief: A literal as a C expression.
+    // input:
+    // output: Err for a native the runtime does not implement.
+    fn literal(&mut self, value: &ExprLiteral) -> Result<String, Diagnostic> {
+        Ok(match value {
+            ExprLiteral::IntLiteral(i64::MIN) => "nh_int(INT64_MIN)".to_string(),
+            ExprLiteral::IntLiteral(v) => format!("nh_int(INT64_C({}))", v),
+            ExprLiteral::NumberLiteral(v) if v.is_nan() => "nh_float(NAN)".to_string(),
+            ExprLiteral::NumberLiteral(v) if v.is_infinite() => {
+                format!("nh_float({}INFINITY)", if *v < 0.0 { "-" } else { "" })
+            }
+            // Rust writes the shortest digits that read back the same, so does C.
+            ExprLiteral::NumberLiteral(v) => format!("nh_float({:e})", v),
+            ExprLiteral::StringLiteral(text) => {
+                let count = self.strings.len();
+                let id = *self.strings.entry(text.clone()).or_insert(count);
+                format!("nh_str(&nh_s{})", id)
+            }
+            ExprLiteral::True => "nh_bool(true)".to_string(),
+            ExprLiteral::False => "nh_bool(false)".to_string(),
+            ExprLiteral::Nil => "nh_nil()".to_string(),
+            // The natives the Parser desugars interpolation and for-in loops to.
+            ExprLiteral::FunctionLiteral(function) => {
+                let text = function.two_string();
+                match NATIVES
+                    .iter()
+                    .find(|(name, _)| text == format!("<native fn {}>", name))
+                {
+                    Some((name, _)) => {
+                        self.used.insert(name);
+                        format!("nh_native(&nh_native_{})", name)
+                    }
+                    None => {
+                        return Err(Diagnostic::error(
+                            E_UNSUPPORTED,
+                            format!("The C backend does not support {}.", text),
+                        ))
+                    }
+                }
+            }
+            other => {
+                return Err(Diagnostic::error(
+                    E_UNSUPPORTED,
+                    format!(
+                        "The C backend does not support {} values.",
+                        other.type_name()
+                    ),
+                ))
+            }
+        })
+    }
+
+    // brief: Find the variable a name refers to, capturing it from enclosing functions.
+    // input:
+    // output: Err for a native the runtime does not implement.
+    fn resolve(&mut self, name: &Token) -> Result<Place, Diagnostic> {
+        if let Some(place) = self.lookup(self.states.len() - 1, &name.lexeme) {
+            return Ok(place);
+        }
+        let native = name.lexeme.as_str();
+        if self.natives.contains(native)
+            && !NATIVES.iter().any(|(name, _)| *name == native)
+            && !self.top_level.contains(native)
+        {
+            return Err(unsupported(&format!("the native {}", native), name));
+        }
+        Ok(Place::Global(self.global(native)))
+    }
+
+    // brief: Look a name up in the scopes of the function at `level`, then in the enclosing
+    // ones; a variable found there becomes a cell of every function in between.
+    // input:
+    // output: None for a global.
+    fn lookup(&mut self, level: usize, name: &str) -> Option<Place> {
+        let state = &self.states[level];
+        for scope in state.scopes.iter().rev() {
+            if let Some((_, id)) = scope.iter().rev().find(|(declared, _)| declared == name) {
+                return Some(Place::Local(*id));
+            }
+        }
+        if level == 0 {
+            return None;
+        }
+        let id = match self.lookup(level - 1, name)? {
+            Place::Local(id) => {
+                self.found.insert(id);
+                id
+            }
+            Place::Cell(cell) => self.states[level - 1].cells[cell],
+            Place::Global(_) => return None,
+        };
+        let cells = &mut self.states[level].cells;
+        Some(Place::Cell(match cells.iter().position(|v| *v == id) {
+            Some(cell) => cell,
+            None => {
+                cells.push(id);
+                cells.len() - 1
+            }
+        }))
+    }
+
+    // brief: The C lvalue of a local or captured variable.
+    // input:
+    // output:
+    fn access(&self, place: Place) -> String {
+        match place {
+            Place::Local(id) if self.captured.contains(&id) => format!("(*l{})", id),
+            Place::Local(id) => format!("l{}", id),
+            Place::Cell(cell) => format!("(*self->cells[{}])", cell),
+            Place::Global(slot) => format!("nh_globals[{}].value", slot),
+        }
+    }
+
+    // brief: The box of a captured variable, in the innermost function.
+    // input: local id of the variable.
+    // output:
+    fn pointer(&self, id: usize) -> String {
+        let state = self.states.last().unwrap();
+        if state.scopes.iter().flatten().any(|(_, local)| *local == id) {
+            return format!("l{}", id);
+        }
+        let cell = state.cells.iter().position(|v| *v == id).unwrap_or(0);
+        format!("self->cells[{}]", cell)
+    }
+
+    // brief: Declare a local variable in the innermost scope.
+    // input: its initial value.
+    // output: its local id.
+    fn local(&mut self, name: &Token, value: &str) -> usize {
+        let id = self.id();
+        self.state()
+            .scopes
+            .last_mut()
+            .unwrap()
+            .push((name.lexeme.clone(), id));
+        match self.captured.contains(&id) {
+            true => self.line(format!("NhValue *l{} = nh_box({});", id, value)),
+            false => self.line(format!("NhValue l{} = {};", id, value)),
+        }
+        id
+    }
+
+    // brief: The slot of a global, added the first time it is named.
+    // input:
+    // output:
+    fn global(&mut self, name: &str) -> usize {
+        match self.globals.iter().position(|v| v == name) {
+            Some(slot) => slot,
+            None => {
+                self.globals.push(name.to_string());
+                self.globals.len() - 1
+            }
+        }
+    }
+
+    // Whether a declaration here defines a global.
+    fn is_top_level(&self) -> bool {
+        self.states.len() == 1 && self.states[0].scopes.is_empty()
+    }
+
+    // brief: Evaluate a C expression into a new temporary.
+    // input:
+    // output: the name of the temporary.
+    fn temp(&mut self, value: String) -> String {
+        let temp = format!("t{}", self.id());
+        self.line(format!("NhValue {} = {};", temp, value));
+        temp
+    }
+
+    // Point runtime errors of what follows at a token.
+    fn at(&mut self, token: &Token) {
+        self.line(format!("nh_at({}, {});", token.line_number, token.column));
+    }
+
+    fn id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn line(&mut self, text: String) {
+        let state = self.state();
+        state.code.push_str(&"    ".repeat(state.indent));
+        state.code.push_str(&text);
+        state.code.push('\n');
+    }
+
+    fn open(&mut self, text: String) {
+        self.line(text);
+        self.state().indent += 1;
+    }
+
+    fn close(&mut self, text: &str) {
+        self.state().indent -= 1;
+        self.line(text.to_string());
+    }
+
+    fn reopen(&mut self, text: &str) {
+        self.close(text);
+        self.state().indent += 1;
+    }
+}
+
+// The name a top level statement declares.
+fn declared_name(statement: &Stmt) -> Option<String> {
+    match statement {
+        Stmt::Let { name, .. }
+        | Stmt::Function { name, .. }
+        | Stmt::Class { name, .. }
+        | Stmt::Import { name, .. } => Some(name.lexeme.clone()),
+        _ => None,
+    }
+}
+
+fn unsupported(what: &str, token: &Token) -> Diagnostic {
+    Diagnostic::error(
+        E_UNSUPPORTED,
+        format!("The C backend does not support {}.", what),
+    )
+    .at_token(token)
+}
+
+// brief: A C string literal holding `text`, bytes outside printable ASCII as octal escapes.
+// input:
+// output:
+fn c_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            // "?" too, "??" starts a trigraph.
+            b'"' | b'\\' | b'?' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, scanner::Scanner};
+
+    fn emit(source: &str) -> Result<String, Diagnostic> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        CEmitter::new("test.noah").emit(&statements)
+    }
+
+    #[test]
+    fn only_captured_locals_are_boxed() {
+        let c = emit("fn f() { let a = 1; let b = 2; fn g() { return a; } return g; }").unwrap();
+        assert!(
+            c.contains("NhValue *l2 = nh_box(nh_int(INT64_C(1)));"),
+            "{}",
+            c
+        );
+        assert!(c.contains("NhValue l3 = nh_int(INT64_C(2));"), "{}", c);
+        assert!(
+            c.contains("nh_closure(&nh_proto_5, 1, (NhValue *[]){l2})"),
+            "{}",
+            c
+        );
+        assert!(c.contains("static const NhProto nh_proto_1 = {\"f\", 0, 0, nh_fn_1};"));
+    }
+
+    #[test]
+    fn globals_strings_and_natives() {
+        let c = emit("let s = \"a\\\"b\\n\"; print len(s);").unwrap();
+        assert!(c.contains("static const NhString nh_s0 = {4, \"a\\\"b\\012\"};"));
+        assert!(c.contains("nh_define(&nh_globals[1], nh_native(&nh_native_len));"));
+        assert!(c.contains("nh_define(&nh_globals[0], nh_str(&nh_s0));"));
+        assert_eq!(c_string("é??"), "\"\\303\\251\\?\\?\"");
+    }
+
+    #[test]
+    fn unsupported_constructs_are_errors() {
+        for (source, message, column) in [
+            ("class A {}", "classes", 7),
+            ("print [1];", "lists", 7),
+            ("fn f(...r) {}", "rest parameters", 9),
+            ("print sqrt(2);", "the native sqrt", 7),
+        ] {
+            let err = emit(source).unwrap_err();
+            assert_eq!(err.code, E_UNSUPPORTED);
+            assert_eq!(
+                err.message,
+                format!("The C backend does not support {}.", message)
+            );
+            assert_eq!((err.line, err.column), (1, column), "{}", source);
+        }
+        // A script may define a global named like a native.
+        assert!(emit("fn sqrt(x) { return x; } print sqrt(2);").is_ok());
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{
+    callable::{Arity, Callable},
+    convert::IntoLiteral,
+    diagnostic::{Diagnostic, E_UNDEFINED_VARIABLE},
+    expr::ExprLiteral,
+    interpreter::Interpreter,
+    module::ModuleLoader,
+    output::Output,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    stack::{stack_address, stack_end},
+    stdlib::NativeFn,
+    stmt::Stmt,
+};
+
+// Noah embedded in a Rust host, on top of the tree-walking Interpreter.
+// Globals live as long as the Engine: a later eval, call_function or get_global sees what an
+// earlier eval defined, and scripts see what the host defined.
+pub struct Engine {
+    interpreter: Interpreter,
+    stack_budget: Option<usize>, // set by the host, otherwise what is left of the stack.
+    stack_end: Option<usize>,    // of the thread the Engine was made on, it can not leave it.
+}
+
+// Bytes at the end of its thread's stack an Engine leaves to natives and to the host. Calls
+// stop short of them with "Stack overflow.", so on an 8MiB main thread a release build reaches
+// MAX_CALL_DEPTH and a debug build about 250 calls; on a 2MiB spawned thread they reach about
+// 450 and 55 calls.
+pub const ENGINE_STACK_MARGIN: usize = 256 * 1024;
+
+// Bytes of its caller's stack an Engine lets calls use where the end of the stack can not be
+// found out, see stack_end. That leaves room on a 2MiB spawned thread.
+pub const ENGINE_STACK_BUDGET: usize = 1024 * 1024;
+
+impl Engine {
+    // brief: An Engine with the standard library defined.
+    // input:
+    // output:
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+            stack_budget: None,
+            stack_end: stack_end(),
+        }
+    }
+
+    // brief: Change how deep calls may nest, see MAX_CALL_DEPTH. Calls also stop at the
+    // stack budget, whichever comes first.
+    // input:
+    // output:
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.interpreter.set_max_depth(max_depth);
+    }
+
+    // brief: Let scripts use a fixed amount of the calling thread's stack instead of all but
+    // ENGINE_STACK_MARGIN of it.
+    // input: bytes, keep it well under what is left of the stack of the thread calling the
+    // Engine.
+    // output:
+    pub fn set_stack_budget(&mut self, budget: usize) {
+        self.stack_budget = Some(budget);
+    }
+
+    // brief: Send what scripts print somewhere else than stdout, e.g. Output::buffer().
+    // input:
+    // output:
+    pub fn set_output(&mut self, output: Output) {
+        self.interpreter.set_output(output);
+    }
+
+    // brief: Where `import` looks for modules: relative paths start in `dir`, then in every
+    // directory of `search_path`. By default only the current directory is searched.
+    // input:
+    // output:
+    pub fn set_module_path(&mut self, dir: &Path, search_path: Vec<PathBuf>) {
+        self.interpreter
+            .set_modules(Rc::new(ModuleLoader::new(search_path)), dir);
+    }
+
+    // brief: Scan, parse, resolve and run some statements.
+    // input: complete statements, ";" included.
+    // output: the value of the last statement if it is an expression statement, nil otherwise.
+    // When the scanner or parser finds several errors the first one is returned, the others
+    // are added to its notes.
+    pub fn eval(&mut self, source: &str) -> Result<ExprLiteral, Diagnostic> {
+        let tokens = Scanner::new(source.to_string())
+            .scan_tokens()
+            .map_err(first_error)?;
+        let mut statements = Parser::new(tokens).parse().map_err(first_error)?;
+        let locals = Resolver::new().resolve(&statements)?;
+        self.interpreter.resolve(locals);
+        self.interpreter.limit_stack(self.stack_budget());
+
+        let last = match statements.last() {
+            Some(Stmt::Expression(_)) => statements.pop(),
+            _ => None,
+        };
+        self.interpreter.interpreter(&statements)?;
+        match last {
+            Some(Stmt::Expression(expr)) => self.interpreter.evaluate(&expr),
+            _ => Ok(ExprLiteral::Nil),
+        }
+    }
+
+    // brief: Call a global function or class from the host.
+    // input:
+    // output:
+    pub fn call_function(
+        &mut self,
+        name: &str,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        let callee = self.get_global(name).ok_or_else(|| {
+            Diagnostic::error(
+                E_UNDEFINED_VARIABLE,
+                format!("Undefined variable {}.", name),
+            )
+        })?;
+        self.interpreter.limit_stack(self.stack_budget());
+        self.interpreter.call(&callee, arguments)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl IntoLiteral) {
+        self.interpreter.define_global(name, value.into_literal());
+    }
+
+    // brief: Read a global variable.
+    // input:
+    // output: None if neither the host nor a script defined it.
+    pub fn get_global(&self, name: &str) -> Option<ExprLiteral> {
+        self.interpreter.global(name)
+    }
+
+    // brief: Define a host closure as a global native, the same way as the standard library.
+    // input: the closure reports bad arguments as an Err, it becomes "name: message.".
+    // output:
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        arity: impl Into<Arity>,
+        body: impl Fn(&[ExprLiteral]) -> Result<ExprLiteral, String> + 'static,
+    ) {
+        self.register_callable(name, Rc::new(NativeFn::new(name, arity, body)));
+    }
+
+    // brief: Define any Callable of the host as a global function.
+    // input:
+    // output:
+    pub fn register_callable(&mut self, name: &str, callable: Rc<dyn Callable>) {
+        self.set_global(name, callable);
+    }
+
+    // brief: How many bytes of the stack calls made from here may use.
+    // input:
+    // output:
+    fn stack_budget(&self) -> usize {
+        match (self.stack_budget, self.stack_end) {
+            (Some(budget), _) => budget,
+            (None, Some(end)) => stack_address()
+                .saturating_sub(end)
+                .saturating_sub(ENGINE_STACK_MARGIN),
+            (None, None) => ENGINE_STACK_BUDGET,
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// brief: Keep the first of several diagnostics, with the others as notes.
+// input:
+// output:
+fn first_error(errors: Vec<Diagnostic>) -> Diagnostic {
+    let mut errors = errors.into_iter();
+    let first = errors
+        .next()
+        .expect("a failed stage reports at least one diagnostic");
+    errors.fold(first, |first, other| first.with_note(other.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{
+        convert::FromLiteral,
+        diagnostic::{E_ARITY, E_EXPECT_TOKEN, E_IMPORT, E_NATIVE},
+    };
+
+    #[test]
+    fn modules_are_found_from_the_module_path() {
+        let mut engine = Engine::new();
+        let lib = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/noah/modules/lib");
+        engine.set_module_path(Path::new("no/such/dir"), vec![lib]);
+        engine.eval("import \"util.noah\" as util;").unwrap();
+        assert_eq!(engine.eval("util.twice(4);").unwrap().to::<i64>(), Ok(8));
+        let err = engine.eval("import \"nope.noah\" as nope;").unwrap_err();
+        assert_eq!(err.code, E_IMPORT);
+    }
+
+    #[test]
+    fn eval_keeps_globals() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.eval("let a = 40;").unwrap(), ExprLiteral::Nil);
+        assert_eq!(engine.eval("a + 2;").unwrap().to::<i64>(), Ok(42));
+
+        engine.set_global("name", "host");
+        assert_eq!(
+            engine.eval("\"hi \" + name;").unwrap().to::<String>(),
+            Ok("hi host".to_string())
+        );
+        engine.eval("let xs = [1, 2];").unwrap();
+        assert_eq!(
+            Vec::<i64>::from_literal(&engine.get_global("xs").unwrap()),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(engine.get_global("nope"), None);
+
+        let err = engine.eval("let = ; let = ;").unwrap_err();
+        assert_eq!(err.code, E_EXPECT_TOKEN);
+        assert_eq!(err.notes.len(), 1);
+    }
+
+    #[test]
+    fn print_goes_to_the_output() {
+        let mut engine = Engine::new();
+        let (output, buffer) = Output::buffer();
+        engine.set_output(output);
+        engine
+            .eval("print 1 + 2; for (let x in [\"a\", nil]) print x;")
+            .unwrap();
+        assert_eq!(*buffer.borrow(), "3\na\nNil\n");
+    }
+
+    #[test]
+    fn host_calls_script_and_script_calls_host() {
+        let mut engine = Engine::new();
+        engine.register("twice", 1, |args| {
+            let n = i64::from_literal(&args[0])?;
+            Ok((n * 2).into_literal())
+        });
+        engine
+            .eval("fn add(a, b = 1) { return twice(a) + b; }")
+            .unwrap();
+
+        let value = engine.call_function("add", vec![20.into_literal()]);
+        assert_eq!(value.unwrap().to::<i64>(), Ok(41));
+
+        let err = engine.call_function("add", vec![]).unwrap_err();
+        assert_eq!(err.code, E_ARITY);
+        let err = engine.call_function("twice", vec!["x".into_literal()]);
+        assert_eq!(err.unwrap_err().code, E_NATIVE);
+        let err = engine.call_function("missing", vec![]);
+        assert_eq!(err.unwrap_err().code, E_UNDEFINED_VARIABLE);
+    }
+}
@@ -1,14 +1,20 @@
-use super::{expr::ExprLiteral, token::Token};
-use std::collections::{btree_map::OccupiedEntry, hash_map::VacantEntry, HashMap};
+use super::{
+    diagnostic::{Diagnostic, E_INTERNAL, E_UNDEFINED_VARIABLE},
+    expr::ExprLiteral,
+    token::Token,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-#[derive(Debug, Clone)]
+// Scopes are shared: a closure and the block that created it hold the same Rc,
+// so assignments through either of them are visible to both.
+#[derive(Debug)]
 pub struct Environment {
-    pub enclosing: Option<Box<Environment>>,
+    pub enclosing: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, ExprLiteral>,
 }
 
 impl Environment {
-    pub fn new(env: Option<Box<Environment>>) -> Self {
+    pub fn new(env: Option<Rc<RefCell<Environment>>>) -> Self {
         Self {
             enclosing: env,
             values: HashMap::new(),
@@ -19,32 +25,171 @@ impl Environment {
         self.values.insert(name, value);
     }
 
-    pub fn get(&self, name: &Token) -> Result<ExprLiteral, String> {
+    // brief: The global scope at the end of the chain.
+    // input:
+    // output:
+    pub fn root(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut env = env.clone();
+        loop {
+            let enclosing = env.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => env = enclosing,
+                None => return env,
+            }
+        }
+    }
+
+    // brief: All the variables defined directly in this scope, sorted by name.
+    // input:
+    // output:
+    pub fn values(&self) -> Vec<(String, ExprLiteral)> {
+        let mut values: Vec<(String, ExprLiteral)> = self
+            .values
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        values
+    }
+
+    // brief: A variable defined directly in this scope.
+    // input:
+    // output:
+    pub fn lookup(&self, name: &str) -> Option<ExprLiteral> {
+        self.values.get(name).cloned()
+    }
+
+    // brief: Change a variable defined directly in this scope.
+    // input:
+    // output: false if it is not defined here.
+    pub fn set(&mut self, name: &str, value: ExprLiteral) -> bool {
+        match self.values.get_mut(name) {
+            Some(v) => {
+                *v = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<ExprLiteral, Diagnostic> {
         match self.values.get(&name.lexeme) {
             Some(v) => Ok(v.clone()),
             None => {
                 if let Some(v) = &self.enclosing {
-                    v.get(name)
+                    v.borrow().get(name)
                 } else {
-                    Err(format!("Undefined variable {}.", name.lexeme))
+                    Err(Diagnostic::error(
+                        E_UNDEFINED_VARIABLE,
+                        format!("Undefined variable {}.", name.lexeme),
+                    )
+                    .at_token(name))
                 }
             }
         }
     }
 
-    pub fn assign(&mut self, name: &Token, value: ExprLiteral) -> Result<(), String> {
+    pub fn assign(&mut self, name: &Token, value: ExprLiteral) -> Result<(), Diagnostic> {
         match self.values.get_mut(&name.lexeme) {
             Some(v) => {
                 *v = value;
                 Ok(())
             }
             None => {
-                if let Some(v) = &mut self.enclosing {
-                    v.assign(name, value)
+                if let Some(v) = &self.enclosing {
+                    v.borrow_mut().assign(name, value)
                 } else {
-                    Err(format!("Undefined variable {}.", name.lexeme))
+                    Err(Diagnostic::error(
+                        E_UNDEFINED_VARIABLE,
+                        format!("Undefined variable {}.", name.lexeme),
+                    )
+                    .at_token(name))
                 }
             }
         }
     }
+
+    // brief: Get a variable from the scope `distance` hops away, distance comes from the Resolver.
+    // input:
+    // output:
+    pub fn get_at(&self, distance: usize, name: &Token) -> Result<ExprLiteral, Diagnostic> {
+        if distance == 0 {
+            return match self.values.get(&name.lexeme) {
+                Some(v) => Ok(v.clone()),
+                None => Err(Diagnostic::error(
+                    E_INTERNAL,
+                    format!("Resolved variable {} is missing.", name.lexeme),
+                )
+                .at_token(name)),
+            };
+        }
+        match &self.enclosing {
+            Some(v) => v.borrow().get_at(distance - 1, name),
+            None => Err(Diagnostic::error(
+                E_INTERNAL,
+                format!("Resolved variable {} is missing.", name.lexeme),
+            )
+            .at_token(name)),
+        }
+    }
+
+    // brief: Assign a variable in the scope `distance` hops away.
+    // input:
+    // output:
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        name: &Token,
+        value: ExprLiteral,
+    ) -> Result<(), Diagnostic> {
+        if distance == 0 {
+            self.values.insert(name.lexeme.clone(), value);
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(v) => v.borrow_mut().assign_at(distance - 1, name, value),
+            None => Err(Diagnostic::error(
+                E_INTERNAL,
+                format!("Resolved variable {} is missing.", name.lexeme),
+            )
+            .at_token(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::token::TokenType;
+
+    fn name(lexeme: &str) -> Token {
+        Token::new(TokenType::Identifier, lexeme.to_string(), None, 1)
+    }
+
+    #[test]
+    fn shared_scope_sees_assignment() {
+        let outer = Rc::new(RefCell::new(Environment::new(None)));
+        outer
+            .borrow_mut()
+            .define("a".to_string(), ExprLiteral::NumberLiteral(1.0));
+
+        let inner = Environment::new(Some(outer.clone()));
+        inner
+            .enclosing
+            .as_ref()
+            .unwrap()
+            .borrow_mut()
+            .assign(&name("a"), ExprLiteral::NumberLiteral(2.0))
+            .unwrap();
+
+        assert_eq!(
+            outer.borrow().get(&name("a")),
+            Ok(ExprLiteral::NumberLiteral(2.0))
+        );
+        assert_eq!(
+            inner.get_at(1, &name("a")),
+            Ok(ExprLiteral::NumberLiteral(2.0))
+        );
+        assert!(inner.get_at(0, &name("a")).is_err());
+    }
 }
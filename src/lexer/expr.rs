@@ -1,8 +1,22 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use super::{
     callable::Callable,
-    token::{LiterialValue, Token, TokenType},
+    class::{MyClass, MyInstance},
+    diagnostic::{Diagnostic, E_BAD_OPERAND, E_INDEX},
+    map::MyMap,
+    module::MyModule,
+    printer::Printer,
+    token::{Token, TokenType},
 };
 
+// Every Variable/Assign/This/Super node gets a unique id, the Resolver uses it as the key of the scope depth.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
     Unary {
@@ -21,9 +35,11 @@ pub enum Expr {
         value: ExprLiteral,
     },
     Variable {
+        id: usize,
         name: Token,
     },
     Assign {
+        id: usize,
         name: Token, // not Expr
         value: Box<Expr>,
     },
@@ -37,41 +53,61 @@ pub enum Expr {
         paren: Token,
         arguments: Vec<Expr>,
     },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        id: usize,
+        keyword: Token,
+    },
+    Super {
+        id: usize,
+        keyword: Token,
+        method: Token,
+    },
+    List {
+        bracket: Token, // "[" of the literal.
+        elements: Vec<Expr>,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token, // "[" after the object.
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Map {
+        brace: Token, // "{" of the literal.
+        entries: Vec<(Expr, Expr)>,
+    },
 }
 
 impl Expr {
-    // brief: Convert an Expression to String recursively, which mainly forcus on print/debug.
+    // brief: A fresh id for a Variable, Assign, This or Super node, unique in the whole process so that
+    // statements parsed separately (e.g. repl lines) never collide.
+    // input:
+    // output:
+    pub fn next_id() -> usize {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // brief: Convert an Expression to source, see Printer.
     // input:
     // output: String.
     pub fn two_string(&self) -> String {
-        match self {
-            Expr::Literal { value } => value.two_string().to_string(),
-            Expr::Unary { operator, right } => {
-                let operator_str = operator.lexeme.clone();
-                let right_str = right.two_string(); // recursion occurs!
-                format!("( {} {} )", operator_str, right_str)
-            }
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => {
-                let left_str = left.two_string();
-                let operator_str = operator.lexeme.clone();
-                let right_str = right.two_string();
-                format!("( {} {} {} )", left_str, operator_str, right_str)
-            }
-            Expr::Grouping { expression } => {
-                format!("( {} )", expression.two_string())
-            }
-            Expr::Variable { name } => name.lexeme.clone(), // Todo: Check.
-
-            Expr::Assign { name, value } => {
-                todo!()
-            }
-            _ => todo!(),
-        }
+        Printer::expr(self)
     }
+    #[allow(dead_code)]
     pub fn print(&self) {
         println!("{}", self.two_string());
     }
@@ -79,24 +115,48 @@ impl Expr {
 
 #[derive(Clone, Debug)]
 pub enum ExprLiteral {
+    IntLiteral(i64),
     NumberLiteral(f64),
     StringLiteral(String),
     True,
     False,
     Nil,
-    FunctionLiteral(Box<dyn Callable>),
+    FunctionLiteral(Rc<dyn Callable>),
+    ClassLiteral(Rc<MyClass>),
+    InstanceLiteral(Rc<RefCell<MyInstance>>),
+    ListLiteral(Rc<RefCell<Vec<ExprLiteral>>>), // shared and mutable, like instances.
+    MapLiteral(Rc<RefCell<MyMap>>),             // shared and mutable, like instances.
+    ModuleLiteral(Rc<MyModule>),                // what `import ... as name` binds.
+}
+
+// Two numeric operands after promotion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberPair {
+    Int(i64, i64),
+    Float(f64, f64),
 }
 
 impl PartialEq for ExprLiteral {
     fn eq(&self, other: &Self) -> bool {
         use ExprLiteral::*;
         match (self, other) {
+            (IntLiteral(v1), IntLiteral(v2)) => *v1 == *v2,
             (NumberLiteral(v1), NumberLiteral(v2)) => *v1 == *v2,
+            (IntLiteral(v1), NumberLiteral(v2)) | (NumberLiteral(v2), IntLiteral(v1)) => {
+                *v1 as f64 == *v2
+            }
             (StringLiteral(v1), StringLiteral(v2)) => *v1 == *v2,
             (ExprLiteral::True, ExprLiteral::True) => true,
             (ExprLiteral::False, ExprLiteral::False) => true,
             (ExprLiteral::Nil, ExprLiteral::Nil) => true,
-            (FunctionLiteral(_), FunctionLiteral(_)) => false,
+            (FunctionLiteral(f1), FunctionLiteral(f2)) => {
+                Rc::as_ptr(f1) as *const () == Rc::as_ptr(f2) as *const ()
+            }
+            (ClassLiteral(c1), ClassLiteral(c2)) => Rc::ptr_eq(c1, c2),
+            (InstanceLiteral(i1), InstanceLiteral(i2)) => Rc::ptr_eq(i1, i2),
+            (ListLiteral(l1), ListLiteral(l2)) => Rc::ptr_eq(l1, l2),
+            (MapLiteral(m1), MapLiteral(m2)) => Rc::ptr_eq(m1, m2),
+            (ModuleLiteral(m1), ModuleLiteral(m2)) => Rc::ptr_eq(m1, m2),
             _ => false,
         }
     }
@@ -108,28 +168,336 @@ impl PartialEq for ExprLiteral {
 
 impl ExprLiteral {
     pub fn two_string(&self) -> String {
+        self.display(&mut vec![])
+    }
+
+    // brief: two_string, strings inside a list or a map are quoted.
+    // input: the lists and maps being printed, so that one holding itself prints [...] or {...} .
+    // output:
+    fn display(&self, open: &mut Vec<*const ()>) -> String {
+        let item = |v: &ExprLiteral, open: &mut Vec<*const ()>| match v {
+            Self::StringLiteral(s) => format!("{:?}", s),
+            _ => v.display(open),
+        };
         match self {
+            Self::IntLiteral(v) => v.to_string(),
             Self::NumberLiteral(v) => v.to_string(),
             Self::StringLiteral(v) => v.clone(),
             Self::True => "True".to_string(),
             Self::False => "False".to_string(),
             Self::Nil => "Nil".to_string(),
-            Self::FunctionLiteral(f) => {
-                todo!()
+            Self::FunctionLiteral(f) => f.two_string(),
+            Self::ClassLiteral(c) => c.two_string(),
+            Self::InstanceLiteral(i) => i.borrow().two_string(),
+            Self::ModuleLiteral(m) => m.two_string(),
+            Self::ListLiteral(list) => {
+                let ptr = Rc::as_ptr(list) as *const ();
+                if open.contains(&ptr) {
+                    return "[...]".to_string();
+                }
+                open.push(ptr);
+                let items: Vec<String> = list.borrow().iter().map(|v| item(v, open)).collect();
+                open.pop();
+                format!("[{}]", items.join(", "))
+            }
+            Self::MapLiteral(map) => {
+                let ptr = Rc::as_ptr(map) as *const ();
+                if open.contains(&ptr) {
+                    return "{...}".to_string();
+                }
+                open.push(ptr);
+                let items: Vec<String> = map
+                    .borrow()
+                    .entries()
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", item(k, open), item(v, open)))
+                    .collect();
+                open.pop();
+                format!("{{{}}}", items.join(", "))
             }
         }
     }
 
-    pub fn is_equal(&self, other: &ExprLiteral) -> bool {
-        match (self, other) {
-            (ExprLiteral::NumberLiteral(v1), ExprLiteral::NumberLiteral(v2)) => *v1 == *v2,
-            (ExprLiteral::StringLiteral(s1), ExprLiteral::StringLiteral(s2)) => *s1 == *s2,
-            (ExprLiteral::True, ExprLiteral::True) => true,
-            (ExprLiteral::False, ExprLiteral::False) => true,
-            (ExprLiteral::Nil, ExprLiteral::Nil) => true,
-            _ => false,
+    // brief: Name of the type of the value, for messages.
+    // input:
+    // output:
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::IntLiteral(_) => "int",
+            Self::NumberLiteral(_) => "float",
+            Self::StringLiteral(_) => "string",
+            Self::True | Self::False => "bool",
+            Self::Nil => "nil",
+            Self::FunctionLiteral(_) => "function",
+            Self::ClassLiteral(_) => "class",
+            Self::InstanceLiteral(_) => "instance",
+            Self::ListLiteral(_) => "list",
+            Self::MapLiteral(_) => "map",
+            Self::ModuleLiteral(_) => "module",
         }
     }
+
+    pub fn list(values: Vec<ExprLiteral>) -> ExprLiteral {
+        ExprLiteral::ListLiteral(Rc::new(RefCell::new(values)))
+    }
+
+    // brief: A map from (key, value) pairs, a later pair replaces an earlier one with the same key.
+    // input:
+    // output: Err with the reason if a key can not be hashed.
+    pub fn map(entries: Vec<(ExprLiteral, ExprLiteral)>) -> Result<ExprLiteral, String> {
+        let mut map = MyMap::new();
+        for (key, value) in entries {
+            map.insert(key, value)?;
+        }
+        Ok(ExprLiteral::MapLiteral(Rc::new(RefCell::new(map))))
+    }
+
+    // brief: Check an index against a length.
+    // input:
+    // output: Err with the reason, the caller adds the position.
+    pub fn list_index(index: &ExprLiteral, len: usize) -> Result<usize, String> {
+        match index {
+            ExprLiteral::IntLiteral(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+            ExprLiteral::IntLiteral(i) => {
+                Err(format!("index {} out of range for length {}", i, len))
+            }
+            other => Err(format!(
+                "index must be an integer, got {}",
+                other.two_string()
+            )),
+        }
+    }
+
+    // brief: object[index], on lists, maps and on the chars of a string.
+    // Shared by the Interpreter and the Vm.
+    // input:
+    // output: Err with the reason, the caller adds the position.
+    pub fn get_index(&self, index: &ExprLiteral) -> Result<ExprLiteral, String> {
+        match self {
+            ExprLiteral::ListLiteral(list) => {
+                let list = list.borrow();
+                Ok(list[Self::list_index(index, list.len())?].clone())
+            }
+            ExprLiteral::StringLiteral(s) => {
+                let i = Self::list_index(index, s.chars().count())?;
+                Ok(ExprLiteral::StringLiteral(
+                    s.chars().nth(i).unwrap().to_string(),
+                ))
+            }
+            ExprLiteral::MapLiteral(map) => match map.borrow().get(index)? {
+                Some(value) => Ok(value),
+                None => Err(format!("key {} not found", index.quoted())),
+            },
+            other => Err(format!(
+                "can only index lists, maps and strings, got {}",
+                other.two_string()
+            )),
+        }
+    }
+
+    // brief: object[index] = value, on lists and maps, a map gets a new entry if needed.
+    // input:
+    // output:
+    pub fn set_index(&self, index: &ExprLiteral, value: ExprLiteral) -> Result<(), String> {
+        match self {
+            ExprLiteral::ListLiteral(list) => {
+                let mut list = list.borrow_mut();
+                let i = Self::list_index(index, list.len())?;
+                list[i] = value;
+                Ok(())
+            }
+            ExprLiteral::MapLiteral(map) => map.borrow_mut().insert(index.clone(), value),
+            other => Err(format!(
+                "can only assign into lists and maps, got {}",
+                other.two_string()
+            )),
+        }
+    }
+
+    // brief: Promote two numeric operands: two ints stay ints, an int meeting a float becomes a float.
+    // input:
+    // output: None if either operand is not a number.
+    pub fn number_operands(left: &ExprLiteral, right: &ExprLiteral) -> Option<NumberPair> {
+        use ExprLiteral::*;
+        match (left, right) {
+            (IntLiteral(l), IntLiteral(r)) => Some(NumberPair::Int(*l, *r)),
+            (IntLiteral(l), NumberLiteral(r)) => Some(NumberPair::Float(*l as f64, *r)),
+            (NumberLiteral(l), IntLiteral(r)) => Some(NumberPair::Float(*l, *r as f64)),
+            (NumberLiteral(l), NumberLiteral(r)) => Some(NumberPair::Float(*l, *r)),
+            _ => None,
+        }
+    }
+
+    // brief: "+" "-" "*" "/" "%" on numbers.
+    // Int arithmetic is checked, "/" truncates toward zero and "%" takes the sign of the dividend.
+    // input:
+    // output: Err with the reason, the caller adds the line.
+    pub fn arithmetic(
+        operator: &TokenType,
+        left: &ExprLiteral,
+        right: &ExprLiteral,
+    ) -> Result<ExprLiteral, String> {
+        match Self::number_operands(left, right) {
+            Some(NumberPair::Int(l, r)) => {
+                if r == 0 && matches!(operator, TokenType::Slash | TokenType::Percent) {
+                    return Err("integer division by zero".to_string());
+                }
+                let value = match operator {
+                    TokenType::Plus => l.checked_add(r),
+                    TokenType::Minus => l.checked_sub(r),
+                    TokenType::Star => l.checked_mul(r),
+                    TokenType::Slash => l.checked_div(r),
+                    TokenType::Percent => l.checked_rem(r),
+                    _ => return Err("no matching arithmetic operator".to_string()),
+                };
+                match value {
+                    Some(v) => Ok(ExprLiteral::IntLiteral(v)),
+                    None => Err("integer overflow".to_string()),
+                }
+            }
+            Some(NumberPair::Float(l, r)) => {
+                let value = match operator {
+                    TokenType::Plus => l + r,
+                    TokenType::Minus => l - r,
+                    TokenType::Star => l * r,
+                    TokenType::Slash => l / r,
+                    TokenType::Percent => l % r,
+                    _ => return Err("no matching arithmetic operator".to_string()),
+                };
+                Ok(ExprLiteral::NumberLiteral(value))
+            }
+            None => Err("operands must be numbers".to_string()),
+        }
+    }
+
+    // brief: ">" ">=" "<" "<=" on numbers, ints and floats compare by value.
+    // input:
+    // output: True or False.
+    pub fn compare(
+        operator: &TokenType,
+        left: &ExprLiteral,
+        right: &ExprLiteral,
+    ) -> Result<ExprLiteral, String> {
+        let ordering = match Self::number_operands(left, right) {
+            Some(NumberPair::Int(l, r)) => l.partial_cmp(&r),
+            Some(NumberPair::Float(l, r)) => l.partial_cmp(&r),
+            None => return Err("operands must be numbers".to_string()),
+        };
+        let Some(ordering) = ordering else {
+            return Ok(ExprLiteral::False); // NaN compares false with everything.
+        };
+        let value = match operator {
+            TokenType::Greater => ordering.is_gt(),
+            TokenType::GreaterEqual => ordering.is_ge(),
+            TokenType::Less => ordering.is_lt(),
+            TokenType::LessEqual => ordering.is_le(),
+            _ => return Err("no matching comparison operator".to_string()),
+        };
+        Ok(Self::from_bool(value))
+    }
+
+    // brief: Every binary operator: "+" also joins two strings, "==" and "!=" work on any values.
+    // Shared by the Interpreter and the Vm so that both backends agree.
+    // input:
+    // output: Err with the reason, see operand_error.
+    pub fn binary(
+        operator: &TokenType,
+        left: &ExprLiteral,
+        right: &ExprLiteral,
+    ) -> Result<ExprLiteral, String> {
+        match operator {
+            TokenType::Plus => match (left, right) {
+                (ExprLiteral::StringLiteral(l), ExprLiteral::StringLiteral(r)) => {
+                    Ok(ExprLiteral::StringLiteral(format!("{}{}", l, r)))
+                }
+                _ => Self::arithmetic(operator, left, right),
+            },
+            TokenType::Minus | TokenType::Slash | TokenType::Star | TokenType::Percent => {
+                Self::arithmetic(operator, left, right)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => Self::compare(operator, left, right),
+            TokenType::EqualEqual => Ok(Self::from_bool(left == right)),
+            TokenType::BangEqual => Ok(Self::from_bool(left != right)),
+            _ => Err("no matchine Binary operator".to_string()),
+        }
+    }
+
+    // brief: The diagnostic for a failed binary, the same for both backends.
+    // input: lexeme of the operator, reason from binary().
+    // output:
+    pub fn operand_error(
+        lexeme: &str,
+        msg: String,
+        left: &ExprLiteral,
+        right: &ExprLiteral,
+    ) -> Diagnostic {
+        Diagnostic::error(
+            E_BAD_OPERAND,
+            format!("Bad operands for {}: {}.", lexeme, msg),
+        )
+        .with_note(format!(
+            "left is {}, right is {}",
+            left.two_string(),
+            right.two_string()
+        ))
+    }
+
+    // brief: two_string, with quotes around strings.
+    // input:
+    // output:
+    pub fn quoted(&self) -> String {
+        match self {
+            Self::StringLiteral(s) => format!("{:?}", s),
+            _ => self.two_string(),
+        }
+    }
+
+    // brief: The diagnostic for a failed object[index], the same for both backends.
+    // input: reason from get_index or set_index.
+    // output:
+    pub fn index_error(msg: String) -> Diagnostic {
+        Diagnostic::error(E_INDEX, format!("Bad index: {}.", msg))
+    }
+
+    // brief: The diagnostic for a map literal with a key that can not be hashed.
+    // input: reason from map().
+    // output:
+    pub fn key_error(msg: String) -> Diagnostic {
+        Diagnostic::error(E_INDEX, format!("Bad map key: {}.", msg))
+    }
+
+    pub fn from_bool(value: bool) -> ExprLiteral {
+        if value {
+            ExprLiteral::True
+        } else {
+            ExprLiteral::False
+        }
+    }
+
+    // brief: All is true but nil and false.
+    // input:
+    // output:
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, ExprLiteral::False | ExprLiteral::Nil)
+    }
+
+    // brief: Unary "-".
+    // input:
+    // output:
+    pub fn negate(&self) -> Result<ExprLiteral, String> {
+        match self {
+            ExprLiteral::IntLiteral(v) => match v.checked_neg() {
+                Some(v) => Ok(ExprLiteral::IntLiteral(v)),
+                None => Err("integer overflow".to_string()),
+            },
+            ExprLiteral::NumberLiteral(v) => Ok(ExprLiteral::NumberLiteral(-v)),
+            _ => Err("operand must be a number".to_string()),
+        }
+    }
+
     // brief: Increase the degree of code coupling.
     // input:
     // output:
@@ -157,10 +525,9 @@ impl ExprLiteral {
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::Binary;
-
+    use super::Token;
     use super::{Expr, ExprLiteral};
-    use super::{LiterialValue, Token, TokenType};
+    use crate::lexer::token::TokenType;
 
     #[test]
     fn test_expr() {
@@ -184,5 +551,36 @@ mod tests {
 
         test.print();
     }
+
+    #[test]
+    fn int_and_float_arithmetic() {
+        use ExprLiteral::*;
+        let op = |t: TokenType, l: ExprLiteral, r: ExprLiteral| ExprLiteral::arithmetic(&t, &l, &r);
+
+        assert_eq!(
+            op(TokenType::Slash, IntLiteral(7), IntLiteral(2)),
+            Ok(IntLiteral(3))
+        );
+        assert_eq!(
+            op(TokenType::Slash, IntLiteral(-7), IntLiteral(2)),
+            Ok(IntLiteral(-3))
+        );
+        assert_eq!(
+            op(TokenType::Percent, IntLiteral(-7), IntLiteral(2)),
+            Ok(IntLiteral(-1))
+        );
+        assert_eq!(
+            op(TokenType::Plus, IntLiteral(1), NumberLiteral(0.5)),
+            Ok(NumberLiteral(1.5))
+        );
+        assert!(op(TokenType::Slash, IntLiteral(1), IntLiteral(0)).is_err());
+        assert!(op(TokenType::Star, IntLiteral(i64::MAX), IntLiteral(2)).is_err());
+        assert!(IntLiteral(i64::MIN).negate().is_err());
+        assert_eq!(IntLiteral(2), NumberLiteral(2.0));
+        assert_eq!(
+            ExprLiteral::compare(&TokenType::Less, &IntLiteral(1), &NumberLiteral(1.5)),
+            Ok(True)
+        );
+    }
     // cargo test expr -- --nocapture
 }
@@ -0,0 +1,368 @@
+use super::{
+    diagnostic::{Diagnostic, E_INTERNAL},
+    parser::Parser,
+    printer::Printer,
+    scanner::Scanner,
+    token::{Token, TokenType},
+};
+
+// What a "{" opened: a block (or class body) or a map literal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Brace {
+    Block,
+    Map,
+}
+
+// Source => the same tokens in the canonical style: four spaces per block, one statement per
+// line, one space around binary operators and after "," and ":", at most one blank line.
+// It works on the token stream rather than on the syntax tree, so that comments and what the
+// Parser desugars stay as they were written.
+struct Formatter {
+    out: String,
+    indent: usize,
+    braces: Vec<Brace>,
+    parens: usize,    // "(" and "[" still open, a ";" inside is part of a for.
+    line_break: bool, // the next token goes on a new line.
+    prev: Option<(Token, bool)>, // last code token written, and whether it was a unary operator.
+    prev_brace: Option<Brace>, // what the last token opened or closed, if it was a brace.
+    prev_comment: bool, // the last thing written is a comment.
+    prev_end_line: usize, // source line the last token, comments included, ends on.
+}
+
+// brief: Format a whole script.
+// input:
+// output: the formatted source, or the errors that stop the script from parsing. The result
+// is parsed again: if its syntax tree differs from the one of the input, it is an error too.
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let tokens = Scanner::new(source.to_string())
+        .keep_comments()
+        .scan_tokens()?;
+    let code: Vec<Token> = tokens
+        .iter()
+        .filter(|token| token.token_type != TokenType::Comment)
+        .cloned()
+        .collect();
+    let before = Printer::program(&Parser::new(code).parse()?);
+
+    let formatted = Formatter::new().format(&tokens);
+    let after = Scanner::new(formatted.clone())
+        .scan_tokens()
+        .and_then(|tokens| Parser::new(tokens).parse())
+        .map(|statements| Printer::program(&statements));
+    if after.as_ref() != Ok(&before) {
+        return Err(vec![Diagnostic::error(
+            E_INTERNAL,
+            "Formatting would change the program, the source is left as it is.",
+        )
+        .with_note("this is a bug of the formatter")]);
+    }
+    Ok(formatted)
+}
+
+impl Formatter {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+            braces: vec![],
+            parens: 0,
+            line_break: false,
+            prev: None,
+            prev_brace: None,
+            prev_comment: false,
+            prev_end_line: 1,
+        }
+    }
+
+    fn format(mut self, tokens: &[Token]) -> String {
+        for token in tokens {
+            match token.token_type {
+                TokenType::Eof => break,
+                TokenType::Comment => self.comment(token),
+                _ => self.code(token),
+            }
+            self.prev_end_line = token.line_number + token.lexeme.matches('\n').count();
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    // brief: A comment stays at the end of the line it was on, or on its own line.
+    // input:
+    // output:
+    fn comment(&mut self, token: &Token) {
+        let text = token.lexeme.trim_end_matches('\r');
+        if self.prev.is_some() && token.line_number == self.prev_end_line {
+            self.out.push(' ');
+            self.out.push_str(text);
+        } else {
+            // Even in the middle of a statement, the code after it goes on the next line.
+            self.line_break = true;
+            self.break_line(token);
+            self.out.push_str(text);
+        }
+        self.line_break = true;
+        self.prev_comment = true;
+    }
+
+    fn code(&mut self, token: &Token) {
+        let brace = match token.token_type {
+            TokenType::LeftBrace if self.opens_block() => Some(Brace::Block),
+            TokenType::LeftBrace => Some(Brace::Map),
+            TokenType::RightBrace => Some(self.braces.pop().unwrap_or(Brace::Block)),
+            _ => None,
+        };
+        let unary = match token.token_type {
+            TokenType::Bang => true,
+            TokenType::Minus => !self.after_operand(),
+            _ => false,
+        };
+
+        match (&token.token_type, brace) {
+            (TokenType::RightBrace, Some(Brace::Block)) => {
+                self.indent = self.indent.saturating_sub(1);
+                let empty = self.prev_brace == Some(Brace::Block)
+                    && self.prev_is(TokenType::LeftBrace)
+                    && !self.prev_comment;
+                if !empty {
+                    self.break_line(token); // no blank line before "}".
+                }
+                self.line_break = false;
+            }
+            // "} else", unless a comment ended the line of the "}".
+            (TokenType::Else, _)
+                if self.prev_brace == Some(Brace::Block)
+                    && self.prev_is(TokenType::RightBrace)
+                    && !self.prev_comment =>
+            {
+                self.line_break = false;
+                self.out.push(' ');
+            }
+            _ if self.line_break => self.break_line(token),
+            _ if self.prev.is_some() && self.space_before(token, brace) => self.out.push(' '),
+            _ => {}
+        }
+        self.out.push_str(&token.lexeme);
+
+        match (&token.token_type, brace) {
+            (TokenType::LeftParen | TokenType::LeftBracket, _) => self.parens += 1,
+            (TokenType::RightParen | TokenType::RightBracket, _) => {
+                self.parens = self.parens.saturating_sub(1)
+            }
+            (TokenType::Semicolon, _) if self.parens == 0 => self.line_break = true,
+            (TokenType::LeftBrace, Some(Brace::Block)) => {
+                self.indent += 1;
+                self.line_break = true;
+            }
+            (TokenType::RightBrace, Some(Brace::Block)) => self.line_break = true,
+            _ => {}
+        }
+        if let (TokenType::LeftBrace, Some(brace)) = (&token.token_type, brace) {
+            self.braces.push(brace);
+        }
+        self.prev = Some((token.clone(), unary));
+        self.prev_brace = brace;
+        self.prev_comment = false;
+    }
+
+    // brief: Start a new line, keeping one blank line where the source had some, and
+    // the indentation of `token`.
+    // input:
+    // output:
+    fn break_line(&mut self, token: &Token) {
+        self.line_break = false;
+        if self.out.is_empty() {
+            return;
+        }
+        let blank = token.line_number > self.prev_end_line + 1
+            && token.token_type != TokenType::RightBrace
+            && !(self.prev_brace == Some(Brace::Block) && self.prev_is(TokenType::LeftBrace));
+        self.out.push('\n');
+        if blank {
+            self.out.push('\n');
+        }
+        // A statement cut by a comment goes on one level deeper.
+        let mut indent = self.indent;
+        if token.token_type != TokenType::RightBrace && !self.at_statement_start() {
+            indent += 1;
+        }
+        self.out.push_str(&"    ".repeat(indent));
+    }
+
+    fn prev_is(&self, token_type: TokenType) -> bool {
+        matches!(&self.prev, Some((token, _)) if token.token_type == token_type)
+    }
+
+    // brief: Whether the last code token ended a statement or opened or closed a block.
+    // input:
+    // output:
+    fn at_statement_start(&self) -> bool {
+        match &self.prev {
+            None => true,
+            Some((token, _)) => match token.token_type {
+                TokenType::Semicolon => self.parens == 0,
+                TokenType::LeftBrace | TokenType::RightBrace => {
+                    self.prev_brace == Some(Brace::Block)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    // brief: Whether the last code token ends an operand: a "-" after it is binary, a "(" a
+    // call and a "[" an index.
+    // input:
+    // output:
+    fn after_operand(&self) -> bool {
+        match &self.prev {
+            None => false,
+            Some((token, _)) => match token.token_type {
+                TokenType::Identifier
+                | TokenType::Number
+                | TokenType::String
+                | TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::This => true,
+                TokenType::RightBrace => self.prev_brace == Some(Brace::Map),
+                _ => false,
+            },
+        }
+    }
+
+    // brief: Whether a "{" now opens a block rather than a map, the way the Parser decides:
+    // where a statement starts, after ")" or "else", and after the name of a class or the
+    // return type of a function.
+    // input:
+    // output:
+    fn opens_block(&self) -> bool {
+        match &self.prev {
+            None => true,
+            Some((token, _)) => match token.token_type {
+                TokenType::RightParen
+                | TokenType::Else
+                | TokenType::Identifier
+                | TokenType::Nil
+                | TokenType::Fn => true,
+                _ => self.at_statement_start(),
+            },
+        }
+    }
+
+    fn space_before(&self, token: &Token, brace: Option<Brace>) -> bool {
+        let Some((prev, prev_unary)) = &self.prev else {
+            return false;
+        };
+        if *prev_unary {
+            return false;
+        }
+        // The part of a string after "${...}" starts with its "}".
+        let continues_string = matches!(
+            token.token_type,
+            TokenType::String | TokenType::Interpolation
+        ) && token.lexeme.starts_with('}');
+        let tight_after = matches!(
+            prev.token_type,
+            TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::Dot
+                | TokenType::Ellipsis
+                | TokenType::Interpolation
+        ) || (prev.token_type == TokenType::LeftBrace
+            && self.prev_brace == Some(Brace::Map));
+        let tight_before = match token.token_type {
+            TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Dot
+            | TokenType::Colon => true,
+            TokenType::RightBrace => brace == Some(Brace::Map),
+            TokenType::LeftParen | TokenType::LeftBracket => self.after_operand(),
+            _ => continues_string,
+        };
+        !(tight_after || tight_before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_style() {
+        let source = "// header
+
+let   a=1 ;let m={ \"k\" : [ 1,-2 ] } ;  // trailing
+fn f( x , y=2 , ...rest ){
+if(x<y){return -x ;}else   if (!x) {
+  return f(x-1,y) ;
+}
+
+
+else return;
+}
+class A<B{init(n){this.n=n;}  empty(){}}
+for(let i=0;i<3;i=i+1) print \"i = ${ i * 2 }, ${ {\"a\": 1}[\"a\"] }\";
+for (;;) { break; }
+print ({}) ; print a - -a ;
+";
+        let expected = "// header
+
+let a = 1;
+let m = {\"k\": [1, -2]}; // trailing
+fn f(x, y = 2, ...rest) {
+    if (x < y) {
+        return -x;
+    } else if (!x) {
+        return f(x - 1, y);
+    } else return;
+}
+class A < B {
+    init(n) {
+        this.n = n;
+    }
+    empty() {}
+}
+for (let i = 0; i < 3; i = i + 1) print \"i = ${i * 2}, ${{\"a\": 1}[\"a\"]}\";
+for (;;) {
+    break;
+}
+print ({});
+print a - -a;
+";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn comments_and_multi_line_strings_stay() {
+        let source = "{ // opens
+    // inside
+let s = \"two
+lines\";   print s;
+}
+let x = f(1, // first
+2);
+// last
+";
+        let expected = "{ // opens
+    // inside
+    let s = \"two
+lines\";
+    print s;
+}
+let x = f(1, // first
+    2);
+// last
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source("").unwrap(), "");
+        assert!(format_source("let = 1;").is_err());
+    }
+}
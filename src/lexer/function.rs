@@ -1,69 +1,180 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
 use crate::lexer::environment::Environment;
 
 use super::{
-    callable::Callable, expr::ExprLiteral, interpreter::Interpreter, stmt::Stmt, stmt::Stmt::Block,
-    stmt::Stmt::Function, token::Token,
+    callable::{Arity, Callable},
+    diagnostic::{Diagnostic, Origin, E_INTERNAL},
+    expr::{Expr, ExprLiteral},
+    interpreter::{Interpreter, IsReturn},
+    stmt::Stmt,
+    token::{Token, TokenType},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct MyFunction {
     name: Token,
     params: Vec<Token>,
-    body: Vec<Stmt>,
-    pub closure: Environment,
+    defaults: Rc<Vec<Expr>>,
+    rest: Option<Token>,
+    arity: Arity,
+    signature: String,
+    body: Rc<Vec<Stmt>>,
+    pub closure: Rc<RefCell<Environment>>, // shared with the scope the function was declared in.
+    is_initializer: bool,                  // "init" of a class always returns "this".
+    origin: Option<Rc<Origin>>,            // the file declaring it, errors in the body point there.
+    locals: Rc<HashMap<usize, usize>>,     // scope depths of the program declaring it.
 }
 impl MyFunction {
-    pub fn new(declaration: Stmt, closure: Environment) -> Result<Self, String> {
-        if let Stmt::Function { name, params, body } = declaration.clone() {
+    pub fn new(
+        declaration: Stmt,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+        origin: Option<Rc<Origin>>,
+        locals: Rc<HashMap<usize, usize>>,
+    ) -> Result<Self, Diagnostic> {
+        let arity = declaration.arity();
+        let signature = declaration.signature();
+        if let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            ..
+        } = declaration.clone()
+        {
             if let Stmt::Block { statements } = *body {
                 return Ok(Self {
                     name,
                     params,
-                    body: statements,
+                    defaults: Rc::new(defaults),
+                    rest,
+                    arity,
+                    signature,
+                    body: Rc::new(statements),
                     closure,
+                    is_initializer,
+                    origin,
+                    locals,
                 });
             }
         }
-        Err(
-            format!("Error occur when construct MyFunction, find mismatched type, expected Stmt::Function got {:?}.", declaration)
-        )
+        Err(Diagnostic::error(
+            E_INTERNAL,
+            format!(
+                "Error occur when construct MyFunction, expected Stmt::Function got {:?}.",
+                declaration
+            ),
+        ))
     }
-}
-impl Callable for MyFunction {
-    fn arity(&self) -> usize {
-        self.params.len()
+
+    // brief: Turn a method into a bound method, with "this" defined in a scope between the
+    // method's closure and its body.
+    // input: the instance to bind.
+    // output:
+    pub fn bind(&self, instance: ExprLiteral) -> MyFunction {
+        let mut environment = Environment::new(Some(self.closure.clone()));
+        environment.define("this".to_string(), instance);
+
+        let mut function = self.clone();
+        function.closure = Rc::new(RefCell::new(environment));
+        function
     }
 
-    fn call(
-        &mut self,
+    // brief: "this" of a bound initializer.
+    // input:
+    // output:
+    fn this(&self) -> Result<ExprLiteral, Diagnostic> {
+        let this = Token::new(
+            TokenType::This,
+            "this".to_string(),
+            None,
+            self.name.line_number,
+        );
+        self.closure.borrow().get_at(0, &this)
+    }
+
+    // brief: Bind the arguments and run the body.
+    // input:
+    // output:
+    fn run(
+        &self,
         interpreter: &mut Interpreter,
         arguments: Vec<ExprLiteral>,
-    ) -> Result<ExprLiteral, String> {
-        //let mut environment = Environment::new(Some(Box::new(interpreter.globals.clone())));
-        let mut environment = Environment::new(Some(Box::new(self.closure.clone())));
-
-        // define itsef into environment, sothat we can call function-self recurisivly.
-        environment.define(
-            self.name.lexeme.clone(),
-            ExprLiteral::FunctionLiteral(Box::new(self.clone())),
-        );
+    ) -> Result<ExprLiteral, Diagnostic> {
+        // A new scope for every call, its parent is the scope captured when declared.
+        let environment = Rc::new(RefCell::new(Environment::new(Some(self.closure.clone()))));
+
+        // Missing arguments take their defaults, evaluated in order so that a default can use
+        // the params before it.
+        let first_default = self.params.len() - self.defaults.len();
+        let mut arguments = arguments.into_iter();
+        for (index, param) in self.params.iter().enumerate() {
+            let value = match arguments.next() {
+                Some(value) => value,
+                None => interpreter
+                    .evaluate_in(&self.defaults[index - first_default], environment.clone())?,
+            };
+            environment.borrow_mut().define(param.lexeme.clone(), value);
+        }
+        if let Some(rest) = &self.rest {
+            environment
+                .borrow_mut()
+                .define(rest.lexeme.clone(), ExprLiteral::list(arguments.collect()));
+        }
 
-        // new  clean environment including no variables expect gloabls. so the funtion also can't be found.
+        let value = match interpreter.execute_block(&self.body, environment)? {
+            IsReturn::Yes(val) => val,
+            // The Resolver keeps break and continue inside loops.
+            IsReturn::No | IsReturn::Break | IsReturn::Continue => ExprLiteral::Nil,
+        };
 
-        for (index, item) in self.params.iter().enumerate() {
-            environment.define(item.lexeme.clone(), arguments[index].clone()); // including params. Combine virtual params with real arguments.
+        if self.is_initializer {
+            return self.this();
         }
+        Ok(value)
+    }
+}
 
-        let ans = interpreter.execute_function_block(&self.body, &mut environment);
+// The closure may hold the function itself, so Debug must not walk into it.
+impl Debug for MyFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
 
-        self.closure = environment;
+impl Callable for MyFunction {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
 
-        ans
+    fn signature(&self) -> String {
+        self.signature.clone()
     }
 
-    fn clone_box(&self) -> Box<dyn Callable> {
-        Box::new(self.clone())
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        // The body sees the globals of the script or module declaring the function, not those
+        // of the caller.
+        let globals = Environment::root(&self.closure);
+        let result = interpreter.with_program(globals, self.locals.clone(), |interpreter| {
+            self.run(interpreter, arguments)
+        });
+        match &self.origin {
+            Some(origin) => result.map_err(|err| err.or_in(origin)),
+            None => result,
+        }
     }
 
-    // Todo: add two_string()
+    fn bind_this(&self, instance: ExprLiteral) -> Option<Rc<dyn Callable>> {
+        Some(Rc::new(self.bind(instance)))
+    }
+
+    fn two_string(&self) -> String {
+        format!("<fn {}>", self.name.lexeme)
+    }
 }
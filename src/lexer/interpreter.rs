@@ -1,23 +1,54 @@
-use std::{arch::x86_64::_SIDD_SWORD_OPS, ffi::c_long};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use super::{
-    callable::MyClock,
+    callable::Callable,
+    class::{MyClass, MyInstance},
+    diagnostic::{
+        Diagnostic, Origin, TraceFrame, E_BAD_OPERAND, E_BAD_SUPERCLASS, E_INTERNAL,
+        E_NOT_CALLABLE, E_PROPERTY, E_STACK_OVERFLOW,
+    },
     environment::Environment,
     expr::{Expr, ExprLiteral},
     function::MyFunction,
-    parser::{self, Parser},
+    module::{ModuleLoader, MyModule},
+    output::Output,
+    stack::stack_address,
+    stdlib::Registry,
     stmt::Stmt,
     token::{Token, TokenType},
 };
 
+// Calls a program may nest before it fails with "Stack overflow.", the default of both backends.
+// Every call of the Interpreter also nests Rust calls, the driver runs it on a thread with a
+// stack big enough for this depth.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
 pub struct Interpreter {
-    environment: Environment, // struct to save variavle and create local scope.
-    pub globals: Environment, // global scope.
+    environment: Rc<RefCell<Environment>>, // struct to save variavle and create local scope.
+    pub globals: Rc<RefCell<Environment>>, // global scope.
+    locals: Rc<HashMap<usize, usize>>,     // Expr id => scope depth, of the running program.
+    output: Output,                        // where print writes.
+    errors: Output,                        // where report writes.
+    call_stack: Vec<(Rc<dyn Callable>, usize)>, // active calls and the line they were made on.
+    max_depth: usize,
+    stack_limit: Option<(usize, usize)>, // address the host called from, bytes calls may use.
+    origin: Option<Rc<Origin>>,          // the file being run, kept by the functions it declares.
+    modules: Rc<ModuleLoader>,           // shared with the Interpreters running the modules.
+    module_dir: PathBuf,                 // where relative imports start.
 }
 
+// How a statement finished: normally, or unwinding up to a function (Yes) or a loop.
 pub enum IsReturn {
     Yes(ExprLiteral),
     No,
+    Break,
+    Continue,
 }
 
 impl Interpreter {
@@ -25,23 +56,146 @@ impl Interpreter {
     // input:
     // output:
     pub fn new() -> Self {
-        let mut globals = Environment::new(None);
-
-        globals.define(
-            "clock".to_string(),
-            ExprLiteral::FunctionLiteral(Box::new(MyClock)),
-        );
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
+        for (name, value) in Registry::standard().entries() {
+            globals.borrow_mut().define(name.to_string(), value);
+        }
 
         Self {
             environment: globals.clone(),
             globals,
+            locals: Rc::default(),
+            output: Output::Stdout,
+            errors: Output::Stderr,
+            call_stack: vec![],
+            max_depth: MAX_CALL_DEPTH,
+            stack_limit: None,
+            origin: None,
+            modules: Rc::default(),
+            module_dir: PathBuf::from("."),
         }
     }
 
+    // brief: Change how deep calls may nest, see MAX_CALL_DEPTH.
+    // input:
+    // output:
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // brief: Fail with "Stack overflow." once calls use more than `budget` bytes of the Rust
+    // stack below this point, for hosts that can not choose the stack they run on.
+    // input: budget: bytes, must leave room for one more call on the thread's stack.
+    // output:
+    pub fn limit_stack(&mut self, budget: usize) {
+        self.stack_limit = Some((stack_address(), budget));
+    }
+
+    // brief: Name the file being run, so that errors inside its functions point into it
+    // wherever they are called from, e.g. from a module.
+    // input:
+    // output:
+    pub fn set_origin(&mut self, origin: Rc<Origin>) {
+        self.origin = Some(origin);
+    }
+
+    // brief: Load imports through `modules`, relative paths start in `dir`.
+    // input: dir: usually the directory of the script.
+    // output:
+    pub fn set_modules(&mut self, modules: Rc<ModuleLoader>, dir: &Path) {
+        self.modules = modules;
+        self.module_dir = dir.to_path_buf();
+    }
+
+    // brief: Send what print writes somewhere else than stdout.
+    // input:
+    // output:
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    // brief: Where print writes, for natives that write there too.
+    // input:
+    // output:
+    pub fn output(&mut self) -> &mut Output {
+        &mut self.output
+    }
+
+    // brief: Send what report writes somewhere else than stderr.
+    // input:
+    // output:
+    pub fn set_error_output(&mut self, errors: Output) {
+        self.errors = errors;
+    }
+
+    // brief: Write a rendered runtime error to the error sink.
+    // input: the source the diagnostic points into.
+    // output:
+    pub fn report(&mut self, diagnostic: &Diagnostic, source: &str) {
+        let _ = self.errors.write(&diagnostic.render(source)); // nowhere left to complain.
+    }
+
+    // brief: Define argc() and argv(i) natives, so that the script can read its command line.
+    // input: script path and the arguments following it.
+    // output:
+    pub fn define_script_args(&mut self, script: &str, args: Vec<String>) {
+        for (name, value) in Registry::script_args(script, args).entries() {
+            self.define_global(name, value);
+        }
+    }
+
+    // brief: Define a global variable visible from the top level scope.
+    // input:
+    // output:
+    pub fn define_global(&mut self, name: &str, value: ExprLiteral) {
+        self.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    // brief: Read a global variable.
+    // input:
+    // output: None if it was never defined.
+    pub fn global(&self, name: &str) -> Option<ExprLiteral> {
+        self.globals.borrow().lookup(name)
+    }
+
+    // brief: The scope the interpreter is currently running in.
+    // input:
+    // output:
+    pub fn environment(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    // brief: Use the scope depths computed by the Resolver for the program about to run.
+    // Functions keep those of the program declaring them, so the depths of a program are
+    // dropped once it has run and none of its functions is left, e.g. after an Engine::eval.
+    // input: Expr id => number of scopes between the use and the declaration.
+    // output:
+    pub fn resolve(&mut self, locals: HashMap<usize, usize>) {
+        self.locals = Rc::new(locals);
+    }
+
+    // brief: Run with the globals and the scope depths of the program a called function comes
+    // from, e.g. a module or an earlier eval.
+    // input:
+    // output:
+    pub fn with_program<T>(
+        &mut self,
+        globals: Rc<RefCell<Environment>>,
+        locals: Rc<HashMap<usize, usize>>,
+        run: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let saved_globals = mem::replace(&mut self.globals, globals);
+        let saved_locals = mem::replace(&mut self.locals, locals);
+        let result = run(self);
+        self.globals = saved_globals;
+        self.locals = saved_locals;
+        result
+    }
+
     // brief: Pub function to evaluate Vec<Stmt> by Match all kinds of Stmt.
     // input:
     // output:
-    pub fn interpreter(&mut self, statements: &Vec<Stmt>) -> Result<ExprLiteral, String> {
+    pub fn interpreter(&mut self, statements: &Vec<Stmt>) -> Result<ExprLiteral, Diagnostic> {
         for statement in statements {
             if let IsReturn::Yes(val) = self.execute(statement)? {
                 return Ok(val);
@@ -50,14 +204,7 @@ impl Interpreter {
         Ok(ExprLiteral::Nil)
     }
 
-    fn check_return(&self, result: IsReturn) -> Result<IsReturn, String> {
-        if let IsReturn::Yes(val) = result {
-            return Ok(IsReturn::Yes(val));
-        }
-        Ok(IsReturn::No)
-    }
-
-    fn execute(&mut self, statement: &Stmt) -> Result<IsReturn, String> {
+    fn execute(&mut self, statement: &Stmt) -> Result<IsReturn, Diagnostic> {
         match statement {
             // If just an expression.
             Stmt::Expression(v) => {
@@ -65,28 +212,22 @@ impl Interpreter {
             }
             // If a print statement.
             Stmt::Print(v) => {
-                println!("{}", (self.evaluate(v)?).two_string()); // Print Expression.
+                let value = self.evaluate(v)?;
+                self.output.print(&value.two_string())?;
             }
             // If a Var defination.
-            Stmt::Let { name, initializer } => {
-                let value;
-                if *initializer
-                    != (Expr::Literal {
-                        value: ExprLiteral::Nil,
-                    })
-                {
-                    value = self.evaluate(initializer)?;
-                    self.environment.define(name.lexeme.clone(), value); // Define variable in the temp Environment.
-                }
+            Stmt::Let {
+                name, initializer, ..
+            } => {
+                let value = self.evaluate(initializer)?;
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), value); // Define variable in the temp Environment.
             }
             // If a Block.
             Stmt::Block { statements } => {
-                self.environment = Environment::new(Some(Box::new(self.environment.clone()))); // Save temp environment.and Restore later.
-                let block_return = self.interpreter(statements)?; // Scope recursively; // return Todo
-                self.environment = *self.environment.enclosing.clone().unwrap();
-                if block_return != ExprLiteral::Nil {
-                    return Ok(IsReturn::Yes(block_return));
-                }
+                let environment = Environment::new(Some(self.environment.clone()));
+                return self.execute_block(statements, Rc::new(RefCell::new(environment)));
             }
             // If an If.
             Stmt::If {
@@ -107,75 +248,284 @@ impl Interpreter {
                 }
             }
             // If a While
-            Stmt::While { condition, body } => {
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
                 let mut while_condition = self.evaluate(condition)?;
                 while self.is_truthy(&while_condition) == ExprLiteral::True {
-                    // self.execute(body)?;
-                    if let IsReturn::Yes(val) = self.execute(body)? {
-                        return Ok(IsReturn::Yes(val));
+                    match self.execute(body)? {
+                        IsReturn::Yes(val) => return Ok(IsReturn::Yes(val)),
+                        IsReturn::Break => break,
+                        IsReturn::No | IsReturn::Continue => {}
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
                     }
                     while_condition = self.evaluate(condition)?;
                 }
             }
             // If a Function statement.
-            Stmt::Function { name, params, body } => {
-                let function = MyFunction::new(statement.clone(), self.environment.clone())?; // closure don't include function-itself
-                self.environment.define(
+            Stmt::Function { name, .. } => {
+                // The closure is the current scope itself, not a copy, so the function can see
+                // itself and every variable declared after it in the same scope.
+                let function = MyFunction::new(
+                    statement.clone(),
+                    self.environment.clone(),
+                    false,
+                    self.origin.clone(),
+                    self.locals.clone(),
+                )?;
+                self.environment.borrow_mut().define(
                     name.lexeme.clone(),
-                    ExprLiteral::FunctionLiteral(Box::new(function.clone())),
+                    ExprLiteral::FunctionLiteral(Rc::new(function)),
                 );
             }
-            Stmt::Return { keyword, value } => {
-                let return_value = if *value
-                    == (Expr::Literal {
-                        value: ExprLiteral::Nil,
-                    }) {
-                    ExprLiteral::Nil
-                } else {
-                    //println!("{:?}", self.evaluate(value)?);
-                    self.evaluate(value)?
+            Stmt::Return { value, .. } => {
+                let return_value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => ExprLiteral::Nil,
                 };
-
                 return Ok(IsReturn::Yes(return_value));
             }
+            Stmt::Break { .. } => return Ok(IsReturn::Break),
+            Stmt::Continue { .. } => return Ok(IsReturn::Continue),
+            // If a Class.
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.execute_class(name, superclass, methods)?,
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+            } => {
+                let module = self.import(path).map_err(|err| err.or_at_token(keyword))?;
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), ExprLiteral::ModuleLiteral(module));
+            }
         }
         Ok(IsReturn::No)
     }
 
-    // brief: Interperter a function block , and refresh the Global environemnt.
+    // brief: Load a module, running it on a new Interpreter that shares the loader and the
+    // output of this one.
+    // input: the path as written in the import.
+    // output:
+    fn import(&mut self, path: &str) -> Result<Rc<MyModule>, Diagnostic> {
+        let modules = self.modules.clone();
+        let from = self.module_dir.clone();
+        modules.load(path, &from, |(statements, locals), dir, origin| {
+            let mut module = Interpreter::new();
+            module.set_origin(origin);
+            module.set_max_depth(self.max_depth);
+            module.stack_limit = self.stack_limit;
+            module.set_modules(self.modules.clone(), dir);
+            module.resolve(locals);
+            module.output = mem::replace(&mut self.output, Output::Stdout);
+            module.errors = mem::replace(&mut self.errors, Output::Stderr);
+            let before = module.globals.borrow().values();
+            let result = module.interpreter(&statements);
+            self.output = mem::replace(&mut module.output, Output::Stdout);
+            self.errors = mem::replace(&mut module.errors, Output::Stderr);
+            result?;
+            Ok((before, module.globals.clone()))
+        })
+    }
+
+    // brief: Build a MyClass, methods of a subclass close over an extra scope holding "super".
+    // input:
+    // output:
+    fn execute_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &Vec<Stmt>,
+    ) -> Result<(), Diagnostic> {
+        let superclass = match superclass {
+            Some(expr) => match self.evaluate(expr)? {
+                ExprLiteral::ClassLiteral(class) => Some(class),
+                _ => {
+                    return Err(Diagnostic::error(
+                        E_BAD_SUPERCLASS,
+                        format!("Superclass of {} must be a class.", name.lexeme),
+                    )
+                    .at_token(name))
+                }
+            },
+            None => None,
+        };
+
+        self.environment
+            .borrow_mut()
+            .define(name.lexeme.clone(), ExprLiteral::Nil);
+
+        let mut closure = self.environment.clone();
+        if let Some(superclass) = &superclass {
+            let mut environment = Environment::new(Some(closure));
+            environment.define(
+                "super".to_string(),
+                ExprLiteral::ClassLiteral(superclass.clone()),
+            );
+            closure = Rc::new(RefCell::new(environment));
+        }
+
+        let mut class_methods = HashMap::new();
+        for method in methods {
+            if let Stmt::Function {
+                name: method_name, ..
+            } = method
+            {
+                let is_initializer = method_name.lexeme == "init";
+                let function = MyFunction::new(
+                    method.clone(),
+                    closure.clone(),
+                    is_initializer,
+                    self.origin.clone(),
+                    self.locals.clone(),
+                )?;
+                class_methods.insert(
+                    method_name.lexeme.clone(),
+                    Rc::new(function) as Rc<dyn Callable>,
+                );
+            }
+        }
+
+        let class = MyClass::new(name.lexeme.clone(), superclass, class_methods);
+        self.environment
+            .borrow_mut()
+            .assign(name, ExprLiteral::ClassLiteral(Rc::new(class)))
+    }
+
+    // brief: Execute statements in a new scope, and restore the previous scope afterwards,
+    // even if an error occurs. Used by blocks and function calls.
     // input:
     // output:
-    pub fn execute_function_block(
+    pub fn execute_block(
         &mut self,
         statements: &Vec<Stmt>,
-        environemnt: &mut Environment,
-    ) -> Result<ExprLiteral, String> {
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<IsReturn, Diagnostic> {
         let previous = self.environment.clone(); // save origin environment.
+        self.environment = environment;
 
-        self.environment = environemnt.clone(); // create a function call temporary environment by clone the function's closure.
+        let mut result = Ok(IsReturn::No);
+        for statement in statements {
+            result = self.execute(statement);
+            if !matches!(result, Ok(IsReturn::No)) {
+                break; // An error, a return, a break or a continue.
+            }
+        }
 
-        let return_value = self.interpreter(statements)?; // interpreter in the temp environment.
+        self.environment = previous; // restore the origin environment.
+        result
+    }
 
-        // dbg!("{:?}", self.environment.enclosing.clone());
+    // brief: Evaluate an Expression in another scope, e.g. a default in the scope of a call.
+    // input:
+    // output:
+    pub fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = self.evaluate(expr);
+        self.environment = previous;
+        result
+    }
 
-        environemnt.enclosing = self.environment.enclosing.clone(); // restore the changes back to closure.
+    // brief: Call a function or a class from the host, see call_at.
+    // input:
+    // output:
+    pub fn call(
+        &mut self,
+        callee: &ExprLiteral,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        self.call_at(callee, arguments, 0)
+    }
 
-        self.environment = previous; // restore the origin environment.
+    // brief: Call a function or a class after checking the depth and the number of arguments.
+    // An error raised inside the call gets the stack trace of that moment.
+    // input: line of the call, 0 when made by the host.
+    // output: errors without a position are left for the caller to place.
+    fn call_at(
+        &mut self,
+        callee: &ExprLiteral,
+        arguments: Vec<ExprLiteral>,
+        line: usize,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        let callable: Rc<dyn Callable> = match callee {
+            ExprLiteral::FunctionLiteral(f) => f.clone(),
+            ExprLiteral::ClassLiteral(c) => c.clone(),
+            _ => {
+                return Err(Diagnostic::error(
+                    E_NOT_CALLABLE,
+                    format!(
+                        "Can only call functions and classes, got {}.",
+                        callee.two_string()
+                    ),
+                ))
+            }
+        };
+        let too_deep = self
+            .stack_limit
+            .is_some_and(|(base, budget)| base.abs_diff(stack_address()) > budget);
+        if self.call_stack.len() >= self.max_depth || too_deep {
+            return Err(Diagnostic::error(E_STACK_OVERFLOW, "Stack overflow."));
+        }
+        let arity = callable.arity();
+        if !arity.accepts(arguments.len()) {
+            return Err(arity.mismatch(callable.as_ref(), arguments.len()));
+        }
+
+        self.call_stack.push((callable.clone(), line));
+        let result = callable
+            .call(self, arguments)
+            .map_err(|err| err.with_trace(self.trace()));
+        self.call_stack.pop();
+        result
+    }
 
-        Ok(return_value)
+    // brief: The active calls, innermost first.
+    // input:
+    // output:
+    fn trace(&self) -> Vec<TraceFrame> {
+        self.call_stack
+            .iter()
+            .rev()
+            .map(|(callable, line)| TraceFrame {
+                function: callable.two_string(),
+                line: *line,
+            })
+            .collect()
+    }
+
+    // brief: Get a variable from the scope chosen by the Resolver, or from globals if unresolved.
+    // input:
+    // output:
+    fn look_up_variable(&self, name: &Token, id: usize) -> Result<ExprLiteral, Diagnostic> {
+        match self.locals.get(&id) {
+            Some(distance) => self.environment.borrow().get_at(*distance, name),
+            None => self.globals.borrow().get(name),
+        }
     }
 
     // brief: Evaluate an Expression.
     // input:
     // output:
-    pub fn evaluate(&mut self, expr: &Expr) -> Result<ExprLiteral, String> {
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<ExprLiteral, Diagnostic> {
         self.match_expr(expr)
     }
 
     // brief: Match all kinds of Expression recursively.
     // input:
     // output:
-    fn match_expr(&mut self, expr: &Expr) -> Result<ExprLiteral, String> {
+    fn match_expr(&mut self, expr: &Expr) -> Result<ExprLiteral, Diagnostic> {
         match expr {
             // 1 Literal
             Expr::Literal { value } => Ok(value.clone()),
@@ -186,21 +536,19 @@ impl Interpreter {
             // 3 Unary
             Expr::Unary { operator, right } => {
                 if operator.token_type == TokenType::Minus {
-                    if let ExprLiteral::NumberLiteral(v) = self.evaluate(right)? {
-                        return Ok(ExprLiteral::NumberLiteral(-v));
-                    }
-                    return Err(format!(
-                        "Error occur when interpreter number at line {} at {}.",
-                        operator.line_number, operator.lexeme
-                    ));
+                    return self.evaluate(right)?.negate().map_err(|msg| {
+                        Diagnostic::error(E_BAD_OPERAND, format!("Bad operand for -: {}.", msg))
+                            .at_token(operator)
+                    });
                 } else if operator.token_type == TokenType::Bang {
                     let evaluated = self.evaluate(right)?;
-                    return Ok(self.is_truthy(&evaluated));
+                    return Ok(ExprLiteral::from_bool(!evaluated.is_truthy()));
                 }
-                Err(format!(
-                    "Error occur when interpreter at line {} at {} for no matching unary operator.",
-                    operator.line_number, operator.lexeme
-                ))
+                Err(Diagnostic::error(
+                    E_BAD_OPERAND,
+                    format!("No matching unary operator {}.", operator.lexeme),
+                )
+                .at_token(operator))
             }
             // 8 Call
             Expr::Call {
@@ -209,35 +557,163 @@ impl Interpreter {
                 arguments,
             } => {
                 let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|x| self.evaluate(x))
+                    .collect::<Result<Vec<ExprLiteral>, Diagnostic>>()?;
+                self.call_at(&callee, arguments, paren.line_number)
+                    .map_err(|err| err.or_at_token(paren))
+            }
 
-                let arguments: Result<Vec<ExprLiteral>, String> =
-                    arguments.iter().map(|x| self.evaluate(x)).collect();
-
-                if let ExprLiteral::FunctionLiteral(mut f) = callee {
-                    let args = arguments?;
-                    if args.len() != f.arity() {
-                        return Err(format!(
-                            "Error occur , function expect {} args, but got {} at line: {}.",
-                            f.arity(),
-                            args.len(),
-                            paren.line_number
-                        ));
-                    }
-                    return f.call(self, args);
+            // 9 Get
+            Expr::Get { object, name } => match self.evaluate(object)? {
+                ExprLiteral::ModuleLiteral(module) => module.get(&name.lexeme).ok_or_else(|| {
+                    Diagnostic::error(
+                        E_PROPERTY,
+                        format!("Module {} has no export {}.", module.name, name.lexeme),
+                    )
+                    .at_token(name)
+                }),
+                ExprLiteral::InstanceLiteral(instance) => MyInstance::get(&instance, &name.lexeme)
+                    .ok_or_else(|| {
+                        Diagnostic::error(
+                            E_PROPERTY,
+                            format!("Undefined property {}.", name.lexeme),
+                        )
+                        .at_token(name)
+                    }),
+                _ => Err(Diagnostic::error(
+                    E_PROPERTY,
+                    format!(
+                        "Only instances have properties, can't read {}.",
+                        name.lexeme
+                    ),
+                )
+                .at_token(name)),
+            },
+
+            // 10 Set
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => match self.evaluate(object)? {
+                ExprLiteral::InstanceLiteral(instance) => {
+                    let value = self.evaluate(value)?;
+                    instance.borrow_mut().set(&name.lexeme, value.clone());
+                    Ok(value)
+                }
+                _ => Err(Diagnostic::error(
+                    E_PROPERTY,
+                    format!("Only instances have fields, can't set {}.", name.lexeme),
+                )
+                .at_token(name)),
+            },
+
+            // 13 List
+            Expr::List { elements, .. } => {
+                let values: Result<Vec<ExprLiteral>, Diagnostic> =
+                    elements.iter().map(|x| self.evaluate(x)).collect();
+                Ok(ExprLiteral::list(values?))
+            }
+
+            // 16 Map
+            Expr::Map { brace, entries } => {
+                let mut values = vec![];
+                for (key, value) in entries {
+                    values.push((self.evaluate(key)?, self.evaluate(value)?));
+                }
+                ExprLiteral::map(values).map_err(|msg| ExprLiteral::key_error(msg).at_token(brace))
+            }
+
+            // 14 Index
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                object
+                    .get_index(&index)
+                    .map_err(|msg| ExprLiteral::index_error(msg).at_token(bracket))
+            }
+
+            // 15 SetIndex
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                object
+                    .set_index(&index, value.clone())
+                    .map_err(|msg| ExprLiteral::index_error(msg).at_token(bracket))?;
+                Ok(value)
+            }
+
+            // 11 This
+            Expr::This { id, keyword } => self.look_up_variable(keyword, *id),
+
+            // 12 Super
+            Expr::Super {
+                id,
+                keyword,
+                method,
+            } => {
+                // "this" always lives in the scope right inside the one holding "super".
+                let Some(&distance) = self.locals.get(id).filter(|distance| **distance > 0) else {
+                    return Err(Diagnostic::error(
+                        E_INTERNAL,
+                        "super was not resolved to a class scope.",
+                    )
+                    .at_token(keyword));
+                };
+                let superclass = self.environment.borrow().get_at(distance, keyword)?;
+                let this = Token::new(
+                    TokenType::This,
+                    "this".to_string(),
+                    None,
+                    keyword.line_number,
+                );
+                let object = self.environment.borrow().get_at(distance - 1, &this)?;
+
+                let ExprLiteral::ClassLiteral(superclass) = superclass else {
+                    return Err(
+                        Diagnostic::error(E_BAD_SUPERCLASS, "Superclass must be a class.")
+                            .at_token(keyword),
+                    );
+                };
+                match superclass
+                    .find_method(&method.lexeme)
+                    .and_then(|function| function.bind_this(object))
+                {
+                    Some(function) => Ok(ExprLiteral::FunctionLiteral(function)),
+                    None => Err(Diagnostic::error(
+                        E_PROPERTY,
+                        format!("Undefined property {}.", method.lexeme),
+                    )
+                    .at_token(method)),
                 }
-                Err(format!(
-                    "Error occur when interpreter a function at line : {} at {}. Expected FunctionLiteral, got others.",
-                    paren.line_number, paren.lexeme
-                ))
             }
 
             // 4 Variable
-            Expr::Variable { name } => Ok(self.environment.get(name)?), // Get variable.
+            Expr::Variable { id, name } => self.look_up_variable(name, *id), // Get variable.
 
             // 6 Assign
-            Expr::Assign { name, value } => {
+            Expr::Assign { id, name, value } => {
                 let new_value = self.evaluate(value)?; // recursively.
-                self.environment.assign(name, new_value.clone())?; // define variable.
+                match self.locals.get(id) {
+                    Some(distance) => self.environment.borrow_mut().assign_at(
+                        *distance,
+                        name,
+                        new_value.clone(),
+                    )?,
+                    None => self.globals.borrow_mut().assign(name, new_value.clone())?,
+                }
                 Ok(new_value)
             }
             // 7 Logical
@@ -269,189 +745,69 @@ impl Interpreter {
                 let left_operand = self.evaluate(left)?; // recursively.
                 let right_operand = self.evaluate(right)?; // recursively.
 
-                match operator.token_type {
-                    TokenType::Minus => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            return Ok(ExprLiteral::NumberLiteral(l_number - r_number));
-                        }
-                        Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::Slash => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            return Ok(ExprLiteral::NumberLiteral(l_number / r_number));
-                        }
-                        Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::Star => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            return Ok(ExprLiteral::NumberLiteral(l_number * r_number));
-                        }
-                        Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::Plus => match (left_operand, right_operand) {
-                        (
-                            ExprLiteral::NumberLiteral(l_number),
-                            ExprLiteral::NumberLiteral(r_number),
-                        ) => Ok(ExprLiteral::NumberLiteral(l_number + r_number)),
-
-                        (
-                            ExprLiteral::StringLiteral(l_string),
-                            ExprLiteral::StringLiteral(r_string),
-                        ) => Ok(ExprLiteral::StringLiteral(format!(
-                            "{}{}",
-                            l_string, r_string
-                        ))),
-
-                        _ => {
-                            Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                        }
+                ExprLiteral::binary(&operator.token_type, &left_operand, &right_operand).map_err(
+                    |msg| {
+                        ExprLiteral::operand_error(
+                            &operator.lexeme,
+                            msg,
+                            &left_operand,
+                            &right_operand,
+                        )
+                        .at_token(operator)
                     },
-                    TokenType::Greater => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            if l_number > r_number {
-                                return Ok(ExprLiteral::True);
-                            } else {
-                                return Ok(ExprLiteral::False);
-                            }
-                        }
-                         Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::GreaterEqual => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            if l_number >= r_number {
-                                return Ok(ExprLiteral::True);
-                            } else {
-                                return Ok(ExprLiteral::False);
-                            }
-                        }
-                         Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::Less => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            if l_number < r_number {
-                                return Ok(ExprLiteral::True);
-                            } else {
-                                return Ok(ExprLiteral::False);
-                            }
-                        }
-                         Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::LessEqual => {
-                        if let (true, l_number, r_number) =
-                            self.check_number_operands(&left_operand, &right_operand)
-                        {
-                            if l_number <= r_number {
-                                return Ok(ExprLiteral::True);
-                            } else {
-                                return Ok(ExprLiteral::False);
-                            }
-                        }
-                         Err(format!(
-                            "Error occur when interpreter at line {} at {} for some wrong operand.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    },
-                    TokenType::EqualEqual => {
-                        if left_operand == right_operand {
-                            Ok(ExprLiteral::True)
-                        } else {
-                            Ok(ExprLiteral::False)
-                        }
-                    },
-                    TokenType::BangEqual => {
-                        if left_operand  != right_operand {
-                            Ok(ExprLiteral::True)
-                        } else {
-                            Ok(ExprLiteral::False)
-                        }
-                    },
-                    _ => {
-                         Err(format!(
-                            "Error occur when interpreter at line {} at {} for no matchine Binary operator.",
-                            operator.line_number, operator.lexeme
-                        ))
-                    }
-                }
+                )
             }
         }
     }
 
-    // brief: operand is f64 ?
-    // input:
-    // output:
-    fn check_number_operands(
-        &self,
-        l_operand: &ExprLiteral,
-        r_operand: &ExprLiteral,
-    ) -> (bool, f64, f64) {
-        if let (true, v1) = self.check_number_operand(l_operand) {
-            if let (true, v2) = self.check_number_operand(r_operand) {
-                return (true, v1, v2);
-            }
-        }
-        (false, 0.0, 0.0)
-    }
-
-    // brief: operand is f64 ?
-    // input:
-    // output:
-    fn check_number_operand(&self, operand: &ExprLiteral) -> (bool, f64) {
-        if let ExprLiteral::NumberLiteral(v) = operand {
-            return (true, *v);
-        }
-        (false, 0.0)
-    }
-
     // brief: All is true but nil and false.
     // input:
     // output:
     fn is_truthy(&self, expr: &ExprLiteral) -> ExprLiteral {
-        match expr {
-            ExprLiteral::False | ExprLiteral::Nil => ExprLiteral::False,
-            _ => ExprLiteral::True,
-        }
+        ExprLiteral::from_bool(expr.is_truthy())
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::rc::Rc;
+
     use super::Interpreter;
-    use super::Parser;
-    use crate::Scanner;
+    use crate::lexer::diagnostic::{
+        E_BAD_OPERAND, E_INTERNAL, E_NATIVE, E_NOT_CALLABLE, E_UNDEFINED_VARIABLE,
+    };
+    use crate::lexer::expr::{Expr, ExprLiteral};
+    use crate::lexer::parser::Parser;
+    use crate::lexer::resolver::Resolver;
+    use crate::lexer::scanner::Scanner;
+    use crate::lexer::token::{Token, TokenType};
+
+    // brief: Run a program through the whole pipeline, and keep the Interpreter for inspection.
+    fn run(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        run_on(&mut interpreter, source);
+        interpreter
+    }
+
+    // brief: Run one more program on an Interpreter, as the REPL and an Engine do.
+    fn run_on(interpreter: &mut Interpreter, source: &str) {
+        let tok = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let pas = Parser::new(tok).parse().unwrap();
+        interpreter.resolve(Resolver::new().resolve(&pas).unwrap());
+        interpreter.interpreter(&pas).unwrap();
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> ExprLiteral {
+        let name = Token::new(TokenType::Identifier, name.to_string(), None, 0);
+        interpreter.globals.borrow().get(&name).unwrap()
+    }
 
     #[test]
     fn test_inter_one() {
@@ -460,7 +816,8 @@ mod tests {
 
         let tok = scan.scan_tokens().unwrap();
 
-        let pas = Parser::new(tok).parse().unwrap();
+        // "=" needs an l_value on its left side since assignment was added.
+        assert!(Parser::new(tok).parse().is_err());
 
         // match Interpreter::new().evaluate(&pas) {
         //     Ok(v) => {
@@ -485,7 +842,7 @@ mod tests {
         let pas = Parser::new(tok).parse().unwrap();
 
         match Interpreter::new().interpreter(&pas) {
-            Ok(()) => {
+            Ok(_) => {
                 println!("[    PASS!     ] ---> Compile Successfully.");
             }
             Err(v) => {
@@ -505,7 +862,7 @@ mod tests {
         let pas = Parser::new(tok).parse().unwrap();
 
         match Interpreter::new().interpreter(&pas) {
-            Ok(()) => {
+            Ok(_) => {
                 println!("[    PASS!     ] ---> Compile Successfully.");
             }
             Err(v) => {
@@ -525,7 +882,7 @@ mod tests {
         let pas = Parser::new(tok).parse().unwrap();
 
         match Interpreter::new().interpreter(&pas) {
-            Ok(()) => {
+            Ok(_) => {
                 println!("[    PASS!     ] ---> Compile Successfully.");
             }
             Err(v) => {
@@ -546,7 +903,7 @@ mod tests {
         let pas = Parser::new(tok).parse().unwrap();
 
         match Interpreter::new().interpreter(&pas) {
-            Ok(()) => {
+            Ok(_) => {
                 println!("[    PASS!     ] ---> Compile Successfully.");
             }
             Err(v) => {
@@ -555,6 +912,192 @@ mod tests {
         }
         //        dbg!(pas);
     }
-}
 
-// cargo test unique-keyword -- --nocapture
+    #[test]
+    fn closures_share_captured_variables() {
+        let interpreter = run("fn make_counter() {
+                let count = 0.0;
+                fn counter() { count = count + 1.0; return count; }
+                return counter;
+            }
+            let c = make_counter();
+            c(); c();
+            let d = make_counter();
+            let a = c();
+            let b = d();");
+
+        assert_eq!(global(&interpreter, "a"), ExprLiteral::NumberLiteral(3.0));
+        assert_eq!(global(&interpreter, "b"), ExprLiteral::NumberLiteral(1.0));
+    }
+
+    #[test]
+    fn later_programs_drop_the_scope_depths_of_earlier_ones() {
+        let mut interpreter = run("fn make_counter() {
+                let count = 0;
+                fn counter() { count = count + 1; return count; }
+                return counter;
+            }
+            let c = make_counter();");
+        run_on(&mut interpreter, "let x = c(); { let n = x; n = n + 1; }");
+        let depths = interpreter.locals.clone();
+        for _ in 0..3 {
+            run_on(&mut interpreter, "let x = c(); { let n = x; n = n + 1; }");
+            assert_eq!(interpreter.locals.len(), depths.len());
+        }
+        // Nothing declared by those programs is left, so their depths are gone.
+        assert_eq!(Rc::strong_count(&depths), 1);
+        // counter still finds count through the depths of the program declaring it.
+        run_on(&mut interpreter, "let last = c();");
+        assert_eq!(global(&interpreter, "last"), ExprLiteral::IntLiteral(5));
+    }
+
+    #[test]
+    fn closures_bind_lexically() {
+        let interpreter = run("let a = \"global\";
+            let first; let second;
+            {
+                fn show() { return a; }
+                first = show();
+                let a = \"block\";
+                second = show();
+            }");
+
+        let global_a = ExprLiteral::StringLiteral("global".to_string());
+        assert_eq!(global(&interpreter, "first"), global_a);
+        assert_eq!(global(&interpreter, "second"), global_a);
+    }
+
+    #[test]
+    fn return_nil_from_nested_block() {
+        let interpreter = run("fn f() { if (true) { return nil; } return 1.0; }
+            let a = f();
+            fn fib(n) { if (n < 2.0) return n; return fib(n - 1.0) + fib(n - 2.0); }
+            let b = fib(10.0);");
+
+        assert_eq!(global(&interpreter, "a"), ExprLiteral::Nil);
+        assert_eq!(global(&interpreter, "b"), ExprLiteral::NumberLiteral(55.0));
+    }
+
+    #[test]
+    fn integers_stay_integers() {
+        let interpreter = run("let a = 7 / 2; let b = 7 / 2.0; let c = 10 % 3 * 4 - 1;");
+
+        assert_eq!(global(&interpreter, "a"), ExprLiteral::IntLiteral(3));
+        assert_eq!(global(&interpreter, "b"), ExprLiteral::NumberLiteral(3.5));
+        assert_eq!(global(&interpreter, "c"), ExprLiteral::IntLiteral(3));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let interpreter = run("let sum = 0;
+            for (let i = 0; i < 10; i = i + 1) { if (i % 2 == 0) continue; if (i > 7) break; sum = sum + i; }
+            let n = 0; while (true) { n = n + 1; if (n == 5) break; }
+            fn f() { for (let i = 0; ; i = i + 1) { { let x = i; if (x == 3) return x; } } }
+            let r = f();");
+
+        assert_eq!(global(&interpreter, "sum"), ExprLiteral::IntLiteral(16));
+        assert_eq!(global(&interpreter, "n"), ExprLiteral::IntLiteral(5));
+        assert_eq!(global(&interpreter, "r"), ExprLiteral::IntLiteral(3));
+    }
+
+    #[test]
+    fn lists() {
+        let interpreter = run(
+            "let xs = [1, 2, 3]; let ys = xs; push(ys, 4); xs[0] = \"a\";
+            let total = 0; for (let x in slice(xs, 1, len(xs))) total = total + x;
+            let last = pop(xs); let chars = \"\"; for (let c in \"hello\") chars = c + chars;
+            let nested = [[1], []]; nested[1] = nested;",
+        );
+
+        assert_eq!(global(&interpreter, "total"), ExprLiteral::IntLiteral(9));
+        assert_eq!(global(&interpreter, "last"), ExprLiteral::IntLiteral(4));
+        assert_eq!(global(&interpreter, "xs").two_string(), "[\"a\", 2, 3]");
+        assert_eq!(global(&interpreter, "ys"), global(&interpreter, "xs"));
+        assert_eq!(
+            global(&interpreter, "chars"),
+            ExprLiteral::StringLiteral("olleh".to_string())
+        );
+        assert_eq!(global(&interpreter, "nested").two_string(), "[[1], [...]]");
+    }
+
+    #[test]
+    fn maps() {
+        let interpreter = run(
+            "let m = {\"a\": 1, 2: \"two\"}; m[\"b\"] = [m[\"a\"]]; m[2.0] = 2;
+            let n = len(m); let ks = keys(m); let vs = values(m);
+            let had = has(m, \"a\"); let removed = remove(m, \"a\"); let gone = has(m, \"a\");
+            let missing = remove(m, \"zzz\");",
+        );
+
+        assert_eq!(global(&interpreter, "n"), ExprLiteral::IntLiteral(3));
+        assert_eq!(global(&interpreter, "ks").two_string(), "[\"a\", 2, \"b\"]");
+        assert_eq!(global(&interpreter, "vs").two_string(), "[1, 2, [1]]");
+        assert_eq!(global(&interpreter, "had"), ExprLiteral::True);
+        assert_eq!(global(&interpreter, "removed"), ExprLiteral::IntLiteral(1));
+        assert_eq!(global(&interpreter, "gone"), ExprLiteral::False);
+        assert_eq!(global(&interpreter, "missing"), ExprLiteral::Nil);
+        assert_eq!(global(&interpreter, "m").two_string(), "{2: 2, \"b\": [1]}");
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_source() {
+        let run_err = |source: &str| {
+            let tok = Scanner::new(source.to_string()).scan_tokens().unwrap();
+            let pas = Parser::new(tok).parse().unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.define_script_args("test.noah", vec![]);
+            interpreter.resolve(Resolver::new().resolve(&pas).unwrap());
+            interpreter.interpreter(&pas).unwrap_err()
+        };
+
+        let err = run_err("let a = 1;\nprint a + nope;");
+        assert_eq!(err.code, E_UNDEFINED_VARIABLE);
+        assert_eq!((err.line, err.column), (2, 11));
+
+        let err = run_err("let a = 1;\n  a(2);");
+        assert_eq!(err.code, E_NOT_CALLABLE);
+        assert_eq!((err.line, err.column), (2, 6));
+
+        let err = run_err("print 1 + \"a\";");
+        assert_eq!(err.code, E_BAD_OPERAND);
+        assert_eq!(err.column, 9);
+
+        // Natives know nothing about positions, the call site is used instead.
+        let err = run_err("fn f() {}\nf(argv(\"x\"));");
+        assert_eq!(err.code, E_NATIVE);
+        assert_eq!((err.line, err.column), (2, 11));
+    }
+
+    #[test]
+    fn classes_with_inheritance() {
+        let interpreter = run("class A {
+                init(n) { this.n = n; }
+                get() { return this.n; }
+                twice() { return this.get() * 2; }
+            }
+            class B < A {
+                init(n) { super.init(n + 1); }
+                get() { return super.get() * 10; }
+            }
+            let b = B(1);
+            let a = b.twice();
+            let m = b.get;
+            b.n = 5;
+            let c = m();");
+
+        assert_eq!(global(&interpreter, "a"), ExprLiteral::IntLiteral(40));
+        assert_eq!(global(&interpreter, "c"), ExprLiteral::IntLiteral(50));
+    }
+
+    #[test]
+    fn unresolved_super_is_an_internal_error() {
+        let token = |token_type, lexeme: &str| Token::new(token_type, lexeme.to_string(), None, 1);
+        let expr = Expr::Super {
+            id: Expr::next_id(),
+            keyword: token(TokenType::Super, "super"),
+            method: token(TokenType::Identifier, "m"),
+        };
+        let err = Interpreter::new().evaluate(&expr).unwrap_err();
+        assert_eq!(err.code, E_INTERNAL);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use super::expr::ExprLiteral;
+
+// The hashable form of a map key. Only nil, booleans, numbers and strings can be keys.
+// Keys follow "==": 1 and 1.0 are the same key, so integral floats are stored as ints.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Int(i64),
+    Float(u64), // bits of a float with a fractional part.
+    Str(String),
+    True,
+    False,
+    Nil,
+}
+
+impl MapKey {
+    // brief: Hash a value.
+    // input:
+    // output: Err with the reason for values that can not be keys.
+    pub fn from_value(value: &ExprLiteral) -> Result<MapKey, String> {
+        match value {
+            ExprLiteral::IntLiteral(v) => Ok(MapKey::Int(*v)),
+            ExprLiteral::NumberLiteral(v) if v.is_nan() => {
+                Err("NaN can not be a map key".to_string())
+            }
+            ExprLiteral::NumberLiteral(v)
+                if v.fract() == 0.0 && *v >= i64::MIN as f64 && *v < i64::MAX as f64 =>
+            {
+                Ok(MapKey::Int(*v as i64)) // -0.0 lands here as well.
+            }
+            ExprLiteral::NumberLiteral(v) => Ok(MapKey::Float(v.to_bits())),
+            ExprLiteral::StringLiteral(v) => Ok(MapKey::Str(v.clone())),
+            ExprLiteral::True => Ok(MapKey::True),
+            ExprLiteral::False => Ok(MapKey::False),
+            ExprLiteral::Nil => Ok(MapKey::Nil),
+            // Not two_string: the key may be the map being borrowed.
+            other => Err(format!("a {} can not be a map key", other.type_name())),
+        }
+    }
+}
+
+// Runtime map, entries keep their insertion order so that printing and keys() are stable.
+#[derive(Debug, Default)]
+pub struct MyMap {
+    entries: Vec<(ExprLiteral, ExprLiteral)>,
+    index: HashMap<MapKey, usize>, // key => position in entries.
+}
+
+impl MyMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &ExprLiteral) -> Result<Option<ExprLiteral>, String> {
+        let key = MapKey::from_value(key)?;
+        Ok(self.index.get(&key).map(|i| self.entries[*i].1.clone()))
+    }
+
+    pub fn contains(&self, key: &ExprLiteral) -> Result<bool, String> {
+        Ok(self.index.contains_key(&MapKey::from_value(key)?))
+    }
+
+    // brief: Add or replace an entry, a replaced entry keeps its place.
+    // input:
+    // output:
+    pub fn insert(&mut self, key: ExprLiteral, value: ExprLiteral) -> Result<(), String> {
+        match self.index.get(&MapKey::from_value(&key)?) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.index
+                    .insert(MapKey::from_value(&key)?, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    // brief: Remove an entry.
+    // input:
+    // output: the value, None if the key was not there.
+    pub fn remove(&mut self, key: &ExprLiteral) -> Result<Option<ExprLiteral>, String> {
+        let Some(i) = self.index.remove(&MapKey::from_value(key)?) else {
+            return Ok(None);
+        };
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub fn entries(&self) -> &[(ExprLiteral, ExprLiteral)] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_follow_equality() {
+        let mut map = MyMap::new();
+        map.insert(ExprLiteral::IntLiteral(1), ExprLiteral::True)
+            .unwrap();
+        map.insert(ExprLiteral::NumberLiteral(1.0), ExprLiteral::False)
+            .unwrap();
+        map.insert(ExprLiteral::NumberLiteral(1.5), ExprLiteral::Nil)
+            .unwrap();
+        assert_eq!(map.entries().len(), 2);
+        assert_eq!(
+            map.get(&ExprLiteral::IntLiteral(1)),
+            Ok(Some(ExprLiteral::False))
+        );
+        assert!(map
+            .insert(ExprLiteral::NumberLiteral(f64::NAN), ExprLiteral::Nil)
+            .is_err());
+        assert!(map.get(&ExprLiteral::list(vec![])).is_err());
+    }
+
+    #[test]
+    fn remove_keeps_the_order() {
+        let mut map = MyMap::new();
+        for key in ["a", "b", "c"] {
+            map.insert(
+                ExprLiteral::StringLiteral(key.to_string()),
+                ExprLiteral::Nil,
+            )
+            .unwrap();
+        }
+        let b = ExprLiteral::StringLiteral("b".to_string());
+        assert_eq!(map.remove(&b), Ok(Some(ExprLiteral::Nil)));
+        assert_eq!(map.remove(&b), Ok(None));
+        let c = ExprLiteral::StringLiteral("c".to_string());
+        assert!(map.contains(&c).unwrap());
+        let keys: Vec<String> = map.entries().iter().map(|v| v.0.two_string()).collect();
+        assert_eq!(keys, vec!["a", "c"]);
+    }
+}
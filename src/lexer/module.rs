@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{
+    diagnostic::{Diagnostic, Origin, E_IMPORT, E_IMPORT_CYCLE},
+    environment::Environment,
+    expr::ExprLiteral,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    stmt::Stmt,
+};
+
+// A script loaded by `import "path" as name;`: the globals its top level defined.
+// Every import of the same file shares one MyModule, the file runs only the first time.
+pub struct MyModule {
+    pub name: String, // file name without its extension.
+    pub path: PathBuf,
+    globals: Rc<RefCell<Environment>>, // live, the functions of the module write them too.
+    natives: HashMap<String, ExprLiteral>, // the globals it started with, hidden from `get`.
+}
+
+impl MyModule {
+    // brief: What `lib.name` reads, the current value of a global of the module.
+    // input:
+    // output: None if the module did not define `name`, or left a native as it was.
+    pub fn get(&self, name: &str) -> Option<ExprLiteral> {
+        let value = self.globals.borrow().lookup(name)?;
+        match self.natives.get(name) {
+            Some(native) if *native == value => None,
+            _ => Some(value),
+        }
+    }
+
+    pub fn two_string(&self) -> String {
+        format!("<module {}>", self.name)
+    }
+}
+
+// Exports hold functions closing over the module globals, Debug must not walk into them.
+impl Debug for MyModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
+
+// Finds, runs once and caches the modules of a program, shared by the backend running the
+// script and the ones running its modules.
+// A relative path is looked up next to the importing file first, then in every directory of
+// the search path in order.
+#[derive(Default)]
+pub struct ModuleLoader {
+    search_path: Vec<PathBuf>,
+    cache: RefCell<HashMap<PathBuf, Rc<MyModule>>>, // by canonical path.
+    loading: RefCell<Vec<PathBuf>>,                 // modules being run, the outermost first.
+}
+
+// A module ready to run: its syntax tree and the scope depths found by the Resolver.
+pub type ModuleProgram = (Vec<Stmt>, HashMap<usize, usize>);
+
+impl ModuleLoader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            ..Self::default()
+        }
+    }
+
+    // brief: Mark the script the program starts from as being loaded, so that a module
+    // importing it back is an import cycle rather than a second copy of it.
+    // input:
+    // output:
+    pub fn start(&self, script: &Path) {
+        let key = script
+            .canonicalize()
+            .unwrap_or_else(|_| script.to_path_buf());
+        self.loading.borrow_mut().push(key);
+    }
+
+    // brief: The file an import names.
+    // input: the path as written, the directory of the importing file.
+    // output: Err if no candidate is a file.
+    pub fn find(&self, request: &str, from: &Path) -> Result<PathBuf, Diagnostic> {
+        let request = Path::new(request);
+        let candidates: Vec<PathBuf> = if request.is_absolute() {
+            vec![request.to_path_buf()]
+        } else {
+            std::iter::once(from)
+                .chain(self.search_path.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(request))
+                .collect()
+        };
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => {
+                let tried: Vec<String> = candidates
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                Err(Diagnostic::error(
+                    E_IMPORT,
+                    format!("Can not find module \"{}\".", request.display()),
+                )
+                .with_note(format!("tried {}", tried.join(", "))))
+            }
+        }
+    }
+
+    // brief: Load a module, running it the first time.
+    // input: the path as written, the directory of the importing file, and how to run a
+    // module: from its program, directory and origin to the globals defined before and after
+    // it ran. Functions of the module keep the origin, errors inside them point into it.
+    // output: Err if the module can not be found or read, does not compile, fails while
+    // running or imports a module being loaded. Errors from inside the module are notes.
+    pub fn load<R>(&self, request: &str, from: &Path, run: R) -> Result<Rc<MyModule>, Diagnostic>
+    where
+        R: FnOnce(ModuleProgram, &Path, Rc<Origin>) -> Result<Exports, Diagnostic>,
+    {
+        let path = self.find(request, from)?;
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Some(module) = self.cache.borrow().get(&key) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.loading.borrow().iter().position(|p| *p == key) {
+            let cycle: Vec<String> = self.loading.borrow()[start..]
+                .iter()
+                .chain(std::iter::once(&key))
+                .map(|p| file_name(p))
+                .collect();
+            return Err(Diagnostic::error(
+                E_IMPORT_CYCLE,
+                format!("Import cycle: {}.", cycle.join(" -> ")),
+            ));
+        }
+
+        let file = path.display().to_string();
+        let source = fs::read_to_string(&path).map_err(|err| {
+            Diagnostic::error(E_IMPORT, format!("Can not read {}: {}.", file, err))
+        })?;
+        let origin = Origin::new(&file, &source);
+        let failed = |err: Diagnostic| {
+            Diagnostic::error(E_IMPORT, format!("Module \"{}\" failed.", request))
+                .with_note(err.or_in(&origin).to_string())
+        };
+        let program = front_end(&source).map_err(failed)?;
+
+        self.loading.borrow_mut().push(key.clone());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let result = run(program, dir, origin.clone());
+        self.loading.borrow_mut().pop();
+        // A cycle further down is reported as it is, not as a failure of every module on it.
+        // Its position is in another file, the importing statement gives it one.
+        let (before, globals) = result.map_err(|err| match err.code {
+            E_IMPORT_CYCLE => Diagnostic::error(E_IMPORT_CYCLE, err.message),
+            _ => failed(err),
+        })?;
+
+        let module = Rc::new(MyModule {
+            name: path
+                .file_stem()
+                .map_or(file.clone(), |stem| stem.to_string_lossy().to_string()),
+            path,
+            globals,
+            natives: before.into_iter().collect(),
+        });
+        self.cache.borrow_mut().insert(key, module.clone());
+        Ok(module)
+    }
+}
+
+// The globals of a module before it ran, and the scope holding them once it ran.
+pub type Exports = (Vec<(String, ExprLiteral)>, Rc<RefCell<Environment>>);
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or(path.display().to_string(), |name| {
+        name.to_string_lossy().to_string()
+    })
+}
+
+// brief: Scan, parse and resolve a module.
+// input:
+// output: the first error, a module is checked in full by running `noah check` on it.
+fn front_end(source: &str) -> Result<ModuleProgram, Diagnostic> {
+    let first = |errors: Vec<Diagnostic>| errors.into_iter().next().unwrap();
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .map_err(first)?;
+    let statements = Parser::new(tokens).parse().map_err(first)?;
+    let locals = Resolver::new().resolve(&statements)?;
+    Ok((statements, locals))
+}
@@ -0,0 +1,416 @@
+use super::{
+    expr::{Expr, ExprLiteral},
+    stmt::Stmt,
+    token::TokenType,
+};
+
+// A pass over the syntax tree that does ahead of time what every run would do the same way:
+// - constant Binary, Unary and Logical subtrees are folded into a Literal, unless evaluating
+//   them fails, so that the error still happens at runtime at the same place;
+// - if and while on a constant condition keep only the branch that runs;
+// - statements after return, break and continue are removed, so are statements and blocks
+//   with no effect, like what is left of a desugared for loop that never runs.
+//
+// Run it on a program the Resolver accepted, and resolve the result again: a block that is
+// removed or unwrapped changes the depth of the variables used in it.
+
+// brief: Optimize a program.
+// input:
+// output: a program printing the same and failing the same way.
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    block(statements)
+}
+
+// brief: Optimize the statements of a block, dropping those that can never run.
+// input:
+// output:
+fn block(statements: Vec<Stmt>) -> Vec<Stmt> {
+    let mut out = vec![];
+    for statement in statements {
+        let Some(statement) = stmt(statement) else {
+            continue;
+        };
+        let jumps = matches!(
+            statement,
+            Stmt::Return { .. } | Stmt::Break { .. } | Stmt::Continue { .. }
+        );
+        out.push(statement);
+        if jumps {
+            break;
+        }
+    }
+    out
+}
+
+// brief: Optimize one statement.
+// input:
+// output: None if it has no effect.
+fn stmt(statement: Stmt) -> Option<Stmt> {
+    match statement {
+        Stmt::Expression(expr) => {
+            let expr = fold(expr);
+            (!is_constant(&expr)).then_some(Stmt::Expression(expr))
+        }
+        Stmt::Print(expr) => Some(Stmt::Print(fold(expr))),
+        Stmt::Let {
+            name,
+            annotation,
+            initializer,
+        } => Some(Stmt::Let {
+            name,
+            annotation,
+            initializer: fold(initializer),
+        }),
+        Stmt::Block { statements } => {
+            let statements = block(statements);
+            let declares = statements.iter().any(|statement| {
+                matches!(
+                    statement,
+                    Stmt::Let { .. }
+                        | Stmt::Function { .. }
+                        | Stmt::Class { .. }
+                        | Stmt::Import { .. }
+                )
+            });
+            let only_constant_lets = statements.iter().all(|statement| {
+                matches!(statement, Stmt::Let { initializer, .. } if is_constant(initializer))
+            });
+            match statements.len() {
+                // Variables that are never read, e.g. what is left of a for loop that never runs.
+                _ if only_constant_lets => None,
+                1 if !declares => statements.into_iter().next(),
+                _ => Some(Stmt::Block { statements }),
+            }
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = fold(condition);
+            if let Expr::Literal { value } = &condition {
+                return match value.is_truthy() {
+                    true => stmt(*then_branch),
+                    false => else_branch.and_then(|branch| stmt(*branch)),
+                };
+            }
+            let then_branch = stmt(*then_branch);
+            let else_branch = else_branch.and_then(|branch| stmt(*branch));
+            match (then_branch, else_branch) {
+                (None, None) => stmt(Stmt::Expression(condition)),
+                (then_branch, else_branch) => Some(Stmt::If {
+                    condition,
+                    then_branch: Box::new(then_branch.unwrap_or(empty())),
+                    else_branch: else_branch.map(Box::new),
+                }),
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => {
+            let condition = fold(condition);
+            if matches!(&condition, Expr::Literal { value } if !value.is_truthy()) {
+                return None;
+            }
+            Some(Stmt::While {
+                condition,
+                body: Box::new(stmt(*body).unwrap_or(empty())),
+                increment: increment
+                    .map(fold)
+                    .filter(|increment| !is_constant(increment)),
+            })
+        }
+        function @ Stmt::Function { .. } => Some(self::function(function)),
+        Stmt::Return { keyword, value } => Some(Stmt::Return {
+            keyword,
+            value: value.map(fold),
+        }),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => Some(Stmt::Class {
+            name,
+            superclass,
+            methods: methods.into_iter().map(function).collect(),
+        }),
+        other @ (Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Import { .. }) => Some(other),
+    }
+}
+
+// brief: Optimize the defaults and the body of a function, the body stays a block.
+// input: a Stmt::Function.
+// output:
+fn function(function: Stmt) -> Stmt {
+    let Stmt::Function {
+        name,
+        params,
+        defaults,
+        rest,
+        body,
+        annotations,
+        returns,
+    } = function
+    else {
+        return function;
+    };
+    let body = match *body {
+        Stmt::Block { statements } => Stmt::Block {
+            statements: block(statements),
+        },
+        other => other,
+    };
+    Stmt::Function {
+        name,
+        params,
+        defaults: defaults.into_iter().map(fold).collect(),
+        rest,
+        body: Box::new(body),
+        annotations,
+        returns,
+    }
+}
+
+// brief: Fold the constant subtrees of an expression.
+// input:
+// output:
+fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (fold(*left), fold(*right));
+            if let (Some(l), Some(r)) = (scalar(&left), scalar(&right)) {
+                if let Some(value) = ExprLiteral::binary(&operator.token_type, l, r)
+                    .ok()
+                    .and_then(finite)
+                {
+                    return Expr::Literal { value };
+                }
+            }
+            Expr::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Unary { operator, right } => {
+            let right = fold(*right);
+            if let Some(value) = scalar(&right) {
+                let value = match operator.token_type {
+                    TokenType::Minus => value.negate().ok(),
+                    TokenType::Bang => Some(ExprLiteral::from_bool(!value.is_truthy())),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    return Expr::Literal { value };
+                }
+            }
+            Expr::Unary {
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (fold(*left), fold(*right));
+            // "or" gives its left operand when it is truthy, "and" when it is not.
+            if let Some(value) = scalar(&left) {
+                let keeps_left = value.is_truthy() == (operator.token_type == TokenType::Or);
+                return if keeps_left { left } else { right };
+            }
+            Expr::Logical {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Grouping { expression } => match fold(*expression) {
+            literal @ Expr::Literal { .. } => literal,
+            expression => Expr::Grouping {
+                expression: Box::new(expression),
+            },
+        },
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => {
+            let callee = fold(*callee);
+            let arguments: Vec<Expr> = arguments.into_iter().map(fold).collect();
+            // to_string of a constant, from the interpolation of one.
+            if let (
+                Expr::Literal {
+                    value: ExprLiteral::FunctionLiteral(function),
+                },
+                [argument],
+            ) = (&callee, arguments.as_slice())
+            {
+                if let (true, Some(value)) = (
+                    function.two_string() == "<native fn to_string>",
+                    scalar(argument),
+                ) {
+                    if let Ok(value) = function.call_native(vec![value.clone()]) {
+                        return Expr::Literal { value };
+                    }
+                }
+            }
+            Expr::Call {
+                callee: Box::new(callee),
+                paren,
+                arguments,
+            }
+        }
+        Expr::Assign { id, name, value } => Expr::Assign {
+            id,
+            name,
+            value: Box::new(fold(*value)),
+        },
+        Expr::Get { object, name } => Expr::Get {
+            object: Box::new(fold(*object)),
+            name,
+        },
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => Expr::Set {
+            object: Box::new(fold(*object)),
+            name,
+            value: Box::new(fold(*value)),
+        },
+        Expr::List { bracket, elements } => Expr::List {
+            bracket,
+            elements: elements.into_iter().map(fold).collect(),
+        },
+        Expr::Index {
+            object,
+            bracket,
+            index,
+        } => Expr::Index {
+            object: Box::new(fold(*object)),
+            bracket,
+            index: Box::new(fold(*index)),
+        },
+        Expr::SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+        } => Expr::SetIndex {
+            object: Box::new(fold(*object)),
+            bracket,
+            index: Box::new(fold(*index)),
+            value: Box::new(fold(*value)),
+        },
+        Expr::Map { brace, entries } => Expr::Map {
+            brace,
+            entries: entries
+                .into_iter()
+                .map(|(key, value)| (fold(key), fold(value)))
+                .collect(),
+        },
+        other @ (Expr::Literal { .. }
+        | Expr::Variable { .. }
+        | Expr::This { .. }
+        | Expr::Super { .. }) => other,
+    }
+}
+
+// brief: The value of a literal number, string, boolean or nil.
+// input:
+// output: None for anything else, other values are shared or have an identity.
+fn scalar(expr: &Expr) -> Option<&ExprLiteral> {
+    match expr {
+        Expr::Literal {
+            value:
+                value @ (ExprLiteral::IntLiteral(_)
+                | ExprLiteral::NumberLiteral(_)
+                | ExprLiteral::StringLiteral(_)
+                | ExprLiteral::True
+                | ExprLiteral::False
+                | ExprLiteral::Nil),
+        } => Some(value),
+        _ => None,
+    }
+}
+
+// Infinities and NaN have no literal to print the folded tree back to source with.
+fn finite(value: ExprLiteral) -> Option<ExprLiteral> {
+    match value {
+        ExprLiteral::NumberLiteral(v) if !v.is_finite() => None,
+        value => Some(value),
+    }
+}
+
+// Whether evaluating an expression can do nothing but give a value.
+fn is_constant(expr: &Expr) -> bool {
+    scalar(expr).is_some()
+}
+
+fn empty() -> Stmt {
+    Stmt::Block { statements: vec![] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, printer::Printer, scanner::Scanner};
+
+    fn optimized(source: &str) -> String {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Printer::program(&optimize(statements))
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        assert_eq!(optimized("print 1 + 2 * 3;"), "print 7;\n");
+        assert_eq!(optimized("print -(1 - 3) < 4;"), "print true;\n");
+        assert_eq!(optimized("print \"n = ${2 * 3}\";"), "print \"n = 6\";\n");
+        assert_eq!(optimized("print nil or x;"), "print x;\n");
+        assert_eq!(optimized("print 1 and 2 + x;"), "print 2 + x;\n");
+        assert_eq!(optimized("print x + 1 * 2;"), "print x + 2;\n");
+        // What fails at runtime is left for the runtime to report.
+        assert_eq!(optimized("print 1 / 0;"), "print 1 / 0;\n");
+        assert_eq!(optimized("print 1 + \"a\";"), "print 1 + \"a\";\n");
+        assert_eq!(optimized("print 1 / 0.0;"), "print 1 / 0.0;\n");
+    }
+
+    #[test]
+    fn dead_code_is_removed() {
+        assert_eq!(optimized("if (1 > 2) print 1; else print 2;"), "print 2;\n");
+        assert_eq!(optimized("if (nil) print 1; 1 + 2; while (false) f();"), "");
+        assert_eq!(
+            optimized("fn f() { return 1; print 2; }"),
+            "fn f() {\n    return 1;\n}\n"
+        );
+        assert_eq!(
+            optimized("while (x) { break; x = 1; }"),
+            "while (x) break;\n"
+        );
+        assert_eq!(optimized("if (x) {} else {}"), "x;\n");
+        assert_eq!(optimized("{ print x; }"), "print x;\n");
+    }
+
+    #[test]
+    fn desugared_for_loops_are_simplified() {
+        assert_eq!(optimized("for (let i = 0; false; i = i + 1) print i;"), "");
+        assert_eq!(
+            optimized("for (i = 0; 1 > 2; i = i + 1) print i;"),
+            "i = 0;\n"
+        );
+        assert_eq!(
+            optimized("for (let i = 0; i < 1 + 2; i = i + 1) print i * (2 * 2);"),
+            "for (let i = 0; i < 3; i = i + 1) print i * 4;\n"
+        );
+        assert_eq!(optimized("for (;;) { 1; }"), "while (true) {}\n");
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufWriter, Write},
+    rc::Rc,
+};
+
+use super::diagnostic::{Diagnostic, E_OUTPUT};
+
+// Where the text of a program goes: what it prints, or the errors reported for it.
+// Both backends write through one, so hosts and tests can capture the exact output.
+pub enum Output {
+    Stdout,
+    Stderr,
+    Buffer(Rc<RefCell<String>>), // shared with whoever reads the text back.
+    File(BufWriter<File>),       // flushed when dropped.
+    Callback(Box<dyn FnMut(&str)>),
+}
+
+impl Output {
+    // brief: An in-memory sink.
+    // input:
+    // output: the sink and the buffer it appends to.
+    pub fn buffer() -> (Output, Rc<RefCell<String>>) {
+        let buffer = Rc::new(RefCell::new(String::new()));
+        (Output::Buffer(buffer.clone()), buffer)
+    }
+
+    // brief: A sink writing into a new file, or truncating an existing one.
+    // input:
+    // output:
+    pub fn file(path: &str) -> io::Result<Output> {
+        Ok(Output::File(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn callback(callback: impl FnMut(&str) + 'static) -> Output {
+        Output::Callback(Box::new(callback))
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(text.as_bytes()),
+            Output::Stderr => io::stderr().lock().write_all(text.as_bytes()),
+            Output::Buffer(buffer) => {
+                buffer.borrow_mut().push_str(text);
+                Ok(())
+            }
+            Output::File(file) => file.write_all(text.as_bytes()),
+            Output::Callback(callback) => {
+                callback(text);
+                Ok(())
+            }
+        }
+    }
+
+    // brief: Push buffered text out, e.g. a prompt that does not end with a newline.
+    // input:
+    // output:
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => file.flush(),
+            Output::Buffer(_) | Output::Callback(_) => Ok(()),
+        }
+    }
+
+    // brief: Write one line for a print statement.
+    // input:
+    // output: Err if the sink failed, e.g. a closed pipe or a full disk.
+    pub fn print(&mut self, line: &str) -> Result<(), Diagnostic> {
+        self.write(&format!("{}\n", line)).map_err(|err| {
+            Diagnostic::error(E_OUTPUT, format!("Can not write the output: {}.", err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sinks_receive_the_text() {
+        let (mut output, buffer) = Output::buffer();
+        output.print("a").unwrap();
+        output.print("b").unwrap();
+        assert_eq!(*buffer.borrow(), "a\nb\n");
+
+        let lines = Rc::new(RefCell::new(vec![]));
+        let seen = lines.clone();
+        let mut output = Output::callback(move |text| seen.borrow_mut().push(text.to_string()));
+        output.print("c").unwrap();
+        assert_eq!(*lines.borrow(), vec!["c\n"]);
+    }
+}
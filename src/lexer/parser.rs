@@ -1,12 +1,20 @@
+use std::rc::Rc;
+
 use super::{
+    diagnostic::{
+        Diagnostic, E_BAD_PARAMS, E_EXPECT_EXPRESSION, E_EXPECT_TOKEN, E_INVALID_ASSIGN,
+        E_TOO_MANY_ARGS,
+    },
     expr::{Expr, ExprLiteral},
+    stdlib,
     stmt::Stmt,
     token::{LiterialValue, Token, TokenType},
 };
 
 pub struct Parser {
-    tokens: Vec<Token>, //
-    current: usize,     // num to index when parse Vec<Token>
+    tokens: Vec<Token>,      //
+    current: usize,          // num to index when parse Vec<Token>
+    errors: Vec<Diagnostic>, // every error met so far, parsing goes on after synchronize().
 }
 
 impl Parser {
@@ -14,13 +22,19 @@ impl Parser {
     // input:
     // output:
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            errors: vec![],
+        }
     }
 
     /*
     program -> declaration * EOF
 
-    declaration -> letDecl | statement | funDecl
+    declaration -> classDecl | letDecl | statement | funDecl
+
+    classDecl -> "class" Identifier ( "<" Identifier ) ? "{" function * "}"
 
     funDecl -> "fn" function
 
@@ -46,7 +60,8 @@ impl Parser {
 
     expression -> assignment
 
-    assignment -> Identifier "=" assignment | logic_or
+    assignment -> ( call "." ) ? Identifier "=" assignment | call "[" expression "]" "=" assignment
+                | logic_or
 
     logic_or -> logic_and ( "or" logic_and) *
 
@@ -58,77 +73,139 @@ impl Parser {
 
     term -> factor ( ( "-" | "+" ) factor ) *
 
-    factor -> unary ( ( "/" | "*") unary ) *
+    factor -> unary ( ( "/" | "*" | "%" ) unary ) *
 
     unary -> ( ( "!" | "-" ) unary ) | call
 
-    call -> primary ( "(" arguments ? ")" ) *
+    call -> primary ( "(" arguments ? ")" | "." Identifier | "[" expression "]" ) *
 
     arguments -> expression ( "," expression ) *
 
-    primary -> NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | Identifier
+    primary -> NUMBER | STRING | "true" | "false" | "nil" | "this" | "(" expression ")" | Identifier
+             | "super" "." Identifier | "[" ( expression ( "," expression ) * "," ? ) ? "]"
+             | "{" ( expression ":" expression ( "," expression ":" expression ) * "," ? ) ? "}"
+
+    A "{" starting a statement always opens a block, a map literal in that place must be written
+    inside parentheses: ({"a": 1})["a"];
     */
 
     // brief: Pub function used to Parse a Vec<Stmt>.
     // input:
-    // output:
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    // output: Err with every error found, if there is any.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let (statements, errors) = self.parse_partial();
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // brief: Parse the whole input, recovering after every error.
+    // input:
+    // output: The statements that parsed fine, and every error met on the way.
+    pub fn parse_partial(&mut self) -> (Vec<Stmt>, Vec<Diagnostic>) {
         let mut statements = vec![];
         while !self.is_at_end() {
             // while not at end, continue to call self.declaration().
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
-        Ok(statements)
+        (statements, std::mem::take(&mut self.errors))
     }
 
-    // brief: declaration -> letDecl | statement | funDecl
+    // brief: declaration -> classDecl | letDecl | importDecl | statement | funDecl
     // input:
-    // output:
-    fn declaration(&mut self) -> Result<Stmt, String> {
-        if self.match_tokens(&[TokenType::Let]) {
-            match self.let_declaration() {
-                Ok(v) => {
-                    return Ok(v);
-                }
-                Err(err) => {
-                    self.synchronize();
-                    return Err(err);
-                }
+    // output: None if the declaration is broken, the error is recorded and the parser
+    // synchronized to the next statement.
+    fn declaration(&mut self) -> Option<Stmt> {
+        let result = if self.match_tokens(&[TokenType::CLass]) {
+            self.class_declaration()
+        } else if self.match_tokens(&[TokenType::Let]) {
+            self.let_declaration()
+        } else if self.match_tokens(&[TokenType::Fn]) {
+            self.function("function".to_string())
+        } else if self.match_tokens(&[TokenType::Import]) {
+            self.import_declaration()
+        } else {
+            self.statement()
+        };
+
+        match result {
+            Ok(v) => Some(v),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
             }
         }
+    }
 
-        if self.match_tokens(&[TokenType::Fn]) {
-            match self.function("function".to_string()) {
-                Ok(v) => {
-                    return Ok(v);
-                }
-                Err(err) => {
-                    self.synchronize();
-                    return Err(err);
-                }
-            }
+    // brief: classDecl -> "class" Identifier ( "<" Identifier ) ? "{" function * "}"
+    // input:
+    // output:
+    fn class_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let name = self.consume(TokenType::Identifier)?;
+
+        let mut superclass = None;
+        if self.match_tokens(&[TokenType::Less]) {
+            superclass = Some(Expr::Variable {
+                id: Expr::next_id(),
+                name: self.consume(TokenType::Identifier)?,
+            });
         }
-        match self.statement() {
-            Ok(v) => Ok(v),
-            Err(err) => {
-                self.synchronize(); // Todo: Check, the return of parse() function will be changed. not a single string, but Vec<String>
-                Err(err)
-            }
+
+        self.consume(TokenType::LeftBrace)?;
+
+        let mut methods = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method".to_string())?); // Methods have no "fn".
         }
+
+        self.consume(TokenType::RightBrace)?;
+
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+        })
     }
 
-    // brief: function -> Identifier "(" parameters ? ")" block
+    // brief: function -> Identifier "(" parameters ? ")" ( "->" type ) ? block
     // input:
     // output:
-    fn function(&mut self, kind: String) -> Result<Stmt, String> {
+    fn function(&mut self, _kind: String) -> Result<Stmt, Diagnostic> {
         let name = self.consume(TokenType::Identifier)?;
         self.consume(TokenType::LeftParen)?;
 
         let mut params = vec![];
+        let mut defaults = vec![];
+        let mut rest = None;
+        let mut annotations = vec![];
 
         if !self.check(TokenType::RightParen) {
             loop {
-                params.push(self.consume(TokenType::Identifier)?);
+                if self.match_tokens(&[TokenType::Ellipsis]) {
+                    rest = Some(self.consume(TokenType::Identifier)?);
+                    annotations.push(self.annotation()?);
+                    break; // the rest parameter is the last one, ")" must follow.
+                }
+                let param = self.consume(TokenType::Identifier)?;
+                annotations.push(self.annotation()?);
+                if self.match_tokens(&[TokenType::Equal]) {
+                    defaults.push(self.expression()?);
+                } else if !defaults.is_empty() {
+                    return Err(Diagnostic::error(
+                        E_BAD_PARAMS,
+                        format!(
+                            "Parameter {} needs a default, it follows one that has a default.",
+                            param.lexeme
+                        ),
+                    )
+                    .at_token(&param));
+                }
+                params.push(param);
                 if !self.match_tokens(&[TokenType::Comma]) {
                     break;
                 }
@@ -136,28 +213,72 @@ impl Parser {
         }
         let paren = self.consume(TokenType::RightParen)?;
 
-        if params.len() >= 255 {
-            return Err(format!(
-                "There are too many parameters at line {} at {}.",
-                paren.line_number, paren.lexeme
-            ));
+        if params.len() + rest.is_some() as usize >= 255 {
+            return Err(
+                Diagnostic::error(E_TOO_MANY_ARGS, "Can't have 255 or more parameters.")
+                    .at_token(&paren),
+            );
         }
 
+        let returns = if self.match_tokens(&[TokenType::Arrow]) {
+            Some(self.type_name()?)
+        } else {
+            None
+        };
+
         self.consume(TokenType::LeftBrace)?;
 
         let body = Box::new(self.block()?);
 
-        Ok(Stmt::Function { name, params, body })
+        Ok(Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            annotations,
+            returns,
+        })
     }
 
-    // parameters -> Identifier ("," Identifier ) *
+    // parameters -> parameter ( "," parameter ) * ( "," "..." Identifier ( ":" type ) ? ) ?
+    //             | "..." Identifier ( ":" type ) ?
+    // parameter -> Identifier ( ":" type ) ? ( "=" expression ) ?
+
+    // brief: type -> Identifier | "nil" | "fn"
+    // Any name parses, the TypeChecker tells the types it knows.
+    // input:
+    // output:
+    fn type_name(&mut self) -> Result<Token, Diagnostic> {
+        if self.match_tokens(&[TokenType::Identifier, TokenType::Nil, TokenType::Fn]) {
+            Ok(self.previous())
+        } else {
+            Err(Diagnostic::error(
+                E_EXPECT_TOKEN,
+                format!("Expected a type, found {}.", self.peek().lexeme),
+            )
+            .at_token(&self.peek()))
+        }
+    }
+
+    // brief: The optional ( ":" type ) after a variable or a parameter.
+    // input:
+    // output:
+    fn annotation(&mut self) -> Result<Option<Token>, Diagnostic> {
+        if self.match_tokens(&[TokenType::Colon]) {
+            Ok(Some(self.type_name()?))
+        } else {
+            Ok(None)
+        }
+    }
 
-    // brief: letDecl -> "let" Identifier ( "=" expression ) ? ";"
+    // brief: letDecl -> "let" Identifier ( ":" type ) ? ( "=" expression ) ? ";"
     // input:
 
     // output:
-    fn let_declaration(&mut self) -> Result<Stmt, String> {
+    fn let_declaration(&mut self) -> Result<Stmt, Diagnostic> {
         let name = self.consume(TokenType::Identifier)?;
+        let annotation = self.annotation()?;
         let mut initializer = Expr::Literal {
             value: ExprLiteral::Nil,
         };
@@ -166,13 +287,41 @@ impl Parser {
         }
         let _ = self.consume(TokenType::Semicolon)?;
 
-        Ok(Stmt::Let { name, initializer })
+        Ok(Stmt::Let {
+            name,
+            annotation,
+            initializer,
+        })
+    }
+
+    // brief: importDecl -> "import" String "as" Identifier ";"
+    // input:
+    // output:
+    fn import_declaration(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword = self.previous();
+        let path = self.consume(TokenType::String)?;
+        let Some(LiterialValue::StringValue(path)) = path.literial else {
+            return Err(Diagnostic::error(
+                E_EXPECT_TOKEN,
+                "Expected the path of the module as a string.",
+            )
+            .at_token(&path));
+        };
+        self.consume(TokenType::As)?;
+        let name = self.consume(TokenType::Identifier)?;
+        self.consume(TokenType::Semicolon)?;
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            name,
+        })
     }
 
     // brief: statement -> exprStmt | printStmt | block | ifStmt | whileStmt | returnStmt
+    //                      | breakStmt | continueStmt
     // input:
     // output:
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn statement(&mut self) -> Result<Stmt, Diagnostic> {
         if self.match_tokens(&[TokenType::Print]) {
             self.print_statement()
         } else if self.match_tokens(&[TokenType::LeftBrace]) {
@@ -185,6 +334,14 @@ impl Parser {
             self.for_statement() // Syntactic sugar.
         } else if self.match_tokens(&[TokenType::Return]) {
             self.return_statement()
+        } else if self.match_tokens(&[TokenType::Break]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Semicolon)?;
+            Ok(Stmt::Break { keyword })
+        } else if self.match_tokens(&[TokenType::Continue]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Semicolon)?;
+            Ok(Stmt::Continue { keyword })
         } else {
             self.expression_statement()
         }
@@ -193,13 +350,11 @@ impl Parser {
     // brief: returnStmt -> return expression ? ";"
     // input:
     // output:
-    fn return_statement(&mut self) -> Result<Stmt, String> {
+    fn return_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let keyword = self.previous();
-        let mut value = Expr::Literal {
-            value: ExprLiteral::Nil,
-        };
+        let mut value = None;
         if !self.check(TokenType::Semicolon) {
-            value = self.expression()?;
+            value = Some(self.expression()?);
         }
         self.consume(TokenType::Semicolon)?;
 
@@ -208,14 +363,23 @@ impl Parser {
 
     // for ( initializer condition increment ) body
     // -----------------Syntactic sugar----------------------
-    // { initializer while ( condition ) { body increment } }
+    // { initializer while ( condition ) body increment }
+    // The increment is kept apart from the body, so that "continue" does not skip it.
 
     // brief: for_statement -> "for" "(" ( letDecl | exprStmt | ";" ) expression ? ";" expression ? ")" statement
+    //                       | "for" "(" "let" Identifier "in" expression ")" statement
     // input:
     // output:
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    fn for_statement(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(TokenType::LeftParen)?;
 
+        if self.check(TokenType::Let)
+            && self.token_type_at(1) == Some(TokenType::Identifier)
+            && self.token_type_at(2) == Some(TokenType::In)
+        {
+            return self.for_in_statement();
+        }
+
         let initializer = if self.match_tokens(&[TokenType::Semicolon]) {
             None
         } else if self.match_tokens(&[TokenType::Let]) {
@@ -242,32 +406,118 @@ impl Parser {
 
         self.consume(TokenType::RightParen)?;
 
-        let mut body = self.statement()?;
-
-        if increment.is_some() {
-            body = Stmt::Block {
-                statements: vec![body, Stmt::Expression(increment.unwrap())],
-            }
-        }
-
-        body = Stmt::While {
+        let mut body = Stmt::While {
             condition: condition.unwrap(),
-            body: Box::new(body),
+            body: Box::new(self.statement()?),
+            increment,
         };
 
-        if initializer.is_some() {
+        if let Some(initializer) = initializer {
             body = Stmt::Block {
-                statements: vec![initializer.unwrap(), body],
+                statements: vec![initializer, body],
             }
         }
 
         Ok(body)
     }
 
+    // for ( let x in sequence ) body
+    // -----------------Syntactic sugar----------------------
+    // { let @sequence = items(sequence); let @index = 0;
+    //   while ( @index < len(@sequence) ) { let x = @sequence[@index]; body } @index = @index + 1 }
+    // The hidden names can not be written in a script, items and len are the natives themselves
+    // so that a script defining its own len does not break the loop.
+
+    // brief: The part of a for-in after "(".
+    // input:
+    // output:
+    fn for_in_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        self.consume(TokenType::Let)?;
+        let name = self.consume(TokenType::Identifier)?;
+        let keyword = self.consume(TokenType::In)?;
+        let sequence = self.expression()?;
+        self.consume(TokenType::RightParen)?;
+        let body = self.statement()?;
+
+        let token = |token_type: TokenType, lexeme: &str| Token {
+            token_type,
+            lexeme: lexeme.to_string(),
+            literial: None,
+            ..keyword.clone()
+        };
+        let sequence_name = token(TokenType::Identifier, "@sequence");
+        let index_name = token(TokenType::Identifier, "@index");
+        let variable = |name: &Token| Expr::Variable {
+            id: Expr::next_id(),
+            name: name.clone(),
+        };
+        let int = |value: i64| Expr::Literal {
+            value: ExprLiteral::IntLiteral(value),
+        };
+
+        let condition = Expr::Binary {
+            left: Box::new(variable(&index_name)),
+            operator: token(TokenType::Less, "<"),
+            right: Box::new(Expr::Call {
+                callee: Box::new(Expr::Literal {
+                    value: ExprLiteral::FunctionLiteral(Rc::new(stdlib::len())),
+                }),
+                paren: token(TokenType::RightParen, ")"),
+                arguments: vec![variable(&sequence_name)],
+            }),
+        };
+        let increment = Expr::Assign {
+            id: Expr::next_id(),
+            name: index_name.clone(),
+            value: Box::new(Expr::Binary {
+                left: Box::new(variable(&index_name)),
+                operator: token(TokenType::Plus, "+"),
+                right: Box::new(int(1)),
+            }),
+        };
+        let item = Stmt::Let {
+            name,
+            annotation: None,
+            initializer: Expr::Index {
+                object: Box::new(variable(&sequence_name)),
+                bracket: token(TokenType::LeftBracket, "["),
+                index: Box::new(variable(&index_name)),
+            },
+        };
+
+        Ok(Stmt::Block {
+            statements: vec![
+                Stmt::Let {
+                    name: sequence_name,
+                    annotation: None,
+                    initializer: Expr::Call {
+                        callee: Box::new(Expr::Literal {
+                            value: ExprLiteral::FunctionLiteral(Rc::new(stdlib::items())),
+                        }),
+                        paren: token(TokenType::RightParen, ")"),
+                        arguments: vec![sequence],
+                    },
+                },
+                Stmt::Let {
+                    name: index_name,
+                    annotation: None,
+                    initializer: int(0),
+                },
+                Stmt::While {
+                    condition,
+                    body: Box::new(Stmt::Block {
+                        statements: vec![item, body],
+                    }),
+                    increment: Some(increment),
+                },
+            ],
+        })
+    }
+
     // brief: whileStmt -> "while" "(" expression ")" statement
     // input:
     // output:
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn while_statement(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(TokenType::LeftParen)?;
 
         let condition = self.expression()?;
@@ -276,13 +526,17 @@ impl Parser {
 
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::While { condition, body })
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+        })
     }
 
     // brief: ifStmt -> "if" "(" expression ")" statement ("else" statement ) ?
     // input:
     // output:
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    fn if_statement(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(TokenType::LeftParen)?;
 
         let condition = self.expression()?;
@@ -307,7 +561,7 @@ impl Parser {
     // brief: printstmt -> "print" expression ";"
     // input:
     // output:
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn print_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let expr = self.expression()?;
 
         self.consume(TokenType::Semicolon)?;
@@ -318,7 +572,7 @@ impl Parser {
     // brief: exprStmt -> expression ";"
     // input:
     // output:
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<Stmt, Diagnostic> {
         let expr = self.expression()?;
 
         self.consume(TokenType::Semicolon)?;
@@ -330,11 +584,13 @@ impl Parser {
     // input:
     // output:
     // Attention: "{" needed to be consumed before calling block().
-    fn block(&mut self) -> Result<Stmt, String> {
+    fn block(&mut self) -> Result<Stmt, Diagnostic> {
         let mut statements = vec![];
         // is_at_end check for forgeting closing "}"
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
         self.consume(TokenType::RightBrace)?;
         Ok(Stmt::Block { statements })
@@ -343,28 +599,48 @@ impl Parser {
     // brief: expression -> assignment
     // input:
     // output:
-    fn expression(&mut self) -> Result<Expr, String> {
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
         self.assignment()
     }
 
-    // brief: assignment -> Identifier "=" assignment | logic_or
+    // brief: assignment -> ( call "." ) ? Identifier "=" assignment | logic_or
     // input:
     // output:
-    fn assignment(&mut self) -> Result<Expr, String> {
+    fn assignment(&mut self) -> Result<Expr, Diagnostic> {
         let expr = self.logic_or()?;
         if self.match_tokens(&[TokenType::Equal]) {
             let equals = self.previous();
             let value = self.assignment()?;
-            if let Expr::Variable { name } = expr {
+            if let Expr::Variable { name, .. } = expr {
                 return Ok(Expr::Assign {
+                    id: Expr::next_id(),
+                    name,
+                    value: Box::new(value),
+                });
+            } else if let Expr::Get { object, name } = expr {
+                return Ok(Expr::Set {
+                    object,
                     name,
                     value: Box::new(value),
                 });
+            } else if let Expr::Index {
+                object,
+                bracket,
+                index,
+            } = expr
+            {
+                return Ok(Expr::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                });
             } else {
-                return Err(format!(
-                    "Error occurs when assignment at line: {} at {}.",
-                    equals.line_number, equals.lexeme
-                ));
+                return Err(
+                    Diagnostic::error(E_INVALID_ASSIGN, "Invalid assignment target.")
+                        .at_token(&equals)
+                        .with_note("only variables, properties and list items can be assigned to"),
+                );
             }
         }
         Ok(expr)
@@ -373,7 +649,7 @@ impl Parser {
     // brief: logic_or -> logic_and ( "or" logic_and) *
     // input:
     // output:
-    fn logic_or(&mut self) -> Result<Expr, String> {
+    fn logic_or(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.logic_and()?;
 
         while self.match_tokens(&[TokenType::Or]) {
@@ -392,7 +668,7 @@ impl Parser {
     // brief: logic_and -> equality ( "and" equality) *
     // input:
     // output:
-    fn logic_and(&mut self) -> Result<Expr, String> {
+    fn logic_and(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.equality()?;
 
         while self.match_tokens(&[TokenType::And]) {
@@ -411,7 +687,7 @@ impl Parser {
     // brief: equality -> comparision ( ("!=" | "==") comparision  ) *
     // input:
     // output:
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.comparision()?;
 
         while self.match_tokens(&[TokenType::BangEqual, TokenType::EqualEqual]) {
@@ -431,7 +707,7 @@ impl Parser {
     // brief: comparision -> term ( ( ">" | ">=" | "<" | "<=") term ) *
     // input:
     // output:
-    fn comparision(&mut self) -> Result<Expr, String> {
+    fn comparision(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.term()?;
 
         while self.match_tokens(&[
@@ -455,7 +731,7 @@ impl Parser {
     // brief: term -> factor ( ( "-" | "+" ) factor ) *
     // input:
     // output:
-    fn term(&mut self) -> Result<Expr, String> {
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.factor()?;
 
         while self.match_tokens(&[TokenType::Minus, TokenType::Plus]) {
@@ -471,13 +747,13 @@ impl Parser {
         Ok(expr)
     }
 
-    // brief: factor -> unary ( ( "/" | "*") unary ) *
+    // brief: factor -> unary ( ( "/" | "*" | "%" ) unary ) *
     // input:
     // output:
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.unary()?;
 
-        while self.match_tokens(&[TokenType::Slash, TokenType::Star]) {
+        while self.match_tokens(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
             let operator = self.previous();
             let right_expr = self.unary()?;
 
@@ -493,7 +769,7 @@ impl Parser {
     // brief: unary -> ( ( "!" | "-" ) unary ) | call
     // input:
     // output:
-    fn unary(&mut self) -> Result<Expr, String> {
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
         if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous();
             let right_expr = self.unary()?;
@@ -506,14 +782,29 @@ impl Parser {
         self.call()
     }
 
-    // brief: call -> primary ( "(" arguments ? ")" ) *
+    // brief: call -> primary ( "(" arguments ? ")" | "." Identifier ) *
     // input:
     // output
-    fn call(&mut self) -> Result<Expr, String> {
+    fn call(&mut self) -> Result<Expr, Diagnostic> {
         let mut expr = self.primary()?;
         loop {
             if self.match_tokens(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_tokens(&[TokenType::Dot]) {
+                let name = self.consume(TokenType::Identifier)?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.match_tokens(&[TokenType::LeftBracket]) {
+                let bracket = self.previous();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket)?;
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
             } else {
                 break;
             }
@@ -521,7 +812,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, Diagnostic> {
         let mut arguments = vec![];
 
         if !self.check(TokenType::RightParen) {
@@ -535,10 +826,10 @@ impl Parser {
         let paren = self.consume(TokenType::RightParen)?;
 
         if arguments.len() >= 255 {
-            return Err(format!(
-                "There are too many arguments at line {} at {}.",
-                paren.line_number, paren.lexeme
-            ));
+            return Err(
+                Diagnostic::error(E_TOO_MANY_ARGS, "Can't have 255 or more arguments.")
+                    .at_token(&paren),
+            );
         }
 
         Ok(Expr::Call {
@@ -548,17 +839,12 @@ impl Parser {
         })
     }
 
-    // brief: arguments -> expression ( "," expression ) *
-    // input:
-    // output
-    fn arguments(&mut self) {
-        todo!()
-    }
-
-    // brief: primary -> NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | Idetifier
+    // brief: primary -> NUMBER | STRING | "true" | "false" | "nil" | "this" | "(" expression ")" | Idetifier
+    //                  | "super" "." Identifier | "[" ( expression ( "," expression ) * "," ? ) ? "]"
+    //                  | "{" ( expression ":" expression ( "," expression ":" expression ) * "," ? ) ? "}"
     // input:
     // output:
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
         if self.match_tokens(&[TokenType::False]) {
             Ok(Expr::Literal {
                 value: ExprLiteral::False,
@@ -577,24 +863,49 @@ impl Parser {
                     value: ExprLiteral::StringLiteral(v),
                 });
             }
-            Err(format!(
-                "Error occur at parsering String at line {} in {}, Maybe an error from Scanner.",
-                self.peek().line_number,
-                self.peek().lexeme
-            ))
+            Err(Diagnostic::error(
+                E_EXPECT_TOKEN,
+                "String token without a value, maybe an error from Scanner.",
+            )
+            .at_token(&self.previous()))
+        } else if self.match_tokens(&[TokenType::Interpolation]) {
+            self.interpolation()
         } else if self.match_tokens(&[TokenType::Number]) {
-            if let Some(LiterialValue::FloatValue(v)) = self.previous().literial {
-                return Ok(Expr::Literal {
-                    value: ExprLiteral::NumberLiteral(v),
-                });
+            match self.previous().literial {
+                Some(LiterialValue::FloatValue(v)) => {
+                    return Ok(Expr::Literal {
+                        value: ExprLiteral::NumberLiteral(v),
+                    });
+                }
+                Some(LiterialValue::IntValue(v)) => {
+                    return Ok(Expr::Literal {
+                        value: ExprLiteral::IntLiteral(v),
+                    });
+                }
+                _ => {}
             }
-            Err(format!(
-                "Error occur at parsering Number at line {} in {}, Maybe an error from Scanner.",
-                self.peek().line_number,
-                self.peek().lexeme
-            ))
+            Err(Diagnostic::error(
+                E_EXPECT_TOKEN,
+                "Number token without a value, maybe an error from Scanner.",
+            )
+            .at_token(&self.previous()))
+        } else if self.match_tokens(&[TokenType::This]) {
+            Ok(Expr::This {
+                id: Expr::next_id(),
+                keyword: self.previous(),
+            })
+        } else if self.match_tokens(&[TokenType::Super]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Dot)?;
+            let method = self.consume(TokenType::Identifier)?;
+            Ok(Expr::Super {
+                id: Expr::next_id(),
+                keyword,
+                method,
+            })
         } else if self.match_tokens(&[TokenType::Identifier]) {
             Ok(Expr::Variable {
+                id: Expr::next_id(),
                 name: self.previous(),
             })
         } else if self.match_tokens(&[TokenType::LeftParen]) {
@@ -603,12 +914,37 @@ impl Parser {
             Ok(Expr::Grouping {
                 expression: Box::new(expr),
             })
+        } else if self.match_tokens(&[TokenType::LeftBracket]) {
+            let bracket = self.previous();
+            let mut elements = vec![];
+            while !self.check(TokenType::RightBracket) {
+                elements.push(self.expression()?);
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket)?;
+            Ok(Expr::List { bracket, elements })
+        } else if self.match_tokens(&[TokenType::LeftBrace]) {
+            // Only reached inside an expression, statement() takes "{" as a block first.
+            let brace = self.previous();
+            let mut entries = vec![];
+            while !self.check(TokenType::RightBrace) {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon)?;
+                entries.push((key, self.expression()?));
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace)?;
+            Ok(Expr::Map { brace, entries })
         } else {
-            Err(format!(
-                "Parsering error occurs for finding nothing to match with at line {} in {}.",
-                self.peek().line_number,
-                self.peek().lexeme,
-            ))
+            Err(Diagnostic::error(
+                E_EXPECT_EXPRESSION,
+                format!("Expected expression, found {}.", self.peek().lexeme),
+            )
+            .at_token(&self.peek()))
         }
     }
 
@@ -632,19 +968,95 @@ impl Parser {
     //     }
     // }
 
+    // brief: The rest of a string with "${...}" in it, after its first part.
+    // "a ${x} b" is desugared to "a " + to_string(x) + " b", the Scanner split it into
+    // Interpolation("a "), the tokens of x and String(" b"). Like the for-in loop, the call
+    // does not go through the global to_string.
+    // input:
+    // output:
+    fn interpolation(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = Self::string_part(&self.previous());
+        loop {
+            // The parts after a "${...}" start at its "}", a string in it starts with a quote.
+            let next = self.peek();
+            let empty = matches!(
+                next.token_type,
+                TokenType::String | TokenType::Interpolation
+            ) && next.lexeme.starts_with('}');
+            if empty {
+                return Err(Diagnostic::error(
+                    E_EXPECT_EXPRESSION,
+                    "Expected expression inside ${}.",
+                )
+                .at_token(&next));
+            }
+
+            let value = self.expression()?;
+            let anchor = self.peek();
+            let token = |token_type: TokenType, lexeme: &str| Token {
+                token_type,
+                lexeme: lexeme.to_string(),
+                literial: None,
+                ..anchor.clone()
+            };
+            let text = Expr::Call {
+                callee: Box::new(Expr::Literal {
+                    value: ExprLiteral::FunctionLiteral(Rc::new(stdlib::to_string())),
+                }),
+                paren: token(TokenType::RightParen, ")"),
+                arguments: vec![value],
+            };
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator: token(TokenType::Plus, "+"),
+                right: Box::new(text),
+            };
+
+            let last = !self.match_tokens(&[TokenType::Interpolation]);
+            if last {
+                self.consume(TokenType::String)?;
+            }
+            let part = self.previous();
+            if !matches!(&part.literial, Some(LiterialValue::StringValue(v)) if v.is_empty()) {
+                expr = Expr::Binary {
+                    left: Box::new(expr),
+                    operator: Token {
+                        token_type: TokenType::Plus,
+                        lexeme: "+".to_string(),
+                        literial: None,
+                        ..part.clone()
+                    },
+                    right: Box::new(Self::string_part(&part)),
+                };
+            }
+            if last {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn string_part(token: &Token) -> Expr {
+        let value = match &token.literial {
+            Some(LiterialValue::StringValue(v)) => v.clone(),
+            _ => String::new(),
+        };
+        Expr::Literal {
+            value: ExprLiteral::StringLiteral(value),
+        }
+    }
+
     // brief: Consume the current token, if tokentype matched.
     // input:
     // output:
-    fn consume(&mut self, token_type: TokenType) -> Result<Token, String> {
+    fn consume(&mut self, token_type: TokenType) -> Result<Token, Diagnostic> {
         if self.check(token_type.clone()) {
             Ok(self.advance())
         } else {
-            Err(format!(
-                "Parsering error occur when consuming token {} at line: {} in {}.",
-                token_type,
-                self.peek().line_number,
-                self.peek().lexeme,
-            ))
+            Err(Diagnostic::error(
+                E_EXPECT_TOKEN,
+                format!("Expected {}, found {}.", token_type, self.peek().lexeme),
+            )
+            .at_token(&self.peek()))
         }
     }
 
@@ -661,11 +1073,17 @@ impl Parser {
                 TokenType::CLass
                 | TokenType::Fn
                 | TokenType::Let
+                | TokenType::Import
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue => return,
+
+                // Leave the "}" to the block being parsed, so it is not reported again.
+                TokenType::RightBrace => return,
 
                 _ => {
                     self.advance();
@@ -686,6 +1104,15 @@ impl Parser {
         false
     }
 
+    // brief: Type of the token `offset` places after the current one.
+    // input:
+    // output: None past the end.
+    fn token_type_at(&self, offset: usize) -> Option<TokenType> {
+        self.tokens
+            .get(self.current + offset)
+            .map(|v| v.token_type.clone())
+    }
+
     // brief: Check tempToken by using peek().
     // input:
     // output:
@@ -733,13 +1160,13 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Scanner;
+    use crate::lexer::{callable::Arity, scanner::Scanner};
     #[test]
     fn parser_test_one() {
         let sources = "1.0 * 3.0 * 2.0 + 2.0 * 4.0 == 11.0".to_string();
         let mut scan = Scanner::new(sources);
 
-        let tok = scan.scan_tokens().unwrap();
+        let _tok = scan.scan_tokens().unwrap();
 
         // let pas = Parser::new(tok).parse().unwrap().two_string();
 
@@ -755,7 +1182,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -772,7 +1199,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -789,7 +1216,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -806,7 +1233,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -822,7 +1249,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -839,7 +1266,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -858,7 +1285,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -875,7 +1302,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -892,7 +1319,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -908,7 +1335,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -925,7 +1352,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -933,6 +1360,133 @@ mod tests {
         }
     }
 
+    #[test]
+    fn report_every_error() {
+        let sources =
+            "let a = ;\nprint 1 +;\nfn f() { let b = * 2; return b; }\nlet ok = 1;\nprint )";
+        let tok = Scanner::new(sources.to_string()).scan_tokens().unwrap();
+
+        let (statements, errors) = Parser::new(tok.clone()).parse_partial();
+        let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 5]);
+        // "fn f" and "let ok" survive.
+        assert_eq!(statements.len(), 2);
+
+        assert_eq!(Parser::new(tok).parse().unwrap_err(), errors);
+    }
+
+    #[test]
+    fn lists_and_indexing() {
+        let tok = Scanner::new("xs[0][1] = [1, [2, 3],];".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        let Stmt::Expression(Expr::SetIndex { object, value, .. }) = &statements[0] else {
+            panic!("{:?}", statements);
+        };
+        assert!(matches!(object.as_ref(), Expr::Index { .. }));
+        assert!(matches!(value.as_ref(), Expr::List { elements, .. } if elements.len() == 2));
+
+        let tok = Scanner::new("for (let x in xs) print x;".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        assert!(matches!(&statements[0], Stmt::Block { statements } if statements.len() == 3));
+    }
+
+    #[test]
+    fn braces_open_blocks_before_maps() {
+        let tok = Scanner::new("{ print 1; } let m = {\"a\": 1, 2: {},}; ({})[1];".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        assert!(matches!(&statements[0], Stmt::Block { .. }));
+        assert!(
+            matches!(&statements[1], Stmt::Let { initializer: Expr::Map { entries, .. }, .. } if entries.len() == 2)
+        );
+        assert!(matches!(
+            &statements[2],
+            Stmt::Expression(Expr::Index { .. })
+        ));
+
+        // A map literal can not start a statement.
+        let tok = Scanner::new("{\"a\": 1};".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert!(Parser::new(tok).parse().is_err());
+    }
+
+    #[test]
+    fn defaults_and_rest_parameters() {
+        let tok = Scanner::new("fn f(a, b = 1 + 2, ...rest) {} fn g(...all) {}".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        assert!(matches!(
+            &statements[0],
+            Stmt::Function { params, defaults, rest: Some(_), .. } if params.len() == 2 && defaults.len() == 1
+        ));
+        assert_eq!(statements[0].signature(), "f(a, b = ..., ...rest)");
+        assert_eq!(statements[1].arity(), Arity::at_least(0));
+
+        for source in ["fn f(a = 1, b) {}", "fn f(...a, b) {}", "fn f(...a = 1) {}"] {
+            let tok = Scanner::new(source.to_string()).scan_tokens().unwrap();
+            assert!(Parser::new(tok).parse().is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn type_annotations() {
+        let source = "let x: num = 1; fn f(a: str, b = 2, ...rest: list) -> nil {}";
+        let tok = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        assert!(matches!(
+            &statements[0],
+            Stmt::Let { annotation: Some(t), .. } if t.lexeme == "num"
+        ));
+        let Stmt::Function {
+            annotations,
+            returns: Some(returns),
+            ..
+        } = &statements[1]
+        else {
+            panic!("expected an annotated function, got {:?}", statements[1]);
+        };
+        let names: Vec<Option<&str>> = annotations
+            .iter()
+            .map(|a| a.as_ref().map(|t| t.lexeme.as_str()))
+            .collect();
+        assert_eq!(names, vec![Some("str"), None, Some("list")]);
+        assert_eq!(returns.token_type, TokenType::Nil);
+
+        for source in ["let x: = 1;", "let x: 1 = 1;", "fn f() -> {}"] {
+            let tok = Scanner::new(source.to_string()).scan_tokens().unwrap();
+            assert!(Parser::new(tok).parse().is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn interpolation_is_desugared_to_concatenation() {
+        let tok = Scanner::new("\"a ${x} b ${y}\";".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tok).parse().unwrap();
+        let Stmt::Expression(Expr::Binary { left, right, .. }) = &statements[0] else {
+            panic!("expected a concatenation, got {:?}", statements[0]);
+        };
+        // (("a " + to_string(x)) + " b ") + to_string(y), the empty last part is dropped.
+        assert!(matches!(right.as_ref(), Expr::Call { arguments, .. } if arguments.len() == 1));
+        assert!(matches!(left.as_ref(), Expr::Binary { right, .. }
+            if matches!(right.as_ref(), Expr::Literal { value: ExprLiteral::StringLiteral(v) } if v == " b ")));
+
+        let tok = Scanner::new("print \"a ${}\";".to_string())
+            .scan_tokens()
+            .unwrap();
+        let errors = Parser::new(tok).parse().unwrap_err();
+        assert_eq!(errors[0].code, E_EXPECT_EXPRESSION);
+        assert_eq!(errors[0].message, "Expected expression inside ${}.");
+    }
+
     #[test]
     fn parser_test_tenthree() {
         let sources = "fn add( a, b ){ a + b; }".to_string();
@@ -942,7 +1496,7 @@ mod tests {
 
         match Parser::new(tok).parse() {
             Err(error) => {
-                println!("[    Error!    ] ---> {}", error);
+                println!("[    Error!    ] ---> {:?}", error);
             }
             Ok(v) => {
                 dbg!(v);
@@ -0,0 +1,581 @@
+use super::{
+    expr::{Expr, ExprLiteral},
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+// How tightly an expression binds, the levels of the Parser from assignment up to primary.
+// A child that binds less tightly than its place needs is put in parentheses.
+const ASSIGNMENT: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const TERM: u8 = 6;
+const FACTOR: u8 = 7;
+const UNARY: u8 = 8;
+const CALL: u8 = 9;
+const PRIMARY: u8 = 10;
+
+// Syntax tree => source, one statement per line and four spaces per block.
+// What the Parser desugared is printed as it was written: for and for-in loops and "${...}"
+// interpolation. Comments are not in the tree, the formatter keeps them.
+pub struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    // brief: Print a whole program.
+    // input:
+    // output: the source, every statement ends with a newline.
+    pub fn program(statements: &[Stmt]) -> String {
+        let mut printer = Self {
+            out: String::new(),
+            indent: 0,
+        };
+        for statement in statements {
+            printer.line(statement);
+        }
+        printer.out
+    }
+
+    // brief: Print one statement, blocks over several lines.
+    // input:
+    // output: without the last newline.
+    pub fn stmt(statement: &Stmt) -> String {
+        let mut out = Self::program(std::slice::from_ref(statement));
+        out.pop();
+        out
+    }
+
+    // brief: Print an expression on one line.
+    // input:
+    // output:
+    pub fn expr(expr: &Expr) -> String {
+        Self::expr_in(expr, ASSIGNMENT)
+    }
+
+    // brief: A statement on its own line(s), at the current indentation.
+    // input:
+    // output:
+    fn line(&mut self, statement: &Stmt) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.statement(statement);
+        self.out.push('\n');
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(expr) => self.push(&format!("{};", Self::expr(expr))),
+            Stmt::Print(expr) => self.push(&format!("print {};", Self::expr(expr))),
+            Stmt::Let {
+                name,
+                annotation,
+                initializer,
+            } => self.push(&format!(
+                "let {}{} = {};",
+                name.lexeme,
+                typed(annotation),
+                Self::expr(initializer)
+            )),
+            Stmt::Block { statements } => {
+                if let Some((name, sequence, body)) = for_in(statements) {
+                    self.push(&format!("for (let {} in {}) ", name, Self::expr(sequence)));
+                    return self.statement(body);
+                }
+                if let [initializer @ (Stmt::Let { .. } | Stmt::Expression(_)), Stmt::While {
+                    condition,
+                    body,
+                    increment: Some(increment),
+                }] = statements.as_slice()
+                {
+                    let initializer = Self::stmt(initializer);
+                    return self.for_loop(&initializer, condition, increment, body);
+                }
+                self.block(statements);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.push(&format!("if ({}) ", Self::expr(condition)));
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    // "}" ends the line of a block, any other branch ended with ";".
+                    if matches!(then_branch.as_ref(), Stmt::Block { .. }) {
+                        self.push(" else ");
+                    } else {
+                        self.out.push('\n');
+                        self.push(&format!("{}else ", "    ".repeat(self.indent)));
+                    }
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment: None,
+            } => {
+                self.push(&format!("while ({}) ", Self::expr(condition)));
+                self.statement(body);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment: Some(increment),
+            } => self.for_loop(";", condition, increment, body),
+            Stmt::Function { .. } => {
+                self.push("fn ");
+                self.function(statement);
+            }
+            Stmt::Return { value, .. } => match value {
+                Some(value) => self.push(&format!("return {};", Self::expr(value))),
+                None => self.push("return;"),
+            },
+            Stmt::Break { .. } => self.push("break;"),
+            Stmt::Continue { .. } => self.push("continue;"),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.push(&format!("class {}", name.lexeme));
+                if let Some(superclass) = superclass {
+                    self.push(&format!(" < {}", Self::expr(superclass)));
+                }
+                if methods.is_empty() {
+                    return self.push(" {}");
+                }
+                self.push(" {\n");
+                self.indent += 1;
+                for method in methods {
+                    self.out.push_str(&"    ".repeat(self.indent));
+                    self.function(method);
+                    self.out.push('\n');
+                }
+                self.indent -= 1;
+                self.push(&format!("{}}}", "    ".repeat(self.indent)));
+            }
+            Stmt::Import { path, name, .. } => {
+                self.push(&format!("import \"{}\" as {};", escape(path), name.lexeme))
+            }
+        }
+    }
+
+    // brief: "for (initializer condition; increment) body", the initializer ends with ";".
+    // input:
+    // output:
+    fn for_loop(&mut self, initializer: &str, condition: &Expr, increment: &Expr, body: &Stmt) {
+        // The Parser fills in true for a missing condition.
+        let condition = match condition {
+            Expr::Literal {
+                value: ExprLiteral::True,
+            } => String::new(),
+            other => format!(" {}", Self::expr(other)),
+        };
+        self.push(&format!(
+            "for ({}{}; {}) ",
+            initializer,
+            condition,
+            Self::expr(increment)
+        ));
+        self.statement(body);
+    }
+
+    // brief: A function after "fn", or a method: name, parameters and body.
+    // input:
+    // output:
+    fn function(&mut self, function: &Stmt) {
+        let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            body,
+            annotations,
+            returns,
+        } = function
+        else {
+            return;
+        };
+        let annotation = |index: usize| typed(annotations.get(index).unwrap_or(&None));
+        let first_default = params.len() - defaults.len();
+        let mut parts: Vec<String> = params
+            .iter()
+            .enumerate()
+            .map(|(index, param)| match index.checked_sub(first_default) {
+                None => format!("{}{}", param.lexeme, annotation(index)),
+                Some(i) => format!(
+                    "{}{} = {}",
+                    param.lexeme,
+                    annotation(index),
+                    Self::expr(&defaults[i])
+                ),
+            })
+            .collect();
+        if let Some(rest) = rest {
+            parts.push(format!("...{}{}", rest.lexeme, annotation(params.len())));
+        }
+        let returns = match returns {
+            Some(returns) => format!("-> {} ", returns.lexeme),
+            None => String::new(),
+        };
+        self.push(&format!(
+            "{}({}) {}",
+            name.lexeme,
+            parts.join(", "),
+            returns
+        ));
+        self.statement(body);
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        if statements.is_empty() {
+            return self.push("{}");
+        }
+        self.push("{\n");
+        self.indent += 1;
+        for statement in statements {
+            self.line(statement);
+        }
+        self.indent -= 1;
+        self.push(&format!("{}}}", "    ".repeat(self.indent)));
+    }
+
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    // brief: Print `expr` where an expression binding at least as tightly as `min` is needed.
+    // input:
+    // output:
+    fn expr_in(expr: &Expr, min: u8) -> String {
+        let text = match expr {
+            Expr::Literal { value } => literal(value),
+            Expr::Unary { operator, right } => {
+                format!("{}{}", operator.lexeme, Self::expr_in(right, UNARY))
+            }
+            Expr::Binary { .. } if interpolation(expr).is_some() => {
+                let parts = interpolation(expr).unwrap_or_default();
+                let mut text = String::from("\"");
+                for part in parts {
+                    match part {
+                        Part::Text(value) => text.push_str(&escape(value)),
+                        Part::Value(value) => text.push_str(&format!("${{{}}}", Self::expr(value))),
+                    }
+                }
+                text.push('"');
+                text
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let level = precedence(expr);
+                format!(
+                    "{} {} {}",
+                    Self::expr_in(left, level),
+                    operator.lexeme,
+                    Self::expr_in(right, level + 1)
+                )
+            }
+            Expr::Grouping { expression } => format!("({})", Self::expr(expression)),
+            Expr::Variable { name, .. } => name.lexeme.clone(),
+            Expr::Assign { name, value, .. } => {
+                format!("{} = {}", name.lexeme, Self::expr_in(value, ASSIGNMENT))
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => format!("{}({})", Self::expr_in(callee, CALL), Self::list(arguments)),
+            Expr::Get { object, name } => {
+                format!("{}.{}", Self::expr_in(object, CALL), name.lexeme)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => format!(
+                "{}.{} = {}",
+                Self::expr_in(object, CALL),
+                name.lexeme,
+                Self::expr_in(value, ASSIGNMENT)
+            ),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Super { method, .. } => format!("super.{}", method.lexeme),
+            Expr::List { elements, .. } => format!("[{}]", Self::list(elements)),
+            Expr::Index { object, index, .. } => {
+                format!("{}[{}]", Self::expr_in(object, CALL), Self::expr(index))
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "{}[{}] = {}",
+                Self::expr_in(object, CALL),
+                Self::expr(index),
+                Self::expr_in(value, ASSIGNMENT)
+            ),
+            Expr::Map { entries, .. } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", Self::expr(key), Self::expr(value)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+        };
+        if precedence(expr) < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    fn list(items: &[Expr]) -> String {
+        items
+            .iter()
+            .map(Self::expr)
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign { .. } | Expr::Set { .. } | Expr::SetIndex { .. } => ASSIGNMENT,
+        Expr::Binary { .. } if interpolation(expr).is_some() => PRIMARY,
+        Expr::Logical { operator, .. } if operator.token_type == TokenType::Or => OR,
+        Expr::Logical { .. } => AND,
+        Expr::Binary { operator, .. } => match operator.token_type {
+            TokenType::EqualEqual | TokenType::BangEqual => EQUALITY,
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => COMPARISON,
+            TokenType::Plus | TokenType::Minus => TERM,
+            _ => FACTOR,
+        },
+        Expr::Unary { .. } => UNARY,
+        Expr::Call { .. } | Expr::Get { .. } | Expr::Index { .. } => CALL,
+        _ => PRIMARY,
+    }
+}
+
+// brief: A value as it is written in source.
+// input:
+// output:
+fn literal(value: &ExprLiteral) -> String {
+    match value {
+        ExprLiteral::StringLiteral(s) => format!("\"{}\"", escape(s)),
+        // Display never uses an exponent, the Scanner can read it back.
+        ExprLiteral::NumberLiteral(v) if v.fract() == 0.0 => format!("{}.0", v),
+        ExprLiteral::NumberLiteral(v) => format!("{}", v),
+        ExprLiteral::True => "true".to_string(),
+        ExprLiteral::False => "false".to_string(),
+        ExprLiteral::Nil => "nil".to_string(),
+        // Natives the Parser calls directly, e.g. len in a for-in loop: their name.
+        ExprLiteral::FunctionLiteral(f) => f
+            .signature()
+            .split('(')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        other => other.two_string(),
+    }
+}
+
+// brief: The text of a string literal, with escapes for what the Scanner would read otherwise.
+// input:
+// output:
+fn escape(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A piece of an interpolated string.
+enum Part<'a> {
+    Text(&'a str),
+    Value(&'a Expr),
+}
+
+// brief: The pieces of a desugared "a ${x} b": "a " + to_string(x) + " b", where to_string is
+// the native itself, which only the Parser can write.
+// input:
+// output: None if `expr` is not one.
+fn interpolation(expr: &Expr) -> Option<Vec<Part<'_>>> {
+    let Expr::Binary {
+        left,
+        operator,
+        right,
+    } = expr
+    else {
+        return None;
+    };
+    if operator.token_type != TokenType::Plus {
+        return None;
+    }
+    let mut parts = match left.as_ref() {
+        Expr::Literal {
+            value: ExprLiteral::StringLiteral(s),
+        } => vec![Part::Text(s)],
+        other => interpolation(other)?,
+    };
+    match right.as_ref() {
+        Expr::Call {
+            callee, arguments, ..
+        } if matches!(
+            callee.as_ref(),
+            Expr::Literal {
+                value: ExprLiteral::FunctionLiteral(_)
+            }
+        ) && arguments.len() == 1 =>
+        {
+            parts.push(Part::Value(&arguments[0]))
+        }
+        // Only a part following a value, "a" + "b" is a plain concatenation.
+        Expr::Literal {
+            value: ExprLiteral::StringLiteral(s),
+        } if matches!(parts.last(), Some(Part::Value(_))) => parts.push(Part::Text(s)),
+        _ => return None,
+    }
+    Some(parts)
+}
+
+// brief: The ": type" after a variable or a parameter.
+// input:
+// output: empty if it has no annotation.
+fn typed(annotation: &Option<Token>) -> String {
+    match annotation {
+        Some(annotation) => format!(": {}", annotation.lexeme),
+        None => String::new(),
+    }
+}
+
+// brief: The pieces of a desugared for-in loop, see Parser::for_in_statement.
+// input: the statements of a block.
+// output: the loop variable, the sequence and the body.
+fn for_in(statements: &[Stmt]) -> Option<(&str, &Expr, &Stmt)> {
+    let [Stmt::Let {
+        name: sequence_name,
+        initializer: sequence,
+        ..
+    }, Stmt::Let { .. }, Stmt::While { body, .. }] = statements
+    else {
+        return None;
+    };
+    let Expr::Call { arguments, .. } = sequence else {
+        return None;
+    };
+    let ([sequence], "@sequence") = (arguments.as_slice(), sequence_name.lexeme.as_str()) else {
+        return None;
+    };
+    let Stmt::Block { statements } = body.as_ref() else {
+        return None;
+    };
+    let [Stmt::Let { name, .. }, body] = statements.as_slice() else {
+        return None;
+    };
+    Some((&name.lexeme, sequence, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, scanner::Scanner};
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn every_statement_round_trips() {
+        let source = "let a = nil;
+let b = -(1 + 2) * 3 - 4 / 2.0 % 5;
+print !true and (false or a == nil);
+a = b = [1, \"x\\ty\", {\"k\": [2.5]}];
+fn f(x, y = 1, ...rest) {
+    if (x < y) return x;
+    else return;
+}
+class A < B {
+    init(n) {
+        this.n = n;
+        super.init(n)[0] = f(n).m;
+    }
+    empty() {}
+}
+for (let i = 0; i < 3; i = i + 1) {
+    if (i == 1) {
+        continue;
+    } else {
+        break;
+    }
+}
+for (; a; a = nil) print a;
+for (let x in [1, 2]) print \"x = ${x + 1}, ${{}}\\${\";
+while (true) {}
+{
+    print 1;
+}
+";
+        let printed = Printer::program(&parse(source));
+        assert_eq!(printed, source);
+        assert_eq!(Printer::program(&parse(&printed)), printed);
+    }
+
+    #[test]
+    fn parentheses_follow_precedence() {
+        let tok = |t: TokenType, lexeme: &str| {
+            crate::lexer::token::Token::new(t, lexeme.to_string(), None, 1)
+        };
+        let int = |v| Expr::Literal {
+            value: ExprLiteral::IntLiteral(v),
+        };
+        // (1 + 2) * 3 built without a Grouping, as an optimizer could.
+        let sum = Expr::Binary {
+            left: Box::new(int(1)),
+            operator: tok(TokenType::Plus, "+"),
+            right: Box::new(int(2)),
+        };
+        let product = Expr::Binary {
+            left: Box::new(sum.clone()),
+            operator: tok(TokenType::Star, "*"),
+            right: Box::new(int(3)),
+        };
+        assert_eq!(product.two_string(), "(1 + 2) * 3");
+        let difference = Expr::Binary {
+            left: Box::new(int(1)),
+            operator: tok(TokenType::Minus, "-"),
+            right: Box::new(sum),
+        };
+        assert_eq!(difference.two_string(), "1 - (1 + 2)");
+        assert_eq!(
+            Printer::stmt(&Stmt::Print(Expr::Literal {
+                value: ExprLiteral::NumberLiteral(1e21)
+            })),
+            "print 1000000000000000000000.0;"
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+
+use super::{
+    diagnostic::{
+        Diagnostic, E_ALREADY_DECLARED, E_BAD_JUMP, E_BAD_RETURN, E_BAD_SUPER, E_BAD_THIS,
+        E_OWN_INITIALIZER, E_SELF_INHERIT,
+    },
+    expr::Expr,
+    stmt::Stmt,
+    token::Token,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+// Static pass between Parser and Interpreter: for every local Variable/Assign/This/Super it records how many
+// scopes lie between the use and the declaration, so the Interpreter can jump straight there
+// instead of searching by name. Globals are left unresolved.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, bool>>, // name => is the initializer finished ?
+    locals: HashMap<usize, usize>,      // Expr id => scope depth.
+    current_function: FunctionType,
+    current_class: ClassType,
+    loop_depth: usize, // loops around the statement, inside the current function.
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![],
+            locals: HashMap::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+        }
+    }
+
+    // brief: Pub function to resolve a whole program.
+    // input:
+    // output: Expr id => scope depth, to be handed to Interpreter::resolve.
+    pub fn resolve(mut self, statements: &Vec<Stmt>) -> Result<HashMap<usize, usize>, Diagnostic> {
+        self.resolve_statements(statements)?;
+        Ok(self.locals)
+    }
+
+    fn resolve_statements(&mut self, statements: &Vec<Stmt>) -> Result<(), Diagnostic> {
+        for statement in statements {
+            self.resolve_stmt(statement)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_stmt(&mut self, statement: &Stmt) -> Result<(), Diagnostic> {
+        match statement {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                let result = self.resolve_statements(statements);
+                self.end_scope();
+                result?;
+            }
+            Stmt::Let {
+                name, initializer, ..
+            } => {
+                self.declare(name)?;
+                self.resolve_expr(initializer)?;
+                self.define(name);
+            }
+            Stmt::Import { name, .. } => {
+                self.declare(name)?;
+                self.define(name);
+            }
+            Stmt::Function { name, .. } => {
+                // Define before the body, so that the function can call itself.
+                self.declare(name)?;
+                self.define(name);
+                self.resolve_function(statement, FunctionType::Function)?;
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.resolve_expr(expr)?,
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(condition)?;
+                self.resolve_stmt(then_branch)?;
+                if let Some(v) = else_branch {
+                    self.resolve_stmt(v)?;
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.resolve_expr(condition)?;
+                self.loop_depth += 1;
+                let result = self.resolve_stmt(body);
+                self.loop_depth -= 1;
+                result?;
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment)?;
+                }
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                if self.loop_depth == 0 {
+                    return Err(Diagnostic::error(
+                        E_BAD_JUMP,
+                        format!("Can't use {} outside of a loop.", keyword.lexeme),
+                    )
+                    .at_token(keyword));
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    return Err(Diagnostic::error(
+                        E_BAD_RETURN,
+                        "Can't return from top-level code.",
+                    )
+                    .at_token(keyword));
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        return Err(Diagnostic::error(
+                            E_BAD_RETURN,
+                            "Can't return a value from an initializer.",
+                        )
+                        .at_token(keyword)
+                        .with_note("init always returns this"));
+                    }
+                    self.resolve_expr(value)?;
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                let result = self.resolve_class(name, superclass, methods);
+                self.current_class = enclosing_class;
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    // brief: Methods are wrapped by a "super" scope (subclasses only) and a "this" scope,
+    // the same scopes the Interpreter creates when it binds them.
+    // input:
+    // output:
+    fn resolve_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &Vec<Stmt>,
+    ) -> Result<(), Diagnostic> {
+        self.declare(name)?;
+        self.define(name);
+
+        if let Some(superclass) = superclass {
+            if let Expr::Variable {
+                name: super_name, ..
+            } = superclass
+            {
+                if super_name.lexeme == name.lexeme {
+                    return Err(Diagnostic::error(
+                        E_SELF_INHERIT,
+                        format!("Class {} can't inherit from itself.", name.lexeme),
+                    )
+                    .at_token(super_name));
+                }
+            }
+            self.current_class = ClassType::Subclass;
+            self.resolve_expr(superclass)?;
+
+            self.begin_scope();
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert("super".to_string(), true);
+        }
+
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert("this".to_string(), true);
+
+        let mut result = Ok(());
+        for method in methods {
+            let kind = match method {
+                Stmt::Function { name, .. } if name.lexeme == "init" => FunctionType::Initializer,
+                _ => FunctionType::Method,
+            };
+            result = self.resolve_function(method, kind);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.end_scope();
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        result
+    }
+
+    // brief: Params and body share one scope, the same as MyFunction::call does at runtime.
+    // input:
+    // output:
+    fn resolve_function(&mut self, function: &Stmt, kind: FunctionType) -> Result<(), Diagnostic> {
+        let Stmt::Function {
+            params,
+            defaults,
+            rest,
+            body,
+            ..
+        } = function
+        else {
+            return Ok(());
+        };
+        let Stmt::Block { statements } = body.as_ref() else {
+            return Ok(());
+        };
+
+        let enclosing_function = self.current_function;
+        let enclosing_loops = self.loop_depth;
+        self.current_function = kind;
+        self.loop_depth = 0; // a loop outside the function can not be left from inside it.
+        self.begin_scope();
+
+        // A default is evaluated at call time and sees the parameters before its own.
+        let first_default = params.len() - defaults.len();
+        let mut result = Ok(());
+        for (index, param) in params.iter().chain(rest).enumerate() {
+            if let Some(default) = index
+                .checked_sub(first_default)
+                .and_then(|i| defaults.get(i))
+            {
+                result = self.resolve_expr(default);
+                if result.is_err() {
+                    break;
+                }
+            }
+            result = self.declare(param);
+            if result.is_err() {
+                break;
+            }
+            self.define(param);
+        }
+        if result.is_ok() {
+            result = self.resolve_statements(statements);
+        }
+
+        self.end_scope();
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loops;
+        result
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Variable { id, name } => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&name.lexeme) == Some(&false) {
+                        return Err(Diagnostic::error(
+                            E_OWN_INITIALIZER,
+                            format!(
+                                "Can't read local variable {} in its own initializer.",
+                                name.lexeme
+                            ),
+                        )
+                        .at_token(name));
+                    }
+                }
+                self.resolve_local(*id, name);
+            }
+            Expr::Assign { id, name, value } => {
+                self.resolve_expr(value)?;
+                self.resolve_local(*id, name);
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expr(left)?;
+                self.resolve_expr(right)?;
+            }
+            Expr::Unary { right, .. } => self.resolve_expr(right)?,
+            Expr::Grouping { expression } => self.resolve_expr(expression)?,
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.resolve_expr(callee)?;
+                for argument in arguments {
+                    self.resolve_expr(argument)?;
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expr(object)?,
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.resolve_expr(element)?;
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)?;
+            }
+            Expr::Set { object, value, .. } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(object)?;
+            }
+            Expr::This { id, keyword } => {
+                if self.current_class == ClassType::None {
+                    return Err(Diagnostic::error(
+                        E_BAD_THIS,
+                        "Can't use this outside of a class.",
+                    )
+                    .at_token(keyword));
+                }
+                self.resolve_local(*id, keyword);
+            }
+            Expr::Super { id, keyword, .. } => {
+                if self.current_class == ClassType::None {
+                    return Err(Diagnostic::error(
+                        E_BAD_SUPER,
+                        "Can't use super outside of a class.",
+                    )
+                    .at_token(keyword));
+                } else if self.current_class != ClassType::Subclass {
+                    return Err(Diagnostic::error(
+                        E_BAD_SUPER,
+                        "Can't use super in a class with no superclass.",
+                    )
+                    .at_token(keyword));
+                }
+                self.resolve_local(*id, keyword);
+            }
+            Expr::Literal { .. } => {}
+        }
+        Ok(())
+    }
+
+    // brief: Record the depth of the innermost scope declaring `name`, nothing if it's a global.
+    // input:
+    // output:
+    fn resolve_local(&mut self, id: usize, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                self.locals.insert(id, depth);
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), Diagnostic> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                return Err(Diagnostic::error(
+                    E_ALREADY_DECLARED,
+                    format!("Already a variable named {} in this scope.", name.lexeme),
+                )
+                .at_token(name));
+            }
+            scope.insert(name.lexeme.clone(), false);
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{parser::Parser, scanner::Scanner};
+
+    fn resolve(source: &str) -> Result<HashMap<usize, usize>, Diagnostic> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&statements)
+    }
+
+    #[test]
+    fn globals_stay_unresolved() {
+        assert_eq!(resolve("let a = 1.0; print a;").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn locals_get_their_depth() {
+        let locals = resolve("{ let a = 1.0; { print a; } }").unwrap();
+        assert_eq!(locals.values().collect::<Vec<_>>(), vec![&1]);
+    }
+
+    #[test]
+    fn reject_bad_scopes() {
+        assert!(resolve("{ let a = a; }").is_err());
+        assert!(resolve("{ let a = 1.0; let a = 2.0; }").is_err());
+        assert!(resolve("fn f(a, a) {}").is_err());
+        assert!(resolve("return 1.0;").is_err());
+    }
+
+    #[test]
+    fn reject_bad_classes() {
+        assert!(resolve("print this;").is_err());
+        assert!(resolve("fn f() { return super.g; }").is_err());
+        assert!(resolve("class A { f() { return super.f(); } }").is_err());
+        assert!(resolve("class A < A {}").is_err());
+        assert!(resolve("class A { init() { return 1; } }").is_err());
+        assert!(resolve("class A { init() { return; } }").is_ok());
+        assert!(resolve("class A {} class B < A { f() { return super.f; } }").is_ok());
+    }
+
+    #[test]
+    fn jumps_stay_inside_loops() {
+        assert_eq!(resolve("break;").unwrap_err().code, E_BAD_JUMP);
+        assert!(resolve("if (true) { continue; }").is_err());
+        assert!(resolve("while (true) { fn f() { break; } }").is_err());
+        assert!(resolve("while (true) { if (true) break; else continue; }").is_ok());
+        assert!(resolve("for (;;) { while (true) break; continue; }").is_ok());
+    }
+}
@@ -0,0 +1,587 @@
+/* Runtime of the C programs `noah build --emit c` writes, see emitter.rs.
+ *
+ * A value is tagged like ExprLiteral, only with the variants the C backend supports.
+ * Operators, printing and errors behave as in the tree-walker: checked integer arithmetic,
+ * floats printed the way Rust prints an f64, runtime errors reported with their code and
+ * position before exiting with 70.
+ * Nothing is ever freed: strings, closures and captured variables live until the program
+ * exits.
+ *
+ * The emitted code defines NH_FILE, the script it comes from, before this runtime and
+ * nh_script(), the top level, after it, with the NhNative of every native it uses. Helpers
+ * are static inline, so that a program using only some of them compiles without warnings. */
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define NH_EXIT_RUNTIME 70
+#define NH_MAX_CALL_DEPTH 1000
+
+/* Error codes, the same as diagnostic.rs. */
+#define E_UNDEFINED_VARIABLE 301
+#define E_BAD_OPERAND 302
+#define E_NOT_CALLABLE 303
+#define E_ARITY 304
+#define E_NATIVE 307
+#define E_STACK_OVERFLOW 308
+
+typedef enum {
+    NH_NIL,
+    NH_BOOL,
+    NH_INT,
+    NH_FLOAT,
+    NH_STRING,
+    NH_FUNCTION,
+    NH_NATIVE,
+} NhTag;
+
+typedef struct {
+    size_t length; /* in bytes, the chars are UTF-8 and may hold '\0'. */
+    const char *chars;
+} NhString;
+
+typedef struct NhValue NhValue;
+typedef struct NhClosure NhClosure;
+
+/* A Noah function: its code, lifted to a C function, and its arity. */
+typedef struct {
+    const char *name;
+    int min, max; /* arguments accepted, defaults make min lower than max. */
+    NhValue (*code)(NhClosure *self, int argc, NhValue *argv);
+} NhProto;
+
+typedef struct {
+    const char *name;
+    int arity;
+    NhValue (*code)(NhValue *argv);
+} NhNative;
+
+struct NhValue {
+    NhTag tag;
+    union {
+        bool boolean;
+        int64_t integer;
+        double number;
+        const NhString *string;
+        NhClosure *function;
+        const NhNative *native;
+    } as;
+};
+
+/* A function value: its proto and the variables it captured, shared with the scope that
+ * declared them. */
+struct NhClosure {
+    const NhProto *proto;
+    NhValue **cells;
+};
+
+/* A variable of the top level, defined when its `let` or `fn` runs. */
+typedef struct {
+    const char *name;
+    bool defined;
+    NhValue value;
+} NhGlobal;
+
+static int nh_line, nh_column; /* of what runs now, for runtime errors. */
+static int nh_depth;           /* calls active. */
+
+static void nh_script(void);
+
+/* ---- errors ---- */
+
+static inline void nh_at(int line, int column) {
+    nh_line = line;
+    nh_column = column;
+}
+
+static inline void nh_fail(int code, const char *format, ...) {
+    va_list args;
+    int digits = snprintf(NULL, 0, "%d", nh_line);
+    fflush(stdout);
+    fprintf(stderr, "error[E%04d]: ", code);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fprintf(stderr, "\n%*s--> %s:%d:%d\n", digits, "", NH_FILE, nh_line, nh_column);
+    exit(NH_EXIT_RUNTIME);
+}
+
+static inline void *nh_alloc(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        fflush(stdout);
+        fprintf(stderr, "Out of memory.\n");
+        exit(NH_EXIT_RUNTIME);
+    }
+    return memory;
+}
+
+/* ---- values ---- */
+
+static inline NhValue nh_nil(void) {
+    NhValue value = {NH_NIL, {.boolean = false}};
+    return value;
+}
+
+static inline NhValue nh_bool(bool boolean) {
+    NhValue value = {NH_BOOL, {.boolean = boolean}};
+    return value;
+}
+
+static inline NhValue nh_int(int64_t integer) {
+    NhValue value = {NH_INT, {.integer = integer}};
+    return value;
+}
+
+static inline NhValue nh_float(double number) {
+    NhValue value = {NH_FLOAT, {.number = number}};
+    return value;
+}
+
+static inline NhValue nh_str(const NhString *string) {
+    NhValue value = {NH_STRING, {.string = string}};
+    return value;
+}
+
+static inline NhValue nh_native(const NhNative *native) {
+    NhValue value = {NH_NATIVE, {.native = native}};
+    return value;
+}
+
+static inline NhValue nh_closure(const NhProto *proto, int count, NhValue **cells) {
+    NhClosure *closure = nh_alloc(sizeof(NhClosure));
+    closure->proto = proto;
+    closure->cells = NULL;
+    if (count > 0) {
+        closure->cells = nh_alloc(sizeof(NhValue *) * (size_t)count);
+        memcpy(closure->cells, cells, sizeof(NhValue *) * (size_t)count);
+    }
+    NhValue value = {NH_FUNCTION, {.function = closure}};
+    return value;
+}
+
+/* A variable some inner function captures. */
+static inline NhValue *nh_box(NhValue value) {
+    NhValue *cell = nh_alloc(sizeof(NhValue));
+    *cell = value;
+    return cell;
+}
+
+static inline bool nh_truthy(NhValue value) {
+    return !(value.tag == NH_NIL || (value.tag == NH_BOOL && !value.as.boolean));
+}
+
+/* ---- strings ---- */
+
+static inline const NhString *nh_string(const char *chars, size_t length) {
+    NhString *string = nh_alloc(sizeof(NhString));
+    char *copy = nh_alloc(length + 1);
+    memcpy(copy, chars, length);
+    copy[length] = '\0';
+    string->chars = copy;
+    string->length = length;
+    return string;
+}
+
+static inline const NhString *nh_concat(const NhString *left, const NhString *right) {
+    NhString *string = nh_alloc(sizeof(NhString));
+    char *chars = nh_alloc(left->length + right->length + 1);
+    memcpy(chars, left->chars, left->length);
+    memcpy(chars + left->length, right->chars, right->length);
+    chars[left->length + right->length] = '\0';
+    string->chars = chars;
+    string->length = left->length + right->length;
+    return string;
+}
+
+/* The shortest decimal that reads back as the same double, without an exponent, the way
+ * Rust's Display prints an f64: 6.0 is "6", 1e21 is "1000000000000000000000". */
+static inline void nh_format_float(double number, char *out, size_t size) {
+    if (isnan(number)) {
+        snprintf(out, size, "NaN");
+        return;
+    }
+    if (isinf(number)) {
+        snprintf(out, size, number < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (number == 0) {
+        snprintf(out, size, signbit(number) ? "-0" : "0");
+        return;
+    }
+    char scientific[40];
+    for (int precision = 1; precision <= 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision - 1, number);
+        if (strtod(scientific, NULL) == number) {
+            break;
+        }
+    }
+    /* scientific is [-]d[.ddd]e[+-]x, gather its digits and its exponent. */
+    char digits[20];
+    size_t count = 0;
+    const char *c = scientific;
+    bool negative = *c == '-';
+    if (negative) {
+        c++;
+    }
+    for (; *c != 'e'; c++) {
+        if (*c != '.') {
+            digits[count++] = *c;
+        }
+    }
+    int exponent = atoi(c + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+
+    size_t n = 0;
+    if (negative) {
+        out[n++] = '-';
+    }
+    if (exponent < 0) {
+        out[n++] = '0';
+        out[n++] = '.';
+        for (int i = -1; i > exponent; i--) {
+            out[n++] = '0';
+        }
+        for (size_t i = 0; i < count; i++) {
+            out[n++] = digits[i];
+        }
+    } else {
+        for (int i = 0; i <= exponent; i++) {
+            out[n++] = (size_t)i < count ? digits[i] : '0';
+        }
+        if (count > (size_t)exponent + 1) {
+            out[n++] = '.';
+            for (size_t i = (size_t)exponent + 1; i < count; i++) {
+                out[n++] = digits[i];
+            }
+        }
+    }
+    out[n] = '\0';
+}
+
+/* What print shows, like ExprLiteral::two_string. */
+static inline const NhString *nh_to_string(NhValue value) {
+    char buffer[400]; /* DBL_MAX has 309 digits, the smallest double 324 decimals. */
+    const char *text = buffer;
+    switch (value.tag) {
+    case NH_NIL:
+        text = "Nil";
+        break;
+    case NH_BOOL:
+        text = value.as.boolean ? "True" : "False";
+        break;
+    case NH_INT:
+        snprintf(buffer, sizeof buffer, "%" PRId64, value.as.integer);
+        break;
+    case NH_FLOAT:
+        nh_format_float(value.as.number, buffer, sizeof buffer);
+        break;
+    case NH_STRING:
+        return value.as.string;
+    case NH_FUNCTION:
+        snprintf(buffer, sizeof buffer, "<fn %s>", value.as.function->proto->name);
+        break;
+    case NH_NATIVE:
+        snprintf(buffer, sizeof buffer, "<native fn %s>", value.as.native->name);
+        break;
+    }
+    return nh_string(text, strlen(text));
+}
+
+static inline void nh_print(NhValue value) {
+    const NhString *string = nh_to_string(value);
+    fwrite(string->chars, 1, string->length, stdout);
+    fputc('\n', stdout);
+}
+
+/* ---- globals ---- */
+
+static inline void nh_define(NhGlobal *global, NhValue value) {
+    global->defined = true;
+    global->value = value;
+}
+
+static inline NhValue nh_get(NhGlobal *global) {
+    if (!global->defined) {
+        nh_fail(E_UNDEFINED_VARIABLE, "Undefined variable %s.", global->name);
+    }
+    return global->value;
+}
+
+static inline void nh_set(NhGlobal *global, NhValue value) {
+    if (!global->defined) {
+        nh_fail(E_UNDEFINED_VARIABLE, "Undefined variable %s.", global->name);
+    }
+    global->value = value;
+}
+
+/* ---- operators ---- */
+
+static inline bool nh_is_number(NhValue value) {
+    return value.tag == NH_INT || value.tag == NH_FLOAT;
+}
+
+static inline double nh_as_float(NhValue value) {
+    return value.tag == NH_INT ? (double)value.as.integer : value.as.number;
+}
+
+static inline void nh_bad_operands(const char *operator, const char *reason) {
+    nh_fail(E_BAD_OPERAND, "Bad operands for %s: %s.", operator, reason);
+}
+
+/* + - * / % on two numbers, like ExprLiteral::arithmetic. */
+static inline NhValue nh_arithmetic(char operator, NhValue left, NhValue right) {
+    const char lexeme[2] = {operator, '\0'};
+    if (!nh_is_number(left) || !nh_is_number(right)) {
+        nh_bad_operands(lexeme, "operands must be numbers");
+    }
+    if (left.tag == NH_FLOAT || right.tag == NH_FLOAT) {
+        double l = nh_as_float(left), r = nh_as_float(right);
+        switch (operator) {
+        case '+':
+            return nh_float(l + r);
+        case '-':
+            return nh_float(l - r);
+        case '*':
+            return nh_float(l * r);
+        case '/':
+            return nh_float(l / r);
+        default:
+            return nh_float(fmod(l, r));
+        }
+    }
+    int64_t l = left.as.integer, r = right.as.integer;
+    bool overflow = false;
+    switch (operator) {
+    case '+':
+        overflow = (r > 0 && l > INT64_MAX - r) || (r < 0 && l < INT64_MIN - r);
+        break;
+    case '-':
+        overflow = (r < 0 && l > INT64_MAX + r) || (r > 0 && l < INT64_MIN + r);
+        break;
+    case '*':
+        if (l > 0) {
+            overflow = r > 0 ? l > INT64_MAX / r : r < INT64_MIN / l;
+        } else if (l < 0) {
+            overflow = r > 0 ? l < INT64_MIN / r : r != 0 && l < INT64_MAX / r;
+        }
+        break;
+    default: /* '/' and '%' */
+        if (r == 0) {
+            nh_bad_operands(lexeme, "integer division by zero");
+        }
+        overflow = l == INT64_MIN && r == -1;
+        break;
+    }
+    if (overflow) {
+        nh_bad_operands(lexeme, "integer overflow");
+    }
+    switch (operator) {
+    case '+':
+        return nh_int(l + r);
+    case '-':
+        return nh_int(l - r);
+    case '*':
+        return nh_int(l * r);
+    case '/':
+        return nh_int(l / r);
+    default:
+        return nh_int(l % r);
+    }
+}
+
+static inline NhValue nh_add(NhValue left, NhValue right) {
+    if (left.tag == NH_STRING && right.tag == NH_STRING) {
+        return nh_str(nh_concat(left.as.string, right.as.string));
+    }
+    return nh_arithmetic('+', left, right);
+}
+
+static inline NhValue nh_subtract(NhValue left, NhValue right) {
+    return nh_arithmetic('-', left, right);
+}
+
+static inline NhValue nh_multiply(NhValue left, NhValue right) {
+    return nh_arithmetic('*', left, right);
+}
+
+static inline NhValue nh_divide(NhValue left, NhValue right) {
+    return nh_arithmetic('/', left, right);
+}
+
+static inline NhValue nh_modulo(NhValue left, NhValue right) {
+    return nh_arithmetic('%', left, right);
+}
+
+/* The order of two numbers: -1, 0 or 1, 2 when one is NaN, which compares false. */
+static inline int nh_order(const char *operator, NhValue left, NhValue right) {
+    if (!nh_is_number(left) || !nh_is_number(right)) {
+        nh_bad_operands(operator, "operands must be numbers");
+    }
+    if (left.tag == NH_INT && right.tag == NH_INT) {
+        return (left.as.integer > right.as.integer) - (left.as.integer < right.as.integer);
+    }
+    double l = nh_as_float(left), r = nh_as_float(right);
+    if (isnan(l) || isnan(r)) {
+        return 2;
+    }
+    return (l > r) - (l < r);
+}
+
+static inline NhValue nh_greater(NhValue left, NhValue right) {
+    return nh_bool(nh_order(">", left, right) == 1);
+}
+
+static inline NhValue nh_greater_equal(NhValue left, NhValue right) {
+    int order = nh_order(">=", left, right);
+    return nh_bool(order == 1 || order == 0);
+}
+
+static inline NhValue nh_less(NhValue left, NhValue right) {
+    return nh_bool(nh_order("<", left, right) == -1);
+}
+
+static inline NhValue nh_less_equal(NhValue left, NhValue right) {
+    int order = nh_order("<=", left, right);
+    return nh_bool(order == -1 || order == 0);
+}
+
+/* ==, numbers and strings by value, functions by identity. */
+static inline bool nh_equals(NhValue left, NhValue right) {
+    if (nh_is_number(left) && nh_is_number(right)) {
+        if (left.tag == NH_INT && right.tag == NH_INT) {
+            return left.as.integer == right.as.integer;
+        }
+        return nh_as_float(left) == nh_as_float(right);
+    }
+    if (left.tag != right.tag) {
+        return false;
+    }
+    switch (left.tag) {
+    case NH_NIL:
+        return true;
+    case NH_BOOL:
+        return left.as.boolean == right.as.boolean;
+    case NH_STRING:
+        return left.as.string->length == right.as.string->length &&
+               memcmp(left.as.string->chars, right.as.string->chars,
+                      left.as.string->length) == 0;
+    case NH_FUNCTION:
+        return left.as.function == right.as.function;
+    case NH_NATIVE:
+        return left.as.native == right.as.native;
+    default:
+        return false;
+    }
+}
+
+static inline NhValue nh_equal(NhValue left, NhValue right) {
+    return nh_bool(nh_equals(left, right));
+}
+
+static inline NhValue nh_not_equal(NhValue left, NhValue right) {
+    return nh_bool(!nh_equals(left, right));
+}
+
+static inline NhValue nh_negate(NhValue right) {
+    if (right.tag == NH_FLOAT) {
+        return nh_float(-right.as.number);
+    }
+    if (right.tag != NH_INT) {
+        nh_fail(E_BAD_OPERAND, "Bad operand for -: operand must be a number.");
+    }
+    if (right.as.integer == INT64_MIN) {
+        nh_fail(E_BAD_OPERAND, "Bad operand for -: integer overflow.");
+    }
+    return nh_int(-right.as.integer);
+}
+
+static inline NhValue nh_not(NhValue right) {
+    return nh_bool(!nh_truthy(right));
+}
+
+/* ---- calls ---- */
+
+/* "argument" after an exact count of 1, like Arity::noun. */
+static inline const char *nh_argument_noun(int count) {
+    return count == 1 ? "argument" : "arguments";
+}
+
+static inline NhValue nh_call(NhValue callee, int argc, NhValue *argv) {
+    if (callee.tag != NH_FUNCTION && callee.tag != NH_NATIVE) {
+        nh_fail(E_NOT_CALLABLE, "Can only call functions and classes, got %s.",
+                nh_to_string(callee)->chars);
+    }
+    if (nh_depth >= NH_MAX_CALL_DEPTH) {
+        nh_fail(E_STACK_OVERFLOW, "Stack overflow.");
+    }
+    NhValue result;
+    nh_depth++;
+    if (callee.tag == NH_NATIVE) {
+        const NhNative *native = callee.as.native;
+        if (argc != native->arity) {
+            nh_fail(E_ARITY, "<native fn %s> expects %d %s but got %d.", native->name,
+                    native->arity, nh_argument_noun(native->arity), argc);
+        }
+        result = native->code(argv);
+    } else {
+        NhClosure *closure = callee.as.function;
+        const NhProto *proto = closure->proto;
+        if (argc < proto->min || argc > proto->max) {
+            if (proto->min == proto->max) {
+                nh_fail(E_ARITY, "<fn %s> expects %d %s but got %d.", proto->name,
+                        proto->min, nh_argument_noun(proto->min), argc);
+            }
+            nh_fail(E_ARITY, "<fn %s> expects %d to %d arguments but got %d.", proto->name,
+                    proto->min, proto->max, argc);
+        }
+        result = proto->code(closure, argc, argv);
+    }
+    nh_depth--;
+    return result;
+}
+
+/* ---- natives ---- */
+
+static inline NhValue nh_clock_code(NhValue *argv) {
+    struct timespec now;
+    (void)argv;
+    timespec_get(&now, TIME_UTC);
+    return nh_float((double)now.tv_sec + (double)now.tv_nsec / 1e9);
+}
+
+static inline NhValue nh_len_code(NhValue *argv) {
+    if (argv[0].tag != NH_STRING) {
+        nh_fail(E_NATIVE, "len: expects a list, a map or a string, got %s.",
+                nh_to_string(argv[0])->chars);
+    }
+    /* Characters, not bytes: count what does not continue a UTF-8 sequence. */
+    int64_t count = 0;
+    for (size_t i = 0; i < argv[0].as.string->length; i++) {
+        count += ((unsigned char)argv[0].as.string->chars[i] & 0xC0) != 0x80;
+    }
+    return nh_int(count);
+}
+
+static inline NhValue nh_to_string_code(NhValue *argv) {
+    return nh_str(nh_to_string(argv[0]));
+}
+
+static inline NhValue nh_type_of_code(NhValue *argv) {
+    static const char *names[] = {"nil", "bool", "int", "float", "string", "function", "function"};
+    const char *name = names[argv[0].tag];
+    return nh_str(nh_string(name, strlen(name)));
+}
+
+int main(void) {
+    nh_script();
+    return 0;
+}
@@ -1,13 +1,29 @@
-use std::{collections::HashMap, num::ParseFloatError};
-
-use super::token::{LiterialValue, Token, TokenType};
-
+use std::{
+    collections::HashMap,
+    num::{ParseFloatError, ParseIntError},
+};
+
+use super::{
+    diagnostic::{
+        Diagnostic, Span, E_BAD_ESCAPE, E_BAD_NUMBER, E_UNEXPECTED_CHAR, E_UNTERMINATED_STRING,
+    },
+    token::{LiterialValue, Token, TokenType},
+};
+
+// The cursor moves over the source one UTF-8 char at a time: start and current are byte
+// offsets, so that lexemes are slices of the source, while columns count chars.
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
-    start: usize,
-    current: usize,
+    start: usize,   // byte offset of the lexeme being scanned.
+    current: usize, // byte offset of the next char.
     line: usize,
+    column: usize,       // chars between the start of the line and current.
+    start_line: usize,   // line of start, a string may span several lines.
+    start_column: usize, // column of start.
+    // One entry per "${" not closed yet: the "{" opened inside it, so that its "}" is found.
+    interpolations: Vec<usize>,
+    keep_comments: bool, // add Comment tokens, for the formatter.
 
     keywords: HashMap<&'static str, TokenType>,
 }
@@ -20,20 +36,38 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            column: 0,
+            start_line: 1,
+            start_column: 0,
+            interpolations: vec![],
+            keep_comments: false,
 
             keywords: Self::get_keyword_hashmap(),
         }
     }
 
+    // brief: Also add a Comment token for every comment, the Parser does not expect them.
+    // input:
+    // output:
+    pub fn keep_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
     pub fn get_keyword_hashmap() -> HashMap<&'static str, TokenType> {
         HashMap::from([
             ("and", TokenType::And),
+            ("as", TokenType::As),
+            ("break", TokenType::Break),
             ("class", TokenType::CLass),
+            ("continue", TokenType::Continue),
             ("else", TokenType::Else),
             ("false", TokenType::False),
             ("for", TokenType::For),
             ("fn", TokenType::Fn),
             ("if", TokenType::If),
+            ("import", TokenType::Import),
+            ("in", TokenType::In),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
@@ -48,13 +82,15 @@ impl Scanner {
 
     // brief: scan tokens from  self.source,
     // input:
-    // output: Convert self.source into Vec<String>
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, String> {
+    // output: Convert self.source into Vec<Token>, or every error found on the way.
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<Diagnostic>> {
         let mut scan_errors = vec![];
 
         // Scan the source String.
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
 
             match self.scan_token() {
                 Ok(_) => {}
@@ -64,23 +100,29 @@ impl Scanner {
             }
         }
 
+        if !self.interpolations.is_empty() {
+            scan_errors.push(
+                self.error(
+                    E_UNTERMINATED_STRING,
+                    "Unterminated string interpolation.".to_string(),
+                )
+                .with_note("add a closing } to end the ${...}"),
+            );
+        }
+
         // Add an EOF.
         self.tokens.push(Token {
             token_type: TokenType::Eof,
             lexeme: "EOF".to_string(),
             literial: None,
             line_number: self.line,
+            column: self.column + 1,
+            span: Span::new(self.current, self.current),
         });
 
-        // Return all thErrors.
+        // Return all the errors.
         if !scan_errors.is_empty() {
-            let mut joined = "".to_string();
-
-            for msg in scan_errors {
-                joined.push_str(&msg);
-            }
-            joined.push('\n');
-            Err(joined)
+            Err(scan_errors)
         } else {
             Ok(self.tokens.clone())
         }
@@ -93,21 +135,55 @@ impl Scanner {
     // brief: match token and add token to self, used by scan_tokens.
     // input:
     // output:
-    fn scan_token(&mut self) -> Result<(), String> {
+    fn scan_token(&mut self) -> Result<(), Diagnostic> {
         let temp_char: char = self.advance();
 
         // Notice: After advance(), self.current points to the new character.
         match temp_char {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                // The "}" of a "${", the string goes on after it.
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.find_a_string()?;
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace);
+                }
+                None => self.add_token(TokenType::RightBrace),
+            },
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::Ellipsis);
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
+            }
+            '-' => {
+                if self.second_operator_match('>') {
+                    self.add_token(TokenType::Arrow);
+                } else {
+                    self.add_token(TokenType::Minus);
+                }
+            }
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
             '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
             '!' => {
                 if self.second_operator_match('=') {
                     self.add_token(TokenType::BangEqual);
@@ -142,6 +218,9 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -151,6 +230,7 @@ impl Scanner {
 
             '\n' => {
                 self.line += 1;
+                self.column = 0;
             }
 
             '"' => {
@@ -163,7 +243,10 @@ impl Scanner {
                 } else if self.is_alpha(temp_char) {
                     self.find_an_identifier()?;
                 } else {
-                    return Err(format!("Unexpected character at line: {}", self.line));
+                    return Err(self.error(
+                        E_UNEXPECTED_CHAR,
+                        format!("Unexpected character {:?}.", temp_char),
+                    ));
                 }
             }
         };
@@ -175,8 +258,11 @@ impl Scanner {
     // output: next char.
     // Notice: After advance(), self.current points to the new character.
     fn advance(&mut self) -> char {
-        let current_char = self.source.chars().nth(self.current).unwrap_or('\0');
-        self.current += 1;
+        let current_char = self.peek();
+        if !self.is_at_end() {
+            self.current += current_char.len_utf8();
+            self.column += 1;
+        }
         current_char
     }
 
@@ -196,48 +282,141 @@ impl Scanner {
             token_type,
             lexeme: text,
             literial,
-            line_number: self.line,
+            line_number: self.start_line,
+            column: self.start_column + 1,
+            span: Span::new(self.start, self.current),
         });
     }
 
+    // brief: An error pointing at the lexeme scanned so far.
+    // input:
+    // output:
+    fn error(&self, code: u16, message: String) -> Diagnostic {
+        Diagnostic::error(code, message).at(
+            Span::new(self.start, self.current),
+            self.start_line,
+            self.start_column + 1,
+        )
+    }
+
     // brief: wether the second character matched.(conditional advanced.)
     // input:
     // output:
     fn second_operator_match(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current).unwrap_or('\0') != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        self.current += 1;
+        self.advance();
         true
     }
 
-    // brief: Do sth when find an string.
+    // brief: Do sth when find an string, after its opening " or after the } of a "${".
+    // Escapes are replaced by the chars they stand for, and "${" ends the part: the tokens of
+    // the expression follow, then the rest of the string starting at its "}".
     // input:
     // output: Err or Ok
-    fn find_a_string(&mut self) -> Result<(), String> {
-        while self.peek() != '"' && !self.is_at_end() {
-            self.advance();
+    fn find_a_string(&mut self) -> Result<(), Diagnostic> {
+        let mut value = String::new();
+        let mut bad_escape = None; // the first one, the string is still scanned to its end.
+        loop {
+            if self.is_at_end() {
+                return Err(self
+                    .error(E_UNTERMINATED_STRING, "Unterminated string.".to_string())
+                    .with_note("add a closing \" to end the string"));
+            }
+            let (offset, column) = (self.current, self.column);
+            match self.advance() {
+                '"' => break,
+                '\n' => {
+                    self.line += 1;
+                    self.column = 0;
+                    value.push('\n');
+                }
+                '\\' => {
+                    match self.escape() {
+                        Ok(c) => value.push(c),
+                        Err(message) => {
+                            let span = Span::new(offset, self.current);
+                            bad_escape.get_or_insert(
+                            Diagnostic::error(E_BAD_ESCAPE, message)
+                                .at(span, self.line, column + 1)
+                                .with_note("valid escapes are \\n \\t \\r \\0 \\\" \\\\ \\$ and \\u{...}"),
+                        );
+                        }
+                    }
+                }
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    return self.string_part(TokenType::Interpolation, value, bad_escape);
+                }
+                c => value.push(c),
+            }
         }
+        self.string_part(TokenType::String, value, bad_escape)
+    }
 
-        if self.is_at_end() {
-            return Err(String::from("Unterminated String Error!"));
+    // brief: Add the token of a string or of a part of one.
+    // input:
+    // output: the first bad escape in it, if any.
+    fn string_part(
+        &mut self,
+        token_type: TokenType,
+        value: String,
+        bad_escape: Option<Diagnostic>,
+    ) -> Result<(), Diagnostic> {
+        if let Some(err) = bad_escape {
+            return Err(err);
         }
-
-        self.advance(); // consume the second ".
-
-        let value = self.source[(self.start + 1)..(self.current - 1)].to_string();
-        self.add_token_with_literial(TokenType::String, Some(LiterialValue::StringValue(value)));
+        self.add_token_with_literial(token_type, Some(LiterialValue::StringValue(value)));
         Ok(())
     }
 
+    // brief: The char an escape stands for, after its "\\".
+    // input:
+    // output: Err with the message if it is not a valid escape.
+    fn escape(&mut self) -> Result<char, String> {
+        match self.advance() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            '$' => Ok('$'),
+            'u' => {
+                if !self.second_operator_match('{') {
+                    return Err("Expected { after \\u.".to_string());
+                }
+                let mut digits = String::new();
+                while self.peek().is_ascii_hexdigit() {
+                    digits.push(self.advance());
+                }
+                if !self.second_operator_match('}') {
+                    return Err(format!("Unterminated unicode escape \\u{{{}.", digits));
+                }
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() <= 6)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("Invalid unicode escape \\u{{{}}}.", digits))
+            }
+            '\n' => {
+                self.line += 1;
+                self.column = 0;
+                Err("Unknown escape sequence \\ at the end of a line.".to_string())
+            }
+            '\0' if self.is_at_end() => Err("Unterminated escape sequence.".to_string()),
+            c => Err(format!("Unknown escape sequence \\{}.", c)),
+        }
+    }
+
     // brief: Do sth when find an Number, and check whether legal.
+    // "10" is an IntValue, "10.0" is a FloatValue.
     // input:
     // output: Err or Ok
 
-    fn find_a_number(&mut self) -> Result<(), String> {
+    fn find_a_number(&mut self) -> Result<(), Diagnostic> {
         // if is_at_end return Err.
         while self.is_digit(self.peek()) {
             self.advance();
@@ -250,7 +429,25 @@ impl Scanner {
                 self.advance();
             }
         } else {
-            return Err(String::from("digit Error!"));
+            let value: Result<i64, ParseIntError> = self.source[self.start..self.current].parse();
+            return match value {
+                Ok(v) => {
+                    self.add_token_with_literial(
+                        TokenType::Number,
+                        Some(LiterialValue::IntValue(v)),
+                    );
+                    Ok(())
+                }
+                Err(_) => Err(self
+                    .error(
+                        E_BAD_NUMBER,
+                        format!(
+                            "Integer literal {} is too large.",
+                            &self.source[self.start..self.current]
+                        ),
+                    )
+                    .with_note(format!("integers must fit in [{}, {}]", i64::MIN, i64::MAX))),
+            };
         }
         let value: Result<f64, ParseFloatError> = self.source[self.start..self.current].parse();
         match value {
@@ -259,14 +456,20 @@ impl Scanner {
                 Ok(())
             }
 
-            Err(_) => Err(String::from("Parse Error!")),
+            Err(_) => Err(self.error(
+                E_BAD_NUMBER,
+                format!(
+                    "Invalid number literal {}.",
+                    &self.source[self.start..self.current]
+                ),
+            )),
         }
     }
 
     // brief: Do sth when find an Identifier, or keyword.
     // input:
     // output: Ok
-    fn find_an_identifier(&mut self) -> Result<(), String> {
+    fn find_an_identifier(&mut self) -> Result<(), Diagnostic> {
         // if is_at_end return Err.
         while self.is_alpha_and_digit(self.peek()) {
             self.advance();
@@ -292,22 +495,25 @@ impl Scanner {
         c.is_ascii_digit()
     }
 
+    // Identifiers may use any letter, e.g. `let 名字 = 1;`, digits stay ASCII.
     fn is_alpha(&self, c: char) -> bool {
-        c.is_ascii_alphabetic() || c == '_'
+        c.is_alphabetic() || c == '_'
     }
 
     // brief: Return current char.
     // input:
     // output:
     fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap_or('\0')
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     // brief: Return next char.
     // input:
     // output:
     fn peek_next(&self) -> char {
-        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 }
 
@@ -320,7 +526,7 @@ mod tests {
         let sources = "(())".to_string();
         let mut scan = Scanner::new(sources);
 
-        let res = scan.scan_tokens().unwrap();
+        let _res = scan.scan_tokens().unwrap();
 
         //dbg!(res);
     }
@@ -337,7 +543,7 @@ mod tests {
                 //dbg!(res);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
@@ -354,7 +560,7 @@ mod tests {
                 //dbg!(res);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
@@ -371,7 +577,7 @@ mod tests {
                 //dbg!(res);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
@@ -388,7 +594,7 @@ mod tests {
                 //dbg!(res);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
@@ -405,7 +611,7 @@ mod tests {
                 //dbg!(res);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
@@ -443,10 +649,150 @@ mod tests {
                 dbg!(v);
             }
             Err(st) => {
-                println!("print is -> {}", st);
+                println!("print is -> {:?}", st);
             }
         }
     }
+    #[test]
+    fn handle_int_and_float_numbers() {
+        let sources = "10 10.5 7 % 2".to_string();
+        let mut scan = Scanner::new(sources);
+
+        let res = scan.scan_tokens().unwrap();
+
+        assert_eq!(res[0].literial, Some(LiterialValue::IntValue(10)));
+        assert_eq!(res[1].literial, Some(LiterialValue::FloatValue(10.5)));
+        assert_eq!(res[3].token_type, TokenType::Percent);
+        assert!(Scanner::new("99999999999999999999".to_string())
+            .scan_tokens()
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_and_errors_carry_positions() {
+        let res = Scanner::new("let a\n  = 10;".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert_eq!((res[1].line_number, res[1].column), (1, 5));
+        assert_eq!(res[1].span, Span::new(4, 5));
+        assert_eq!((res[3].line_number, res[3].column), (2, 5));
+        assert_eq!(res[3].span, Span::new(10, 12));
+
+        let errors = Scanner::new("let a = 1;\n  # \"abc".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, E_UNEXPECTED_CHAR);
+        assert_eq!((errors[0].line, errors[0].column), (2, 3));
+        assert_eq!(errors[1].code, E_UNTERMINATED_STRING);
+        assert_eq!((errors[1].line, errors[1].column), (2, 5));
+    }
+
+    #[test]
+    fn non_ascii_source() {
+        let res = Scanner::new("let 名字 = \"你好，世界\";\n名字 + é;".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert_eq!(res[1].token_type, TokenType::Identifier);
+        assert_eq!(res[1].lexeme, "名字");
+        assert_eq!(
+            res[3].literial,
+            Some(LiterialValue::StringValue("你好，世界".to_string()))
+        );
+        // Spans are bytes, columns are chars.
+        assert_eq!(res[3].span, Span::new(13, 30));
+        assert_eq!((res[3].line_number, res[3].column), (1, 10));
+        assert_eq!((res[7].lexeme.as_str(), res[7].column), ("é", 6));
+
+        let errors = Scanner::new("\"ü\" € 1".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(errors[0].message, "Unexpected character '€'.");
+        assert_eq!(
+            (errors[0].column, errors[0].span),
+            (5, Some(Span::new(5, 8)))
+        );
+    }
+
+    #[test]
+    fn large_sources_scan_in_linear_time() {
+        let source = "let s = \"ß\" + 1.5; // ключ\n".repeat(20_000);
+        let res = Scanner::new(source).scan_tokens().unwrap();
+        assert_eq!(res.len(), 7 * 20_000 + 1);
+        assert_eq!(res[res.len() - 2].line_number, 20_000);
+    }
+
+    #[test]
+    fn escapes_and_multi_line_strings() {
+        let res = Scanner::new("\"a\\n\\t\\\"\\\\\\$\\u{4e2d}\"\n\"x\ny\" z".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert_eq!(
+            res[0].literial,
+            Some(LiterialValue::StringValue("a\n\t\"\\$中".to_string()))
+        );
+        assert_eq!(
+            res[1].literial,
+            Some(LiterialValue::StringValue("x\ny".to_string()))
+        );
+        // A string is on the line it starts on, what follows it on the line it ends on.
+        assert_eq!((res[1].line_number, res[1].column), (2, 1));
+        assert_eq!((res[2].line_number, res[2].column), (3, 4));
+
+        let errors = Scanner::new("\"a\\q \\u{110000}\"; \"\\u{41\"".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Unknown escape sequence \\q.",
+                "Unterminated unicode escape \\u{41.",
+            ]
+        );
+        assert_eq!((errors[0].code, errors[0].column), (E_BAD_ESCAPE, 3));
+    }
+
+    #[test]
+    fn interpolation_splits_the_string() {
+        let res = Scanner::new("\"a ${x + 1} b ${ {\"k\": \"${y}\"} } c\"".to_string())
+            .scan_tokens()
+            .unwrap();
+        let types: Vec<TokenType> = res.iter().map(|t| t.token_type.clone()).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Interpolation,
+                TokenType::Identifier,
+                TokenType::Plus,
+                TokenType::Number,
+                TokenType::Interpolation,
+                TokenType::LeftBrace,
+                TokenType::String,
+                TokenType::Colon,
+                TokenType::Interpolation,
+                TokenType::Identifier,
+                TokenType::String,
+                TokenType::RightBrace,
+                TokenType::String,
+                TokenType::Eof,
+            ]
+        );
+        assert_eq!(
+            res[4].literial,
+            Some(LiterialValue::StringValue(" b ".to_string()))
+        );
+        assert_eq!(
+            res[12].literial,
+            Some(LiterialValue::StringValue(" c".to_string()))
+        );
+
+        let errors = Scanner::new("\"a ${x".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(errors[0].code, E_UNTERMINATED_STRING);
+    }
+
     // cargo test <unique signature: keyword> --  --nocapture
 
     // #[test]
@@ -0,0 +1,78 @@
+use std::fs;
+
+// Where the Rust stack of the current thread ends, so that an Engine can stop calls before
+// they run out of it. Stacks grow down on every target Noah runs on.
+
+// brief: Roughly where the Rust stack of the current thread is.
+// input:
+// output: the address of a local of this call.
+#[inline(never)]
+pub fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// brief: The lowest address the stack of the current thread may grow down to.
+// input:
+// output: None where it can not be found out: off Linux, without /proc, or for a main thread
+// whose stack size is unlimited.
+pub fn stack_end() -> Option<usize> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let here = stack_address();
+    let maps = fs::read_to_string("/proc/self/maps").ok()?;
+    for line in maps.lines() {
+        // start-end perms offset dev inode [path]
+        let mut fields = line.split_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let start = usize::from_str_radix(start, 16).ok()?;
+        let end = usize::from_str_radix(end, 16).ok()?;
+        if !(start..end).contains(&here) {
+            continue;
+        }
+        // The main thread's stack is mapped as it grows, up to the stack size limit. Other
+        // threads get the whole mapping up front, their guard page is a mapping of its own.
+        return match fields.nth(4) {
+            Some("[stack]") => end.checked_sub(main_stack_limit()?),
+            _ => Some(start),
+        };
+    }
+    None
+}
+
+// brief: The soft limit on the size of the main thread's stack.
+// input:
+// output: bytes, None if unlimited or unknown.
+fn main_stack_limit() -> Option<usize> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max stack size"))?;
+    line["Max stack size".len()..]
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{stack_address, stack_end};
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn a_spawned_thread_ends_within_its_stack_size() {
+        thread::Builder::new()
+            .stack_size(1024 * 1024)
+            .spawn(|| {
+                let room = stack_address() - stack_end().unwrap();
+                assert!(room > 512 * 1024 && room <= 1024 * 1024, "{}", room);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
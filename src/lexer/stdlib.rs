@@ -0,0 +1,654 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    io::BufRead,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    callable::{Arity, Callable},
+    diagnostic::{Diagnostic, E_NATIVE},
+    expr::ExprLiteral,
+    map::MyMap,
+    output::Output,
+};
+
+pub type NativeBody = dyn Fn(&[ExprLiteral]) -> Result<ExprLiteral, String>;
+pub type WritingBody = dyn Fn(&mut Output, &[ExprLiteral]) -> Result<ExprLiteral, String>;
+
+// What a native runs: most only look at their arguments, a few write to the output too.
+enum Body {
+    Pure(Box<NativeBody>),
+    Writing(Box<WritingBody>),
+}
+
+// A function written in Rust. Both backends check the arity before calling it, the body only
+// reports what is wrong with the arguments and the error gets the name of the function.
+// Optional and variadic arguments are simply missing from or added to the slice.
+pub struct NativeFn {
+    name: &'static str,
+    arity: Arity,
+    body: Body,
+}
+
+impl NativeFn {
+    pub fn new(
+        name: &'static str,
+        arity: impl Into<Arity>,
+        body: impl Fn(&[ExprLiteral]) -> Result<ExprLiteral, String> + 'static,
+    ) -> Self {
+        Self {
+            name,
+            arity: arity.into(),
+            body: Body::Pure(Box::new(body)),
+        }
+    }
+
+    // brief: A native that writes where print writes, whichever backend calls it.
+    // input:
+    // output:
+    pub fn writing(
+        name: &'static str,
+        arity: impl Into<Arity>,
+        body: impl Fn(&mut Output, &[ExprLiteral]) -> Result<ExprLiteral, String> + 'static,
+    ) -> Self {
+        Self {
+            name,
+            arity: arity.into(),
+            body: Body::Writing(Box::new(body)),
+        }
+    }
+}
+
+impl Debug for NativeFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
+
+impl Callable for NativeFn {
+    fn call_native(&self, arguments: Vec<ExprLiteral>) -> Result<ExprLiteral, Diagnostic> {
+        self.call_with_output(&mut Output::Stdout, arguments)
+    }
+
+    fn call_with_output(
+        &self,
+        output: &mut Output,
+        arguments: Vec<ExprLiteral>,
+    ) -> Result<ExprLiteral, Diagnostic> {
+        match &self.body {
+            Body::Pure(body) => body(&arguments),
+            Body::Writing(body) => body(output, &arguments),
+        }
+        .map_err(|msg| Diagnostic::error(E_NATIVE, format!("{}: {}.", self.name, msg)))
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    // Natives have no parameter names: "_" is required, "[_]" optional, "..." any more.
+    fn signature(&self) -> String {
+        let optional = self.arity.max.map_or(0, |max| max - self.arity.min);
+        let mut params = vec!["_"; self.arity.min];
+        params.extend(vec!["[_]"; optional]);
+        if self.arity.max.is_none() {
+            params.push("...");
+        }
+        format!("{}({})", self.name, params.join(", "))
+    }
+
+    fn two_string(&self) -> String {
+        format!("<native fn {}>", self.name)
+    }
+}
+
+// The natives a program starts with, in the order they were defined.
+// The Interpreter and the Vm define every entry as a global.
+#[derive(Default)]
+pub struct Registry {
+    entries: Vec<(&'static str, ExprLiteral)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(
+        &mut self,
+        name: &'static str,
+        arity: impl Into<Arity>,
+        body: impl Fn(&[ExprLiteral]) -> Result<ExprLiteral, String> + 'static,
+    ) {
+        self.add(NativeFn::new(name, arity, body));
+    }
+
+    pub fn add(&mut self, native: NativeFn) {
+        self.entries
+            .push((native.name, ExprLiteral::FunctionLiteral(Rc::new(native))));
+    }
+
+    pub fn entries(self) -> Vec<(&'static str, ExprLiteral)> {
+        self.entries
+    }
+
+    // brief: The whole standard library.
+    // input:
+    // output:
+    pub fn standard() -> Self {
+        let mut registry = Self::new();
+        registry.define("clock", 0, |_| {
+            let since_the_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards");
+            Ok(ExprLiteral::NumberLiteral(since_the_epoch.as_secs_f64()))
+        });
+        registry.define_strings();
+        registry.define_math();
+        registry.define_lists();
+        registry.define_maps();
+        registry.define("type_of", 1, |args| {
+            Ok(ExprLiteral::StringLiteral(args[0].type_name().to_string()))
+        });
+        // input(prompt?) shows the prompt on the same line as the answer.
+        registry.add(NativeFn::writing(
+            "input",
+            Arity::range(0, 1),
+            |output, args| {
+                if let Some(prompt) = args.first() {
+                    output
+                        .write(&prompt.two_string())
+                        .and_then(|_| output.flush())
+                        .map_err(|err| format!("can not write the prompt, {}", err))?;
+                }
+                let mut line = String::new();
+                match std::io::stdin().lock().read_line(&mut line) {
+                    Ok(0) => Ok(ExprLiteral::Nil), // end of input.
+                    Ok(_) => Ok(ExprLiteral::StringLiteral(
+                        line.trim_end_matches(['\n', '\r']).to_string(),
+                    )),
+                    Err(err) => Err(format!("can not read stdin, {}", err)),
+                }
+            },
+        ));
+        registry.define("assert", Arity::range(1, 2), |args| {
+            if args[0].is_truthy() {
+                Ok(ExprLiteral::Nil)
+            } else {
+                Err(args
+                    .get(1)
+                    .map_or("assertion failed".to_string(), |msg| msg.two_string()))
+            }
+        });
+        registry
+    }
+
+    // brief: The natives the Parser calls in what it desugars that are not globals, for the
+    // readers of syntax trees.
+    // input:
+    // output:
+    pub fn desugared() -> Self {
+        let mut registry = Self::new();
+        registry.add(items());
+        registry
+    }
+
+    // brief: argc() and argv(i) for a script run with `args`.
+    // input: script path and the arguments following it.
+    // output:
+    pub fn script_args(script: &str, args: Vec<String>) -> Self {
+        let mut all = vec![script.to_string()];
+        all.extend(args);
+        let all = Rc::new(all);
+
+        let mut registry = Self::new();
+        let count = all.len();
+        registry.define("argc", 0, move |_| {
+            Ok(ExprLiteral::IntLiteral(count as i64))
+        });
+        registry.define("argv", 1, move |args| match args[0] {
+            ExprLiteral::IntLiteral(v) if v >= 0 && (v as usize) < all.len() => {
+                Ok(ExprLiteral::StringLiteral(all[v as usize].clone()))
+            }
+            ExprLiteral::IntLiteral(v) => {
+                Err(format!("index {} out of range, argc() is {}", v, all.len()))
+            }
+            ref other => Err(expected("an integer", other)),
+        });
+        registry
+    }
+
+    fn define_strings(&mut self) {
+        self.define("substr", 3, |args| {
+            let s = string(&args[0])?;
+            let count = s.chars().count();
+            let (start, end) = range(&args[1], &args[2], count)?;
+            Ok(ExprLiteral::StringLiteral(
+                s.chars().skip(start).take(end - start).collect(),
+            ))
+        });
+        self.define("upper", 1, |args| {
+            Ok(ExprLiteral::StringLiteral(string(&args[0])?.to_uppercase()))
+        });
+        self.define("lower", 1, |args| {
+            Ok(ExprLiteral::StringLiteral(string(&args[0])?.to_lowercase()))
+        });
+        self.define("split", 2, |args| {
+            let (s, separator) = (string(&args[0])?, string(&args[1])?);
+            let parts: Vec<ExprLiteral> = if separator.is_empty() {
+                s.chars()
+                    .map(|c| ExprLiteral::StringLiteral(c.to_string()))
+                    .collect()
+            } else {
+                s.split(separator)
+                    .map(|part| ExprLiteral::StringLiteral(part.to_string()))
+                    .collect()
+            };
+            Ok(ExprLiteral::list(parts))
+        });
+        self.define("to_number", 1, |args| {
+            let s = string(&args[0])?.trim();
+            if let Ok(v) = s.parse::<i64>() {
+                return Ok(ExprLiteral::IntLiteral(v));
+            }
+            match s.parse::<f64>() {
+                Ok(v) => Ok(ExprLiteral::NumberLiteral(v)),
+                Err(_) => Ok(ExprLiteral::Nil), // not a number.
+            }
+        });
+        self.add(to_string());
+        // format("{} + {} = {}", 1, 2, 3), one argument for every "{}".
+        self.define("format", Arity::at_least(1), |args| {
+            let template = string(&args[0])?;
+            let holes = template.matches("{}").count();
+            if holes != args.len() - 1 {
+                return Err(format!(
+                    "the template has {} placeholders but got {} values",
+                    holes,
+                    args.len() - 1
+                ));
+            }
+            let mut values = args[1..].iter();
+            let pieces: Vec<String> = template
+                .split("{}")
+                .enumerate()
+                .map(|(i, piece)| match i {
+                    0 => piece.to_string(),
+                    _ => format!("{}{}", values.next().unwrap().two_string(), piece),
+                })
+                .collect();
+            Ok(ExprLiteral::StringLiteral(pieces.concat()))
+        });
+    }
+
+    fn define_math(&mut self) {
+        self.define("max", Arity::at_least(1), |args| {
+            extreme(args, |a, b| a > b)
+        });
+        self.define("min", Arity::at_least(1), |args| {
+            extreme(args, |a, b| a < b)
+        });
+        self.define("sqrt", 1, |args| {
+            Ok(ExprLiteral::NumberLiteral(number(&args[0])?.sqrt()))
+        });
+        self.define("floor", 1, |args| match args[0] {
+            ExprLiteral::IntLiteral(v) => Ok(ExprLiteral::IntLiteral(v)),
+            _ => {
+                let v = number(&args[0])?.floor();
+                if v.is_finite() && v >= i64::MIN as f64 && v < i64::MAX as f64 {
+                    Ok(ExprLiteral::IntLiteral(v as i64))
+                } else {
+                    Err(format!("{} does not fit in an integer", v))
+                }
+            }
+        });
+        self.define("abs", 1, |args| match args[0] {
+            ExprLiteral::IntLiteral(v) => v
+                .checked_abs()
+                .map(ExprLiteral::IntLiteral)
+                .ok_or_else(|| "integer overflow".to_string()),
+            _ => Ok(ExprLiteral::NumberLiteral(number(&args[0])?.abs())),
+        });
+        self.define("pow", 2, |args| match (&args[0], &args[1]) {
+            // Ints stay ints for a non-negative exponent, like the arithmetic operators.
+            (ExprLiteral::IntLiteral(base), ExprLiteral::IntLiteral(exp)) if *exp >= 0 => {
+                u32::try_from(*exp)
+                    .ok()
+                    .and_then(|exp| base.checked_pow(exp))
+                    .map(ExprLiteral::IntLiteral)
+                    .ok_or_else(|| "integer overflow".to_string())
+            }
+            _ => Ok(ExprLiteral::NumberLiteral(
+                number(&args[0])?.powf(number(&args[1])?),
+            )),
+        });
+
+        // xorshift64*, seeded from the clock unless the script calls seed(n).
+        let state = Rc::new(Cell::new(scramble(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|v| v.as_nanos() as u64)
+                .unwrap_or(0),
+        )));
+        let seed_state = state.clone();
+        self.define("seed", 1, move |args| match args[0] {
+            ExprLiteral::IntLiteral(v) => {
+                seed_state.set(scramble(v as u64));
+                Ok(ExprLiteral::Nil)
+            }
+            ref other => Err(expected("an integer", other)),
+        });
+        self.define("random", 0, move |_| {
+            let mut x = state.get();
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            state.set(x);
+            let bits = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11; // 53 random bits.
+            Ok(ExprLiteral::NumberLiteral(
+                bits as f64 / (1u64 << 53) as f64,
+            ))
+        });
+    }
+
+    fn define_lists(&mut self) {
+        self.add(len());
+        self.define("push", 2, |args| {
+            list(&args[0])?.borrow_mut().push(args[1].clone());
+            Ok(ExprLiteral::Nil)
+        });
+        self.define("pop", 1, |args| {
+            list(&args[0])?
+                .borrow_mut()
+                .pop()
+                .ok_or_else(|| "the list is empty".to_string())
+        });
+        self.define("slice", 3, |args| {
+            let list = list(&args[0])?.borrow();
+            let (start, end) = range(&args[1], &args[2], list.len())?;
+            Ok(ExprLiteral::list(list[start..end].to_vec()))
+        });
+    }
+
+    fn define_maps(&mut self) {
+        self.define("keys", 1, |args| {
+            let map = map(&args[0])?.borrow();
+            Ok(ExprLiteral::list(
+                map.entries().iter().map(|(k, _)| k.clone()).collect(),
+            ))
+        });
+        self.define("values", 1, |args| {
+            let map = map(&args[0])?.borrow();
+            Ok(ExprLiteral::list(
+                map.entries().iter().map(|(_, v)| v.clone()).collect(),
+            ))
+        });
+        self.define("has", 2, |args| {
+            let found = map(&args[0])?.borrow().contains(&args[1])?;
+            Ok(ExprLiteral::from_bool(found))
+        });
+        // Returns the removed value, nil if there was none.
+        self.define("remove", 2, |args| {
+            let value = map(&args[0])?.borrow_mut().remove(&args[1])?;
+            Ok(value.unwrap_or(ExprLiteral::Nil))
+        });
+    }
+}
+
+// brief: len(v), the number of items of a list or a map, or of chars of a string.
+// The Parser also calls it directly for the desugared for-in loop.
+// input:
+// output:
+pub fn len() -> NativeFn {
+    NativeFn::new("len", 1, |args| {
+        let count = match &args[0] {
+            ExprLiteral::ListLiteral(list) => list.borrow().len(),
+            ExprLiteral::MapLiteral(map) => map.borrow().entries().len(),
+            ExprLiteral::StringLiteral(s) => s.chars().count(),
+            other => return Err(expected("a list, a map or a string", other)),
+        };
+        Ok(ExprLiteral::IntLiteral(count as i64))
+    })
+}
+
+// brief: items(v), what a for-in loop indexes: the chars of a string as a list, so that the
+// loop takes linear time, anything else as it is. Only the Parser calls it, it is no global.
+// input:
+// output:
+pub fn items() -> NativeFn {
+    NativeFn::new("items", 1, |args| match &args[0] {
+        ExprLiteral::StringLiteral(s) => Ok(ExprLiteral::list(
+            s.chars()
+                .map(|c| ExprLiteral::StringLiteral(c.to_string()))
+                .collect(),
+        )),
+        other => Ok(other.clone()),
+    })
+}
+
+// brief: One splitmix64 step, so that close seeds start far apart.
+// input:
+// output: a state for xorshift, which must not start from 0.
+fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    match z ^ (z >> 31) {
+        0 => 0x2545_f491_4f6c_dd1d,
+        state => state,
+    }
+}
+
+// brief: to_string(v), v as print shows it.
+// The Parser also calls it directly for the desugared string interpolation.
+// input:
+// output:
+pub fn to_string() -> NativeFn {
+    NativeFn::new("to_string", 1, |args| {
+        Ok(ExprLiteral::StringLiteral(args[0].two_string()))
+    })
+}
+
+// brief: max and min, the first argument that beats every other one.
+// input: `beats` compares two numbers.
+// output: the argument itself, so that ints stay ints.
+fn extreme(args: &[ExprLiteral], beats: fn(f64, f64) -> bool) -> Result<ExprLiteral, String> {
+    let mut best = &args[0];
+    let mut best_value = number(best)?;
+    for arg in &args[1..] {
+        let value = number(arg)?;
+        if beats(value, best_value) {
+            (best, best_value) = (arg, value);
+        }
+    }
+    Ok(best.clone())
+}
+
+pub fn expected(what: &str, got: &ExprLiteral) -> String {
+    format!("expects {}, got {}", what, got.two_string())
+}
+
+fn string(value: &ExprLiteral) -> Result<&str, String> {
+    match value {
+        ExprLiteral::StringLiteral(s) => Ok(s),
+        other => Err(expected("a string", other)),
+    }
+}
+
+fn number(value: &ExprLiteral) -> Result<f64, String> {
+    match value {
+        ExprLiteral::IntLiteral(v) => Ok(*v as f64),
+        ExprLiteral::NumberLiteral(v) => Ok(*v),
+        other => Err(expected("a number", other)),
+    }
+}
+
+fn list(value: &ExprLiteral) -> Result<&Rc<RefCell<Vec<ExprLiteral>>>, String> {
+    match value {
+        ExprLiteral::ListLiteral(list) => Ok(list),
+        other => Err(expected("a list", other)),
+    }
+}
+
+fn map(value: &ExprLiteral) -> Result<&Rc<RefCell<MyMap>>, String> {
+    match value {
+        ExprLiteral::MapLiteral(map) => Ok(map),
+        other => Err(expected("a map", other)),
+    }
+}
+
+// brief: The [start, end) range of slice and substr.
+// input: end may be `len`, one past the last index.
+// output:
+fn range(start: &ExprLiteral, end: &ExprLiteral, len: usize) -> Result<(usize, usize), String> {
+    let bound = |value: &ExprLiteral| match value {
+        ExprLiteral::IntLiteral(i) if *i >= 0 && (*i as usize) <= len => Ok(*i as usize),
+        ExprLiteral::IntLiteral(i) => Err(format!("index {} out of range for length {}", i, len)),
+        other => Err(format!(
+            "index must be an integer, got {}",
+            other.two_string()
+        )),
+    };
+    let (start, end) = (bound(start)?, bound(end)?);
+    if start > end {
+        return Err(format!("start {} is after end {}", start, end));
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{
+        diagnostic::E_ARITY, interpreter::Interpreter, parser::Parser, resolver::Resolver,
+        scanner::Scanner,
+    };
+
+    fn eval(source: &str) -> Result<String, Diagnostic> {
+        let source = format!("let result = {};", source);
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.resolve(Resolver::new().resolve(&statements).unwrap());
+        interpreter.interpreter(&statements)?;
+        let values = interpreter.globals.borrow().values();
+        let (_, result) = values.iter().find(|(name, _)| name == "result").unwrap();
+        Ok(result.quoted())
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(eval("substr(\"hello\", 1, 3)").unwrap(), "\"el\"");
+        assert_eq!(eval("upper(\"abc\")").unwrap(), "\"ABC\"");
+        assert_eq!(
+            eval("split(\"a,b,,c\", \",\")").unwrap(),
+            "[\"a\", \"b\", \"\", \"c\"]"
+        );
+        assert_eq!(eval("split(\"ab\", \"\")").unwrap(), "[\"a\", \"b\"]");
+        assert_eq!(eval("to_number(\" 42 \")").unwrap(), "42");
+        assert_eq!(eval("to_number(\"2.5\")").unwrap(), "2.5");
+        assert_eq!(eval("to_number(\"x\")").unwrap(), "Nil");
+        assert_eq!(eval("to_string(1.5) + \"!\"").unwrap(), "\"1.5!\"");
+        assert_eq!(eval("len(\"abc\")").unwrap(), "3");
+        assert!(eval("substr(\"abc\", 2, 1)").is_err());
+        assert_eq!(eval("substr(\"abc\", 3, 3)").unwrap(), "\"\"");
+        assert_eq!(
+            eval("substr(\"abc\", 2, 99)").unwrap_err().message,
+            "substr: index 99 out of range for length 3."
+        );
+        assert_eq!(
+            eval("format(\"{} and {}\", 1, [\"x\"])").unwrap(),
+            "\"1 and [\\\"x\\\"]\""
+        );
+        assert!(eval("format(\"{}\", 1, 2)").is_err());
+    }
+
+    #[test]
+    fn slices() {
+        assert_eq!(eval("slice([1, 2, 3], 1, 3)").unwrap(), "[2, 3]");
+        assert_eq!(eval("slice([1, 2, 3], 3, 3)").unwrap(), "[]");
+        assert_eq!(
+            eval("slice([1, 2, 3], 1, 9)").unwrap_err().message,
+            "slice: index 9 out of range for length 3."
+        );
+        assert_eq!(
+            eval("slice([1, 2, 3], -1, 2)").unwrap_err().message,
+            "slice: index -1 out of range for length 3."
+        );
+    }
+
+    #[test]
+    fn for_in_walks_the_chars_of_a_string() {
+        let items = |value| items().call_native(vec![value]).unwrap().quoted();
+        assert_eq!(
+            items(ExprLiteral::StringLiteral("hé".to_string())),
+            "[\"h\", \"é\"]"
+        );
+        assert_eq!(items(ExprLiteral::list(vec![])), "[]");
+        assert_eq!(items(ExprLiteral::IntLiteral(5)), "5"); // len reports it.
+    }
+
+    #[test]
+    fn math() {
+        assert_eq!(eval("sqrt(16)").unwrap(), "4");
+        assert_eq!(eval("floor(-2.5)").unwrap(), "-3");
+        assert_eq!(eval("pow(2, 10)").unwrap(), "1024");
+        assert_eq!(eval("pow(2, -1)").unwrap(), "0.5");
+        assert_eq!(eval("abs(-3)").unwrap(), "3");
+        assert!(eval("pow(10, 100)").is_err());
+        assert!(eval("floor(\"1\")").is_err());
+        assert_eq!(eval("max(1, 2.5, -3)").unwrap(), "2.5");
+        assert_eq!(eval("min(4, 2, 3)").unwrap(), "2");
+        assert!(eval("max(1, \"2\")").is_err());
+    }
+
+    #[test]
+    fn random_is_seedable() {
+        let first = eval("[seed(7), random(), random()]").unwrap();
+        assert_eq!(first, eval("[seed(7), random(), random()]").unwrap());
+        assert_ne!(first, eval("[seed(8), random(), random()]").unwrap());
+        // Each pair 2k, 2k + 1 used to share one state.
+        assert_ne!(
+            eval("[seed(2), random(), random()]").unwrap(),
+            eval("[seed(3), random(), random()]").unwrap()
+        );
+        assert_ne!(
+            eval("[seed(0), random()]").unwrap(),
+            eval("[seed(1), random()]").unwrap()
+        );
+        let values = eval("[random(), random(), random()]").unwrap();
+        for value in values.trim_matches(['[', ']']).split(", ") {
+            let value: f64 = value.parse().unwrap();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn types_and_assert() {
+        assert_eq!(eval("type_of(1)").unwrap(), "\"int\"");
+        assert_eq!(eval("type_of(1.0)").unwrap(), "\"float\"");
+        assert_eq!(eval("type_of({})").unwrap(), "\"map\"");
+        assert_eq!(eval("type_of(clock)").unwrap(), "\"function\"");
+        assert_eq!(eval("assert(1 < 2, \"fine\")").unwrap(), "Nil");
+
+        let err = eval("assert(1 > 2, \"one is not above two\")").unwrap_err();
+        assert_eq!(err.code, E_NATIVE);
+        assert_eq!(err.message, "assert: one is not above two.");
+        assert_eq!(
+            eval("assert(false)").unwrap_err().message,
+            "assert: assertion failed."
+        );
+
+        let err = eval("upper()").unwrap_err();
+        assert_eq!(err.code, E_ARITY);
+        assert_eq!(err.notes, vec!["signature: upper(_)"]);
+        let err = eval("max()").unwrap_err();
+        assert_eq!(
+            err.message,
+            "<native fn max> expects at least 1 argument but got 0."
+        );
+        assert_eq!(err.notes, vec!["signature: max(_, ...)"]);
+    }
+}
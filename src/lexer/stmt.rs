@@ -1,4 +1,9 @@
-use super::{expr::Expr, token::Token};
+use super::{
+    callable::Arity,
+    expr::{Expr, ExprLiteral},
+    printer::Printer,
+    token::Token,
+};
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
@@ -6,6 +11,7 @@ pub enum Stmt {
     Print(Expr),
     Let {
         name: Token,
+        annotation: Option<Token>, // the type after ":", see TypeChecker.
         initializer: Expr,
     },
     Block {
@@ -19,15 +25,108 @@ pub enum Stmt {
     While {
         condition: Expr,
         body: Box<Stmt>,
+        increment: Option<Expr>, // of a desugared for, runs after the body and on continue.
     },
     Function {
         name: Token,
-        params: Vec<Token>,
+        params: Vec<Token>,  // without the rest parameter.
+        defaults: Vec<Expr>, // of the last defaults.len() params.
+        rest: Option<Token>, // "...rest" collects the extra arguments into a list.
         body: Box<Stmt>,
+        annotations: Vec<Option<Token>>, // of params, then of rest.
+        returns: Option<Token>,          // the type after "->".
     },
 
     Return {
         keyword: Token,
-        value: Expr,
+        value: Option<Expr>,
     },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
+    },
+    Class {
+        name: Token,
+        superclass: Option<Expr>, // Expr::Variable
+        methods: Vec<Stmt>,       // Stmt::Function
+    },
+    Import {
+        keyword: Token,
+        path: String, // as written, see ModuleLoader::find.
+        name: Token,
+    },
+}
+
+impl Stmt {
+    // brief: Convert a Statement to source, see Printer.
+    // input:
+    // output: String, blocks over several lines.
+    pub fn two_string(&self) -> String {
+        Printer::stmt(self)
+    }
+
+    // brief: Arity of a Stmt::Function, shared by MyFunction and the Compiler.
+    // input:
+    // output: no arguments for any other statement.
+    pub fn arity(&self) -> Arity {
+        match self {
+            Stmt::Function {
+                params,
+                defaults,
+                rest,
+                ..
+            } => Arity {
+                min: params.len() - defaults.len(),
+                max: if rest.is_some() {
+                    None
+                } else {
+                    Some(params.len())
+                },
+            },
+            _ => Arity::exact(0),
+        }
+    }
+
+    // brief: Signature of a Stmt::Function for arity errors, e.g. "f(a, b = 2, ...rest)".
+    // Only literal defaults are spelled out as in the source, others show as "...".
+    // input:
+    // output: empty for any other statement.
+    pub fn signature(&self) -> String {
+        let Stmt::Function {
+            name,
+            params,
+            defaults,
+            rest,
+            ..
+        } = self
+        else {
+            return String::new();
+        };
+        let first_default = params.len() - defaults.len();
+        let mut parts: Vec<String> = params
+            .iter()
+            .enumerate()
+            .map(|(index, param)| match index.checked_sub(first_default) {
+                None => param.lexeme.clone(),
+                Some(i) => match &defaults[i] {
+                    Expr::Literal { value } => {
+                        let value = match value {
+                            ExprLiteral::True => "true".to_string(),
+                            ExprLiteral::False => "false".to_string(),
+                            ExprLiteral::Nil => "nil".to_string(),
+                            other => other.quoted(),
+                        };
+                        format!("{} = {}", param.lexeme, value)
+                    }
+                    _ => format!("{} = ...", param.lexeme),
+                },
+            })
+            .collect();
+        if let Some(rest) = rest {
+            parts.push(format!("...{}", rest.lexeme));
+        }
+        format!("{}({})", name.lexeme, parts.join(", "))
+    }
 }
@@ -1,9 +1,13 @@
+use super::diagnostic::Span;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literial: Option<LiterialValue>,
     pub line_number: usize,
+    pub column: usize, // 1 based, 0 for tokens not coming from the source.
+    pub span: Span,
 }
 
 impl Token {
@@ -18,6 +22,8 @@ impl Token {
             lexeme,
             literial,
             line_number,
+            column: 0,
+            span: Span::default(),
         }
     }
 
@@ -26,6 +32,7 @@ impl Token {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum LiterialValue {
     IntValue(i64),
@@ -41,13 +48,17 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
     // One or two character tokens.
     Bang,
     BangEqual,
@@ -57,18 +68,26 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,    // "->", before the return type of a function.
+    Ellipsis, // "...", before a rest parameter.
     // Literials.
     Identifier,
-    String,
+    String,        // a whole string, or the last part of an interpolated one.
+    Interpolation, // a part of a string followed by "${", the literial is its text.
     Number,
     // Keywords.
     And,
+    As,
+    Break,
     CLass,
+    Continue,
     Else,
     False,
     Fn,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
@@ -78,10 +97,77 @@ pub enum TokenType {
     True,
     Let,
     While,
+    // Trivia, only from Scanner::keep_comments.
+    Comment, // "// ..." up to the end of the line, the lexeme is the whole comment.
     // Eof.
     Eof,
 }
 
+impl TokenType {
+    // Every token type, in declaration order.
+    const ALL: [TokenType; 52] = [
+        TokenType::LeftParen,
+        TokenType::RightParen,
+        TokenType::LeftBrace,
+        TokenType::RightBrace,
+        TokenType::LeftBracket,
+        TokenType::RightBracket,
+        TokenType::Comma,
+        TokenType::Colon,
+        TokenType::Dot,
+        TokenType::Minus,
+        TokenType::Plus,
+        TokenType::Semicolon,
+        TokenType::Slash,
+        TokenType::Star,
+        TokenType::Percent,
+        TokenType::Bang,
+        TokenType::BangEqual,
+        TokenType::Equal,
+        TokenType::EqualEqual,
+        TokenType::Greater,
+        TokenType::GreaterEqual,
+        TokenType::Less,
+        TokenType::LessEqual,
+        TokenType::Arrow,
+        TokenType::Ellipsis,
+        TokenType::Identifier,
+        TokenType::String,
+        TokenType::Interpolation,
+        TokenType::Number,
+        TokenType::And,
+        TokenType::As,
+        TokenType::Break,
+        TokenType::CLass,
+        TokenType::Continue,
+        TokenType::Else,
+        TokenType::False,
+        TokenType::Fn,
+        TokenType::For,
+        TokenType::If,
+        TokenType::Import,
+        TokenType::In,
+        TokenType::Nil,
+        TokenType::Or,
+        TokenType::Print,
+        TokenType::Return,
+        TokenType::Super,
+        TokenType::This,
+        TokenType::True,
+        TokenType::Let,
+        TokenType::While,
+        TokenType::Comment,
+        TokenType::Eof,
+    ];
+
+    // brief: The token type Display prints as `name`, for ASTs read back from a file.
+    // input:
+    // output:
+    pub fn from_name(name: &str) -> Option<TokenType> {
+        Self::ALL.into_iter().find(|t| t.to_string() == name)
+    }
+}
+
 impl std::fmt::Display for TokenType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -0,0 +1,1126 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Debug,
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{
+    callable::{Arity, Callable},
+    chunk::{FunctionProto, OpCode},
+    class::{MyClass, MyInstance},
+    compiler::Compiler,
+    diagnostic::{
+        Diagnostic, TraceFrame, E_BAD_OPERAND, E_BAD_SUPERCLASS, E_INTERNAL, E_NOT_CALLABLE,
+        E_PROPERTY, E_STACK_OVERFLOW, E_UNDEFINED_VARIABLE,
+    },
+    environment::Environment,
+    expr::ExprLiteral,
+    interpreter::MAX_CALL_DEPTH,
+    module::{ModuleLoader, MyModule},
+    output::Output,
+    stdlib::Registry,
+    token::TokenType,
+};
+
+// A captured variable: still on the stack while its scope is alive, moved in here afterwards.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize), // stack slot.
+    Closed(ExprLiteral),
+}
+
+// The globals of one script or module, shared with the closures declared in it: a function
+// imported from a module reads the globals of the module wherever it is called.
+type Globals = Rc<RefCell<Environment>>;
+
+// Runtime function of the Vm, a FunctionProto plus the variables it captured.
+#[derive(Clone)]
+pub struct Closure {
+    function: Rc<FunctionProto>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    globals: Globals,
+}
+
+impl Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.two_string())
+    }
+}
+
+impl Callable for Closure {
+    fn bind_this(&self, instance: ExprLiteral) -> Option<Rc<dyn Callable>> {
+        Some(Rc::new(BoundMethod {
+            receiver: instance,
+            method: Rc::new(self.clone()),
+        }))
+    }
+
+    fn arity(&self) -> Arity {
+        self.function.arity
+    }
+
+    fn signature(&self) -> String {
+        self.function.signature.clone()
+    }
+
+    fn two_string(&self) -> String {
+        format!("<fn {}>", self.function.name)
+    }
+}
+
+// A method read from an instance, "this" goes into slot 0 when it is called.
+#[derive(Debug)]
+pub struct BoundMethod {
+    receiver: ExprLiteral,
+    method: Rc<Closure>,
+}
+
+impl Callable for BoundMethod {
+    fn arity(&self) -> Arity {
+        self.method.arity()
+    }
+
+    fn signature(&self) -> String {
+        self.method.signature()
+    }
+
+    fn two_string(&self) -> String {
+        self.method.two_string()
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    callee: Rc<dyn Callable>, // what the program called, for stack traces: a class runs its init.
+    ip: usize,
+    slots: usize, // stack index of slot 0.
+    argc: usize,  // arguments actually passed, parameters past it take their defaults.
+}
+
+// Stack based virtual machine running the bytecode of the Compiler.
+// Values, natives and classes are shared with the tree-walking Interpreter, so both print
+// and fail the same way.
+pub struct Vm {
+    stack: Vec<ExprLiteral>,
+    frames: Vec<CallFrame>,
+    globals: Globals,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>, // sorted by stack slot.
+    op_start: usize,                          // offset of the running instruction, for errors.
+    output: Output,                           // where print writes.
+    errors: Output,                           // where report writes.
+    max_depth: usize,                         // frames are on the heap, this is only a guard.
+    modules: Rc<ModuleLoader>,                // shared with the Vms running the modules.
+    module_dir: PathBuf,                      // where relative imports start.
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            stack: vec![],
+            frames: vec![],
+            globals: Rc::new(RefCell::new(Environment::new(None))),
+            open_upvalues: vec![],
+            op_start: 0,
+            output: Output::Stdout,
+            errors: Output::Stderr,
+            max_depth: MAX_CALL_DEPTH,
+            modules: Rc::default(),
+            module_dir: PathBuf::from("."),
+        };
+        for (name, value) in Registry::standard().entries() {
+            vm.define_global(name, value);
+        }
+        vm
+    }
+
+    // brief: Send what print writes somewhere else than stdout, see Interpreter::set_output.
+    // input:
+    // output:
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn set_error_output(&mut self, errors: Output) {
+        self.errors = errors;
+    }
+
+    // brief: Change how deep calls may nest, the same limit as Interpreter::set_max_depth.
+    // input:
+    // output:
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // brief: Load imports through `modules`, see Interpreter::set_modules.
+    // input:
+    // output:
+    pub fn set_modules(&mut self, modules: Rc<ModuleLoader>, dir: &Path) {
+        self.modules = modules;
+        self.module_dir = dir.to_path_buf();
+    }
+
+    // brief: Write a rendered runtime error to the error sink.
+    // input: the source the diagnostic points into.
+    // output:
+    pub fn report(&mut self, diagnostic: &Diagnostic, source: &str) {
+        let _ = self.errors.write(&diagnostic.render(source));
+    }
+
+    // brief: Define argc() and argv(i), the same as Interpreter::define_script_args.
+    // input:
+    // output:
+    pub fn define_script_args(&mut self, script: &str, args: Vec<String>) {
+        for (name, value) in Registry::script_args(script, args).entries() {
+            self.define_global(name, value);
+        }
+    }
+
+    pub fn define_global(&mut self, name: &str, value: ExprLiteral) {
+        self.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    // brief: All the globals, sorted by name.
+    // input:
+    // output:
+    pub fn globals(&self) -> Vec<(String, ExprLiteral)> {
+        self.globals.borrow().values()
+    }
+
+    // brief: Run a script compiled by the Compiler.
+    // input:
+    // output: the stack is reset on error, globals survive.
+    pub fn run(&mut self, script: FunctionProto) -> Result<(), Diagnostic> {
+        let closure = Rc::new(Closure {
+            function: Rc::new(script),
+            upvalues: vec![],
+            globals: self.globals.clone(),
+        });
+        self.stack
+            .push(ExprLiteral::FunctionLiteral(closure.clone()));
+        self.call_closure(closure.clone(), closure, 0)?;
+
+        let result = self.execute().map_err(|err| err.with_trace(self.trace()));
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), Diagnostic> {
+        loop {
+            self.op_start = self.frame().ip;
+            let byte = self.read_byte();
+            let Some(op) = OpCode::from_byte(byte) else {
+                return Err(self.error(E_INTERNAL, format!("Unknown opcode {}.", byte)));
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let value = self.read_constant();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(ExprLiteral::Nil),
+                OpCode::True => self.stack.push(ExprLiteral::True),
+                OpCode::False => self.stack.push(ExprLiteral::False),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    let value = self.frame().closure.globals.borrow().lookup(&name);
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.undefined_variable(&name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.frame()
+                        .closure
+                        .globals
+                        .borrow_mut()
+                        .define(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0).clone();
+                    let globals = self.frame().closure.globals.clone();
+                    if !globals.borrow_mut().set(&name, value) {
+                        return Err(self.undefined_variable(&name));
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.pop();
+                    if let ExprLiteral::ModuleLiteral(module) = &object {
+                        let Some(value) = module.get(&name) else {
+                            return Err(self.error(
+                                E_PROPERTY,
+                                format!("Module {} has no export {}.", module.name, name),
+                            ));
+                        };
+                        self.stack.push(value);
+                        continue;
+                    }
+                    let ExprLiteral::InstanceLiteral(instance) = object else {
+                        return Err(self.error(
+                            E_PROPERTY,
+                            format!("Only instances have properties, can't read {}.", name),
+                        ));
+                    };
+                    match MyInstance::get(&instance, &name) {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.undefined_property(&name)),
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    let ExprLiteral::InstanceLiteral(instance) = self.pop() else {
+                        return Err(self.error(
+                            E_PROPERTY,
+                            format!("Only instances have fields, can't set {}.", name),
+                        ));
+                    };
+                    instance.borrow_mut().set(&name, value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_name();
+                    let superclass = self.pop();
+                    let this = self.pop();
+                    let ExprLiteral::ClassLiteral(superclass) = superclass else {
+                        return Err(self.error(E_BAD_SUPERCLASS, "Superclass must be a class."));
+                    };
+                    match superclass
+                        .find_method(&name)
+                        .and_then(|method| method.bind_this(this))
+                    {
+                        Some(method) => self.stack.push(ExprLiteral::FunctionLiteral(method)),
+                        None => return Err(self.undefined_property(&name)),
+                    }
+                }
+                OpCode::BuildList => {
+                    let count = self.read_u16() as usize;
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(ExprLiteral::list(items));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_u16() as usize;
+                    let items = self.stack.split_off(self.stack.len() - 2 * count);
+                    let mut items = items.into_iter();
+                    let mut entries = Vec::with_capacity(count);
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.push((key, value));
+                    }
+                    let map = ExprLiteral::map(entries)
+                        .map_err(|msg| self.at_instruction(ExprLiteral::key_error(msg)))?;
+                    self.stack.push(map);
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = object
+                        .get_index(&index)
+                        .map_err(|msg| self.at_instruction(ExprLiteral::index_error(msg)))?;
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    object
+                        .set_index(&index, value.clone())
+                        .map_err(|msg| self.at_instruction(ExprLiteral::index_error(msg)))?;
+                    self.stack.push(value);
+                }
+                OpCode::Equal => self.binary(TokenType::EqualEqual, "==")?,
+                OpCode::NotEqual => self.binary(TokenType::BangEqual, "!=")?,
+                OpCode::Greater => self.binary(TokenType::Greater, ">")?,
+                OpCode::GreaterEqual => self.binary(TokenType::GreaterEqual, ">=")?,
+                OpCode::Less => self.binary(TokenType::Less, "<")?,
+                OpCode::LessEqual => self.binary(TokenType::LessEqual, "<=")?,
+                OpCode::Add => self.binary(TokenType::Plus, "+")?,
+                OpCode::Subtract => self.binary(TokenType::Minus, "-")?,
+                OpCode::Multiply => self.binary(TokenType::Star, "*")?,
+                OpCode::Divide => self.binary(TokenType::Slash, "/")?,
+                OpCode::Modulo => self.binary(TokenType::Percent, "%")?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(ExprLiteral::from_bool(!value.is_truthy()));
+                }
+                OpCode::Negate => {
+                    let value = self.pop().negate().map_err(|msg| {
+                        self.error(E_BAD_OPERAND, format!("Bad operand for -: {}.", msg))
+                    })?;
+                    self.stack.push(value);
+                }
+                OpCode::Print => {
+                    let line = self.pop().two_string();
+                    if let Err(err) = self.output.print(&line) {
+                        return Err(self.at_instruction(err));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfPassed => {
+                    let param = self.read_byte() as usize;
+                    let offset = self.read_u16() as usize;
+                    if param < self.frame().argc {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call_value(self.peek(argc).clone(), argc)?;
+                }
+                OpCode::Closure => {
+                    let index = self.read_u16() as usize;
+                    let function = self.frame().closure.function.chunk.functions[index].clone();
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        if is_local {
+                            upvalues.push(self.capture_upvalue(self.frame().slots + index));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[index].clone());
+                        }
+                    }
+                    self.stack
+                        .push(ExprLiteral::FunctionLiteral(Rc::new(Closure {
+                            function,
+                            upvalues,
+                            globals: self.frame().closure.globals.clone(),
+                        })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => self.class()?,
+                OpCode::Import => {
+                    let path = self.read_name();
+                    let module = self.import(&path).map_err(|err| self.at_instruction(err))?;
+                    self.stack.push(ExprLiteral::ModuleLiteral(module));
+                }
+            }
+        }
+    }
+
+    // brief: Load a module, compiling and running it on a new Vm that shares the loader and
+    // the output of this one.
+    // input: the path as written in the import.
+    // output:
+    fn import(&mut self, path: &str) -> Result<Rc<MyModule>, Diagnostic> {
+        let modules = self.modules.clone();
+        let from = self.module_dir.clone();
+        modules.load(path, &from, |(statements, _), dir, origin| {
+            let script = Compiler::new().with_origin(origin).compile(&statements)?;
+            let mut module = Vm::new();
+            module.set_max_depth(self.max_depth);
+            module.set_modules(self.modules.clone(), dir);
+            module.output = mem::replace(&mut self.output, Output::Stdout);
+            module.errors = mem::replace(&mut self.errors, Output::Stderr);
+            let before = module.globals();
+            let result = module.run(script);
+            self.output = mem::replace(&mut module.output, Output::Stdout);
+            self.errors = mem::replace(&mut module.errors, Output::Stderr);
+            result?;
+            Ok((before, module.globals.clone()))
+        })
+    }
+
+    // brief: Collect the method closures on the stack into a MyClass.
+    // input:
+    // output:
+    fn class(&mut self) -> Result<(), Diagnostic> {
+        let name = self.read_name();
+        let has_superclass = self.read_byte() == 1;
+        let count = self.read_byte() as usize;
+        let names: Vec<String> = (0..count).map(|_| self.read_name()).collect();
+
+        let closures = self.stack.split_off(self.stack.len() - count);
+        let mut methods = HashMap::new();
+        for (method_name, closure) in names.into_iter().zip(closures) {
+            if let ExprLiteral::FunctionLiteral(closure) = closure {
+                methods.insert(method_name, closure);
+            }
+        }
+
+        let superclass = if has_superclass {
+            match self.peek(0) {
+                ExprLiteral::ClassLiteral(class) => Some(class.clone()),
+                _ => {
+                    return Err(self.error(
+                        E_BAD_SUPERCLASS,
+                        format!("Superclass of {} must be a class.", name),
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+
+        let class = MyClass::new(name, superclass, methods);
+        self.stack.push(ExprLiteral::ClassLiteral(Rc::new(class)));
+        Ok(())
+    }
+
+    // brief: Call whatever sits below the arguments.
+    // input:
+    // output:
+    fn call_value(&mut self, callee: ExprLiteral, argc: usize) -> Result<(), Diagnostic> {
+        // The script frame does not count, the Interpreter has none.
+        if matches!(
+            callee,
+            ExprLiteral::FunctionLiteral(_) | ExprLiteral::ClassLiteral(_)
+        ) && self.frames.len() > self.max_depth
+        {
+            return Err(self.error(E_STACK_OVERFLOW, "Stack overflow."));
+        }
+        match callee {
+            ExprLiteral::FunctionLiteral(function) => {
+                let any: Rc<dyn Any> = function.clone();
+                let any = match any.downcast::<Closure>() {
+                    Ok(closure) => return self.call_closure(closure, function, argc),
+                    Err(any) => any,
+                };
+                if let Ok(bound) = any.downcast::<BoundMethod>() {
+                    let slot = self.stack.len() - argc - 1;
+                    self.stack[slot] = bound.receiver.clone();
+                    return self.call_closure(bound.method.clone(), function, argc);
+                }
+
+                self.check_arity(function.as_ref(), argc)?;
+                let arguments = self.stack.split_off(self.stack.len() - argc);
+                self.pop();
+                let position = self.position();
+                // Natives get no frame, their entry is added to the trace here.
+                let native = TraceFrame {
+                    function: function.two_string(),
+                    line: position.line,
+                };
+                let value = function
+                    .call_with_output(&mut self.output, arguments)
+                    .map_err(|err| {
+                        let err = if err.span.is_some() {
+                            err
+                        } else {
+                            self.at_instruction(err)
+                        };
+                        let mut trace = self.trace();
+                        trace.insert(0, native);
+                        err.with_trace(trace)
+                    })?;
+                self.stack.push(value);
+                Ok(())
+            }
+            ExprLiteral::ClassLiteral(class) => {
+                self.check_arity(class.as_ref(), argc)?;
+                let slot = self.stack.len() - argc - 1;
+                self.stack[slot] = ExprLiteral::InstanceLiteral(Rc::new(RefCell::new(
+                    MyInstance::new(class.clone()),
+                )));
+
+                let initializer = class.find_method("init");
+                match initializer.map(|init| (init as Rc<dyn Any>).downcast::<Closure>()) {
+                    Some(Ok(closure)) => self.call_closure(closure, class, argc),
+                    Some(Err(_)) => Err(self.error(
+                        E_INTERNAL,
+                        format!("{} was not built by the Vm.", class.two_string()),
+                    )),
+                    None => Ok(()),
+                }
+            }
+            other => Err(self.error(
+                E_NOT_CALLABLE,
+                format!(
+                    "Can only call functions and classes, got {}.",
+                    other.two_string()
+                ),
+            )),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        callee: Rc<dyn Callable>,
+        argc: usize,
+    ) -> Result<(), Diagnostic> {
+        self.check_arity(closure.as_ref(), argc)?;
+        // Missing parameters start as nil until their default runs, extra arguments go into
+        // the rest list.
+        let params = closure.function.params;
+        if argc > params {
+            let rest = self.stack.split_off(self.stack.len() - (argc - params));
+            self.stack.push(ExprLiteral::list(rest));
+        } else {
+            for _ in argc..params {
+                self.stack.push(ExprLiteral::Nil);
+            }
+            if closure.function.arity.max.is_none() {
+                self.stack.push(ExprLiteral::list(vec![]));
+            }
+        }
+        let locals = params + closure.function.arity.max.is_none() as usize;
+        self.frames.push(CallFrame {
+            closure,
+            callee,
+            ip: 0,
+            slots: self.stack.len() - locals - 1,
+            argc,
+        });
+        Ok(())
+    }
+
+    // brief: The active calls, innermost first, the same as the Interpreter's trace.
+    // input:
+    // output:
+    fn trace(&self) -> Vec<TraceFrame> {
+        (1..self.frames.len())
+            .rev()
+            .map(|i| {
+                let caller = &self.frames[i - 1]; // its ip is right after the Call.
+                TraceFrame {
+                    function: self.frames[i].callee.two_string(),
+                    line: caller.closure.function.chunk.position(caller.ip - 1).line,
+                }
+            })
+            .collect()
+    }
+
+    fn check_arity(&self, callee: &dyn Callable, argc: usize) -> Result<(), Diagnostic> {
+        let arity = callee.arity();
+        if !arity.accepts(argc) {
+            return Err(self.at_instruction(arity.mismatch(callee, argc)));
+        }
+        Ok(())
+    }
+
+    // brief: Find or create the upvalue of a stack slot, so that closures share it.
+    // input:
+    // output:
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let index = self
+            .open_upvalues
+            .partition_point(|v| matches!(*v.borrow(), Upvalue::Open(s) if s < slot));
+        if let Some(existing) = self.open_upvalues.get(index) {
+            if matches!(*existing.borrow(), Upvalue::Open(s) if s == slot) {
+                return existing.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(index, upvalue.clone());
+        upvalue
+    }
+
+    // brief: Move every upvalue at or above `last` off the stack.
+    // input:
+    // output:
+    fn close_upvalues(&mut self, last: usize) {
+        let index = self
+            .open_upvalues
+            .partition_point(|v| matches!(*v.borrow(), Upvalue::Open(s) if s < last));
+        for upvalue in self.open_upvalues.split_off(index) {
+            let mut upvalue = upvalue.borrow_mut();
+            if let Upvalue::Open(slot) = *upvalue {
+                *upvalue = Upvalue::Closed(self.stack[slot].clone());
+            }
+        }
+    }
+
+    fn binary(&mut self, operator: TokenType, lexeme: &str) -> Result<(), Diagnostic> {
+        let right = self.pop();
+        let left = self.pop();
+        let value = ExprLiteral::binary(&operator, &left, &right).map_err(|msg| {
+            self.at_instruction(ExprLiteral::operand_error(lexeme, msg, &left, &right))
+        })?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frames.last_mut().unwrap();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> ExprLiteral {
+        let index = self.read_u16() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> String {
+        match self.read_constant() {
+            ExprLiteral::StringLiteral(name) => name,
+            other => other.two_string(),
+        }
+    }
+
+    fn pop(&mut self) -> ExprLiteral {
+        self.stack.pop().unwrap_or(ExprLiteral::Nil)
+    }
+
+    fn peek(&self, distance: usize) -> &ExprLiteral {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn position(&self) -> super::chunk::Position {
+        self.frame().closure.function.chunk.position(self.op_start)
+    }
+
+    // brief: A runtime error pointing at the source of the running instruction.
+    // input:
+    // output:
+    fn error(&self, code: u16, message: impl Into<String>) -> Diagnostic {
+        self.at_instruction(Diagnostic::error(code, message))
+    }
+
+    // brief: Point at the source of the running instruction, in the file its chunk came from.
+    // input:
+    // output:
+    fn at_instruction(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let position = self.position();
+        let diagnostic = diagnostic.at(position.span, position.line, position.column);
+        match &self.frame().closure.function.chunk.origin {
+            Some(origin) => diagnostic.or_in(origin),
+            None => diagnostic,
+        }
+    }
+
+    fn undefined_variable(&self, name: &str) -> Diagnostic {
+        self.error(
+            E_UNDEFINED_VARIABLE,
+            format!("Undefined variable {}.", name),
+        )
+    }
+
+    fn undefined_property(&self, name: &str) -> Diagnostic {
+        self.error(E_PROPERTY, format!("Undefined property {}.", name))
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{
+        compiler::Compiler, interpreter::Interpreter, parser::Parser, resolver::Resolver,
+        scanner::Scanner, stdlib::NativeFn,
+    };
+
+    type Outcome = Result<Vec<(String, String)>, u16>;
+
+    // brief: Run a program on both backends.
+    // output: the globals (as printed) or the error code of each backend.
+    fn run_both(source: &str) -> (Outcome, Outcome) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&statements).unwrap();
+        let printed = |globals: Vec<(String, ExprLiteral)>| {
+            globals
+                .into_iter()
+                .map(|(name, value)| (name, value.two_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut interpreter = Interpreter::new();
+        interpreter.resolve(locals);
+        let tree = match interpreter.interpreter(&statements) {
+            Ok(_) => Ok(printed(interpreter.globals.borrow().values())),
+            Err(err) => Err(err.code),
+        };
+
+        let script = Compiler::new().compile(&statements).unwrap();
+        let mut vm = Vm::new();
+        let bytecode = match vm.run(script) {
+            Ok(()) => Ok(printed(vm.globals())),
+            Err(err) => Err(err.code),
+        };
+        (tree, bytecode)
+    }
+
+    fn assert_same(source: &str) {
+        let (tree, bytecode) = run_both(source);
+        assert_eq!(tree, bytecode, "backends disagree on:\n{}", source);
+    }
+
+    #[test]
+    fn arithmetic_and_strings() {
+        assert_same(
+            "let a = 7 / 2; let b = 7 / 2.0; let c = 10 % 3 * 4 - 1; let d = -(1 + 2);
+            let s = \"ab\" + \"cd\"; let t = 1 < 2 and 2 <= 2.0; let f = !nil or false;
+            let e = 1 == 1.0; let n = \"a\" != \"a\";",
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_same(
+            "let total = 0;
+            for (let i = 0; i < 10; i = i + 1) { if (i % 2 == 0) total = total + i; else total = total - 1; }
+            let n = 0; while (n < 5) n = n + 1;
+            let x = nil or \"default\"; let y = 0 and 1;",
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        assert_same(
+            "let sum = 0;
+            for (let i = 0; i < 10; i = i + 1) { let skip = i % 2 == 0; if (skip) continue; if (i > 7) break; sum = sum + i; }
+            let n = 0; while (true) { n = n + 1; { let x = n; if (x == 5) break; } }
+            let last = nil;
+            for (let i = 0; i < 3; i = i + 1) { let j = i; fn get() { return j; } last = get; if (i == 1) break; }
+            let got = last();
+            let outer = 0;
+            for (let i = 0; i < 3; i = i + 1) { for (let j = 0; j < 3; j = j + 1) { if (j == 1) continue; outer = outer + 1; } }",
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_same(
+            "let xs = [1, 2, 3]; let ys = xs; push(ys, 4); xs[0] = \"a\";
+            let total = 0; for (let x in slice(xs, 1, len(xs))) { if (x == 3) continue; total = total + x; }
+            let fns = [];
+            for (let x in [10, 20, 30]) { fn get() { return x; } push(fns, get); if (x == 20) break; }
+            let got = fns[0]() + fns[1](); let n = len(fns);
+            let last = pop(xs); let empty = [];
+            let text = \"\" + [1, \"b\", [nil]][2][0];",
+        );
+    }
+
+    #[test]
+    fn maps() {
+        assert_same(
+            "let m = {\"a\": 1, 2: \"two\", nil: true, 1.5: false}; m[\"b\"] = [m[\"a\"]]; m[2.0] = 2;
+            let n = len(m); let ks = keys(m); let vs = values(m); let self = {}; self[1] = self;
+            let had = has(m, \"a\"); let removed = remove(m, \"a\"); let gone = has(m, \"a\");
+            let total = 0; for (let k in keys({1: 0, 2: 0, 3: 0})) total = total + k;",
+        );
+    }
+
+    #[test]
+    fn functions_and_closures() {
+        assert_same(
+            "fn fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            let f = fib(15);
+            fn make_counter() { let count = 0; fn counter() { count = count + 1; return count; } return counter; }
+            let c = make_counter(); c(); c(); let d = make_counter();
+            let a = c(); let b = d();
+            let first; let second;
+            { let v = \"global\"; fn show() { return v; } first = show(); v = \"changed\"; second = show(); }
+            let closures = nil;
+            { let fns = nil; for (let i = 0; i < 3; i = i + 1) { let j = i; fn g() { return j; } if (i == 1) fns = g; } closures = fns(); }",
+        );
+    }
+
+    #[test]
+    fn defaults_and_rest() {
+        assert_same(
+            "fn f(a, b = a * 2, c = \"c\") { return [a, b, c]; }
+            let one = f(1); let two = f(1, 5); let three = f(1, nil, 3);
+            fn g(first, ...rest) { return [first, rest, len(rest)]; }
+            let a = g(1); let b = g(1, 2, 3);
+            fn h(x = 0, ...more) { return more; } let c = h(); let d = h(1, 2);
+            let base = 10; fn k(n, m = base + n) { let base = 0; return m; } let e = k(1);
+            fn counter(start = 0) { fn next(step = 1) { start = start + step; return start; } return next; }
+            let next = counter(); next(); let z = next(5);
+            class P { init(x, y = 0, ...tags) { this.sum = x + y; this.tags = tags; } }
+            let p = P(1); let q = P(1, 2, \"a\"); let sums = [p.sum, q.sum, q.tags];
+            let m = max(3, 1.5, 7, 2); let n = min(4); let s = format(\"{} + {} = {}\", 1, 2, 3);",
+        );
+    }
+
+    #[test]
+    fn classes() {
+        assert_same(
+            "class A {
+                init(n) { this.n = n; }
+                get() { return this.n; }
+                twice() { return this.get() * 2; }
+            }
+            class B < A {
+                init(n) { super.init(n + 1); }
+                get() { return super.get() * 10; }
+            }
+            let b = B(1); let a = b.twice(); let m = b.get; b.n = 5; let c = m();
+            let same = b.init(7) == b; let k = A; let i = A(3);
+            { class Local { me() { return Local; } } let l = Local().me(); }
+            fn outer() { class Inner { f() { fn g() { return this; } return g; } } let x = Inner(); return x.f()() == x; }
+            let o = outer();",
+        );
+    }
+
+    #[test]
+    fn runtime_errors_agree() {
+        for source in [
+            "print nope;",
+            "nope = 1;",
+            "let a = 1 + \"a\";",
+            "let a = -\"a\";",
+            "let a = 1; a();",
+            "fn f(x) {} f();",
+            "fn f(a, b = 1) {} f(1, 2, 3);",
+            "fn f(a, b = 1, ...c) {} f();",
+            "fn f(a = nope) {} f();",
+            "class A { init(x, ...y) {} } A();",
+            "max();",
+            "format(\"{}\");",
+            "class A {} A(1);",
+            "class A { init(x) {} } A();",
+            "let a = 1; a.x;",
+            "class A {} A().x;",
+            "let a = 1 / 0;",
+            "let NotClass = 1; class B < NotClass {}",
+            "argv(1);",
+            "let xs = [1]; xs[1];",
+            "let xs = [1]; xs[-1] = 2;",
+            "let xs = [1]; xs[0.5];",
+            "let a = 1; a[0];",
+            "pop([]);",
+            "let m = {}; m[\"x\"];",
+            "let m = {[]: 1};",
+            "let m = {}; m[{}] = 1;",
+            "has(1, 2);",
+            "let m = {}; m[m] = 1;",
+            "let m = {}; remove(m, m);",
+            "slice([1, 2], 2, 1);",
+        ] {
+            let (tree, bytecode) = run_both(source);
+            assert!(tree.is_err(), "{}", source);
+            assert_eq!(tree, bytecode, "backends disagree on:\n{}", source);
+        }
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_source() {
+        let tokens = Scanner::new("let a = 1;\nprint a + nope;".to_string())
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let script = Compiler::new().compile(&statements).unwrap();
+        let err = Vm::new().run(script).unwrap_err();
+        assert_eq!(err.code, E_UNDEFINED_VARIABLE);
+        assert_eq!((err.line, err.column), (2, 11));
+    }
+
+    #[test]
+    fn both_backends_print_to_the_output() {
+        let source = "print 1; print [\"a\", 2.5]; print nope;";
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&statements).unwrap();
+
+        let (output, tree_out) = Output::buffer();
+        let (errors, tree_err) = Output::buffer();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(output);
+        interpreter.set_error_output(errors);
+        interpreter.resolve(locals);
+        let err = interpreter.interpreter(&statements).unwrap_err();
+        interpreter.report(&err, source);
+
+        let (output, vm_out) = Output::buffer();
+        let (errors, vm_err) = Output::buffer();
+        let mut vm = Vm::new();
+        vm.set_output(output);
+        vm.set_error_output(errors);
+        let err = vm
+            .run(Compiler::new().compile(&statements).unwrap())
+            .unwrap_err();
+        vm.report(&err, source);
+
+        assert_eq!(*tree_out.borrow(), "1\n[\"a\", 2.5]\n");
+        assert_eq!(tree_out, vm_out);
+        assert!(tree_err
+            .borrow()
+            .starts_with("error[E0301]: Undefined variable nope."));
+        assert_eq!(tree_err, vm_err);
+    }
+
+    #[test]
+    fn arity_errors_show_the_signature() {
+        let source = "fn f(a, b = 2, c = nil, ...d) {} f();";
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let script = Compiler::new().compile(&statements).unwrap();
+        let err = Vm::new().run(script).unwrap_err();
+        assert_eq!(err.message, "<fn f> expects at least 1 argument but got 0.");
+        assert_eq!(err.notes, vec!["signature: f(a, b = 2, c = nil, ...d)"]);
+    }
+
+    // brief: Run a program that fails on both backends, calls nest at most `max_depth` deep.
+    // output: the error of each backend.
+    fn fail_both(source: &str, max_depth: usize) -> (Diagnostic, Diagnostic) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&statements).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_depth(max_depth);
+        interpreter.resolve(locals);
+        let tree = interpreter.interpreter(&statements).unwrap_err();
+
+        let mut vm = Vm::new();
+        vm.set_max_depth(max_depth);
+        let bytecode = vm
+            .run(Compiler::new().compile(&statements).unwrap())
+            .unwrap_err();
+        (tree, bytecode)
+    }
+
+    #[test]
+    fn deep_recursion_overflows_cleanly() {
+        assert_same("fn down(n) { if (n == 0) return 0; return down(n - 1); } let a = down(50);");
+
+        let (tree, bytecode) = fail_both("fn f() { return f(); }\nf();", 40);
+        assert_eq!((tree.code, tree.line), (E_STACK_OVERFLOW, 1));
+        assert_eq!((bytecode.code, bytecode.line), (E_STACK_OVERFLOW, 1));
+        assert_eq!(tree.trace.len(), 40);
+        assert_eq!(tree.trace, bytecode.trace);
+    }
+
+    #[test]
+    fn runtime_errors_carry_a_stack_trace() {
+        let source = "class A {\n  init(n) { this.n = check(n); }\n}\nfn check(n) {\n  return n + nil;\n}\nfn make() {\n  return A(1);\n}\nmake();";
+        let (tree, bytecode) = fail_both(source, 100);
+        let frames: Vec<String> = tree.trace.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            frames,
+            vec![
+                "<fn check> called at line 2",
+                "<class A> called at line 8",
+                "<fn make> called at line 10",
+            ]
+        );
+        assert_eq!(tree.trace, bytecode.trace);
+        assert!(tree
+            .render(source)
+            .contains("stack trace, innermost call first:"));
+
+        // A failing native is the innermost frame.
+        let source = "fn f(x) {\n  return len(x);\n}\nf(1);";
+        let (tree, bytecode) = fail_both(source, 100);
+        assert_eq!(
+            tree.trace[0].to_string(),
+            "<native fn len> called at line 2"
+        );
+        assert_eq!(tree.trace, bytecode.trace);
+    }
+
+    #[test]
+    fn writing_natives_use_the_output_of_the_backend() {
+        // input writes its prompt like this native, where print writes.
+        let greet = || {
+            let native = NativeFn::writing("greet", 1, |output, args| {
+                output
+                    .write(&format!("hi {}? ", args[0].two_string()))
+                    .map_err(|err| err.to_string())?;
+                Ok(ExprLiteral::Nil)
+            });
+            ExprLiteral::FunctionLiteral(Rc::new(native))
+        };
+        let source = "greet(\"Ada\"); print 1;";
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let locals = Resolver::new().resolve(&statements).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        let (output, tree) = Output::buffer();
+        interpreter.set_output(output);
+        interpreter.define_global("greet", greet());
+        interpreter.resolve(locals);
+        interpreter.interpreter(&statements).unwrap();
+
+        let mut vm = Vm::new();
+        let (output, bytecode) = Output::buffer();
+        vm.set_output(output);
+        vm.define_global("greet", greet());
+        vm.run(Compiler::new().compile(&statements).unwrap())
+            .unwrap();
+
+        assert_eq!(*tree.borrow(), "hi Ada? 1\n");
+        assert_eq!(*bytecode.borrow(), "hi Ada? 1\n");
+    }
+}
@@ -0,0 +1,10 @@
+// Noah as a library: every stage lives in `lexer`, `Engine` runs scripts inside a Rust host.
+pub mod lexer;
+
+pub use lexer::{
+    callable::{Arity, Callable},
+    convert::{FromLiteral, IntoLiteral},
+    diagnostic::Diagnostic,
+    engine::Engine,
+    expr::ExprLiteral,
+};
@@ -1,40 +1,31 @@
-use std::fs;
-mod lexer;
-use lexer::interpreter::Interpreter;
-use lexer::parser::Parser;
-use lexer::scanner::Scanner;
-
-fn run_file(file_path: &String) -> Result<(), String> {
-    let contents = fs::read_to_string(file_path).unwrap();
-
-    let mut scan = Scanner::new(contents);
-
-    let tok = scan.scan_tokens().unwrap();
-
-    let pas = Parser::new(tok).parse().unwrap();
-
-    //    dbg!(pas);
-
-    let _ = Interpreter::new().interpreter(&pas)?; // return 1 ???
-    Ok(())
-}
+use std::{env, process, thread};
+mod driver;
+mod repl;
+use driver::{Command, EXIT_RUNTIME, EXIT_USAGE, USAGE};
 
 fn main() {
-    match run_file(&String::from("test.py")) {
-        Ok(()) => {
-            println!("[     PASS!    ] ---> Compile Successfully!!!");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let code = match Command::from_args(&args) {
+        // The tree-walker recurses on the Rust stack, give it enough for the call depth limit.
+        Ok(command) => thread::Builder::new()
+            .stack_size(command.stack_size())
+            .spawn(move || command.execute())
+            .map_or_else(
+                |err| {
+                    eprintln!(
+                        "[    Error!    ] ---> Can not start the interpreter: {}.",
+                        err
+                    );
+                    EXIT_RUNTIME
+                },
+                |handle| handle.join().unwrap_or(EXIT_RUNTIME),
+            ),
+        Err(msg) => {
+            eprintln!("[    Error!    ] ---> {}", msg);
+            eprintln!("{}", USAGE);
+            EXIT_USAGE
         }
-        Err(v) => {
-            println!("[    Error!    ] ---> {}", v);
-        }
-    }
+    };
+    process::exit(code);
 }
-
-/*
-Todo:
-Organize the overall logic of function definition and functon calling parts.Specifically:
-   1 -----> interperter's environment;
-   2 -----> function's closure;
-   3 -----> environment's enclosing;
-which is so confused and need to be reorganized.
-*/
@@ -0,0 +1,279 @@
+use std::io::{self, BufRead, Write};
+
+use noah::lexer::{
+    diagnostic::{Diagnostic, E_UNTERMINATED_STRING},
+    expr::ExprLiteral,
+    interpreter::Interpreter,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    stmt::Stmt,
+    token::{Token, TokenType},
+};
+
+const HELP: &str = "Meta commands:
+    :tokens <code>   Print the tokens of <code>.
+    :ast <code>      Print the syntax tree of <code>.
+    :env             Print the variables defined at top level.
+    :reset           Forget every variable and function.
+    :help            Print this message.
+    :quit            Leave the repl, Ctrl-D works too.";
+
+// Read-eval-print loop, one Interpreter lives as long as the Repl.
+pub struct Repl {
+    interpreter: Interpreter,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    // brief: Read lines from stdin until Ctrl-D or :quit.
+    // input:
+    // output:
+    pub fn run(&mut self) {
+        println!(
+            "Noah {} repl, type :help for help.",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut buffer = String::new();
+
+        loop {
+            print!("{}", if buffer.is_empty() { "> " } else { "... " });
+            let _ = io::stdout().flush();
+
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break, // Ctrl-D or a broken stdin.
+            };
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                if !self.meta_command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            buffer.push_str(&line);
+            buffer.push('\n');
+
+            // An empty line forces a submit, in case the balance check got confused.
+            if needs_more_input(&buffer) && !line.trim().is_empty() {
+                continue;
+            }
+
+            match self.eval(&buffer) {
+                Ok(values) => {
+                    for value in values {
+                        println!("{}", value.two_string());
+                    }
+                }
+                Err(errors) => report_diagnostics(&buffer, &errors),
+            }
+            buffer.clear();
+        }
+        println!();
+    }
+
+    // brief: Evaluate one input, which may hold several statements.
+    // input:
+    // output: The values of the bare expression statements, Nil excluded.
+    pub fn eval(&mut self, source: &str) -> Result<Vec<ExprLiteral>, Vec<Diagnostic>> {
+        let statements = Self::parse(source)?;
+        let locals = Resolver::new()
+            .resolve(&statements)
+            .map_err(|err| vec![err])?;
+        self.interpreter.resolve(locals);
+
+        let mut values = vec![];
+        for statement in statements {
+            if let Stmt::Expression(expr) = &statement {
+                let value = self.interpreter.evaluate(expr).map_err(|err| vec![err])?;
+                if value != ExprLiteral::Nil {
+                    values.push(value);
+                }
+            } else {
+                self.interpreter
+                    .interpreter(&vec![statement])
+                    .map_err(|err| vec![err])?;
+            }
+        }
+        Ok(values)
+    }
+
+    // brief: Parse the input as it is, else with the ";" the user may have left out, else as
+    // one bare expression: `{"a": 1}` is a map there, not a block.
+    // input:
+    // output: the errors of the input ended with ";" if every attempt fails.
+    fn parse(source: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+        let eof = tokens.len() - 1;
+        if eof == 0 {
+            return Ok(vec![]);
+        }
+        let errors = match Parser::new(tokens.clone()).parse() {
+            Ok(statements) => return Ok(statements),
+            Err(errors) => errors,
+        };
+        if tokens[eof - 1].token_type == TokenType::Semicolon {
+            return Err(errors);
+        }
+        let token = |token_type: TokenType, lexeme: &str| Token {
+            token_type,
+            lexeme: lexeme.to_string(),
+            ..tokens[eof].clone()
+        };
+
+        let mut ended = tokens.clone();
+        ended.insert(eof, token(TokenType::Semicolon, ";"));
+        let errors = match Parser::new(ended).parse() {
+            Ok(statements) => return Ok(statements),
+            Err(errors) => errors,
+        };
+        let mut grouped = tokens.clone();
+        grouped.insert(eof, token(TokenType::Semicolon, ";"));
+        grouped.insert(eof, token(TokenType::RightParen, ")"));
+        grouped.insert(0, token(TokenType::LeftParen, "("));
+        Parser::new(grouped).parse().map_err(|_| errors)
+    }
+
+    // brief: Handle a line starting with ":".
+    // input:
+    // output: false if the repl should stop.
+    fn meta_command(&mut self, line: &str) -> bool {
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            ":tokens" => match Scanner::new(rest.to_string()).scan_tokens() {
+                Ok(tokens) => {
+                    for token in tokens {
+                        println!("{}", token.two_string());
+                    }
+                }
+                Err(errors) => report_diagnostics(rest, &errors),
+            },
+            ":ast" => match Self::parse(rest) {
+                Ok(statements) => println!("{:#?}", statements),
+                Err(errors) => report_diagnostics(rest, &errors),
+            },
+            ":env" => {
+                for (name, value) in self.interpreter.environment().borrow().values() {
+                    println!("{} = {}", name, value.two_string());
+                }
+            }
+            ":reset" => {
+                self.interpreter = Interpreter::new();
+                println!("Environment cleared.");
+            }
+            ":help" => println!("{}", HELP),
+            ":quit" | ":q" => return false,
+            _ => eprintln!(
+                "[    Error!    ] ---> Unknown command {}, try :help.",
+                command
+            ),
+        }
+        true
+    }
+}
+
+// brief: Whether the input still has an unclosed "(", "[", "{" or string.
+// input:
+// output:
+pub fn needs_more_input(source: &str) -> bool {
+    let tokens = match Scanner::new(source.to_string()).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => return errors.iter().any(|err| err.code == E_UNTERMINATED_STRING),
+    };
+
+    let mut depth = 0;
+    for token in tokens {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+// brief: Print diagnostics, pointing into the input they came from.
+// input:
+// output:
+fn report_diagnostics(source: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.clone().with_file("<repl>").render(source));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_survives_between_inputs() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("let a = 1.0;"), Ok(vec![]));
+        assert_eq!(repl.eval("fn add(x) { return x + a; }"), Ok(vec![]));
+        assert_eq!(
+            repl.eval("add(2.0)"),
+            Ok(vec![ExprLiteral::NumberLiteral(3.0)])
+        );
+    }
+
+    #[test]
+    fn bare_expressions_are_printed() {
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.eval("1.0 + 2.0; \"a\""),
+            Ok(vec![
+                ExprLiteral::NumberLiteral(3.0),
+                ExprLiteral::StringLiteral("a".to_string())
+            ])
+        );
+        assert_eq!(repl.eval("nil"), Ok(vec![]));
+    }
+
+    #[test]
+    fn expressions_ending_with_a_brace() {
+        let mut repl = Repl::new();
+        let values = repl.eval("{\"a\": 1}").unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].two_string(), "{\"a\": 1}");
+        assert_eq!(repl.eval("let m = {\"b\": 2}"), Ok(vec![]));
+        assert_eq!(repl.eval("m[\"b\"]"), Ok(vec![ExprLiteral::IntLiteral(2)]));
+        // Blocks and declarations ending with "}" still work without ";".
+        assert_eq!(repl.eval("fn f() { return 3; }"), Ok(vec![]));
+        assert_eq!(repl.eval("{ let x = f(); }"), Ok(vec![]));
+        assert_eq!(repl.eval("f()"), Ok(vec![ExprLiteral::IntLiteral(3)]));
+        assert_eq!(repl.eval("[1,\n 2]").unwrap()[0].two_string(), "[1, 2]");
+        assert!(repl.eval("print 1 +").is_err());
+    }
+
+    #[test]
+    fn reset_forgets_variables() {
+        let mut repl = Repl::new();
+        repl.eval("let a = 1.0;").unwrap();
+        repl.meta_command(":reset");
+        assert!(repl.eval("a").is_err());
+    }
+
+    #[test]
+    fn unbalanced_input_continues() {
+        assert!(needs_more_input("fn f() {\n"));
+        assert!(needs_more_input("print (1.0 +\n"));
+        assert!(needs_more_input("print \"abc\n"));
+        assert!(needs_more_input("[1,\n"));
+        assert!(!needs_more_input("[1,\n2]\n"));
+        assert!(!needs_more_input("fn f() { return 1.0; }\n"));
+        assert!(!needs_more_input("print 1.0;\n"));
+    }
+}
@@ -0,0 +1,46 @@
+// Runs the noah binary itself: main picks the stack of the thread the driver runs on, which
+// the in-process tests of the driver do not cover.
+
+use std::{fs, path::PathBuf, process::Command};
+
+// brief: Write a script into the temporary directory of the test.
+// input:
+// output: its path.
+fn script(name: &str, source: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+const DEEP: &str = "fn down(n) { if (n == 0) return 0; return down(n - 1) + 1; }
+print down(50000);
+";
+
+#[test]
+fn vm_runs_with_a_large_max_depth() {
+    let path = script("deep_vm.noah", DEEP);
+    let run = Command::new(env!("CARGO_BIN_EXE_noah"))
+        .args(["run", "--vm", "--max-depth", "100000"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "50000\n");
+}
+
+#[test]
+fn tree_walker_rejects_a_depth_it_can_not_back() {
+    let path = script("deep_tree.noah", DEEP);
+    let run = Command::new(env!("CARGO_BIN_EXE_noah"))
+        .args(["run", "--max-depth", "100000"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(run.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("at most 16384"));
+}
@@ -0,0 +1,87 @@
+// Engine tests that need a thread of their own: a host does not choose the stack it embeds
+// Noah on, so runaway recursion must come back as an error, never abort the process.
+
+use std::thread;
+
+use noah::{lexer::diagnostic::E_STACK_OVERFLOW, Engine, IntoLiteral};
+
+const RUNAWAY: &str = "fn r(n) { return r(n + 1); } r(0);";
+
+// brief: Run `body` on a thread with the default stack of spawned threads.
+// input:
+// output:
+fn on_spawned_thread(body: impl FnOnce() + Send + 'static) {
+    thread::Builder::new()
+        .stack_size(2 * 1024 * 1024)
+        .spawn(body)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn deep_recursion_is_an_error() {
+    on_spawned_thread(|| {
+        let mut engine = Engine::new();
+        let err = engine.eval(RUNAWAY).unwrap_err();
+        assert_eq!(err.code, E_STACK_OVERFLOW);
+
+        // The Engine is still usable afterwards, from eval and from the host.
+        assert_eq!(engine.eval("1 + 1;").unwrap().to::<i64>(), Ok(2));
+        let err = engine
+            .call_function("r", vec![0i64.into_literal()])
+            .unwrap_err();
+        assert_eq!(err.code, E_STACK_OVERFLOW);
+    });
+}
+
+#[test]
+fn ordinary_recursion_fits_on_a_main_thread() {
+    // The default budget is what is left of the thread's stack, not a fixed slice of it.
+    thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(|| {
+            let mut engine = Engine::new();
+            engine
+                .eval("fn down(n) { if (n == 0) return 0; return down(n - 1); }")
+                .unwrap();
+            assert_eq!(engine.eval("down(200);").unwrap().to::<i64>(), Ok(0));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn max_depth_is_configurable() {
+    on_spawned_thread(|| {
+        let mut engine = Engine::new();
+        engine.set_max_depth(5);
+        engine
+            .eval("fn down(n) { if (n == 0) return 0; return down(n - 1); }")
+            .unwrap();
+        assert_eq!(engine.eval("down(4);").unwrap().to::<i64>(), Ok(0));
+        let err = engine.eval("down(5);").unwrap_err();
+        assert_eq!(err.code, E_STACK_OVERFLOW);
+    });
+}
+
+#[test]
+fn stack_budget_is_configurable() {
+    // A host can also give scripts a fixed budget, e.g. to keep some of its stack for itself.
+    thread::Builder::new()
+        .stack_size(256 * 1024 * 1024)
+        .spawn(|| {
+            let mut engine = Engine::new();
+            engine.set_stack_budget(128 * 1024 * 1024);
+            engine
+                .eval("fn down(n) { if (n == 0) return 0; return down(n - 1); }")
+                .unwrap();
+            assert_eq!(engine.eval("down(500);").unwrap().to::<i64>(), Ok(0));
+            let err = engine.eval(RUNAWAY).unwrap_err();
+            assert_eq!(err.code, E_STACK_OVERFLOW);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
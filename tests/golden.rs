@@ -0,0 +1,457 @@
+// Golden tests: every .noah script under tests/noah runs on both backends and must print what
+// its comments expect, in the style of the Crafting Interpreters test suite.
+//
+//     print 1 + 2; // expect: 3
+//     print nope;  // expect runtime error: Undefined variable nope.
+//     let = 1;     // expect error: Expected Identifier, found =.
+//     let x: int = "a"; // expect type error: Expected int for x, found str.
+//
+// "expect:" lines give the printed output in order. "expect runtime error:" is the message of
+// the error the run stops with, "expect error:" one of a scan, parse or resolve error; both
+// must be reported on the line of the comment. A script with static errors is not run.
+// "expect runtime error at lib/m.noah:3:" is one raised in a module, at that line of the file
+// relative to the script.
+// "expect type error:" is one of the TypeChecker, which does not stop a script from running;
+// it may also report the line of the expected runtime error.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+};
+
+use noah::lexer::{
+    ast::AstFormat,
+    checker::TypeChecker,
+    compiler::Compiler,
+    diagnostic::{Diagnostic, Origin, E_UNSUPPORTED},
+    emitter::CEmitter,
+    formatter::format_source,
+    interpreter::Interpreter,
+    module::ModuleLoader,
+    optimizer::optimize,
+    output::Output,
+    parser::Parser,
+    printer::Printer,
+    resolver::Resolver,
+    scanner::Scanner,
+    stmt::Stmt,
+    vm::Vm,
+};
+
+type Program = (Vec<Stmt>, HashMap<usize, usize>); // statements and their resolved locals.
+
+#[derive(Default)]
+struct Expectations {
+    output: String,
+    runtime_error: Option<(usize, String)>, // line, message.
+    error_file: Option<PathBuf>,            // of the runtime error, if not the script.
+    errors: Vec<(usize, String)>,
+    type_errors: Vec<(usize, String)>,
+}
+
+// What one backend did with a script.
+struct Run {
+    output: String,
+    error: Option<Diagnostic>,
+}
+
+// brief: Read the "// expect" comments of a script.
+// input:
+// output:
+fn expectations(source: &str) -> Expectations {
+    let mut expected = Expectations::default();
+    for (index, line) in source.lines().enumerate() {
+        let Some(start) = line.find("// expect") else {
+            continue;
+        };
+        let comment = &line[start + 3..];
+        if let Some(text) = comment.strip_prefix("expect: ") {
+            expected.output.push_str(text);
+            expected.output.push('\n');
+        } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+            expected.runtime_error = Some((index + 1, message.to_string()));
+        } else if let Some(place) = comment.strip_prefix("expect runtime error at ") {
+            let (place, message) = place.split_once(": ").unwrap();
+            let (file, line) = place.rsplit_once(':').unwrap();
+            expected.runtime_error = Some((line.parse().unwrap(), message.to_string()));
+            expected.error_file = Some(PathBuf::from(file));
+        } else if let Some(message) = comment.strip_prefix("expect error: ") {
+            expected.errors.push((index + 1, message.to_string()));
+        } else if let Some(message) = comment.strip_prefix("expect type error: ") {
+            expected.type_errors.push((index + 1, message.to_string()));
+        }
+    }
+    expected
+}
+
+// brief: Scan, parse and resolve.
+// input:
+// output: every static error, the Resolver stops at its first one.
+fn front_end(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse()?;
+    let locals = Resolver::new()
+        .resolve(&statements)
+        .map_err(|err| vec![err])?;
+    Ok((statements, locals))
+}
+
+// brief: A loader for the imports of `script`, relative to its directory.
+// input:
+// output:
+fn modules(script: &Path) -> Rc<ModuleLoader> {
+    let modules = Rc::new(ModuleLoader::new(vec![]));
+    modules.start(script);
+    modules
+}
+
+fn run_tree(script: &Path, statements: &Vec<Stmt>, locals: HashMap<usize, usize>) -> Run {
+    let (output, buffer) = Output::buffer();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(output);
+    let source = fs::read_to_string(script).unwrap();
+    interpreter.set_origin(Origin::new(&script.display().to_string(), &source));
+    interpreter.set_modules(modules(script), script.parent().unwrap());
+    interpreter.resolve(locals);
+    let error = interpreter.interpreter(statements).err();
+    let output = buffer.borrow().clone();
+    Run { output, error }
+}
+
+fn run_vm(script: &Path, statements: &Vec<Stmt>) -> Run {
+    let (output, buffer) = Output::buffer();
+    let mut vm = Vm::new();
+    vm.set_output(output);
+    vm.set_modules(modules(script), script.parent().unwrap());
+    let error = Compiler::new()
+        .compile(statements)
+        .and_then(|script| vm.run(script))
+        .err();
+    let output = buffer.borrow().clone();
+    Run { output, error }
+}
+
+// brief: Line by line difference, "-" for expected lines and "+" for actual ones.
+// input:
+// output:
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<&str>, Vec<&str>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => out.push_str(&format!("   {}\n", e)),
+            (e, a) => {
+                if let Some(e) = e {
+                    out.push_str(&format!("  -{}\n", e));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("  +{}\n", a));
+                }
+            }
+        }
+    }
+    out
+}
+
+// brief: Run one script on both backends.
+// input:
+// output: a description of every mismatch.
+fn check(script: &Path) -> Vec<String> {
+    let name = script
+        .strip_prefix(env!("CARGO_MANIFEST_DIR"))
+        .unwrap_or(script)
+        .display();
+    let source = fs::read_to_string(script).unwrap();
+    let expected = expectations(&source);
+
+    let (statements, locals) = match front_end(&source) {
+        Ok(program) => program,
+        Err(errors) => {
+            let actual: Vec<(usize, String)> = errors
+                .into_iter()
+                .map(|err| (err.line, err.message))
+                .collect();
+            if actual == expected.errors {
+                return vec![];
+            }
+            return vec![format!(
+                "{}: expected static errors {:?}, got {:?}",
+                name, expected.errors, actual
+            )];
+        }
+    };
+    if !expected.errors.is_empty() {
+        return vec![format!(
+            "{}: expected static errors {:?}",
+            name, expected.errors
+        )];
+    }
+
+    let mut failures = vec![];
+    let type_errors: Vec<(usize, String)> = match TypeChecker::new().check(&statements) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .into_iter()
+            .map(|err| (err.line, err.message))
+            .filter(|(line, _)| Some(*line) != expected.runtime_error.as_ref().map(|e| e.0))
+            .collect(),
+    };
+    if type_errors != expected.type_errors {
+        failures.push(format!(
+            "{}: expected type errors {:?}, got {:?}",
+            name, expected.type_errors, type_errors
+        ));
+    }
+    for (backend, run) in [
+        ("tree", run_tree(script, &statements, locals)),
+        ("vm", run_vm(script, &statements)),
+    ] {
+        if run.output != expected.output {
+            failures.push(format!(
+                "{} [{}]: output differs\n{}",
+                name,
+                backend,
+                diff(&expected.output, &run.output)
+            ));
+        }
+        let file = match &expected.error_file {
+            Some(file) => script.parent().unwrap().join(file),
+            None => script.to_path_buf(),
+        };
+        if let Some(err) = run.error.as_ref().filter(|err| err.span.is_some()) {
+            // Rendered against the file the error is in, whatever source render is given.
+            let line = fs::read_to_string(&file).unwrap_or_default();
+            let line = line.lines().nth(err.line - 1).unwrap_or_default();
+            let snippet = format!("{} | {}\n", err.line, line);
+            if err.file().is_some_and(|f| Path::new(f) != file)
+                || (expected.error_file.is_some() && !err.render(&source).contains(&snippet))
+            {
+                failures.push(format!(
+                    "{} [{}]: expected the runtime error in {}, got\n{}",
+                    name,
+                    backend,
+                    file.display(),
+                    err.render(&source)
+                ));
+            }
+        }
+        let error = run.error.map(|err| (err.line, err.message));
+        if error != expected.runtime_error {
+            failures.push(format!(
+                "{} [{}]: expected runtime error {:?}, got {:?}",
+                name, backend, expected.runtime_error, error
+            ));
+        }
+    }
+    failures
+}
+
+// brief: Every .noah file below `dir`.
+// input:
+// output:
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "noah") {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn golden_scripts() {
+    let mut found = vec![];
+    scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/noah"),
+        &mut found,
+    );
+    found.sort();
+    assert!(!found.is_empty(), "no scripts under tests/noah");
+
+    let failures: Vec<String> = found.iter().flat_map(|script| check(script)).collect();
+    assert!(
+        failures.is_empty(),
+        "{} failures in {} scripts:\n\n{}",
+        failures.len(),
+        found.len(),
+        failures.join("\n\n")
+    );
+}
+
+// Printing the syntax tree back to source, or formatting the source, must not change what a
+// script does: the result runs to the same output.
+#[test]
+fn printed_formatted_and_serialised_scripts_run_the_same() {
+    let mut found = vec![];
+    scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/noah"),
+        &mut found,
+    );
+    for script in found {
+        let source = fs::read_to_string(&script).unwrap();
+        let Ok((statements, locals)) = front_end(&source) else {
+            continue;
+        };
+        let expected = run_tree(&script, &statements, locals).output;
+
+        let printed = Printer::program(&statements);
+        let formatted = format_source(&source).unwrap();
+        assert_eq!(format_source(&formatted).as_ref(), Ok(&formatted));
+        for (what, source) in [("printed", printed.clone()), ("formatted", formatted)] {
+            let (statements, locals) = front_end(&source).unwrap_or_else(|err| {
+                panic!("{} {}: {:?}\n{}", what, script.display(), err, source)
+            });
+            let output = run_tree(&script, &statements, locals).output;
+            assert_eq!(
+                output,
+                expected,
+                "{} {}:\n{}",
+                what,
+                script.display(),
+                source
+            );
+        }
+        // Through the syntax tree formats, the tree and its resolution stay the same.
+        for format in [AstFormat::Json, AstFormat::Sexpr] {
+            let text = format.write(&statements);
+            let read = format
+                .read(&text)
+                .unwrap_or_else(|err| panic!("{:?} {}: {}", format, script.display(), err));
+            assert_eq!(Printer::program(&read), printed, "{:?}", format);
+            let locals = Resolver::new().resolve(&read).unwrap();
+            let output = run_tree(&script, &read, locals).output;
+            assert_eq!(output, expected, "{:?} {}", format, script.display());
+        }
+    }
+}
+
+// The optimized tree of a script prints the same and fails the same way, on both backends.
+#[test]
+fn optimized_scripts_run_the_same() {
+    let mut found = vec![];
+    scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/noah"),
+        &mut found,
+    );
+    let outcome = |run: Run| (run.output, run.error.map(|err| (err.line, err.message)));
+    for script in found {
+        let source = fs::read_to_string(&script).unwrap();
+        let Ok((statements, locals)) = front_end(&source) else {
+            continue;
+        };
+        let expected = outcome(run_tree(&script, &statements, locals));
+
+        let optimized = optimize(statements);
+        let locals = Resolver::new().resolve(&optimized).unwrap();
+        let tree = outcome(run_tree(&script, &optimized, locals));
+        assert_eq!(tree, expected, "tree {}", script.display());
+        let vm = outcome(run_vm(&script, &optimized));
+        assert_eq!(vm, expected, "vm {}", script.display());
+    }
+}
+
+// brief: Emit a program as C, build it with the system cc and run it.
+// input: name of the binary, unique among the tests.
+// output: None if the C backend does not support the program.
+fn run_c(
+    script: &Path,
+    statements: &[Stmt],
+    name: &str,
+) -> Option<(String, Option<(usize, String)>)> {
+    let c = match CEmitter::new(&script.display().to_string()).emit(statements) {
+        Ok(c) => c,
+        Err(err) if err.code == E_UNSUPPORTED => return None,
+        Err(err) => panic!("{}: {}", script.display(), err),
+    };
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let (source, binary) = (dir.join(format!("{}.c", name)), dir.join(name));
+    fs::write(&source, c).unwrap();
+    let cc = Command::new("cc")
+        .args(["-std=c11", "-Wall", "-Wextra", "-Werror", "-o"])
+        .arg(&binary)
+        .arg(&source)
+        .arg("-lm")
+        .output()
+        .unwrap();
+    assert!(
+        cc.status.success(),
+        "cc {}:\n{}",
+        source.display(),
+        String::from_utf8_lossy(&cc.stderr)
+    );
+    let run = Command::new(&binary).output().unwrap();
+    let output = String::from_utf8(run.stdout).unwrap();
+    // error[E0302]: message
+    //  --> file:line:column
+    let stderr = String::from_utf8(run.stderr).unwrap();
+    let mut lines = stderr.lines();
+    let error = lines.next().map(|header| {
+        let message = header.split_once("]: ").unwrap().1.to_string();
+        let position = lines.next().unwrap().rsplit(':').nth(1).unwrap();
+        (position.parse().unwrap(), message)
+    });
+    assert_eq!(run.status.code() == Some(70), error.is_some(), "{}", stderr);
+    Some((output, error))
+}
+
+// Scripts the C backend supports print the same and fail the same way once built with cc, as
+// written and optimized.
+#[test]
+fn c_backend_runs_the_same() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("no cc to build the C backend output with, skipped");
+        return;
+    }
+    let mut found = vec![];
+    scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/noah"),
+        &mut found,
+    );
+    found.sort();
+    let mut built = 0;
+    for (index, script) in found.iter().enumerate() {
+        let source = fs::read_to_string(script).unwrap();
+        let Ok((statements, locals)) = front_end(&source) else {
+            continue;
+        };
+        let run = run_tree(script, &statements, locals);
+        let expected = (run.output, run.error.map(|err| (err.line, err.message)));
+
+        let Some(actual) = run_c(script, &statements, &format!("golden_{}", index)) else {
+            continue;
+        };
+        assert_eq!(actual, expected, "{}", script.display());
+        let optimized = optimize(statements);
+        let name = format!("golden_{}_optimized", index);
+        let actual = run_c(script, &optimized, &name).unwrap();
+        assert_eq!(actual, expected, "optimized {}", script.display());
+        built += 1;
+    }
+    assert!(built >= 10, "only {} scripts built", built);
+}
+
+#[test]
+fn expectations_are_read_from_comments() {
+    let expected = expectations(
+        "print 1; // expect: 1\nprint \"a b\"; // expect: a b\nnope; // expect runtime error: Undefined variable nope.\n",
+    );
+    assert_eq!(expected.output, "1\na b\n");
+    assert_eq!(
+        expected.runtime_error,
+        Some((3, "Undefined variable nope.".to_string()))
+    );
+    assert_eq!(expected.error_file, None);
+    let expected = expectations("m.f(); // expect runtime error at lib/m.noah:7: Stack overflow.");
+    assert_eq!(
+        expected.runtime_error,
+        Some((7, "Stack overflow.".to_string()))
+    );
+    assert_eq!(expected.error_file, Some(PathBuf::from("lib/m.noah")));
+    assert_eq!(diff("a\nb\n", "a\nc\n"), "   a\n  -b\n  +c\n");
+}